@@ -34,7 +34,10 @@
 //! ```
 
 use crate::{
-    channels::storage::{Local, Shared, Storage, StorageExt},
+    channels::{
+        stats::{ChannelRecorder, ChannelStats},
+        storage::{Local, Shared, Storage, StorageExt},
+    },
     error::ResourceType,
     wakers::{PendingWakes, WakerList},
     GlommioError,
@@ -46,6 +49,7 @@ use std::{
     future::Future,
     marker::PhantomData,
     pin::Pin,
+    sync::Arc,
     task::{Context, Poll},
 };
 
@@ -117,6 +121,7 @@ pub struct State<T> {
     wakers: WakerList,
     senders: usize,
     receivers: usize,
+    recorder: Option<Arc<ChannelRecorder>>,
 }
 
 impl<T> State<T> {
@@ -128,6 +133,7 @@ impl<T> State<T> {
             wakers: WakerList::new(),
             senders: 1,
             receivers: 1,
+            recorder: None,
         }
     }
 
@@ -160,14 +166,22 @@ impl<T> State<T> {
             .find(|(seq, _)| *seq == *next)
             .map(|(_, value)| value.clone());
 
-        match found {
+        let outcome = match found {
             Some(value) => {
                 *next += 1;
                 Ok(value)
             }
             None if self.senders == 0 => Err(TryRecvError::Closed),
             None => Err(TryRecvError::Empty),
+        };
+        if let (Some(recorder), Ok(_)) = (&self.recorder, &outcome) {
+            recorder.received(1, self.values.len());
         }
+        outcome
+    }
+
+    fn stats(&self) -> Option<ChannelStats> {
+        self.recorder.as_ref().map(|recorder| recorder.stats())
     }
 }
 
@@ -283,6 +297,9 @@ impl<T: Clone, S: Storage<State<T>>> Sender<T, S> {
                 let seq = state.next_seq;
                 state.next_seq += 1;
                 state.values.push_back((seq, value));
+                if let Some(recorder) = &state.recorder {
+                    recorder.sent(1, state.values.len());
+                }
                 Ok(state.receivers)
             };
             (outcome, state.wakers.take())
@@ -308,6 +325,21 @@ impl<T: Clone, S: Storage<State<T>>> Sender<T, S> {
     pub fn receiver_count(&self) -> usize {
         self.inner.with(|state| state.receivers)
     }
+
+    /// Starts recording the [`ChannelStats`] of this channel under `name`.
+    ///
+    /// Every value read by every receiver counts as received, and the depth
+    /// is the number of values retained.
+    pub fn instrument(&self, name: impl Into<String>) {
+        let recorder = ChannelRecorder::register(name);
+        self.inner.with(|state| state.recorder = Some(recorder));
+    }
+
+    /// Returns the [`ChannelStats`] of this channel, or [`None`] if it was not
+    /// [instrumented](Self::instrument).
+    pub fn stats(&self) -> Option<ChannelStats> {
+        self.inner.with(|state| state.stats())
+    }
 }
 
 impl<T, S: Storage<State<T>>> Clone for Sender<T, S> {
@@ -362,12 +394,26 @@ impl<T: Clone, S: Storage<State<T>>> Receiver<T, S> {
         let next = &mut self.next;
         self.inner.with(|state| match state.take_next(next) {
             Err(TryRecvError::Empty) => {
+                if let Some(recorder) = &state.recorder {
+                    recorder.receiver_idle();
+                }
                 state.wakers.push(waker.waker().clone());
                 Poll::Pending
             }
-            outcome => Poll::Ready(outcome),
+            outcome => {
+                if let Some(recorder) = &state.recorder {
+                    recorder.receiver_woken();
+                }
+                Poll::Ready(outcome)
+            }
         })
     }
+
+    /// Returns the [`ChannelStats`] of this channel, or [`None`] if it was not
+    /// [instrumented](Sender::instrument).
+    pub fn stats(&self) -> Option<ChannelStats> {
+        self.inner.with(|state| state.stats())
+    }
 }
 
 impl<T, S: Storage<State<T>>> Clone for Receiver<T, S> {
@@ -430,6 +476,17 @@ impl<T: Clone, S: Storage<State<T>>> Future for Recv<'_, T, S> {
     }
 }
 
+impl<T, S: Storage<State<T>>> Drop for Recv<'_, T, S> {
+    /// Stops the idle timer a pending receive leaves open when abandoned.
+    fn drop(&mut self) {
+        self.receiver.inner.with(|state| {
+            if let Some(recorder) = &state.recorder {
+                recorder.receiver_woken();
+            }
+        });
+    }
+}
+
 impl<T, S: Storage<State<T>> + fmt::Debug> fmt::Debug for Sender<T, S> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Sender")
@@ -4,22 +4,36 @@
 // This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
 //
 use std::{
+    cell::RefCell,
     fmt::{self, Debug, Formatter},
     io::{Error, ErrorKind},
+    rc::{Rc, Weak},
     sync::{Arc, Mutex},
 };
 
 use crate::{
-    channels::shared_channel::{self, *},
-    GlommioError, Result,
+    channels::{
+        shared_channel::{self, *},
+        watch,
+    },
+    GlommioError, ResourceType, Result,
 };
 
 /// Sender side
-#[derive(Debug)]
 pub struct Senders<T: Send> {
     peer_id: usize,
     producer_id: Option<usize>,
-    senders: Vec<Option<ConnectedSender<T>>>,
+    links: Rc<Links<T>>,
+}
+
+impl<T: Send> Debug for Senders<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Senders")
+            .field("peer_id", &self.peer_id)
+            .field("producer_id", &self.producer_id)
+            .field("senders", &self.links.senders.borrow())
+            .finish()
+    }
 }
 
 impl<T: Send> Senders<T> {
@@ -34,26 +48,43 @@ impl<T: Send> Senders<T> {
     }
 
     /// Number of peers to which messages can be sent.
+    ///
+    /// In a full mesh this counts every peer id handed out so far, including
+    /// the local one and those vacated by peers that left.
     pub fn nr_consumers(&self) -> usize {
-        self.senders.len()
+        self.links.senders.borrow().len()
+    }
+
+    /// Watches the peers of the mesh.
+    ///
+    /// The receiver is told about a peer joining once the channels with it
+    /// are connected, so the new peer can be sent to as soon as it shows up.
+    pub fn membership(&self) -> watch::Receiver<Membership> {
+        self.links.watcher.clone()
     }
 
     /// Send a message to the idx-th consumer
     ///
     /// It returns a [`GlommioError::IoError`] encapsulating a [`InvalidInput`]
     /// if the idx is out of the range of available senders, or the sender
-    /// is a placeholder in the case of full mesh.
+    /// is a placeholder in the case of full mesh. If the consumer left the
+    /// mesh, the message is handed back in a [`GlommioError::Closed`].
     ///
     /// See [`ConnectedSender.send`] for how the underlying sender works.
     ///
     /// [`GlommioError::IoError`]: crate::GlommioError::IoError
+    /// [`GlommioError::Closed`]: crate::GlommioError::Closed
     /// [`InvalidInput`]: https://doc.rust-lang.org/std/io/enum.ErrorKind.html#variant.InvalidInput
     /// [`ConnectedSender.send`]:
     /// crate::channels::shared_channel::ConnectedSender::send
     pub async fn send_to(&self, idx: usize, msg: T) -> Result<(), T> {
-        match self.senders.get(idx) {
-            Some(Some(consumer)) => consumer.send(msg).await,
-            _ => {
+        let consumer = self.links.sender(idx);
+        match consumer {
+            Some(consumer) => consumer.send(msg).await,
+            None if self.links.has_left(idx) => {
+                Err(GlommioError::Closed(ResourceType::Channel(msg)))
+            }
+            None => {
                 let msg = if idx < self.nr_consumers() {
                     "Local message should not be sent via channel mesh".into()
                 } else {
@@ -71,18 +102,23 @@ impl<T: Send> Senders<T> {
     ///
     /// It returns a [`GlommioError::IoError`] encapsulating a [`InvalidInput`]
     /// if the idx is out of the range of available senders, or the sender
-    /// is a placeholder in the case of full mesh.
+    /// is a placeholder in the case of full mesh. If the consumer left the
+    /// mesh, the message is handed back in a [`GlommioError::Closed`].
     ///
     /// See [`ConnectedSender.try_send`] for how the underlying sender works.
     ///
     /// [`GlommioError::IoError`]: crate::GlommioError::IoError
+    /// [`GlommioError::Closed`]: crate::GlommioError::Closed
     /// [`InvalidInput`]: https://doc.rust-lang.org/std/io/enum.ErrorKind.html#variant.InvalidInput
     /// [`ConnectedSender.try_send`]:
     /// crate::channels::shared_channel::ConnectedSender::try_send
     pub fn try_send_to(&self, idx: usize, msg: T) -> Result<(), T> {
-        match self.senders.get(idx) {
-            Some(Some(consumer)) => consumer.try_send(msg),
-            _ => Err(GlommioError::IoError(Error::new(
+        match self.links.sender(idx) {
+            Some(consumer) => consumer.try_send(msg),
+            None if self.links.has_left(idx) => {
+                Err(GlommioError::Closed(ResourceType::Channel(msg)))
+            }
+            None => Err(GlommioError::IoError(Error::new(
                 ErrorKind::InvalidInput,
                 "Local message should not be sent via channel mesh",
             ))),
@@ -91,18 +127,34 @@ impl<T: Send> Senders<T> {
 
     /// Close the senders
     pub fn close(&self) {
-        for sender in self.senders.iter().flatten() {
+        for sender in self.links.senders.borrow().iter().flatten() {
             sender.close();
         }
     }
+
+    // Whether the peer is gone from the mesh, even if the local view has not
+    // caught up with it yet
+    pub(crate) fn has_departed(&self, peer_id: usize) -> bool {
+        let state = self.links.state.lock().unwrap();
+        peer_id != self.peer_id && !state.membership.contains(peer_id)
+    }
 }
 
 /// Receiver side
-#[derive(Debug)]
 pub struct Receivers<T: Send> {
     peer_id: usize,
     consumer_id: Option<usize>,
-    receivers: Vec<Option<ConnectedReceiver<T>>>,
+    links: Rc<Links<T>>,
+}
+
+impl<T: Send> Debug for Receivers<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receivers")
+            .field("peer_id", &self.peer_id)
+            .field("consumer_id", &self.consumer_id)
+            .field("receivers", &self.links.receivers.borrow())
+            .finish()
+    }
 }
 
 impl<T: Send> Receivers<T> {
@@ -117,15 +169,29 @@ impl<T: Send> Receivers<T> {
     }
 
     /// Number of peers from which message can be received.
+    ///
+    /// In a full mesh this counts every peer id handed out so far, including
+    /// the local one and those vacated by peers that left.
     pub fn nr_producers(&self) -> usize {
-        self.receivers.len()
+        self.links.receivers.borrow().len()
+    }
+
+    /// Watches the peers of the mesh.
+    ///
+    /// The receiver is told about a peer joining once the channels with it
+    /// are connected, so its stream is already available from
+    /// [`streams`](Receivers::streams) when it shows up.
+    pub fn membership(&self) -> watch::Receiver<Membership> {
+        self.links.watcher.clone()
     }
 
     /// Receive a message from the idx-th producer
     ///
     /// It returns a [`GlommioError::IoError`] encapsulating a [`InvalidInput`]
     /// if the idx is out of the range of available receivers, or the
-    /// receiver is a placeholder in the case of full mesh.
+    /// receiver is a placeholder in the case of full mesh. The messages of a
+    /// producer that left the mesh can still be drained, after which `None`
+    /// is returned.
     ///
     /// See [`ConnectedReceiver.recv`] for how the underlying sender works.
     ///
@@ -134,9 +200,10 @@ impl<T: Send> Receivers<T> {
     /// [`ConnectedReceiver.recv`]:
     /// crate::channels::shared_channel::ConnectedReceiver::recv
     pub async fn recv_from(&self, idx: usize) -> Result<Option<T>, ()> {
-        match self.receivers.get(idx) {
-            Some(Some(producer)) => Ok(producer.recv().await),
-            _ => Err(GlommioError::IoError(Error::new(
+        let producer = self.links.receivers.borrow().get(idx).cloned().flatten();
+        match producer {
+            Some(producer) => Ok(producer.recv().await),
+            None => Err(GlommioError::IoError(Error::new(
                 ErrorKind::InvalidInput,
                 "Local message should not be received from channel mesh",
             ))),
@@ -146,16 +213,192 @@ impl<T: Send> Receivers<T> {
     /// Returns a vec of [`ConnectedReceiver`]s with the id of their upstream
     /// producers.
     ///
+    /// Only the streams not taken yet are returned, so calling this again
+    /// after the [`membership`](Receivers::membership) changed collects the
+    /// streams of the peers that joined in between.
+    ///
+    /// Collect them into a [`StreamMap`], or [`extend`](Extend::extend) one
+    /// with them, to read from every peer in a single task.
+    ///
     /// [`ConnectedReceiver`]: ../shared_channel/struct.ConnectedReceiver.html
+    /// [`StreamMap`]: crate::channels::StreamMap
     pub fn streams(&mut self) -> Vec<(usize, ConnectedReceiver<T>)> {
-        self.receivers
+        self.links
+            .receivers
+            .borrow_mut()
             .iter_mut()
             .enumerate()
-            .flat_map(|(idx, recv)| recv.take().map(|recv| (idx, recv)))
+            .flat_map(|(idx, recv)| {
+                // `recv_from` borrows `self`, so no other handle is left
+                recv.take()
+                    .and_then(|recv| Rc::try_unwrap(recv).ok())
+                    .map(|recv| (idx, recv))
+            })
             .collect()
     }
 }
 
+/// The peers of a channel mesh, as last seen by one of them.
+///
+/// [`Senders::membership`] and [`Receivers::membership`] watch it change as
+/// peers join and leave. Ids are stable: a peer that leaves vacates its id,
+/// and the next one to join takes the lowest vacant id.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Membership {
+    epoch: u64,
+    roles: Vec<Option<Role>>,
+}
+
+impl Membership {
+    /// Counts the changes: 1 once the mesh is formed, and one more every
+    /// time a peer joins or leaves.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Returns whether the peer with this id is in the mesh.
+    pub fn contains(&self, peer_id: usize) -> bool {
+        self.role(peer_id).is_some()
+    }
+
+    /// Returns the role of the peer with this id, if it is in the mesh.
+    pub fn role(&self, peer_id: usize) -> Option<Role> {
+        self.roles.get(peer_id).copied().flatten()
+    }
+
+    /// Returns the ids of the peers in the mesh, in ascending order.
+    pub fn peers(&self) -> impl Iterator<Item = usize> + '_ {
+        self.roles
+            .iter()
+            .enumerate()
+            .filter_map(|(peer_id, role)| role.map(|_| peer_id))
+    }
+
+    /// Returns the number of peers in the mesh.
+    pub fn nr_peers(&self) -> usize {
+        self.roles.iter().flatten().count()
+    }
+
+    fn vacancy(&self) -> usize {
+        self.roles
+            .iter()
+            .position(Option::is_none)
+            .unwrap_or(self.roles.len())
+    }
+}
+
+// What the senders and receivers of a peer share: the connected channels,
+// which change as peers come and go, and the peer's view of the membership
+struct Links<T: Send> {
+    peer_id: usize,
+    full: bool,
+    senders: RefCell<Vec<Option<Rc<ConnectedSender<T>>>>>,
+    receivers: RefCell<Vec<Option<Rc<ConnectedReceiver<T>>>>>,
+    view: watch::Sender<Membership>,
+    watcher: watch::Receiver<Membership>,
+    state: Arc<Mutex<JoinState<T>>>,
+}
+
+impl<T: Send> Links<T> {
+    fn sender(&self, idx: usize) -> Option<Rc<ConnectedSender<T>>> {
+        self.senders.borrow().get(idx).cloned().flatten()
+    }
+
+    fn has_left(&self, idx: usize) -> bool {
+        self.full && idx != self.peer_id && idx < self.senders.borrow().len()
+    }
+}
+
+impl<T: 'static + Send> Links<T> {
+    // Connects the channels that peers which joined since the last call left
+    // for us, then drops the senders to the peers that left and publishes
+    // the membership the state is in
+    async fn sync(&self) {
+        let (membership, senders, receivers) = {
+            let mut state = self.state.lock().unwrap();
+            let peer_id = self.peer_id;
+            let senders: Vec<_> = state.senders[peer_id]
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(to, sender)| sender.take().map(|sender| (to, sender)))
+                .collect();
+            let receivers: Vec<_> = state
+                .receivers
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(from, row)| row[peer_id].take().map(|receiver| (from, receiver)))
+                .collect();
+            (state.membership.clone(), senders, receivers)
+        };
+
+        let senders: Vec<_> = senders
+            .into_iter()
+            .map(|(to, s)| (to, crate::spawn_local(s.connect()).detach()))
+            .collect();
+        let receivers: Vec<_> = receivers
+            .into_iter()
+            .map(|(from, r)| (from, crate::spawn_local(r.connect()).detach()))
+            .collect();
+
+        let slots = membership.roles.len();
+        for (to, sender) in senders {
+            let sender = sender.await.unwrap();
+            let mut senders = self.senders.borrow_mut();
+            if senders.len() < slots {
+                senders.resize(slots, None);
+            }
+            senders[to] = Some(Rc::new(sender));
+        }
+        for (from, receiver) in receivers {
+            let receiver = receiver.await.unwrap();
+            let mut receivers = self.receivers.borrow_mut();
+            if receivers.len() < slots {
+                receivers.resize(slots, None);
+            }
+            receivers[from] = Some(Rc::new(receiver));
+        }
+
+        if self.full {
+            // The receivers of departed peers stay, for what they sent before
+            // leaving to be drained
+            for (peer_id, sender) in self.senders.borrow_mut().iter_mut().enumerate() {
+                if !membership.contains(peer_id) {
+                    *sender = None;
+                }
+            }
+        }
+
+        if self.view.borrow().epoch < membership.epoch {
+            // `watcher` is a receiver, so this cannot fail
+            self.view.send(membership).ok();
+        }
+    }
+}
+
+impl<T: Send> Drop for Links<T> {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.leave(self.peer_id);
+        }
+    }
+}
+
+// Keeps the links of a peer up to date until the peer leaves
+async fn follow<T: 'static + Send>(
+    links: Weak<Links<T>>,
+    mut updates: watch::SharedReceiver<Membership>,
+) {
+    loop {
+        match links.upgrade() {
+            Some(links) => links.sync().await,
+            None => return,
+        }
+        if updates.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
 struct Peer {
     executor_id: usize,
     role: Role,
@@ -196,6 +439,10 @@ struct JoinState<T: Send> {
     ready: bool,
     senders: Vec<Vec<Option<SharedSender<T>>>>,
     receivers: Vec<Vec<Option<SharedReceiver<T>>>>,
+    membership: Membership,
+    updates: watch::SharedSender<Membership>,
+    // Kept so that publishing never fails, and cloned for every peer
+    subscriber: watch::SharedReceiver<Membership>,
 }
 
 impl<T: Send> JoinState<T> {
@@ -208,12 +455,49 @@ impl<T: Send> JoinState<T> {
             receivers.push((0..nr_peers).map(|_| None).collect());
         }
 
+        let (updates, subscriber) = watch::shared(Membership::default());
+
         Self {
             peers: Vec::new(),
             ready: false,
             senders,
             receivers,
+            membership: Membership::default(),
+            updates,
+            subscriber,
+        }
+    }
+
+    // Adds a slot at the end of the matrices of pending channels
+    fn grow(&mut self) {
+        let slots = self.senders.len() + 1;
+        for row in self.senders.iter_mut() {
+            row.push(None);
+        }
+        for row in self.receivers.iter_mut() {
+            row.push(None);
+        }
+        self.senders.push((0..slots).map(|_| None).collect());
+        self.receivers.push((0..slots).map(|_| None).collect());
+    }
+
+    fn publish(&mut self) {
+        self.membership.epoch += 1;
+        self.updates.send(self.membership.clone()).ok();
+    }
+
+    fn leave(&mut self, peer_id: usize) {
+        if !self.membership.contains(peer_id) {
+            return;
         }
+        self.membership.roles[peer_id] = None;
+        for other in 0..self.senders.len() {
+            self.senders[peer_id][other] = None;
+            self.senders[other][peer_id] = None;
+            self.receivers[peer_id][other] = None;
+            self.receivers[other][peer_id] = None;
+        }
+        self.publish();
     }
 }
 
@@ -263,9 +547,15 @@ impl MeshAdapter for Partial {
 pub type PartialMesh<T> = MeshBuilder<T, Partial>;
 
 /// A builder for channel mesh
+///
+/// The mesh is formed once `nr_peers` peers joined, and a peer leaves it when
+/// both its [`Senders`] and [`Receivers`] are dropped. A full mesh built
+/// [`with_dynamic_membership`](MeshBuilder::with_dynamic_membership) also
+/// takes in peers joining after it was formed.
 pub struct MeshBuilder<T: Send, A: MeshAdapter> {
     nr_peers: usize,
     channel_size: usize,
+    dynamic: bool,
     state: Arc<Mutex<JoinState<T>>>,
     adapter: A,
 }
@@ -275,6 +565,7 @@ impl<T: Send, A: MeshAdapter> Clone for MeshBuilder<T, A> {
         Self {
             nr_peers: self.nr_peers,
             channel_size: self.channel_size,
+            dynamic: self.dynamic,
             state: self.state.clone(),
             adapter: self.adapter.clone(),
         }
@@ -296,6 +587,19 @@ impl<T: 'static + Send> MeshBuilder<T, Full> {
         Self::new(nr_peers, channel_size, Full)
     }
 
+    /// Lets peers join the mesh after it was formed.
+    ///
+    /// Without it, joining a formed mesh fails. With it, the peer takes the
+    /// lowest id a departed peer vacated, or the next one, and the others are
+    /// told through their [`membership`](Senders::membership) watch.
+    ///
+    /// Must be set before the builder is cloned to the peers.
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_dynamic_membership(mut self) -> Self {
+        self.dynamic = true;
+        self
+    }
+
     /// Join a full mesh
     pub async fn join(self) -> Result<(Senders<T>, Receivers<T>), ()> {
         self.join_with(Role::Both).await
@@ -319,20 +623,72 @@ impl<T: 'static + Send, A: MeshAdapter> MeshBuilder<T, A> {
         MeshBuilder {
             nr_peers,
             channel_size,
+            dynamic: false,
             state: Arc::new(Mutex::new(JoinState::new(nr_peers))),
             adapter,
         }
     }
 
-    /// Returns number of all peers in the mesh
+    /// Returns the number of peers the mesh is formed with
     pub fn nr_peers(&self) -> usize {
         self.nr_peers
     }
 
+    /// Returns the capacity of each channel between two peers
+    pub fn channel_size(&self) -> usize {
+        self.channel_size
+    }
+
+    // Takes in a peer after the mesh was formed, leaving the channels with
+    // every peer in the mesh to be picked up by both ends
+    fn admit(&self, state: &mut JoinState<T>, role: Role) -> Result<(usize, usize), ()> {
+        if !self.dynamic {
+            return Err(GlommioError::IoError(Error::other(
+                "The channel mesh is full.",
+            )));
+        }
+
+        let exec_id = crate::executor().id();
+        assert!(
+            !(0..state.peers.len())
+                .any(|p| state.peers[p].executor_id == exec_id && state.membership.contains(p)),
+            "Should not join a mesh more than once."
+        );
+
+        let peer_id = state.membership.vacancy();
+        if peer_id == state.peers.len() {
+            state.peers.push(Peer::new(role));
+            state.membership.roles.push(None);
+            state.grow();
+        } else {
+            state.peers[peer_id] = Peer::new(role);
+        }
+
+        let others: Vec<usize> = state.membership.peers().collect();
+        for other in others {
+            let (tx, rx) = shared_channel::new_bounded(self.channel_size);
+            state.senders[peer_id][other] = Some(tx);
+            state.receivers[peer_id][other] = Some(rx);
+            let (tx, rx) = shared_channel::new_bounded(self.channel_size);
+            state.senders[other][peer_id] = Some(tx);
+            state.receivers[other][peer_id] = Some(rx);
+        }
+
+        state.membership.roles[peer_id] = Some(role);
+        state.publish();
+
+        // Only full meshes admit peers, where every role id is the peer id
+        Ok((peer_id, peer_id))
+    }
+
     async fn register(&self, role: Role) -> Result<(usize, usize), ()> {
         let (is_last, exec_id) = {
             let mut state = self.state.lock().unwrap();
 
+            if state.ready {
+                return self.admit(&mut state, role);
+            }
+
             if state.peers.len() == self.nr_peers {
                 return Err(GlommioError::IoError(Error::other(
                     "The channel mesh is full.",
@@ -372,6 +728,8 @@ impl<T: 'static + Send, A: MeshAdapter> MeshBuilder<T, A> {
                     }
                 }
 
+                state.membership.roles = state.peers.iter().map(|p| Some(p.role)).collect();
+                state.publish();
                 state.ready = true;
             }
         } else {
@@ -391,11 +749,11 @@ impl<T: 'static + Send, A: MeshAdapter> MeshBuilder<T, A> {
         }
 
         let state = self.state.lock().unwrap();
-        // At this point st.ready == true and st.peers is stable.
+        // At this point st.ready == true. Peers admitted since are appended
+        // out of order, so the search is linear.
 
-        let peer_id = state
-            .peers
-            .binary_search_by(|p| p.executor_id.cmp(&exec_id))
+        let peer_id = (0..state.peers.len())
+            .find(|&p| state.peers[p].executor_id == exec_id && state.membership.contains(p))
             .unwrap();
 
         let role_id = state.peers[..peer_id]
@@ -410,18 +768,19 @@ impl<T: 'static + Send, A: MeshAdapter> MeshBuilder<T, A> {
         let (peer_id, role_id) = self.register(role).await?;
 
         // Extract Senders and Receivers for this peer
-        let (mut senders, mut receivers) = {
+        let (mut senders, mut receivers, membership, updates) = {
             let mut st = self.state.lock().unwrap();
+            let slots = st.senders.len();
 
-            let mut row = Vec::with_capacity(self.nr_peers);
-            let mut col = Vec::with_capacity(self.nr_peers);
+            let mut row = Vec::with_capacity(slots);
+            let mut col = Vec::with_capacity(slots);
 
-            for i in 0..self.nr_peers {
+            for i in 0..slots {
                 row.push(st.senders[peer_id][i].take());
                 col.push(st.receivers[i][peer_id].take());
             }
 
-            (row, col)
+            (row, col, st.membership.clone(), st.subscriber.clone())
         };
 
         let producer_id = if role.is_producer() {
@@ -436,10 +795,10 @@ impl<T: 'static + Send, A: MeshAdapter> MeshBuilder<T, A> {
             None
         };
 
-        let mut senders_vec = Vec::with_capacity(self.nr_peers);
-        let mut receivers_vec = Vec::with_capacity(self.nr_peers);
+        let mut senders_vec = Vec::with_capacity(senders.len());
+        let mut receivers_vec = Vec::with_capacity(receivers.len());
 
-        for i in 0..self.nr_peers {
+        for i in 0..senders.len() {
             let sender = senders[i].take();
             let receiver = receivers[i].take();
 
@@ -452,7 +811,7 @@ impl<T: 'static + Send, A: MeshAdapter> MeshBuilder<T, A> {
                         senders_vec.push(None)
                     }
                 }
-                Some(h) => senders_vec.push(Some(h.await.unwrap())),
+                Some(h) => senders_vec.push(Some(Rc::new(h.await.unwrap()))),
             }
 
             match receiver {
@@ -461,20 +820,32 @@ impl<T: 'static + Send, A: MeshAdapter> MeshBuilder<T, A> {
                         receivers_vec.push(None)
                     }
                 }
-                Some(h) => receivers_vec.push(Some(h.await.unwrap())),
+                Some(h) => receivers_vec.push(Some(Rc::new(h.await.unwrap()))),
             }
         }
 
+        let (view, watcher) = watch::watch(membership);
+        let links = Rc::new(Links {
+            peer_id,
+            full: self.adapter.is_full(),
+            senders: RefCell::new(senders_vec),
+            receivers: RefCell::new(receivers_vec),
+            view,
+            watcher,
+            state: self.state.clone(),
+        });
+        crate::spawn_local(follow(Rc::downgrade(&links), updates)).detach();
+
         Ok((
             Senders {
                 peer_id,
                 producer_id,
-                senders: senders_vec,
+                links: links.clone(),
             },
             Receivers {
                 peer_id,
                 consumer_id,
-                receivers: receivers_vec,
+                links,
             },
         ))
     }
@@ -540,6 +911,96 @@ mod tests {
         do_test_channel_mesh(1, 1, 2);
     }
 
+    #[test]
+    fn peers_join_and_leave_a_live_mesh() {
+        let mesh_builder = MeshBuilder::<usize, _>::full(2, 16).with_dynamic_membership();
+        let (joined, formed) = std::sync::mpsc::channel();
+
+        let founders = (0..2)
+            .map(|_| {
+                LocalExecutorBuilder::default().spawn(
+                    enclose!((mesh_builder, joined) move || async move {
+                        let (sender, receiver) = mesh_builder.join().await.unwrap();
+                        let mut membership = sender.membership();
+                        assert_eq!(membership.borrow().nr_peers(), 2);
+                        joined.send(()).unwrap();
+
+                        while !membership.borrow().contains(2) {
+                            membership.changed().await.unwrap();
+                        }
+                        assert_eq!(sender.nr_consumers(), 3);
+                        sender.send_to(2, sender.peer_id()).await.unwrap();
+                        assert_eq!(receiver.recv_from(2).await.unwrap(), Some(2));
+
+                        while membership.borrow().contains(2) {
+                            membership.changed().await.unwrap();
+                        }
+                        assert!(matches!(
+                            sender.send_to(2, 0).await,
+                            Err(GlommioError::Closed(ResourceType::Channel(0)))
+                        ));
+                    }),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        formed.recv().unwrap();
+        formed.recv().unwrap();
+
+        let late =
+            LocalExecutorBuilder::default().spawn(enclose!((mesh_builder) move || async move {
+                let (sender, receiver) = mesh_builder.join().await.unwrap();
+                assert_eq!(sender.peer_id(), 2);
+                assert_eq!(receiver.membership().borrow().nr_peers(), 3);
+                for peer in 0..2 {
+                    sender.send_to(peer, 2).await.unwrap();
+                }
+                for peer in 0..2 {
+                    assert_eq!(receiver.recv_from(peer).await.unwrap(), Some(peer));
+                }
+            }));
+
+        late.unwrap().join().unwrap();
+        for ex in founders {
+            ex.unwrap().join().unwrap();
+        }
+    }
+
+    #[test]
+    fn a_vacated_id_is_taken_by_the_next_peer() {
+        let mesh_builder = MeshBuilder::<usize, _>::full(1, 1).with_dynamic_membership();
+
+        LocalExecutor::default().run(enclose!((mesh_builder) async move {
+            let (sender, receiver) = mesh_builder.clone().join().await.unwrap();
+            let mut membership = receiver.membership();
+            assert_eq!(sender.peer_id(), 0);
+
+            let join_and_leave = || {
+                LocalExecutorBuilder::default()
+                    .spawn(enclose!((mesh_builder) move || async move {
+                        let (sender, _) = mesh_builder.join().await.unwrap();
+                        sender.peer_id()
+                    }))
+                    .unwrap()
+            };
+
+            // Formed, then one join and one departure per peer
+            let first = join_and_leave();
+            while membership.borrow().epoch() < 3 {
+                membership.changed().await.unwrap();
+            }
+            let second = join_and_leave();
+            while membership.borrow().epoch() < 5 {
+                membership.changed().await.unwrap();
+            }
+
+            assert_eq!(first.join().unwrap(), 1);
+            assert_eq!(second.join().unwrap(), 1);
+            assert_eq!(sender.nr_consumers(), 2);
+            assert_eq!(membership.borrow().peers().collect::<Vec<_>>(), [0]);
+        }));
+    }
+
     fn do_test_channel_mesh(nr_peers: usize, channel_size: usize, nr_executors: usize) {
         let mesh_builder = MeshBuilder::full(nr_peers, channel_size);
 
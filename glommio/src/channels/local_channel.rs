@@ -3,12 +3,19 @@
 //
 // This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
 //
-use crate::{channels::ChannelCapacity, GlommioError, ResourceType};
+use crate::{
+    channels::{
+        stats::{ChannelRecorder, ChannelStats},
+        ChannelCapacity,
+    },
+    GlommioError, ResourceType,
+};
 use futures_lite::{stream::Stream, Future};
 use std::{
     cell::RefCell,
     pin::Pin,
     rc::Rc,
+    sync::Arc,
     task::{Context, Poll, Waker},
 };
 
@@ -215,6 +222,9 @@ impl<T, F> Drop for Waiter<'_, T, F> {
                 .remove()
                 .expect("Future has to be linked into the waiting queue");
         }
+        if let Some(kind) = *self.node.kind.borrow() {
+            self.channel.state.borrow().stop_waiting(kind);
+        }
     }
 }
 
@@ -295,6 +305,7 @@ struct State<T> {
     channel: VecDeque<T>,
     recv_waiters: Option<LinkedList<WaiterAdapter>>,
     send_waiters: Option<LinkedList<WaiterAdapter>>,
+    recorder: Option<Arc<ChannelRecorder>>,
 }
 
 impl<T> State<T> {
@@ -305,6 +316,9 @@ impl<T> State<T> {
             Err(GlommioError::WouldBlock(ResourceType::Channel(item)))
         } else {
             self.channel.push_back(item);
+            if let Some(recorder) = &self.recorder {
+                recorder.sent(1, self.channel.len());
+            }
 
             Ok(self.recv_waiters.as_mut().and_then(|x| {
                 x.pop_front().map(|n| {
@@ -327,30 +341,71 @@ impl<T> State<T> {
 
     fn wait_for_room(&mut self) -> PollResult<()> {
         if !self.is_full() {
+            if let Some(recorder) = &self.recorder {
+                recorder.sender_unblocked();
+            }
             PollResult::Ready(())
         } else {
+            if let Some(recorder) = &self.recorder {
+                recorder.sender_blocked();
+            }
             PollResult::Pending(WaiterKind::Sender)
         }
     }
 
+    /// Stops the blocked or idle timer a dropped waiter of `kind` may have
+    /// left open, unless another one of its kind is still queued.
+    fn stop_waiting(&self, kind: WaiterKind) {
+        let Some(recorder) = &self.recorder else {
+            return;
+        };
+        match kind {
+            WaiterKind::Sender => {
+                if self.send_waiters.as_ref().is_none_or(|w| w.is_empty()) {
+                    recorder.sender_unblocked();
+                }
+            }
+            WaiterKind::Receiver => {
+                if self.recv_waiters.as_ref().is_none_or(|w| w.is_empty()) {
+                    recorder.receiver_woken();
+                }
+            }
+        }
+    }
+
     fn recv_one(&mut self) -> PollResult<Option<(T, Option<Waker>)>> {
         match self.channel.pop_front() {
-            Some(item) => PollResult::Ready(Some((
-                item,
-                self.send_waiters.as_mut().and_then(|x| {
-                    x.pop_front()
-                        .and_then(|node| unsafe { node.as_ref() }.waker.borrow_mut().take())
-                }),
-            ))),
+            Some(item) => {
+                if let Some(recorder) = &self.recorder {
+                    recorder.received(1, self.channel.len());
+                }
+                PollResult::Ready(Some((
+                    item,
+                    self.send_waiters.as_mut().and_then(|x| {
+                        x.pop_front()
+                            .and_then(|node| unsafe { node.as_ref() }.waker.borrow_mut().take())
+                    }),
+                )))
+            }
             None => {
                 if self.send_waiters.is_some() {
+                    if let Some(recorder) = &self.recorder {
+                        recorder.receiver_idle();
+                    }
                     PollResult::Pending(WaiterKind::Receiver)
                 } else {
+                    if let Some(recorder) = &self.recorder {
+                        recorder.receiver_woken();
+                    }
                     PollResult::Ready(None)
                 }
             }
         }
     }
+
+    fn stats(&self) -> Option<ChannelStats> {
+        self.recorder.as_ref().map(|recorder| recorder.stats())
+    }
 }
 
 #[derive(Debug)]
@@ -405,6 +460,7 @@ impl<T> LocalChannel<T> {
                 channel,
                 send_waiters: Some(LinkedList::new(WaiterAdapter::NEW)),
                 recv_waiters: Some(LinkedList::new(WaiterAdapter::NEW)),
+                recorder: None,
             })),
         };
 
@@ -575,6 +631,36 @@ impl<T> LocalSender<T> {
         self.channel.state.borrow().channel.len()
     }
 
+    /// Starts recording the [`ChannelStats`] of this channel under `name`.
+    ///
+    /// # Examples
+    /// ```
+    /// use glommio::{channels::local_channel, LocalExecutor};
+    ///
+    /// let ex = LocalExecutor::default();
+    /// ex.run(async move {
+    ///     let (sender, receiver) = local_channel::new_unbounded();
+    ///     sender.instrument("requests");
+    ///     sender.try_send(0).unwrap();
+    ///     sender.try_send(1).unwrap();
+    ///     receiver.recv().await.unwrap();
+    ///
+    ///     let stats = receiver.stats().unwrap();
+    ///     assert_eq!(stats.name(), "requests");
+    ///     assert_eq!((stats.sent(), stats.received()), (2, 1));
+    ///     assert_eq!((stats.depth(), stats.high_watermark()), (1, 2));
+    /// });
+    /// ```
+    pub fn instrument(&self, name: impl Into<String>) {
+        self.channel.state.borrow_mut().recorder = Some(ChannelRecorder::register(name));
+    }
+
+    /// Returns the [`ChannelStats`] of this channel, or [`None`] if it was not
+    /// [instrumented](Self::instrument).
+    pub fn stats(&self) -> Option<ChannelStats> {
+        self.channel.state.borrow().stats()
+    }
+
     fn wait_for_room(&self) -> PollResult<()> {
         // NOTE: it is important that the borrow is dropped
         // if Poll::Pending is returned
@@ -687,7 +773,11 @@ impl<T> Stream for ChannelStream<'_, T> {
 
 impl<T> Drop for ChannelStream<'_, T> {
     fn drop(&mut self) {
-        remove_from_the_waiting_queue(self.node.as_mut(), &mut self.channel.state.borrow_mut());
+        let mut state = self.channel.state.borrow_mut();
+        remove_from_the_waiting_queue(self.node.as_mut(), &mut state);
+        if let Some(kind) = *self.node.kind.borrow() {
+            state.stop_waiting(kind);
+        }
     }
 }
 
@@ -723,10 +813,11 @@ impl<T> Stream for OwnedChannelStream<T> {
 
 impl<T> Drop for OwnedChannelStream<T> {
     fn drop(&mut self) {
-        remove_from_the_waiting_queue(
-            self.node.as_mut(),
-            &mut self.receiver.channel.state.borrow_mut(),
-        );
+        let mut state = self.receiver.channel.state.borrow_mut();
+        remove_from_the_waiting_queue(self.node.as_mut(), &mut state);
+        if let Some(kind) = *self.node.kind.borrow() {
+            state.stop_waiting(kind);
+        }
     }
 }
 
@@ -843,6 +934,12 @@ impl<T> LocalReceiver<T> {
         ChannelStream::new(&self.channel)
     }
 
+    /// Returns the [`ChannelStats`] of this channel, or [`None`] if it was not
+    /// [instrumented](LocalSender::instrument).
+    pub fn stats(&self) -> Option<ChannelStats> {
+        self.channel.state.borrow().stats()
+    }
+
     fn recv_one(&self) -> PollResult<Option<T>> {
         let result = self.channel.state.borrow_mut().recv_one();
         match result {
@@ -70,6 +70,8 @@ pub mod broadcast;
 /// [`StreamExt`]: https://docs.rs/futures-lite/2.6.0/futures_lite/stream/trait.StreamExt.html
 pub mod local_channel;
 pub mod oneshot;
+pub mod persistent_queue;
+pub mod priority_channel;
 pub mod storage;
 pub mod watch;
 
@@ -87,6 +89,12 @@ pub mod watch;
 /// synchronization. That means that to wire N executors to each other you will
 /// have to create O(N^2) channels.
 ///
+/// When a single executor receives from many others, [`new_mpsc_bounded`]
+/// saves it from selecting over one receiver per peer: its sender can be
+/// cloned and connected in any number of executors, each of which gets a
+/// lockless lane of its own, and the one [`ConnectedMpscReceiver`] takes from
+/// the lanes in turn.
+///
 /// The channels are also not bidirectional, so for full bidirectional
 /// communication you will need pairs of channels.
 ///
@@ -158,6 +166,8 @@ pub mod watch;
 /// [`GlommioError`]: ../struct.GlommioError.html
 /// [`ConnectedSender`]: struct.ConnectedSender.html
 /// [`ConnectedReceiver`]: struct.ConnectedReceiver.html
+/// [`ConnectedMpscReceiver`]: shared_channel::ConnectedMpscReceiver
+/// [`new_mpsc_bounded`]: shared_channel::new_mpsc_bounded
 /// [`SharedSender`]: struct.SharedSender.html
 /// [`SharedReceiver`]: struct.SharedReceiver.html
 /// [`Send`]: https://doc.rust-lang.org/std/marker/trait.Send.html
@@ -273,6 +283,15 @@ pub mod channel_mesh;
 
 /// Sharding utilities built on top of full mesh.
 ///
+/// Messages go to the shard a [`ShardRouter`] picks: a plain function of the
+/// message and the number of shards, as below, a capturing closure, or one of
+/// the consistent [`JumpHash`] and [`Rendezvous`] routers, which move few
+/// messages when the number of shards changes.
+///
+/// [`ShardRouter`]: sharding::ShardRouter
+/// [`JumpHash`]: sharding::JumpHash
+/// [`Rendezvous`]: sharding::Rendezvous
+///
 /// Examples
 ///
 /// ```
@@ -324,6 +343,13 @@ pub mod channel_mesh;
 /// ```
 pub mod sharding;
 
+pub mod rpc;
+
+pub(crate) mod stats;
+pub use stats::ChannelStats;
+mod stream_map;
+pub use stream_map::StreamMap;
+
 use std::fmt::Debug;
 
 #[derive(Debug)]
@@ -31,7 +31,7 @@ use crate::{
 };
 use std::{
     fmt,
-    future::Future,
+    future::{poll_fn, Future},
     marker::PhantomData,
     pin::Pin,
     task::{Context, Poll},
@@ -49,6 +49,8 @@ pub struct State<T> {
     /// than an `Option<Waker>` so that taking it yields an obligation to wake
     /// rather than a value that can be dropped on the floor.
     waker: WakerList,
+    /// Holds the sender's waker while it waits in [`Sender::closed`].
+    closed_waker: WakerList,
     /// Set when either half is dropped, so the survivor can tell the
     /// difference between "not yet" and "never".
     sender_gone: bool,
@@ -60,6 +62,7 @@ impl<T> State<T> {
         State {
             value: None,
             waker: WakerList::new(),
+            closed_waker: WakerList::new(),
             sender_gone: false,
             receiver_gone: false,
         }
@@ -165,6 +168,27 @@ impl<T, S: Storage<State<T>>> Sender<T, S> {
     pub fn is_closed(&self) -> bool {
         self.inner.with(|state| state.receiver_gone)
     }
+
+    /// Waits for the receiver to go away.
+    ///
+    /// A service answering on this sender can race its work against this, and
+    /// give up once nobody is waiting for the answer anymore.
+    pub async fn closed(&self) {
+        poll_fn(|cx| self.poll_closed(cx)).await
+    }
+
+    /// Polls for the receiver to go away. See [`closed`](Sender::closed).
+    pub fn poll_closed(&self, cx: &mut Context<'_>) -> Poll<()> {
+        self.inner.with(|state| {
+            if state.receiver_gone {
+                return Poll::Ready(());
+            }
+            // A `closed` future raced against other work is polled again
+            // every time that work wakes
+            state.closed_waker.register(cx.waker());
+            Poll::Pending
+        })
+    }
 }
 
 impl<T, S: Storage<State<T>>> Drop for Sender<T, S> {
@@ -198,7 +222,10 @@ impl<T, S: Storage<State<T>>> Future for Receiver<T, S> {
 
 impl<T, S: Storage<State<T>>> Drop for Receiver<T, S> {
     fn drop(&mut self) {
-        self.inner.with(|state| state.receiver_gone = true);
+        self.inner.with_waking_all(
+            |state| &mut state.closed_waker,
+            |state| state.receiver_gone = true,
+        );
     }
 }
 
@@ -225,6 +252,11 @@ mod tests {
     use std::{
         cell::RefCell,
         rc::Rc,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        task::Waker,
         time::{Duration, Instant},
     };
 
@@ -376,4 +408,48 @@ mod tests {
             assert!(sender.is_closed());
         });
     }
+
+    #[test]
+    fn polling_closed_again_registers_once() {
+        struct Count(AtomicUsize);
+        impl std::task::Wake for Count {
+            fn wake(self: Arc<Self>) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let (sender, receiver) = oneshot::<u32>();
+        let count = Arc::new(Count(AtomicUsize::new(0)));
+        let waker = Waker::from(count.clone());
+        for _ in 0..3 {
+            let poll = sender.poll_closed(&mut Context::from_waker(&waker));
+            assert!(poll.is_pending());
+        }
+        // Ours, and the one registered
+        assert_eq!(Arc::strong_count(&count), 3);
+
+        drop(receiver);
+        assert_eq!(count.0.load(Ordering::Relaxed), 1);
+        assert!(sender
+            .poll_closed(&mut Context::from_waker(Waker::noop()))
+            .is_ready());
+    }
+
+    #[test]
+    fn dropping_a_shared_receiver_wakes_a_sender_waiting_for_it() {
+        let (sender, receiver) = shared::<u32>();
+
+        let start = Instant::now();
+        let dropping = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            drop(receiver);
+        });
+
+        LocalExecutor::default().run(async move {
+            sender.closed().await;
+            assert!(start.elapsed() >= Duration::from_millis(100));
+            assert!(sender.send(1).is_err());
+        });
+        dropping.join().unwrap();
+    }
 }
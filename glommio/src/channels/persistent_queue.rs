@@ -0,0 +1,850 @@
+//! A durable queue of byte records, kept in segment files on disk.
+//!
+//! A [`PersistentSender`] appends every record to the segment file being
+//! written through a [`DmaStreamWriter`], and a [`PersistentReceiver`] hands
+//! them back in order. Records the receiver took are only forgotten once it
+//! [acknowledges](PersistentReceiver::ack) them: the position of the head is
+//! then written aside and segments with nothing left to read are removed.
+//! Building the queue again over the same directory, say after a restart,
+//! picks up from the last acknowledged record.
+//!
+//! While the receiver keeps up, records are handed over from memory and the
+//! segments are only written, never read. Up to a configurable number of
+//! records are held in memory that way; past that the receiver reads the rest
+//! back with a [`DmaStreamReader`] once it gets to them, which is what lets
+//! the queue buffer far more than fits in memory while a downstream is out.
+//!
+//! Records sent are durable once [`sync`](PersistentSender::sync) or
+//! [`close`](PersistentSender::close) returns. Records taken but not
+//! acknowledged are taken again after a restart, so delivery is at least
+//! once.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use glommio::{channels::persistent_queue::PersistentQueueBuilder, LocalExecutor};
+//!
+//! let ex = LocalExecutor::default();
+//! ex.run(async {
+//!     let (sender, receiver) = PersistentQueueBuilder::new("/var/lib/myservice/outbound")
+//!         .with_front_capacity(128)
+//!         .build()
+//!         .await
+//!         .unwrap();
+//!
+//!     sender.send(b"hello".to_vec()).await.unwrap();
+//!     sender.sync().await.unwrap();
+//!
+//!     let record = receiver.recv().await.unwrap().unwrap();
+//!     assert_eq!(record, b"hello");
+//!     receiver.ack().await.unwrap();
+//! });
+//! ```
+//!
+//! [`DmaStreamWriter`]: crate::io::DmaStreamWriter
+//! [`DmaStreamReader`]: crate::io::DmaStreamReader
+
+use crate::{
+    io::{
+        atomic_write, remove, Directory, DmaFile, DmaStreamReader, DmaStreamReaderBuilder,
+        DmaStreamWriter, DmaStreamWriterBuilder,
+    },
+    sync::Mutex,
+    wakers::WakerList,
+};
+use futures_lite::{
+    future,
+    io::{AsyncReadExt, AsyncWriteExt},
+    ready, Stream,
+};
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fmt,
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+};
+
+type Result<T> = crate::Result<T, ()>;
+type Receiving = Pin<Box<dyn Future<Output = Result<Option<Vec<u8>>>>>>;
+
+/// Where the position of the first unacknowledged record is kept
+const HEAD: &str = "head";
+const SEGMENT_SUFFIX: &str = ".seg";
+/// A record is its length plus one, so that zeroes read as the end of a
+/// segment, and a checksum of its payload, followed by the payload
+const HEADER_LEN: usize = 8;
+/// The longest payload whose length plus one fits the header
+const MAX_RECORD_LEN: usize = u32::MAX as usize - 1;
+
+/// Builds a persistent queue over a directory, recovering whatever an earlier
+/// queue left there.
+#[derive(Debug)]
+pub struct PersistentQueueBuilder {
+    path: PathBuf,
+    front_capacity: usize,
+    segment_size: u64,
+}
+
+impl PersistentQueueBuilder {
+    /// Creates a builder for a queue kept in the directory at `path`, which is
+    /// created if it does not exist.
+    ///
+    /// The directory belongs to the queue, and only one queue may use it at a
+    /// time.
+    #[must_use = "The builder must be built to be useful"]
+    pub fn new<P: AsRef<Path>>(path: P) -> PersistentQueueBuilder {
+        PersistentQueueBuilder {
+            path: path.as_ref().to_owned(),
+            front_capacity: 1024,
+            segment_size: 64 << 20,
+        }
+    }
+
+    /// Define how many records the queue holds in memory, ahead of the
+    /// receiver. At least one.
+    ///
+    /// Records past those stay on disk until the receiver gets to them.
+    #[must_use = "The builder must be built to be useful"]
+    pub fn with_front_capacity(mut self, records: usize) -> Self {
+        self.front_capacity = records.max(1);
+        self
+    }
+
+    /// Define the size, in bytes, past which the sender moves on to a new
+    /// segment file.
+    ///
+    /// Segments are removed whole, so smaller ones give space back sooner.
+    #[must_use = "The builder must be built to be useful"]
+    pub fn with_segment_size(mut self, bytes: u64) -> Self {
+        self.segment_size = bytes;
+        self
+    }
+
+    /// Opens the queue, returning its two ends.
+    ///
+    /// Records left unacknowledged by an earlier queue over the same directory
+    /// come out of the receiver first.
+    pub async fn build(self) -> Result<(PersistentSender, PersistentReceiver)> {
+        let dir = Directory::create(&self.path).await?;
+        let mut segments: Vec<u64> = dir
+            .sync_read_dir()?
+            .filter_map(|entry| {
+                let name = entry.ok()?.file_name();
+                name.to_str()?.strip_suffix(SEGMENT_SUFFIX)?.parse().ok()
+            })
+            .collect();
+        segments.sort_unstable();
+
+        let head = match read_head(&self.path).await? {
+            Some(head) => head,
+            None => Position {
+                segment: segments.first().copied().unwrap_or(0),
+                offset: 0,
+            },
+        };
+        // Acknowledged segments only outlive the head written for them if a
+        // crash came in between
+        for &id in segments.iter().filter(|&&id| id < head.segment) {
+            remove(segment_path(&self.path, id)).await?;
+        }
+        segments.retain(|&id| id >= head.segment);
+
+        let spilled = segments.first().map(|&first| {
+            if first == head.segment {
+                head
+            } else {
+                Position {
+                    segment: first,
+                    offset: 0,
+                }
+            }
+        });
+        let active = segments.last().map_or(head.segment, |&id| id + 1);
+
+        let queue = Rc::new(Queue {
+            path: self.path,
+            dir,
+            front_capacity: self.front_capacity,
+            segment_size: self.segment_size,
+            state: RefCell::new(State {
+                front: VecDeque::new(),
+                spilled,
+                sealed: segments.into(),
+                active: Position {
+                    segment: active,
+                    offset: 0,
+                },
+                received: head,
+                acked: head,
+                wakers: WakerList::new(),
+                sender_gone: false,
+            }),
+            writer: Mutex::new(None),
+            reading: Mutex::new(()),
+            acking: Mutex::new(()),
+        });
+        let writer = queue.create_segment(active).await?;
+        *queue.writer.lock().await? = Some(writer);
+
+        Ok((
+            PersistentSender {
+                queue: queue.clone(),
+            },
+            PersistentReceiver {
+                queue,
+                waiting: RefCell::new(None),
+            },
+        ))
+    }
+}
+
+/// The sending end of a persistent queue.
+pub struct PersistentSender {
+    queue: Rc<Queue>,
+}
+
+/// The receiving end of a persistent queue.
+pub struct PersistentReceiver {
+    queue: Rc<Queue>,
+    /// A receive that had to wait, kept until it completes so that dropping
+    /// [`recv`](Self::recv), or going back and forth between it and the
+    /// stream, does not give up on the records it was reading
+    waiting: RefCell<Option<Receiving>>,
+}
+
+impl fmt::Debug for PersistentSender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PersistentSender {{ path: {:?} }}", self.queue.path)
+    }
+}
+
+impl fmt::Debug for PersistentReceiver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PersistentReceiver {{ path: {:?} }}", self.queue.path)
+    }
+}
+
+/// Where a record ends, which is where the next one starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Position {
+    segment: u64,
+    offset: u64,
+}
+
+struct Queue {
+    path: PathBuf,
+    dir: Directory,
+    front_capacity: usize,
+    segment_size: u64,
+    state: RefCell<State>,
+    /// Writes records to the active segment; held across a write, so that
+    /// records never interleave, and across a segment change. `None` once the
+    /// sender is closed.
+    writer: Mutex<Option<DmaStreamWriter>>,
+    /// Held by the receiver while it reads segments.
+    reading: Mutex<()>,
+    /// Held by the receiver while it writes the head and removes segments.
+    /// Those are all before where it reads, so this does not wait for reads.
+    acking: Mutex<()>,
+}
+
+struct State {
+    /// Records ready for the receiver, with where each ends on disk
+    front: VecDeque<(Vec<u8>, Position)>,
+    /// Where the first record that only exists on disk starts, if any. Until
+    /// the receiver catches up with it, new records only go to disk as well.
+    spilled: Option<Position>,
+    /// Segments no longer written to and not acknowledged yet, oldest first
+    sealed: VecDeque<u64>,
+    /// The segment being written, and how long it is
+    active: Position,
+    /// Where the last record handed to the receiver ends
+    received: Position,
+    acked: Position,
+    wakers: WakerList,
+    sender_gone: bool,
+}
+
+impl Queue {
+    fn segment_path(&self, id: u64) -> PathBuf {
+        segment_path(&self.path, id)
+    }
+
+    async fn create_segment(&self, id: u64) -> Result<DmaStreamWriter> {
+        let file = DmaFile::create(self.segment_path(id)).await?;
+        // A segment nobody can find after a crash takes its records with it
+        self.dir.sync().await?;
+        Ok(DmaStreamWriterBuilder::new(file).build())
+    }
+
+    /// Accounts for a record written between `start` and `end` of the active
+    /// segment, and returns whether the segment is full.
+    fn push(&self, item: Vec<u8>, start: u64, end: u64) -> bool {
+        let mut state = self.state.borrow_mut();
+        let segment = state.active.segment;
+        state.active.offset = end;
+        if state.spilled.is_none() {
+            if state.front.len() < self.front_capacity {
+                state.front.push_back((
+                    item,
+                    Position {
+                        segment,
+                        offset: end,
+                    },
+                ));
+            } else {
+                state.spilled = Some(Position {
+                    segment,
+                    offset: start,
+                });
+            }
+        }
+        let wakes = state.wakers.take();
+        drop(state);
+        wakes.wake();
+        end >= self.segment_size
+    }
+
+    fn pop(&self) -> Option<Vec<u8>> {
+        let mut state = self.state.borrow_mut();
+        let (item, position) = state.front.pop_front()?;
+        state.received = position;
+        Some(item)
+    }
+
+    async fn recv(self: Rc<Self>) -> Result<Option<Vec<u8>>> {
+        loop {
+            if let Some(item) = self.pop() {
+                return Ok(Some(item));
+            }
+            if self.state.borrow().spilled.is_some() {
+                self.refill().await?;
+                continue;
+            }
+
+            let closed = future::poll_fn(|cx| {
+                let mut state = self.state.borrow_mut();
+                if !state.front.is_empty() || state.spilled.is_some() {
+                    Poll::Ready(false)
+                } else if state.sender_gone {
+                    Poll::Ready(true)
+                } else {
+                    state.wakers.push(cx.waker().clone());
+                    Poll::Pending
+                }
+            })
+            .await;
+            if closed {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Seals the active segment, so that it can be read, and moves on to a
+    /// new one if the sender is still around.
+    async fn roll(&self, writer: &mut Option<DmaStreamWriter>) -> Result<()> {
+        let active = self.state.borrow().active;
+        if active.offset == 0 {
+            return Ok(());
+        }
+        if let Some(mut sealed) = writer.take() {
+            sealed.close().await?;
+        }
+        let next = active.segment + 1;
+        if !self.state.borrow().sender_gone {
+            *writer = Some(self.create_segment(next).await?);
+        }
+
+        let mut state = self.state.borrow_mut();
+        state.sealed.push_back(active.segment);
+        state.active = Position {
+            segment: next,
+            offset: 0,
+        };
+        Ok(())
+    }
+
+    /// Reads records that only exist on disk into memory, until there is no
+    /// more room or no more records.
+    async fn refill(&self) -> Result<()> {
+        let _reading = self.reading.lock().await?;
+        loop {
+            let (from, active, room) = {
+                let state = self.state.borrow();
+                match state.spilled {
+                    Some(from) if state.front.len() < self.front_capacity => {
+                        (from, state.active, self.front_capacity - state.front.len())
+                    }
+                    _ => return Ok(()),
+                }
+            };
+
+            if from.segment == active.segment {
+                // The active segment is only read once sealed, which leaves
+                // the writer free to keep its buffers
+                let mut writer = self.writer.lock().await?;
+                self.roll(&mut writer).await?;
+                continue;
+            }
+
+            let (records, end, done) = self.read_segment(from, room).await?;
+            let mut state = self.state.borrow_mut();
+            state.front.extend(records);
+            state.spilled = if !done {
+                Some(end)
+            } else if let Some(&next) = state.sealed.iter().find(|&&id| id > from.segment) {
+                Some(Position {
+                    segment: next,
+                    offset: 0,
+                })
+            } else if state.active.offset > 0 {
+                Some(Position {
+                    segment: state.active.segment,
+                    offset: 0,
+                })
+            } else {
+                // Caught up: whatever is sent next can be handed over from
+                // memory again
+                None
+            };
+        }
+    }
+
+    /// Reads up to `max` records from a sealed segment, starting at `from`.
+    ///
+    /// Returns them, where the last one ends, and whether the segment has no
+    /// more.
+    async fn read_segment(
+        &self,
+        from: Position,
+        max: usize,
+    ) -> Result<(Vec<(Vec<u8>, Position)>, Position, bool)> {
+        let file = DmaFile::open(self.segment_path(from.segment)).await?;
+        let size = file.file_size().await?;
+        let mut reader = DmaStreamReaderBuilder::new(file)
+            .with_start_pos(from.offset)
+            .build();
+        let mut records = Vec::new();
+        let mut end = from;
+        let outcome = loop {
+            if records.len() == max {
+                break Ok(false);
+            }
+            match read_record(&mut reader, size.saturating_sub(end.offset)).await {
+                Ok(Some(payload)) => {
+                    end.offset += (HEADER_LEN + payload.len()) as u64;
+                    records.push((payload, end));
+                }
+                Ok(None) => break Ok(true),
+                Err(err) => break Err(err),
+            }
+        };
+        reader.close().await?;
+        Ok((records, end, outcome?))
+    }
+}
+
+impl PersistentSender {
+    /// Appends `item` to the queue.
+    ///
+    /// It is written out in the background and durable once
+    /// [`sync`](Self::sync) returns. This only waits when the writer has too
+    /// much in flight, or while the receiver moves it to a new segment.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] if `item` is longer than
+    /// `u32::MAX - 1` bytes, the most a record holds, and otherwise if writing
+    /// to the segment does.
+    pub async fn send(&self, item: Vec<u8>) -> Result<()> {
+        check_len(item.len())?;
+        let queue = &self.queue;
+        let record = encode(&item);
+        let mut writer = queue.writer.lock().await?;
+        let Some(active) = writer.as_mut() else {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "the queue lost its active segment",
+            )
+            .into());
+        };
+        let start = active.current_pos();
+        active.write_all(&record).await?;
+        if queue.push(item, start, active.current_pos()) {
+            queue.roll(&mut writer).await?;
+        }
+        Ok(())
+    }
+
+    /// Waits for every record sent so far to be durable.
+    pub async fn sync(&self) -> Result<()> {
+        if let Some(writer) = self.queue.writer.lock().await?.as_ref() {
+            writer.sync().await?;
+        }
+        Ok(())
+    }
+
+    /// Closes the sender, making every record sent durable.
+    ///
+    /// The receiver sees the end of the queue once it has taken those
+    /// records. Dropping the sender ends the queue too, but gives up on
+    /// records not [synced](Self::sync) yet if nothing reads them before the
+    /// receiver goes away.
+    pub async fn close(self) -> Result<()> {
+        let mut writer = self.queue.writer.lock().await?;
+        self.queue.state.borrow_mut().sender_gone = true;
+        if let Some(mut writer) = writer.take() {
+            writer.close().await?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PersistentSender {
+    fn drop(&mut self) {
+        let mut state = self.queue.state.borrow_mut();
+        state.sender_gone = true;
+        let wakes = state.wakers.take();
+        drop(state);
+        wakes.wake();
+    }
+}
+
+impl PersistentReceiver {
+    /// Takes the next record from the queue.
+    ///
+    /// If the sender is gone and every record was taken it returns
+    /// [`None`]. Otherwise, waits until a record is available and returns it
+    /// wrapped in [`Some`]. Taking a record does not acknowledge it: see
+    /// [`ack`](Self::ack).
+    ///
+    /// This is also available as a [`Stream`].
+    ///
+    /// # Errors
+    ///
+    /// Fails if reading a segment does.
+    pub async fn recv(&self) -> Result<Option<Vec<u8>>> {
+        future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<Result<Option<Vec<u8>>>> {
+        let mut waiting = self.waiting.borrow_mut();
+        let recv = match &mut *waiting {
+            Some(recv) => recv,
+            None => {
+                if let Some(item) = self.queue.pop() {
+                    return Poll::Ready(Ok(Some(item)));
+                }
+                waiting.insert(Box::pin(self.queue.clone().recv()))
+            }
+        };
+        let res = ready!(recv.as_mut().poll(cx));
+        *waiting = None;
+        Poll::Ready(res)
+    }
+
+    /// Acknowledges every record taken so far.
+    ///
+    /// Acknowledged records are not taken again after a restart, and the
+    /// segments holding nothing but acknowledged records are removed.
+    pub async fn ack(&self) -> Result<()> {
+        let queue = &self.queue;
+        let _acking = queue.acking.lock().await?;
+        let received = {
+            let state = queue.state.borrow();
+            if state.received == state.acked {
+                return Ok(());
+            }
+            state.received
+        };
+
+        let mut head = [0u8; 16];
+        head[..8].copy_from_slice(&received.segment.to_le_bytes());
+        head[8..].copy_from_slice(&received.offset.to_le_bytes());
+        atomic_write(&queue.dir, HEAD, &head).await?;
+
+        let done: Vec<u64> = {
+            let mut state = queue.state.borrow_mut();
+            state.acked = received;
+            let nr_done = state
+                .sealed
+                .iter()
+                .take_while(|&&id| id < received.segment)
+                .count();
+            state.sealed.drain(..nr_done).collect()
+        };
+        for id in done {
+            remove(queue.segment_path(id)).await?;
+        }
+        Ok(())
+    }
+}
+
+impl Stream for PersistentReceiver {
+    type Item = Result<Vec<u8>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_recv(cx).map(Result::transpose)
+    }
+}
+
+fn segment_path(path: &Path, id: u64) -> PathBuf {
+    path.join(format!("{id:020}{SEGMENT_SUFFIX}"))
+}
+
+async fn read_head(path: &Path) -> Result<Option<Position>> {
+    let path = path.join(HEAD);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let file = DmaFile::open(&path).await?;
+    let head = file.read_at(0, 16).await?;
+    if head.len() < 16 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated queue head").into());
+    }
+    let position = Position {
+        segment: u64::from_le_bytes(head[..8].try_into().unwrap()),
+        offset: u64::from_le_bytes(head[8..].try_into().unwrap()),
+    };
+    drop(head);
+    file.close().await?;
+    Ok(Some(position))
+}
+
+/// Refuses a payload too long for its length to fit a record header.
+fn check_len(len: usize) -> Result<()> {
+    if len > MAX_RECORD_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("a record holds at most {MAX_RECORD_LEN} bytes"),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn encode(payload: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(HEADER_LEN + payload.len());
+    record.extend_from_slice(&(payload.len() as u32 + 1).to_le_bytes());
+    record.extend_from_slice(&checksum(payload).to_le_bytes());
+    record.extend_from_slice(payload);
+    record
+}
+
+/// Reads the next record, or returns `None` at the end of the segment.
+///
+/// A record cut short or not matching its checksum was torn by a crash, and
+/// nothing after it was ever acknowledged as written, so it ends the segment
+/// as well. So does a length longer than the `left` bytes of the segment,
+/// which is checked before allocating, as a torn header can claim anything.
+async fn read_record(reader: &mut DmaStreamReader, left: u64) -> Result<Option<Vec<u8>>> {
+    let mut header = [0u8; HEADER_LEN];
+    if !read_exact_or_eof(reader, &mut header).await? {
+        return Ok(None);
+    }
+    let len = u32::from_le_bytes(header[..4].try_into().unwrap());
+    if len == 0 || u64::from(len - 1) > left.saturating_sub(HEADER_LEN as u64) {
+        return Ok(None);
+    }
+    let mut payload = vec![0; len as usize - 1];
+    if !read_exact_or_eof(reader, &mut payload).await?
+        || checksum(&payload) != u32::from_le_bytes(header[4..].try_into().unwrap())
+    {
+        return Ok(None);
+    }
+    Ok(Some(payload))
+}
+
+async fn read_exact_or_eof(reader: &mut DmaStreamReader, buf: &mut [u8]) -> Result<bool> {
+    match reader.read_exact(buf).await {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// FNV-1a, which is plenty to tell a torn record from a whole one.
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, &b| {
+        (hash ^ u32::from(b)).wrapping_mul(0x0100_0193)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{test_utils::make_test_directories, GlommioError};
+    use futures_lite::StreamExt;
+    use std::os::unix::fs::FileExt;
+
+    macro_rules! persistent_queue_test {
+        ( $name:ident, $dir:ident, $code:block) => {
+            #[test]
+            fn $name() {
+                for dir in make_test_directories(&format!("persistent-queue-{}", stringify!($name)))
+                {
+                    let $dir = dir.path.clone();
+                    test_executor!(async move { $code });
+                }
+            }
+        };
+    }
+
+    fn segments(path: &Path) -> usize {
+        std::fs::read_dir(path)
+            .unwrap()
+            .filter(|e| {
+                e.as_ref()
+                    .unwrap()
+                    .file_name()
+                    .to_str()
+                    .unwrap()
+                    .ends_with(SEGMENT_SUFFIX)
+            })
+            .count()
+    }
+
+    fn record(x: usize) -> Vec<u8> {
+        format!("record {x}").into_bytes()
+    }
+
+    persistent_queue_test!(records_come_out_in_order, path, {
+        let (sender, receiver) = PersistentQueueBuilder::new(&path).build().await.unwrap();
+        let consumer = crate::spawn_local(async move {
+            let mut got = Vec::new();
+            while let Some(item) = receiver.recv().await.unwrap() {
+                got.push(item);
+            }
+            got
+        });
+
+        for x in 0..10 {
+            sender.send(record(x)).await.unwrap();
+        }
+        sender.close().await.unwrap();
+        assert_eq!(consumer.await, (0..10).map(record).collect::<Vec<_>>());
+    });
+
+    #[test]
+    fn records_too_long_for_a_header_are_rejected() {
+        assert!(check_len(MAX_RECORD_LEN).is_ok());
+        match check_len(MAX_RECORD_LEN + 1) {
+            Err(GlommioError::IoError(err)) => {
+                assert_eq!(err.kind(), io::ErrorKind::InvalidInput)
+            }
+            res => panic!("expected the record to be rejected, got {res:?}"),
+        }
+    }
+
+    persistent_queue_test!(a_torn_header_ends_the_segment, path, {
+        {
+            let (sender, _receiver) = PersistentQueueBuilder::new(&path).build().await.unwrap();
+            sender.send(record(0)).await.unwrap();
+            sender.close().await.unwrap();
+        }
+        // A header claiming far more than the segment holds, right after the
+        // only record
+        let mut header = [0u8; HEADER_LEN];
+        header[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(segment_path(&path, 0))
+            .unwrap()
+            .write_all_at(&header, (HEADER_LEN + record(0).len()) as u64)
+            .unwrap();
+
+        let (sender, receiver) = PersistentQueueBuilder::new(&path).build().await.unwrap();
+        drop(sender);
+        assert_eq!(receiver.recv().await.unwrap(), Some(record(0)));
+        assert_eq!(receiver.recv().await.unwrap(), None);
+    });
+
+    persistent_queue_test!(a_dropped_recv_loses_nothing, path, {
+        let (sender, receiver) = PersistentQueueBuilder::new(&path)
+            .with_front_capacity(1)
+            .with_segment_size(64)
+            .build()
+            .await
+            .unwrap();
+        for x in 0..20 {
+            sender.send(record(x)).await.unwrap();
+        }
+        sender.close().await.unwrap();
+
+        let mut got = Vec::new();
+        // Started, maybe mid-read of a segment, and given up on
+        while got.len() < 5 {
+            match future::poll_once(receiver.recv()).await {
+                Some(item) => got.push(item.unwrap().unwrap()),
+                None => crate::executor().yield_now().await,
+            }
+        }
+        let mut receiver = receiver;
+        while let Some(item) = receiver.next().await {
+            got.push(item.unwrap());
+            receiver.ack().await.unwrap();
+        }
+        assert_eq!(got, (0..20).map(record).collect::<Vec<_>>());
+    });
+
+    persistent_queue_test!(records_past_the_front_are_read_back, path, {
+        let (sender, receiver) = PersistentQueueBuilder::new(&path)
+            .with_front_capacity(3)
+            .with_segment_size(64)
+            .build()
+            .await
+            .unwrap();
+
+        for x in 0..20 {
+            sender.send(record(x)).await.unwrap();
+        }
+        assert!(segments(&path) > 1);
+
+        for x in 0..20 {
+            assert_eq!(receiver.recv().await.unwrap(), Some(record(x)));
+        }
+        // Caught up with the disk, so records are handed over from memory
+        // again
+        assert!(receiver.queue.state.borrow().spilled.is_none());
+        sender.send(record(20)).await.unwrap();
+        assert_eq!(receiver.queue.state.borrow().front.len(), 1);
+        assert_eq!(receiver.recv().await.unwrap(), Some(record(20)));
+
+        receiver.ack().await.unwrap();
+        assert_eq!(segments(&path), 1);
+    });
+
+    persistent_queue_test!(a_restart_resumes_after_the_last_ack, path, {
+        {
+            let (sender, receiver) = PersistentQueueBuilder::new(&path)
+                .with_front_capacity(2)
+                .with_segment_size(64)
+                .build()
+                .await
+                .unwrap();
+            for x in 0..10 {
+                sender.send(record(x)).await.unwrap();
+            }
+            sender.close().await.unwrap();
+
+            for x in 0..4 {
+                assert_eq!(receiver.recv().await.unwrap(), Some(record(x)));
+            }
+            receiver.ack().await.unwrap();
+            // Taken, but never acknowledged
+            assert_eq!(receiver.recv().await.unwrap(), Some(record(4)));
+        }
+
+        let (sender, receiver) = PersistentQueueBuilder::new(&path).build().await.unwrap();
+        sender.send(record(10)).await.unwrap();
+        drop(sender);
+
+        let got: Vec<_> = receiver.map(|item| item.unwrap()).collect().await;
+        assert_eq!(got, (4..11).map(record).collect::<Vec<_>>());
+    });
+}
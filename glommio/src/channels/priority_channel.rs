@@ -0,0 +1,532 @@
+//! Channels that let urgent messages overtake the rest.
+//!
+//! Every message is sent with a priority class, an index below the number of
+//! classes the channel was created with. Higher classes are more urgent: the
+//! receiver always takes from the highest class that has something waiting,
+//! and only gets to a class once all the ones above it are drained.
+//!
+//! Each class also has a capacity of its own. A sender that filled up one
+//! class waits for room in that class only, so control messages sent in a
+//! higher class are never stuck behind a full lane of bulk data.
+//!
+//! Both flavors of channel are available: [`new_local`] between tasks of the
+//! same executor, as a [`local_channel`], and [`new_shared`] across
+//! executors, as a [`shared_channel`] per class.
+//!
+//! # Examples
+//!
+//! ```
+//! use glommio::{channels::priority_channel, LocalExecutor};
+//!
+//! const DATA: usize = 0;
+//! const CONTROL: usize = 1;
+//!
+//! LocalExecutor::default().run(async {
+//!     let (sender, receiver) = priority_channel::new_local(&[2, 1]);
+//!     sender.try_send(DATA, "chunk").unwrap();
+//!     sender.try_send(DATA, "chunk").unwrap();
+//!     // The data lane is full, but control messages have their own
+//!     sender.try_send(CONTROL, "shutdown").unwrap();
+//!
+//!     assert_eq!(receiver.recv().await, Some("shutdown"));
+//!     assert_eq!(receiver.recv().await, Some("chunk"));
+//! });
+//! ```
+//!
+//! [`local_channel`]: super::local_channel
+//! [`shared_channel`]: super::shared_channel
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fmt,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll, Waker},
+};
+
+use futures_lite::{future, stream::Stream};
+
+use crate::{
+    channels::shared_channel::{
+        self, ConnectedReceiver, ConnectedSender, SharedReceiver, SharedSender,
+    },
+    GlommioError, ResourceType,
+};
+
+type Result<T, V> = crate::Result<T, V>;
+
+/// Creates a new priority channel for tasks of the same executor, with one
+/// class per entry of `capacities`, holding up to that many messages.
+///
+/// # Panics
+///
+/// If `capacities` is empty.
+pub fn new_local<T>(capacities: &[usize]) -> (LocalPrioritySender<T>, LocalPriorityReceiver<T>) {
+    assert!(!capacities.is_empty(), "a priority channel needs a class");
+    let state = Rc::new(RefCell::new(LocalState {
+        lanes: capacities
+            .iter()
+            .map(|capacity| VecDeque::with_capacity(*capacity))
+            .collect(),
+        capacities: capacities.to_vec(),
+        send_waiters: vec![Vec::new(); capacities.len()],
+        recv_waiters: Vec::new(),
+        sender_gone: false,
+        receiver_gone: false,
+    }));
+    (
+        LocalPrioritySender {
+            state: state.clone(),
+        },
+        LocalPriorityReceiver { state },
+    )
+}
+
+struct LocalState<T> {
+    lanes: Vec<VecDeque<T>>,
+    capacities: Vec<usize>,
+    send_waiters: Vec<Vec<Waker>>,
+    recv_waiters: Vec<Waker>,
+    sender_gone: bool,
+    receiver_gone: bool,
+}
+
+impl<T> LocalState<T> {
+    fn push(&mut self, class: usize, item: T) -> Result<(), T> {
+        if self.receiver_gone {
+            Err(GlommioError::Closed(ResourceType::Channel(item)))
+        } else if self.lanes[class].len() >= self.capacities[class] {
+            Err(GlommioError::WouldBlock(ResourceType::Channel(item)))
+        } else {
+            self.lanes[class].push_back(item);
+            self.recv_waiters.drain(..).for_each(Waker::wake);
+            Ok(())
+        }
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        let (class, lane) = self
+            .lanes
+            .iter_mut()
+            .enumerate()
+            .rev()
+            .find(|(_, lane)| !lane.is_empty())?;
+        let item = lane.pop_front();
+        self.send_waiters[class].drain(..).for_each(Waker::wake);
+        item
+    }
+}
+
+/// Send endpoint of a local priority channel.
+pub struct LocalPrioritySender<T> {
+    state: Rc<RefCell<LocalState<T>>>,
+}
+
+/// Receive endpoint of a local priority channel.
+///
+/// Like a [`LocalReceiver`](super::local_channel::LocalReceiver), it keeps
+/// yielding messages until the sender is destroyed, and can be used as a
+/// [`Stream`].
+pub struct LocalPriorityReceiver<T> {
+    state: Rc<RefCell<LocalState<T>>>,
+}
+
+impl<T> fmt::Debug for LocalPrioritySender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalPrioritySender")
+            .field("capacities", &self.state.borrow().capacities)
+            .finish()
+    }
+}
+
+impl<T> fmt::Debug for LocalPriorityReceiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalPriorityReceiver")
+            .field("capacities", &self.state.borrow().capacities)
+            .finish()
+    }
+}
+
+impl<T> LocalPrioritySender<T> {
+    /// The number of priority classes of this channel.
+    pub fn classes(&self) -> usize {
+        self.state.borrow().lanes.len()
+    }
+
+    /// Sends a message in the given class.
+    ///
+    /// It returns a [`GlommioError::Closed`] if the receiver is destroyed, and
+    /// a [`GlommioError::WouldBlock`] if the class has no more room. Either
+    /// way the message is handed back inside the error.
+    ///
+    /// # Panics
+    ///
+    /// If `class` is not below [`classes`](Self::classes).
+    pub fn try_send(&self, class: usize, item: T) -> Result<(), T> {
+        self.state.borrow_mut().push(class, item)
+    }
+
+    /// Sends a message in the given class, waiting for room in it if needed.
+    ///
+    /// It returns a [`GlommioError::Closed`] holding the message if the
+    /// receiver is destroyed.
+    ///
+    /// # Panics
+    ///
+    /// If `class` is not below [`classes`](Self::classes).
+    pub async fn send(&self, class: usize, item: T) -> Result<(), T> {
+        let mut item = Some(item);
+        future::poll_fn(|cx| {
+            let mut state = self.state.borrow_mut();
+            match state.push(class, item.take().unwrap()) {
+                Err(GlommioError::WouldBlock(ResourceType::Channel(back))) => {
+                    item = Some(back);
+                    state.send_waiters[class].push(cx.waker().clone());
+                    Poll::Pending
+                }
+                res => Poll::Ready(res),
+            }
+        })
+        .await
+    }
+}
+
+impl<T> LocalPriorityReceiver<T> {
+    /// Receives the most urgent message waiting in this channel.
+    ///
+    /// If the sender is destroyed and every class is drained it returns
+    /// [`None`]. Otherwise, waits until a message is available.
+    pub async fn recv(&self) -> Option<T> {
+        future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut state = self.state.borrow_mut();
+        match state.pop() {
+            Some(item) => Poll::Ready(Some(item)),
+            None if state.sender_gone => Poll::Ready(None),
+            None => {
+                state.recv_waiters.push(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> Stream for LocalPriorityReceiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.poll_recv(cx)
+    }
+}
+
+impl<T> Drop for LocalPrioritySender<T> {
+    fn drop(&mut self) {
+        let mut state = self.state.borrow_mut();
+        state.sender_gone = true;
+        state.recv_waiters.drain(..).for_each(Waker::wake);
+    }
+}
+
+impl<T> Drop for LocalPriorityReceiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.state.borrow_mut();
+        state.receiver_gone = true;
+        for waiters in state.send_waiters.iter_mut() {
+            waiters.drain(..).for_each(Waker::wake);
+        }
+    }
+}
+
+/// Creates a new priority channel across executors, with one class per entry
+/// of `capacities`, holding up to that many messages.
+///
+/// Each class is a [`shared_channel`] of its own, so both ends have to be
+/// connected before use, just like those.
+///
+/// # Panics
+///
+/// If `capacities` is empty.
+///
+/// # Examples
+///
+/// ```
+/// use glommio::{channels::priority_channel, prelude::*};
+///
+/// let (sender, receiver) = priority_channel::new_shared(&[1024, 16]);
+/// let producer = LocalExecutorBuilder::default()
+///     .spawn(move || async move {
+///         let sender = sender.connect().await;
+///         sender.send(0, "chunk").await.unwrap();
+///         sender.send(1, "reload").await.unwrap();
+///     })
+///     .unwrap();
+/// let consumer = LocalExecutorBuilder::default()
+///     .spawn(move || async move {
+///         let receiver = receiver.connect().await;
+///         let mut received = Vec::new();
+///         while let Some(x) = receiver.recv().await {
+///             received.push(x);
+///         }
+///         received.sort();
+///         assert_eq!(received, ["chunk", "reload"]);
+///     })
+///     .unwrap();
+/// producer.join().unwrap();
+/// consumer.join().unwrap();
+/// ```
+pub fn new_shared<T: Send + Sized>(
+    capacities: &[usize],
+) -> (SharedPrioritySender<T>, SharedPriorityReceiver<T>) {
+    assert!(!capacities.is_empty(), "a priority channel needs a class");
+    let (senders, receivers) = capacities
+        .iter()
+        .map(|capacity| shared_channel::new_bounded(*capacity))
+        .unzip();
+    (
+        SharedPrioritySender { lanes: senders },
+        SharedPriorityReceiver { lanes: receivers },
+    )
+}
+
+/// The sending end of a priority channel across executors. Before it is used
+/// it must be changed into a [`ConnectedPrioritySender`].
+#[derive(Debug)]
+pub struct SharedPrioritySender<T: Send + Sized> {
+    lanes: Vec<SharedSender<T>>,
+}
+
+/// The receiving end of a priority channel across executors. Before it is
+/// used it must be changed into a [`ConnectedPriorityReceiver`].
+#[derive(Debug)]
+pub struct SharedPriorityReceiver<T: Send + Sized> {
+    lanes: Vec<SharedReceiver<T>>,
+}
+
+/// The connected sending end of a priority channel across executors.
+#[derive(Debug)]
+pub struct ConnectedPrioritySender<T: Send + Sized> {
+    lanes: Vec<ConnectedSender<T>>,
+}
+
+/// The connected receiving end of a priority channel across executors.
+#[derive(Debug)]
+pub struct ConnectedPriorityReceiver<T: Send + Sized> {
+    lanes: Vec<ConnectedReceiver<T>>,
+}
+
+impl<T: 'static + Send + Sized> SharedPrioritySender<T> {
+    /// Connects this sender, returning a [`ConnectedPrioritySender`].
+    pub async fn connect(self) -> ConnectedPrioritySender<T> {
+        let mut lanes = Vec::with_capacity(self.lanes.len());
+        for lane in self.lanes {
+            lanes.push(lane.connect().await);
+        }
+        ConnectedPrioritySender { lanes }
+    }
+}
+
+impl<T: 'static + Send + Sized> SharedPriorityReceiver<T> {
+    /// Connects this receiver, returning a [`ConnectedPriorityReceiver`].
+    pub async fn connect(self) -> ConnectedPriorityReceiver<T> {
+        let mut lanes = Vec::with_capacity(self.lanes.len());
+        for lane in self.lanes {
+            lanes.push(lane.connect().await);
+        }
+        ConnectedPriorityReceiver { lanes }
+    }
+}
+
+impl<T: Send + Sized> ConnectedPrioritySender<T> {
+    /// The number of priority classes of this channel.
+    pub fn classes(&self) -> usize {
+        self.lanes.len()
+    }
+
+    /// Sends a message in the given class.
+    ///
+    /// It returns a [`GlommioError::Closed`] if the receiver is destroyed, and
+    /// a [`GlommioError::WouldBlock`] if the class has no more room.
+    ///
+    /// # Panics
+    ///
+    /// If `class` is not below [`classes`](Self::classes).
+    pub fn try_send(&self, class: usize, item: T) -> Result<(), T> {
+        self.lanes[class].try_send(item)
+    }
+
+    /// Sends a message in the given class, waiting for room in it if needed.
+    ///
+    /// It returns a [`GlommioError::Closed`] if the receiver is destroyed.
+    ///
+    /// # Panics
+    ///
+    /// If `class` is not below [`classes`](Self::classes).
+    pub async fn send(&self, class: usize, item: T) -> Result<(), T> {
+        self.lanes[class].send(item).await
+    }
+
+    /// Close the sender
+    pub fn close(&self) {
+        self.lanes.iter().for_each(ConnectedSender::close);
+    }
+}
+
+impl<T: Send + Sized> ConnectedPriorityReceiver<T> {
+    /// Receives the most urgent message waiting in this channel.
+    ///
+    /// If the sender is no longer available and every class is drained it
+    /// returns [`None`]. Otherwise, waits until a message is available.
+    pub async fn recv(&self) -> Option<T> {
+        future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        // Looking first, without registering, keeps a class that stays idle
+        // while another is busy from collecting a waker per message
+        if let Some(item) = self
+            .lanes
+            .iter()
+            .rev()
+            .find_map(ConnectedReceiver::try_recv)
+        {
+            return Poll::Ready(Some(item));
+        }
+        let mut closed = 0;
+        // Every class that has nothing registers to be woken up, so whichever
+        // gets a message first wakes us
+        for lane in self.lanes.iter().rev() {
+            match lane.recv_one(cx) {
+                Poll::Ready(Some(item)) => return Poll::Ready(Some(item)),
+                Poll::Ready(None) => closed += 1,
+                Poll::Pending => {}
+            }
+        }
+        if closed == self.lanes.len() {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl<T: Send + Sized> Stream for ConnectedPriorityReceiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{timer::sleep, LocalExecutor, LocalExecutorBuilder};
+    use futures_lite::StreamExt;
+    use std::time::Duration;
+
+    #[test]
+    fn local_classes_are_drained_in_order() {
+        LocalExecutor::default().run(async {
+            let (sender, receiver) = new_local(&[4, 4, 4]);
+            for (class, x) in [(0, 1), (2, 2), (1, 3), (0, 4), (2, 5)] {
+                sender.try_send(class, x).unwrap();
+            }
+            drop(sender);
+            assert_eq!(receiver.collect::<Vec<_>>().await, [2, 5, 3, 1, 4]);
+        });
+    }
+
+    #[test]
+    fn local_classes_have_their_own_room() {
+        LocalExecutor::default().run(async {
+            let (sender, receiver) = new_local(&[1, 1]);
+            sender.try_send(0, 1).unwrap();
+            assert!(matches!(
+                sender.try_send(0, 2),
+                Err(GlommioError::WouldBlock(ResourceType::Channel(2)))
+            ));
+            sender.try_send(1, 3).unwrap();
+
+            let sender = Rc::new(sender);
+            let blocked = crate::spawn_local(crate::enclose!((sender) async move {
+                sender.send(0, 2).await.unwrap();
+            }))
+            .detach();
+            assert_eq!(receiver.recv().await, Some(3));
+            assert_eq!(receiver.recv().await, Some(1));
+            blocked.await.unwrap();
+            assert_eq!(receiver.recv().await, Some(2));
+
+            drop(receiver);
+            assert!(matches!(
+                sender.send(1, 4).await,
+                Err(GlommioError::Closed(ResourceType::Channel(4)))
+            ));
+        });
+    }
+
+    #[test]
+    fn shared_classes_are_drained_in_order() {
+        let (sender, receiver) = new_shared(&[8, 1]);
+
+        let ex1 = LocalExecutorBuilder::default()
+            .spawn(move || async move {
+                let sender = sender.connect().await;
+                for x in 0..8 {
+                    sender.try_send(0, x).unwrap();
+                }
+                sender.try_send(1, 100).unwrap();
+            })
+            .unwrap();
+
+        let ex2 = LocalExecutorBuilder::default()
+            .spawn(move || async move {
+                let receiver = receiver.connect().await;
+                // Let the sender fill both classes first
+                sleep(Duration::from_millis(100)).await;
+                let received: Vec<_> = receiver.collect().await;
+                assert_eq!(received[0], 100);
+                assert!(received[1..].iter().copied().eq(0..8));
+            })
+            .unwrap();
+
+        ex1.join().unwrap();
+        ex2.join().unwrap();
+    }
+
+    #[test]
+    fn an_idle_class_does_not_collect_wakers() {
+        let (sender, receiver) = new_shared(&[256, 1]);
+        // A sender going away wakes everything, so it stays until we looked
+        let (done, wait_done) = std::sync::mpsc::channel();
+
+        let ex1 = LocalExecutorBuilder::default()
+            .spawn(move || async move {
+                let sender = sender.connect().await;
+                for x in 0..200 {
+                    sender.send(0, x).await.unwrap();
+                }
+                wait_done.recv().unwrap();
+                drop(sender);
+            })
+            .unwrap();
+
+        let ex2 = LocalExecutorBuilder::default()
+            .spawn(move || async move {
+                let receiver = receiver.connect().await;
+                sleep(Duration::from_millis(100)).await;
+                for x in 0..200 {
+                    assert_eq!(receiver.recv().await, Some(x));
+                }
+                assert!(receiver.lanes[1].registered_wakers() <= 1);
+                done.send(()).unwrap();
+            })
+            .unwrap();
+
+        ex1.join().unwrap();
+        ex2.join().unwrap();
+    }
+}
@@ -0,0 +1,497 @@
+//! Request/response calls between shards, over a full [`channel_mesh`].
+//!
+//! Every shard registers a [`Service`] with [`RpcServer::new`], and calls the
+//! services of the others through the [`RpcClient`] it gets back. A call
+//! travels as the request and a [`oneshot::shared`] reply sender through the
+//! mesh, and the caller awaits the reply; this is the pairing that is
+//! otherwise written by hand around every cross-shard question.
+//!
+//! * Backpressure: each shard serves at most as many calls of each peer at
+//!   once as the mesh channels hold. Past that, calls wait in the channel,
+//!   and once it is full, in [`RpcClient::call`] itself.
+//! * Timeouts: [`RpcClient::call_timeout`] bounds a call with
+//!   [`try_timeout`].
+//! * Cancellation: a caller that drops its call, or whose call timed out,
+//!   drops the reply receiver. The call is then skipped if it was still
+//!   queued, and the future of the service is dropped if it was running.
+//!
+//! # Examples
+//!
+//! ```
+//! use futures_lite::{future::ready, FutureExt};
+//! use glommio::{
+//!     channels::{
+//!         channel_mesh::MeshBuilder,
+//!         rpc::{RpcServer, Service, ServiceResult},
+//!     },
+//!     enclose,
+//!     prelude::*,
+//! };
+//!
+//! #[derive(Clone)]
+//! struct Square;
+//!
+//! impl Service<u64, u64> for Square {
+//!     fn serve(&self, request: u64, _src_shard: usize, _cur_shard: usize) -> ServiceResult<u64> {
+//!         ready(request * request).boxed_local()
+//!     }
+//! }
+//!
+//! let nr_shards = 2;
+//! let mesh = MeshBuilder::full(nr_shards, 16);
+//!
+//! let shards = (0..nr_shards).map(|_| {
+//!     LocalExecutorBuilder::default().spawn(enclose!((mesh) move || async move {
+//!         let mut server = RpcServer::new(mesh, Square).await.unwrap();
+//!         let client = server.client();
+//!         let peer = (server.shard_id() + 1) % nr_shards;
+//!         assert_eq!(client.call(peer, 7).await.unwrap(), 49);
+//!         server.close().await;
+//!     }))
+//! });
+//!
+//! for s in shards.collect::<Vec<_>>() {
+//!     s.unwrap().join().unwrap();
+//! }
+//! ```
+//!
+//! [`channel_mesh`]: super::channel_mesh
+//! [`try_timeout`]: crate::timer::try_timeout
+
+use std::{
+    fmt::{self, Debug, Formatter},
+    io,
+    pin::Pin,
+    rc::Rc,
+    time::Duration,
+};
+
+use futures_lite::{future, Future};
+
+use crate::{
+    channels::{
+        channel_mesh::{FullMesh, Senders},
+        oneshot::{self, SharedSender},
+    },
+    sync::Semaphore,
+    task::JoinHandle,
+    timer::try_timeout,
+    GlommioError, ResourceType,
+};
+
+type Result<T> = crate::Result<T, ()>;
+
+/// Alias for return type of `Service`
+pub type ServiceResult<Resp> = Pin<Box<dyn Future<Output = Resp>>>;
+
+/// Trait for serving the calls of other shards
+pub trait Service<Req, Resp>: Clone {
+    /// Answer a request.
+    /// * `request` - The request to answer.
+    /// * `src_shard` - ID of the shard the call comes from.
+    /// * `cur_shard` - ID of the local shard.
+    fn serve(&self, request: Req, src_shard: usize, cur_shard: usize) -> ServiceResult<Resp>;
+}
+
+/// A call in flight between two shards: the request, and where its response
+/// goes.
+///
+/// Public only because it names the messages of an [`RpcMesh`]. It has no
+/// callable surface.
+#[doc(hidden)]
+pub struct Call<Req, Resp> {
+    request: Req,
+    reply: SharedSender<Resp>,
+}
+
+impl<Req, Resp> Debug for Call<Req, Resp> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Call").finish_non_exhaustive()
+    }
+}
+
+/// The full mesh the calls of an [`RpcServer`] travel through.
+pub type RpcMesh<Req, Resp> = FullMesh<Call<Req, Resp>>;
+
+struct Inner<Req: Send, Resp: Send> {
+    nr_shards: usize,
+    shard_id: usize,
+    senders: Senders<Call<Req, Resp>>,
+    local: Box<dyn Fn(Req) -> ServiceResult<Resp>>,
+}
+
+/// Calls the services of the shards of an [`RpcServer`].
+///
+/// Cloning it is cheap, and every clone calls through the same mesh.
+pub struct RpcClient<Req: Send, Resp: Send> {
+    inner: Rc<Inner<Req, Resp>>,
+}
+
+impl<Req: Send, Resp: Send> Clone for RpcClient<Req, Resp> {
+    fn clone(&self) -> Self {
+        RpcClient {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<Req: Send, Resp: Send> Debug for RpcClient<Req, Resp> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RpcClient")
+            .field("shard_id", &self.inner.shard_id)
+            .field("nr_shards", &self.inner.nr_shards)
+            .finish()
+    }
+}
+
+impl<Req: Send + 'static, Resp: Send + 'static> RpcClient<Req, Resp> {
+    /// Returns the total number of shards
+    pub fn nr_shards(&self) -> usize {
+        self.inner.nr_shards
+    }
+
+    /// Returns the shard_id associated with ourselves
+    pub fn shard_id(&self) -> usize {
+        self.inner.shard_id
+    }
+
+    /// Calls the service of `shard` with `request`, and waits for its
+    /// response.
+    ///
+    /// A call to the local shard runs its service directly. Otherwise this
+    /// waits for room in the channel to `shard` first, if it is full.
+    /// Dropping the returned future cancels the call.
+    ///
+    /// This function returns [`GlommioError::Closed`] if the server of either
+    /// shard was closed or dropped the call, or [`InvalidInput`] if `shard`
+    /// is invalid.
+    ///
+    /// [`GlommioError::Closed`]: crate::GlommioError::Closed
+    /// [`InvalidInput`]: std::io::ErrorKind::InvalidInput
+    pub async fn call(&self, shard: usize, request: Req) -> Result<Resp> {
+        if shard == self.inner.shard_id {
+            return Ok((self.inner.local)(request).await);
+        }
+
+        let (reply, response) = oneshot::shared();
+        self.inner
+            .senders
+            .send_to(shard, Call { request, reply })
+            .await
+            .map_err(without_call)?;
+        response.await
+    }
+
+    /// Calls the service of `shard` with `request`, as [`call`] does, but
+    /// fails with [`GlommioError::TimedOut`] if no response came within
+    /// `timeout`. The call is then cancelled.
+    ///
+    /// The timeout counts the time waiting for room in the channel too.
+    ///
+    /// [`call`]: RpcClient::call
+    /// [`GlommioError::TimedOut`]: crate::GlommioError::TimedOut
+    pub async fn call_timeout(
+        &self,
+        shard: usize,
+        request: Req,
+        timeout: Duration,
+    ) -> Result<Resp> {
+        try_timeout(timeout, self.call(shard, request)).await
+    }
+}
+
+// The call that could not be sent only holds a request, which the caller
+// gave away
+fn without_call<Req, Resp>(err: GlommioError<Call<Req, Resp>>) -> GlommioError<()> {
+    match err {
+        GlommioError::Closed(ResourceType::Channel(_)) => {
+            GlommioError::Closed(ResourceType::Channel(()))
+        }
+        err => io::Error::from(err).into(),
+    }
+}
+
+/// Serves the calls of the other shards of a mesh, and hands out the
+/// [`RpcClient`] that calls theirs.
+pub struct RpcServer<Req: Send, Resp: Send> {
+    client: RpcClient<Req, Resp>,
+    forward_tasks: Vec<JoinHandle<()>>,
+    in_flight: Vec<(Rc<Semaphore>, u64)>,
+    closed: bool,
+}
+
+impl<Req: Send, Resp: Send> Debug for RpcServer<Req, Resp> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "RpcServer")
+    }
+}
+
+impl<Req: Send + 'static, Resp: Send + 'static> RpcServer<Req, Resp> {
+    /// Join a full mesh, serving the calls of the other shards with
+    /// `service`.
+    ///
+    /// Only the shards in the mesh at this point are served: the calls of a
+    /// shard joining a mesh with dynamic membership later are left waiting.
+    pub async fn new<S: Service<Req, Resp> + 'static>(
+        mesh: RpcMesh<Req, Resp>,
+        service: S,
+    ) -> Result<Self> {
+        let nr_shards = mesh.nr_peers();
+        let capacity = mesh.channel_size().max(1) as u64;
+
+        let (senders, mut receivers) = mesh.join().await?;
+        let shard_id = senders.peer_id();
+
+        let mut forward_tasks = Vec::with_capacity(nr_shards);
+        let mut in_flight = Vec::with_capacity(nr_shards);
+        for (src_shard, stream) in receivers.streams() {
+            let service = service.clone();
+            let permits = Rc::new(Semaphore::new(capacity));
+            in_flight.push((permits.clone(), capacity));
+            let consumer = crate::spawn_local(async move {
+                while let Some(call) = stream.recv().await {
+                    let Ok(permit) = permits.acquire_static_permit(1).await else {
+                        break;
+                    };
+                    // nobody waits for this one anymore
+                    if call.reply.is_closed() {
+                        continue;
+                    }
+                    let response = service.serve(call.request, src_shard, shard_id);
+                    let reply = call.reply;
+                    crate::spawn_local(async move {
+                        let _permit = permit;
+                        let response = future::or(async { Some(response.await) }, async {
+                            reply.closed().await;
+                            None
+                        })
+                        .await;
+                        if let Some(response) = response {
+                            // the caller may have gone in the meantime
+                            let _ = reply.send(response);
+                        }
+                    })
+                    .detach();
+                }
+            });
+            forward_tasks.push(consumer.detach());
+        }
+
+        let local = Box::new(move |request| service.serve(request, shard_id, shard_id));
+        Ok(Self {
+            client: RpcClient {
+                inner: Rc::new(Inner {
+                    nr_shards,
+                    shard_id,
+                    senders,
+                    local,
+                }),
+            },
+            forward_tasks,
+            in_flight,
+            closed: false,
+        })
+    }
+
+    /// Returns a client calling the services of the shards of this mesh.
+    pub fn client(&self) -> RpcClient<Req, Resp> {
+        self.client.clone()
+    }
+
+    /// Returns the total number of shards
+    pub fn nr_shards(&self) -> usize {
+        self.client.nr_shards()
+    }
+
+    /// Returns the shard_id associated with ourselves
+    pub fn shard_id(&self) -> usize {
+        self.client.shard_id()
+    }
+
+    /// Close this [`RpcServer`] and wait for the calls it serves to finish.
+    ///
+    /// The clients of this shard can no longer call other shards, and the
+    /// calls of other shards keep being served until they close too. So it
+    /// would be important for every shard to close eventually to prevent this
+    /// method from hanging.
+    pub async fn close(&mut self) {
+        if std::mem::replace(&mut self.closed, true) {
+            return;
+        }
+
+        self.client.inner.senders.close();
+
+        while let Some(task) = self.forward_tasks.pop() {
+            task.await;
+        }
+
+        while let Some((permits, capacity)) = self.in_flight.pop() {
+            let _ = permits.acquire(capacity).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::Cell,
+        time::{Duration, Instant},
+    };
+
+    use futures_lite::{future::ready, FutureExt};
+
+    use super::*;
+    use crate::{channels::channel_mesh::MeshBuilder, enclose, prelude::*, timer::sleep};
+
+    #[derive(Clone)]
+    struct Echo;
+
+    impl Service<usize, (usize, usize)> for Echo {
+        fn serve(
+            &self,
+            request: usize,
+            src_shard: usize,
+            cur_shard: usize,
+        ) -> ServiceResult<(usize, usize)> {
+            assert_eq!(request, cur_shard);
+            ready((src_shard, cur_shard)).boxed_local()
+        }
+    }
+
+    #[test]
+    fn every_shard_calls_every_shard() {
+        let nr_shards = 4;
+        let mesh = MeshBuilder::full(nr_shards, 8);
+
+        let shards = (0..nr_shards).map(|_| {
+            LocalExecutorBuilder::default().spawn(enclose!((mesh) move || async move {
+                let mut server = RpcServer::new(mesh, Echo).await.unwrap();
+                let client = server.client();
+                let me = client.shard_id();
+                let calls = (0..nr_shards).map(|shard| {
+                    let client = client.clone();
+                    crate::spawn_local(async move { client.call(shard, shard).await.unwrap() })
+                });
+                for (shard, call) in calls.collect::<Vec<_>>().into_iter().enumerate() {
+                    assert_eq!(call.await, (me, shard));
+                }
+                let err = client.call(nr_shards, nr_shards).await.unwrap_err();
+                assert_eq!(io::Error::from(err).kind(), io::ErrorKind::InvalidInput);
+                server.close().await;
+            }))
+        });
+
+        for s in shards.collect::<Vec<_>>() {
+            s.unwrap().join().unwrap();
+        }
+    }
+
+    // Sleeps for as many milliseconds as asked, counting the calls it
+    // started, finished, and dropped before they finished
+    #[derive(Clone, Default)]
+    struct Slow {
+        started: Rc<Cell<usize>>,
+        finished: Rc<Cell<usize>>,
+        dropped: Rc<Cell<usize>>,
+    }
+
+    struct Unfinished(Rc<Cell<usize>>);
+
+    impl Drop for Unfinished {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    impl Service<u64, u64> for Slow {
+        fn serve(&self, millis: u64, _src_shard: usize, _cur_shard: usize) -> ServiceResult<u64> {
+            let this = self.clone();
+            async move {
+                this.started.set(this.started.get() + 1);
+                let unfinished = Unfinished(this.dropped.clone());
+                sleep(Duration::from_millis(millis)).await;
+                std::mem::forget(unfinished);
+                this.finished.set(this.finished.get() + 1);
+                millis
+            }
+            .boxed_local()
+        }
+    }
+
+    #[test]
+    fn timed_out_calls_are_cancelled() {
+        let mesh = MeshBuilder::full(2, 4);
+        let (tx, rx) = std::sync::mpsc::channel();
+
+        let shards = (0..2).map(|_| {
+            LocalExecutorBuilder::default().spawn(enclose!((mesh, tx) move || async move {
+                let service = Slow::default();
+                let mut server = RpcServer::new(mesh, service.clone()).await.unwrap();
+                let client = server.client();
+                if client.shard_id() == 0 {
+                    let start = Instant::now();
+                    let err = client
+                        .call_timeout(1, 10_000, Duration::from_millis(50))
+                        .await
+                        .unwrap_err();
+                    assert!(matches!(err, GlommioError::TimedOut(_)));
+                    assert!(start.elapsed() < Duration::from_secs(5));
+                    assert_eq!(client.call(1, 0).await.unwrap(), 0);
+                } else {
+                    let start = Instant::now();
+                    while (service.dropped.get() < 1 || service.finished.get() < 1)
+                        && start.elapsed() < Duration::from_secs(5)
+                    {
+                        sleep(Duration::from_millis(1)).await;
+                    }
+                    let counts = (service.started.get(), service.finished.get(), service.dropped.get());
+                    tx.send(counts).unwrap();
+                }
+                server.close().await;
+            }))
+        });
+
+        for s in shards.collect::<Vec<_>>() {
+            s.unwrap().join().unwrap();
+        }
+        // the first call was dropped as soon as it timed out
+        assert_eq!(rx.recv().unwrap(), (2, 1, 1));
+    }
+
+    #[test]
+    fn calls_beyond_the_capacity_wait() {
+        let capacity = 2;
+        let mesh = MeshBuilder::full(2, capacity);
+        let (tx, rx) = std::sync::mpsc::channel();
+
+        let shards = (0..2).map(|_| {
+            LocalExecutorBuilder::default().spawn(enclose!((mesh, tx) move || async move {
+                let service = Slow::default();
+                let mut server = RpcServer::new(mesh, service.clone()).await.unwrap();
+                let client = server.client();
+                if client.shard_id() == 0 {
+                    let calls = (0..4 * capacity).map(|_| {
+                        let client = client.clone();
+                        crate::spawn_local(async move { client.call(1, 100).await.unwrap() })
+                    }).collect::<Vec<_>>();
+                    for call in calls {
+                        assert_eq!(call.await, 100);
+                    }
+                } else {
+                    let mut most = 0;
+                    while service.finished.get() < 4 * capacity {
+                        most = most.max(service.started.get() - service.finished.get());
+                        sleep(Duration::from_millis(5)).await;
+                    }
+                    tx.send(most).unwrap();
+                }
+                server.close().await;
+            }))
+        });
+
+        for s in shards.collect::<Vec<_>>() {
+            s.unwrap().join().unwrap();
+        }
+        assert_eq!(rx.recv().unwrap(), capacity);
+    }
+}
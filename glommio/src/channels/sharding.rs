@@ -1,5 +1,7 @@
 use std::{
+    cell::RefCell,
     fmt::{self, Debug, Formatter},
+    hash::{DefaultHasher, Hash, Hasher},
     pin::Pin,
     rc::Rc,
 };
@@ -7,7 +9,10 @@ use std::{
 use futures_lite::{Future, Stream, StreamExt};
 
 use crate::{
-    channels::channel_mesh::{FullMesh, Senders},
+    channels::{
+        channel_mesh::{FullMesh, Membership, Receivers, Senders},
+        watch,
+    },
     task::JoinHandle,
     GlommioError, ResourceType, Result,
 };
@@ -25,11 +30,97 @@ pub trait Handler<T>: Clone {
     fn handle(&self, msg: T, src_shard: usize, cur_shard: usize) -> HandlerResult;
 }
 
+/// Trait for picking the shard a message goes to
+///
+/// Besides the built-in [`Modulo`], [`JumpHash`] and [`Rendezvous`], any
+/// closure or function taking the message and the number of shards, like a
+/// [`ShardFn`], is a router: it picks the position of the destination among
+/// the shards.
+pub trait ShardRouter<T> {
+    /// Returns the id of the shard `msg` goes to.
+    /// * `msg` - The message to route.
+    /// * `shards` - IDs of the shards in the mesh, in ascending order.
+    fn route(&self, msg: &T, shards: &[usize]) -> usize;
+}
+
+impl<T, F: Fn(&T, usize) -> usize> ShardRouter<T> for F {
+    fn route(&self, msg: &T, shards: &[usize]) -> usize {
+        let idx = self(msg, shards.len());
+        shards.get(idx).copied().unwrap_or(idx)
+    }
+}
+
+// `DefaultHasher::new` is seeded the same everywhere, so that every shard
+// agrees on where a message goes
+fn hash<K: Hash + ?Sized>(key: &K) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Routes the hash of a message modulo the number of shards.
+///
+/// Spreads messages evenly, but a change in the number of shards moves
+/// almost every message to another one.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Modulo;
+
+impl<T: Hash> ShardRouter<T> for Modulo {
+    fn route(&self, msg: &T, shards: &[usize]) -> usize {
+        shards[(hash(msg) % shards.len() as u64) as usize]
+    }
+}
+
+/// Routes with the jump consistent hash of Lamping and Veach.
+///
+/// When a shard joins at the end of the list, only the messages moving to it
+/// change shards, and no memory is needed to get there. Shards leaving from
+/// the middle of the list shift the ones after them though, which
+/// [`Rendezvous`] does not.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JumpHash;
+
+impl<T: Hash> ShardRouter<T> for JumpHash {
+    fn route(&self, msg: &T, shards: &[usize]) -> usize {
+        let mut key = hash(msg);
+        let (mut bucket, mut next) = (0u64, 0u64);
+        while next < shards.len() as u64 {
+            bucket = next;
+            key = key.wrapping_mul(2862933555777941757).wrapping_add(1);
+            next = ((bucket + 1) as f64 * ((1u64 << 31) as f64 / ((key >> 33) + 1) as f64)) as u64;
+        }
+        shards[bucket as usize]
+    }
+}
+
+/// Routes with rendezvous, or highest random weight, hashing.
+///
+/// Every shard is weighted by the hash of the message and its id, and the
+/// heaviest one wins: whichever shard joins or leaves, only the messages it
+/// wins or held change shards. Routing costs a hash per shard.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Rendezvous;
+
+impl<T: Hash> ShardRouter<T> for Rendezvous {
+    fn route(&self, msg: &T, shards: &[usize]) -> usize {
+        let key = hash(msg);
+        *shards
+            .iter()
+            .max_by_key(|&&shard| hash(&(key, shard)))
+            .unwrap()
+    }
+}
+
 /// The public interface for sharding
+///
+/// Messages are spread over the shards in the mesh at the time they are
+/// sent: as shards join and leave a mesh with dynamic membership, the
+/// [`ShardRouter`] is given the new list of shards, and a message whose
+/// shard left on its way is routed again.
 pub struct Sharded<T: Send, H> {
     shard: Rc<Shard<T, H>>,
     consumers: Vec<JoinHandle<()>>,
-    forward_tasks: Vec<JoinHandle<()>>,
+    watcher: JoinHandle<()>,
     closed: bool,
 }
 
@@ -43,43 +134,53 @@ impl<T: Send, H> Debug for Sharded<T, H> {
 pub type ShardFn<T> = fn(&T, usize) -> usize;
 
 impl<T: Send + 'static, H: Handler<T> + 'static> Sharded<T, H> {
-    /// Join a full mesh for sharding
-    pub async fn new(mesh: FullMesh<T>, shard_fn: ShardFn<T>, handler: H) -> Result<Self, ()> {
+    /// Join a full mesh for sharding, spreading messages with `router`
+    pub async fn new<R: ShardRouter<T> + 'static>(
+        mesh: FullMesh<T>,
+        router: R,
+        handler: H,
+    ) -> Result<Self, ()> {
         let nr_shards = mesh.nr_peers();
 
-        let (senders, mut receivers) = mesh.join().await?;
+        let (senders, receivers) = mesh.join().await?;
 
         let shard = Rc::new(Shard {
-            nr_shards,
             shard_id: senders.peer_id(),
-            shard_fn,
+            router: Box::new(router),
+            shards: RefCell::new((0, Vec::with_capacity(nr_shards))),
+            membership: senders.membership(),
             senders,
-            handler: handler.clone(),
+            receivers: RefCell::new(receivers),
+            handler,
+            forward_tasks: RefCell::new(Vec::with_capacity(nr_shards)),
         });
-
-        let mut forward_tasks = Vec::with_capacity(nr_shards);
-        for (src_shard, stream) in receivers.streams() {
-            let handler = handler.clone();
-            let cur_shard = shard.shard_id;
-            let consumer = crate::spawn_local(async move {
-                while let Some(msg) = stream.recv().await {
-                    handler.handle(msg, src_shard, cur_shard).await;
+        shard.forward();
+
+        // Serves the shards joining later. It holds the shard weakly, so that
+        // dropping the `Sharded` leaves the mesh.
+        let mut membership = shard.senders.membership();
+        let weak = Rc::downgrade(&shard);
+        let watcher = crate::spawn_local(async move {
+            while membership.changed().await.is_ok() {
+                match weak.upgrade() {
+                    Some(shard) => shard.forward(),
+                    None => break,
                 }
-            });
-            forward_tasks.push(consumer.detach());
-        }
+            }
+        })
+        .detach();
 
         Ok(Self {
             shard,
             consumers: Vec::new(),
-            forward_tasks,
+            watcher,
             closed: false,
         })
     }
 
-    /// Returns the total number of shards
+    /// Returns the number of shards currently in the mesh
     pub fn nr_shards(&self) -> usize {
-        self.shard.nr_shards
+        self.shard.membership.borrow().nr_peers()
     }
 
     /// Returns the shard_id associated with ourselves
@@ -87,6 +188,15 @@ impl<T: Send + 'static, H: Handler<T> + 'static> Sharded<T, H> {
         self.shard.shard_id
     }
 
+    /// Watches the shards in the mesh.
+    ///
+    /// See [`Senders::membership`].
+    ///
+    /// [`Senders::membership`]: crate::channels::channel_mesh::Senders::membership
+    pub fn membership(&self) -> watch::Receiver<Membership> {
+        self.shard.membership.clone()
+    }
+
     /// Consume messages from a stream. It will return a
     /// [`GlommioError::Closed`] if this [`Sharded`] is closed. Otherwise,
     /// the function will return immediately after spawning a background
@@ -107,7 +217,8 @@ impl<T: Send + 'static, H: Handler<T> + 'static> Sharded<T, H> {
     /// Sends an individual message to a given shard.
     ///
     /// This function returns [`GlommioError::Closed`] if this [`Sharded`] is
-    /// closed, or [`InvalidInput`] if the destination id is invalid.
+    /// closed or the destination left the mesh, or [`InvalidInput`] if the
+    /// destination id is invalid.
     ///
     /// This function ignores the sharding function.
     ///
@@ -119,8 +230,8 @@ impl<T: Send + 'static, H: Handler<T> + 'static> Sharded<T, H> {
 
     /// Sends an individual message to the correct shard.
     ///
-    /// The correct shard is calculated using the sharding function in this
-    /// `Sharded` object.
+    /// The correct shard is calculated using the [`ShardRouter`] in this
+    /// `Sharded` object, over the shards currently in the mesh.
     ///
     /// This function returns [`GlommioError::Closed`] if this [`Sharded`] is
     /// closed.
@@ -130,6 +241,20 @@ impl<T: Send + 'static, H: Handler<T> + 'static> Sharded<T, H> {
         self.shard.send(message).await
     }
 
+    /// Sends a copy of a message to every shard in the mesh, including this
+    /// one, for control messages that concern them all.
+    ///
+    /// Shards leaving the mesh on the way are skipped. This function returns
+    /// [`GlommioError::Closed`] if this [`Sharded`] is closed.
+    ///
+    /// [`GlommioError::Closed`]: crate::GlommioError::Closed
+    pub async fn broadcast(&self, message: T) -> Result<(), T>
+    where
+        T: Clone,
+    {
+        self.shard.broadcast(message).await
+    }
+
     /// Close this [`Sharded`] and wait for all existing background tasks to
     /// finish. No more consuming task will be spawned, but incoming
     /// messages from the streams consumed by existing background tasks
@@ -140,20 +265,34 @@ impl<T: Send + 'static, H: Handler<T> + 'static> Sharded<T, H> {
             consumer.await;
         }
 
+        // The shards that joined before are served, whether or not the
+        // watcher got to them
+        self.watcher.cancel();
+        self.shard.forward();
         self.shard.close();
 
-        while let Some(task) = self.forward_tasks.pop() {
-            task.await;
+        loop {
+            let task = self.shard.forward_tasks.borrow_mut().pop();
+            match task {
+                Some(task) => {
+                    task.await;
+                }
+                None => break,
+            }
         }
     }
 }
 
 struct Shard<T: Send, H> {
-    nr_shards: usize,
     shard_id: usize,
-    shard_fn: ShardFn<T>,
+    router: Box<dyn ShardRouter<T>>,
+    // The shards in the mesh, as of the membership epoch they were listed at
+    shards: RefCell<(u64, Vec<usize>)>,
+    membership: watch::Receiver<Membership>,
     senders: Senders<T>,
+    receivers: RefCell<Receivers<T>>,
     handler: H,
+    forward_tasks: RefCell<Vec<JoinHandle<()>>>,
 }
 
 impl<T: Send + 'static, H: Handler<T> + 'static> Shard<T, H> {
@@ -163,6 +302,20 @@ impl<T: Send + 'static, H: Handler<T> + 'static> Shard<T, H> {
         }
     }
 
+    // Spawns a task handling the messages of every stream not served yet
+    fn forward(&self) {
+        for (src_shard, stream) in self.receivers.borrow_mut().streams() {
+            let handler = self.handler.clone();
+            let cur_shard = self.shard_id;
+            let consumer = crate::spawn_local(async move {
+                while let Some(msg) = stream.recv().await {
+                    handler.handle(msg, src_shard, cur_shard).await;
+                }
+            });
+            self.forward_tasks.borrow_mut().push(consumer.detach());
+        }
+    }
+
     async fn send_to(&self, dst_shard: usize, msg: T) -> Result<(), T> {
         if dst_shard == self.shard_id {
             self.handler.handle(msg, self.shard_id, self.shard_id).await;
@@ -172,9 +325,49 @@ impl<T: Send + 'static, H: Handler<T> + 'static> Shard<T, H> {
         Ok(())
     }
 
-    async fn send(&self, msg: T) -> Result<(), T> {
-        let dst_shard = (self.shard_fn)(&msg, self.nr_shards);
-        self.send_to(dst_shard, msg).await
+    async fn send(&self, mut msg: T) -> Result<(), T> {
+        loop {
+            let dst_shard = self.with_shards(|shards| self.router.route(&msg, shards));
+            match self.send_to(dst_shard, msg).await {
+                Err(GlommioError::Closed(ResourceType::Channel(returned)))
+                    if self.senders.has_departed(dst_shard) =>
+                {
+                    // Route again once the local view lost the shard
+                    msg = returned;
+                    let mut membership = self.membership.clone();
+                    while membership.borrow().contains(dst_shard) {
+                        if membership.changed().await.is_err() {
+                            return Err(GlommioError::Closed(ResourceType::Channel(msg)));
+                        }
+                    }
+                }
+                res => return res,
+            }
+        }
+    }
+
+    async fn broadcast(&self, msg: T) -> Result<(), T>
+    where
+        T: Clone,
+    {
+        for dst_shard in self.with_shards(<[usize]>::to_vec) {
+            match self.send_to(dst_shard, msg.clone()).await {
+                Err(GlommioError::Closed(ResourceType::Channel(_)))
+                    if self.senders.has_departed(dst_shard) => {}
+                Err(err) => return Err(err),
+                Ok(()) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn with_shards<R>(&self, f: impl FnOnce(&[usize]) -> R) -> R {
+        let membership = self.membership.borrow();
+        let mut shards = self.shards.borrow_mut();
+        if shards.0 != membership.epoch() {
+            *shards = (membership.epoch(), membership.peers().collect());
+        }
+        f(&shards.1)
     }
 
     fn close(&self) {
@@ -184,12 +377,19 @@ impl<T: Send + 'static, H: Handler<T> + 'static> Shard<T, H> {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
     use futures_lite::{future::ready, stream::repeat_with, FutureExt, StreamExt};
 
     use crate::{
         channels::{
             channel_mesh::MeshBuilder,
-            sharding::{Handler, HandlerResult, Sharded},
+            sharding::{
+                Handler, HandlerResult, JumpHash, Modulo, Rendezvous, ShardRouter, Sharded,
+            },
         },
         enclose,
         prelude::*,
@@ -276,6 +476,124 @@ mod tests {
         }
     }
 
+    #[test]
+    fn messages_are_routed_to_shards_joining_later() {
+        type Msg = usize;
+
+        fn shard_fn(msg: &Msg, nr_shards: usize) -> usize {
+            *msg % nr_shards
+        }
+
+        #[derive(Clone)]
+        struct RequestHandler {
+            handled: Arc<AtomicUsize>,
+        }
+
+        impl Handler<Msg> for RequestHandler {
+            fn handle(&self, msg: Msg, _src_shard: usize, cur_shard: usize) -> HandlerResult {
+                assert_eq!(msg % 3, cur_shard);
+                self.handled.fetch_add(1, Ordering::Relaxed);
+                ready(()).boxed_local()
+            }
+        }
+
+        let mesh = MeshBuilder::full(2, 16).with_dynamic_membership();
+        let handled = Arc::new(AtomicUsize::new(0));
+        let (joined, formed) = std::sync::mpsc::channel();
+
+        let shard = |joined: Option<std::sync::mpsc::Sender<()>>| {
+            LocalExecutorBuilder::default().spawn(enclose!((mesh, handled) move || async move {
+                let handler = RequestHandler { handled };
+                let mut sharded = Sharded::new(mesh, shard_fn, handler).await.unwrap();
+                let mut membership = sharded.membership();
+                if let Some(joined) = joined {
+                    joined.send(()).unwrap();
+                }
+                while sharded.nr_shards() < 3 {
+                    membership.changed().await.unwrap();
+                }
+                for i in 0..3 {
+                    sharded.send(i).await.unwrap();
+                }
+                sharded.close().await;
+            }))
+        };
+
+        let founders = [shard(Some(joined.clone())), shard(Some(joined))];
+        formed.recv().unwrap();
+        formed.recv().unwrap();
+        let late = shard(None);
+
+        for s in founders.into_iter().chain([late]) {
+            s.unwrap().join().unwrap();
+        }
+        assert_eq!(handled.load(Ordering::Relaxed), 9);
+    }
+
+    #[test]
+    fn routers_move_few_messages_on_resize() {
+        let grown: Vec<usize> = (0..5).collect();
+        for msg in 0..1000u64 {
+            let before = JumpHash.route(&msg, &grown[..4]);
+            let after = JumpHash.route(&msg, &grown);
+            assert!(after == before || after == 4);
+            assert!(grown.contains(&Modulo.route(&msg, &grown)));
+        }
+
+        let shrunk = [0, 1, 3, 4];
+        let mut moved = 0;
+        for msg in 0..1000u64 {
+            let before = Rendezvous.route(&msg, &grown);
+            let after = Rendezvous.route(&msg, &shrunk);
+            if before == 2 {
+                moved += 1;
+            } else {
+                assert_eq!(before, after);
+            }
+        }
+        assert!((100..300).contains(&moved), "{moved} messages moved");
+
+        let offset = 1;
+        let router = move |msg: &u64, nr_shards: usize| (*msg as usize + offset) % nr_shards;
+        assert_eq!(router.route(&0, &shrunk), 1);
+        assert_eq!(router.route(&1, &shrunk), 3);
+    }
+
+    #[test]
+    fn broadcast_reaches_every_shard() {
+        type Msg = usize;
+
+        #[derive(Clone)]
+        struct RequestHandler {
+            handled: Arc<AtomicUsize>,
+        }
+
+        impl Handler<Msg> for RequestHandler {
+            fn handle(&self, _msg: Msg, _src_shard: usize, _cur_shard: usize) -> HandlerResult {
+                self.handled.fetch_add(1, Ordering::Relaxed);
+                ready(()).boxed_local()
+            }
+        }
+
+        let nr_shards = 3;
+        let mesh = MeshBuilder::full(nr_shards, 16);
+        let handled = Arc::new(AtomicUsize::new(0));
+
+        let shards = (0..nr_shards).map(|_| {
+            LocalExecutorBuilder::default().spawn(enclose!((mesh, handled) move || async move {
+                let handler = RequestHandler { handled };
+                let mut sharded = Sharded::new(mesh, Rendezvous, handler).await.unwrap();
+                sharded.broadcast(sharded.shard_id()).await.unwrap();
+                sharded.close().await;
+            }))
+        });
+
+        for s in shards.collect::<Vec<_>>() {
+            s.unwrap().join().unwrap();
+        }
+        assert_eq!(handled.load(Ordering::Relaxed), nr_shards * nr_shards);
+    }
+
     #[test]
     fn test() {
         type Msg = i32;
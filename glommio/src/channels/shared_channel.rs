@@ -5,7 +5,10 @@
 //
 //
 use crate::{
-    channels::spsc_queue::{make, BufferHalf, Consumer, Producer},
+    channels::{
+        spsc_queue::{make, BufferHalf, Consumer, Producer},
+        stats::{ChannelRecorder, ChannelStats},
+    },
     enclose,
     reactor::Reactor,
     sys::{self, SleepNotifier},
@@ -13,11 +16,12 @@ use crate::{
 };
 use futures_lite::{future, stream::Stream};
 use std::{
+    cell::{Cell, RefCell},
     fmt,
     future::Future,
     pin::Pin,
     rc::{Rc, Weak},
-    sync::Arc,
+    sync::{Arc, Mutex},
     task::{Context, Poll},
 };
 
@@ -79,6 +83,8 @@ pub struct ConnectedReceiver<T: Send + Sized> {
     state: Arc<ReceiverState<T>>,
     reactor: Weak<Reactor>,
     notifier: Arc<SleepNotifier>,
+    stats: Cell<NotifyStats>,
+    recorder: RefCell<Option<Arc<ChannelRecorder>>>,
 }
 
 /// The `ConnectedSender` is the sending end of the Shared Channel.
@@ -87,6 +93,55 @@ pub struct ConnectedSender<T: Send + Sized> {
     state: Arc<SenderState<T>>,
     reactor: Weak<Reactor>,
     notifier: Arc<SleepNotifier>,
+    stats: Cell<NotifyStats>,
+    recorder: RefCell<Option<Arc<ChannelRecorder>>>,
+}
+
+/// How many messages a connected endpoint moved, and how many times that
+/// took waking its peer up.
+///
+/// The peer is only notified if it went to sleep, so a pair of busy executors
+/// can move many messages per notification. Batches raise the ratio further,
+/// as they publish all of their messages at once and notify at most once for
+/// them.
+///
+/// See [`ConnectedSender::notify_stats`] and
+/// [`ConnectedReceiver::notify_stats`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct NotifyStats {
+    messages: u64,
+    publishes: u64,
+    notifications: u64,
+}
+
+impl NotifyStats {
+    /// The number of messages sent, or received.
+    pub fn messages(&self) -> u64 {
+        self.messages
+    }
+
+    /// The number of times messages were handed over to the peer: once per
+    /// message sent or received on its own, and once per batch.
+    pub fn publishes(&self) -> u64 {
+        self.publishes
+    }
+
+    /// The number of times the peer had to be woken up.
+    pub fn notifications(&self) -> u64 {
+        self.notifications
+    }
+
+    /// The average number of messages per notification, counting a peer that
+    /// never had to be woken up as notified once.
+    pub fn messages_per_notification(&self) -> f64 {
+        self.messages as f64 / self.notifications.max(1) as f64
+    }
+
+    fn record(&mut self, messages: usize, notified: bool) {
+        self.messages += messages as u64;
+        self.publishes += 1;
+        self.notifications += u64::from(notified);
+    }
 }
 
 /// A sender usable from a thread with no executor.
@@ -239,6 +294,14 @@ impl<T: 'static + Send + Sized> SharedSender<T> {
         let state = self.state.take().unwrap();
         let reactor = crate::executor().reactor();
         state.buffer.connect(reactor.id());
+        ConnectedSender::register(state, reactor).await
+    }
+}
+
+impl<T: 'static + Send + Sized> ConnectedSender<T> {
+    /// Registers a sender whose buffer is already connected to this executor,
+    /// and waits for the receiving end to show up.
+    async fn register(state: Arc<SenderState<T>>, reactor: Rc<Reactor>) -> Self {
         let id = reactor.register_shared_channel(Box::new(enclose! {(state) move || {
             if state.buffer.consumer_disconnected() {
                 state.buffer.capacity()
@@ -255,6 +318,8 @@ impl<T: 'static + Send + Sized> SharedSender<T> {
             state,
             reactor,
             notifier,
+            stats: Cell::default(),
+            recorder: RefCell::default(),
         }
     }
 }
@@ -317,7 +382,7 @@ impl<T: Send + Sized> ConnectedSender<T> {
         }
         match self.state.buffer.try_push(item) {
             None => {
-                self.notifier.notify(false);
+                self.notify_peer(1);
                 Ok(())
             }
             Some(item) => {
@@ -358,6 +423,8 @@ impl<T: Send + Sized> ConnectedSender<T> {
     /// receiver.join().unwrap();
     /// ```
     pub async fn send(&self, item: T) -> Result<(), T> {
+        // Stops the blocked timer should this future be dropped while waiting.
+        defer! { self.record(ChannelRecorder::sender_unblocked); }
         let waiter = future::poll_fn(|cx| self.wait_for_room(cx));
         waiter.await;
         let res = self.try_send(item);
@@ -367,14 +434,112 @@ impl<T: Send + Sized> ConnectedSender<T> {
         res
     }
 
+    /// Sends every item of `items` into this channel, waiting for room as
+    /// needed.
+    ///
+    /// As many items as fit are published at once, and the receiver is
+    /// notified at most once for all of them, instead of once per item as
+    /// with [`send`](Self::send). Batches larger than the channel go out in
+    /// as many rounds as it takes.
+    ///
+    /// It returns a [`GlommioError::Closed`] holding the items that were not
+    /// sent if the receiver is destroyed.
+    ///
+    /// # Examples
+    /// ```
+    /// use glommio::{channels::shared_channel, prelude::*};
+    ///
+    /// let (sender, receiver) = shared_channel::new_bounded(64);
+    /// let producer = LocalExecutorBuilder::default()
+    ///     .spawn(move || async move {
+    ///         let sender = sender.connect().await;
+    ///         sender.send_batch(0..1000).await.unwrap();
+    ///     })
+    ///     .unwrap();
+    /// let receiver = LocalExecutorBuilder::default()
+    ///     .spawn(move || async move {
+    ///         let receiver = receiver.connect().await;
+    ///         let mut received = 0;
+    ///         loop {
+    ///             let batch = receiver.recv_batch(64).await;
+    ///             if batch.is_empty() {
+    ///                 break;
+    ///             }
+    ///             received += batch.len();
+    ///         }
+    ///         assert_eq!(received, 1000);
+    ///     })
+    ///     .unwrap();
+    /// producer.join().unwrap();
+    /// receiver.join().unwrap();
+    /// ```
+    pub async fn send_batch<I: IntoIterator<Item = T>>(&self, items: I) -> Result<(), Vec<T>> {
+        defer! { self.record(ChannelRecorder::sender_unblocked); }
+        let mut items = items.into_iter().peekable();
+        while items.peek().is_some() {
+            future::poll_fn(|cx| self.wait_for_room(cx)).await;
+            if self.state.buffer.consumer_disconnected()
+                || self.state.buffer.buffer.producer_disconnected()
+            {
+                return Err(GlommioError::Closed(ResourceType::Channel(items.collect())));
+            }
+            let pushed = self.state.buffer.try_push_batch(&mut items);
+            if pushed > 0 {
+                self.notify_peer(pushed);
+            }
+        }
+        Ok(())
+    }
+
+    /// How many messages this sender sent, and how often it had to wake up
+    /// the receiver for them.
+    pub fn notify_stats(&self) -> NotifyStats {
+        self.stats.get()
+    }
+
+    /// Starts recording the [`ChannelStats`] of the sending half of this
+    /// channel under `name`.
+    pub fn instrument(&self, name: impl Into<String>) {
+        self.recorder.replace(Some(ChannelRecorder::register(name)));
+    }
+
+    /// Returns the [`ChannelStats`] of this sender, or [`None`] if it was not
+    /// [instrumented](Self::instrument).
+    pub fn stats(&self) -> Option<ChannelStats> {
+        self.recorder
+            .borrow()
+            .as_ref()
+            .map(|recorder| recorder.stats())
+    }
+
+    fn record(&self, f: impl FnOnce(&ChannelRecorder)) {
+        if let Some(recorder) = &*self.recorder.borrow() {
+            f(recorder);
+        }
+    }
+
+    fn notify_peer(&self, messages: usize) {
+        let mut stats = self.stats.get();
+        stats.record(messages, self.notifier.notify(false));
+        self.stats.set(stats);
+        self.record(|r| r.sent(messages, self.state.buffer.size()));
+    }
+
     fn wait_for_room(&self, cx: &Context<'_>) -> Poll<()> {
-        match self.state.buffer.free_space() > 0 || self.state.buffer.producer_disconnected() {
-            true => Poll::Ready(()),
+        match self.state.buffer.free_space() > 0
+            || self.state.buffer.producer_disconnected()
+            || self.state.buffer.consumer_disconnected()
+        {
+            true => {
+                self.record(ChannelRecorder::sender_unblocked);
+                Poll::Ready(())
+            }
             false => {
+                self.record(ChannelRecorder::sender_blocked);
                 self.reactor
                     .upgrade()
                     .unwrap()
-                    .add_shared_channel_waker(self.id, cx.waker().clone());
+                    .add_shared_channel_waker(self.id, cx.waker());
                 Poll::Pending
             }
         }
@@ -420,6 +585,8 @@ impl<T: 'static + Send + Sized> SharedReceiver<T> {
             state,
             reactor,
             notifier,
+            stats: Cell::default(),
+            recorder: RefCell::default(),
         }
     }
 }
@@ -466,33 +633,121 @@ impl<T: Send + Sized> ConnectedReceiver<T> {
     /// [`next`]: https://docs.rs/futures-lite/2.6.0/futures_lite/stream/trait.StreamExt.html#method.next
     /// [`Rc`]: https://doc.rust-lang.org/std/rc/struct.Rc.html
     pub async fn recv(&self) -> Option<T> {
+        // Stops the idle timer should this future be dropped while waiting.
+        defer! { self.record(ChannelRecorder::receiver_woken); }
         let waiter = future::poll_fn(|cx| self.recv_one(cx));
         waiter.await
     }
 
-    fn recv_one(&self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+    /// Receives up to `max` items from this channel
+    ///
+    /// Blocks until at least one item is available, and then takes as many
+    /// as there are, up to `max`, notifying the sender at most once for all
+    /// of them. If the sender is no longer available and the channel is
+    /// drained, or if `max` is zero, it returns an empty [`Vec`].
+    ///
+    /// See [`ConnectedSender::send_batch`] for an example.
+    pub async fn recv_batch(&self, max: usize) -> Vec<T> {
+        defer! { self.record(ChannelRecorder::receiver_woken); }
+        future::poll_fn(|cx| self.recv_some(cx, max)).await
+    }
+
+    /// How many messages this receiver received, and how often it had to
+    /// wake up the sender for them.
+    pub fn notify_stats(&self) -> NotifyStats {
+        self.stats.get()
+    }
+
+    /// Starts recording the [`ChannelStats`] of the receiving half of this
+    /// channel under `name`.
+    pub fn instrument(&self, name: impl Into<String>) {
+        self.recorder.replace(Some(ChannelRecorder::register(name)));
+    }
+
+    /// Returns the [`ChannelStats`] of this receiver, or [`None`] if it was not
+    /// [instrumented](Self::instrument).
+    pub fn stats(&self) -> Option<ChannelStats> {
+        self.recorder
+            .borrow()
+            .as_ref()
+            .map(|recorder| recorder.stats())
+    }
+
+    fn record(&self, f: impl FnOnce(&ChannelRecorder)) {
+        if let Some(recorder) = &*self.recorder.borrow() {
+            f(recorder);
+        }
+    }
+
+    fn notify_peer(&self, messages: usize) {
+        let mut stats = self.stats.get();
+        stats.record(messages, self.notifier.notify(false));
+        self.stats.set(stats);
+        self.record(|r| r.received(messages, self.state.buffer.size()));
+    }
+
+    pub(crate) fn recv_one(&self, cx: &mut Context<'_>) -> Poll<Option<T>> {
         self.do_recv_one(cx, false)
     }
 
+    /// Receives a message if one is waiting, without registering to be woken
+    /// up otherwise.
+    pub(crate) fn try_recv(&self) -> Option<T> {
+        let item = self.state.buffer.try_pop()?;
+        self.notify_peer(1);
+        Some(item)
+    }
+
+    /// How many wakers are waiting for this receiver to have something.
+    #[cfg(test)]
+    pub(crate) fn registered_wakers(&self) -> usize {
+        self.reactor
+            .upgrade()
+            .map_or(0, |reactor| reactor.shared_channel_wakers(self.id))
+    }
+
+    fn recv_some(&self, cx: &mut Context<'_>, max: usize) -> Poll<Vec<T>> {
+        let mut items = Vec::new();
+        // Checked before popping, in case the producer sends its last items
+        // and disconnects in between
+        let disconnected = self.state.buffer.producer_disconnected();
+        if self.state.buffer.try_pop_batch(max, &mut items) > 0 {
+            self.notify_peer(items.len());
+            Poll::Ready(items)
+        } else if disconnected || max == 0 {
+            self.record(ChannelRecorder::receiver_woken);
+            Poll::Ready(items)
+        } else {
+            self.record(ChannelRecorder::receiver_idle);
+            self.reactor
+                .upgrade()
+                .unwrap()
+                .add_shared_channel_waker(self.id, cx.waker());
+            Poll::Pending
+        }
+    }
+
     fn do_recv_one(&self, cx: &mut Context<'_>, disconnected: bool) -> Poll<Option<T>> {
         match self.state.buffer.try_pop() {
             None => {
                 if disconnected {
+                    self.record(ChannelRecorder::receiver_woken);
                     Poll::Ready(None)
                 } else if self.state.buffer.producer_disconnected() {
                     // Double check in case the producer sent the last message and
                     // disconnected right after a `None` is returned from `try_pop`
                     self.do_recv_one(cx, true)
                 } else {
+                    self.record(ChannelRecorder::receiver_idle);
                     self.reactor
                         .upgrade()
                         .unwrap()
-                        .add_shared_channel_waker(self.id, cx.waker().clone());
+                        .add_shared_channel_waker(self.id, cx.waker());
                     Poll::Pending
                 }
             }
             res => {
-                self.notifier.notify(false);
+                self.notify_peer(1);
                 Poll::Ready(res)
             }
         }
@@ -590,6 +845,9 @@ impl<T: Send + Sized> ConnectedSender<T> {
             state: self.state.clone(),
             notifier: self.notifier.clone(),
         };
+        // Nothing records through this sender anymore, and the executor
+        // should stop reporting it
+        self.recorder.take();
 
         // `ConnectedSender::drop` disconnects the buffer, which would close
         // the channel we are handing on.
@@ -610,6 +868,384 @@ impl<T: Send + Sized> Drop for ConnectedSender<T> {
     }
 }
 
+/// Creates a new multi-producer `shared_channel` returning its sender and
+/// receiver endpoints.
+///
+/// The [`SharedMpscSender`] can be cloned and each clone connected in a
+/// different executor, all of them feeding the one [`ConnectedMpscReceiver`].
+/// Every connected sender gets a lane of its own with room for `size`
+/// elements, so senders never contend with each other, and the receiver takes
+/// from the lanes in turn so that a busy sender cannot starve the others.
+///
+/// # Examples
+/// ```
+/// use glommio::{channels::shared_channel, prelude::*};
+///
+/// let (sender, receiver) = shared_channel::new_mpsc_bounded(1);
+/// let producers: Vec<_> = (1..=2)
+///     .map(|x| {
+///         let sender = sender.clone();
+///         LocalExecutorBuilder::default()
+///             .spawn(move || async move {
+///                 let sender = sender.connect().await;
+///                 sender.send(x).await.unwrap();
+///             })
+///             .unwrap()
+///     })
+///     .collect();
+/// drop(sender);
+///
+/// let receiver = LocalExecutorBuilder::default()
+///     .spawn(move || async move {
+///         let receiver = receiver.connect().await;
+///         let mut sum = 0;
+///         while let Some(x) = receiver.recv().await {
+///             sum += x;
+///         }
+///         assert_eq!(sum, 3);
+///     })
+///     .unwrap();
+///
+/// for producer in producers {
+///     producer.join().unwrap();
+/// }
+/// receiver.join().unwrap();
+/// ```
+pub fn new_mpsc_bounded<T: Send + Sized>(
+    size: usize,
+) -> (SharedMpscSender<T>, SharedMpscReceiver<T>) {
+    let hub = Arc::new(Hub {
+        size,
+        state: Mutex::new(HubState {
+            receiver_id: 0,
+            senders: 1,
+            lanes: Vec::new(),
+        }),
+    });
+    (
+        SharedMpscSender {
+            hub: Some(hub.clone()),
+        },
+        SharedMpscReceiver { hub: Some(hub) },
+    )
+}
+
+/// The `SharedMpscSender` is the sending end of a multi-producer Shared
+/// Channel.
+///
+/// Unlike a [`SharedSender`] it can be cloned, and each clone connected in a
+/// different executor. Connecting yields a plain [`ConnectedSender`].
+pub struct SharedMpscSender<T: Send + Sized> {
+    hub: Option<Arc<Hub<T>>>,
+}
+
+/// The `SharedMpscReceiver` is the receiving end of a multi-producer Shared
+/// Channel. Before it is used it must be changed into a
+/// [`ConnectedMpscReceiver`].
+pub struct SharedMpscReceiver<T: Send + Sized> {
+    hub: Option<Arc<Hub<T>>>,
+}
+
+/// The `ConnectedMpscReceiver` is the receiving end of a multi-producer Shared
+/// Channel.
+///
+/// It sees the end of the channel once every [`SharedMpscSender`] is gone,
+/// either dropped or connected, and every connected sender is gone too.
+pub struct ConnectedMpscReceiver<T: Send + Sized> {
+    id: u64,
+    hub: Arc<Hub<T>>,
+    lanes: Rc<RefCell<Lanes<T>>>,
+    reactor: Weak<Reactor>,
+}
+
+impl<T: Send + Sized> fmt::Debug for SharedMpscSender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.hub {
+            Some(_) => write!(f, "Unbound SharedMpscSender"),
+            None => write!(f, "Bound SharedMpscSender"),
+        }
+    }
+}
+
+impl<T: Send + Sized> fmt::Debug for SharedMpscReceiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.hub {
+            Some(_) => write!(f, "Unbound SharedMpscReceiver"),
+            None => write!(f, "Bound SharedMpscReceiver"),
+        }
+    }
+}
+
+impl<T: Send + Sized> fmt::Debug for ConnectedMpscReceiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Connected MpscReceiver {}: {} lanes",
+            self.id,
+            self.lanes.borrow().lanes.len()
+        )
+    }
+}
+
+/// Where the lanes of a multi-producer channel wait for the receiver to take
+/// them over.
+struct Hub<T: Send + Sized> {
+    size: usize,
+    state: Mutex<HubState<T>>,
+}
+
+struct HubState<T: Send + Sized> {
+    /// Id == 0 : never connected
+    /// Id == usize::MAX: disconnected
+    receiver_id: usize,
+    /// How many sender handles may still connect a lane
+    senders: usize,
+    /// Lanes connected by a sender but not taken over by the receiver yet
+    lanes: Vec<Consumer<T>>,
+}
+
+impl<T: Send + Sized> Hub<T> {
+    fn notify(id: usize) {
+        if let Some(notifier) = sys::get_sleep_notifier_for(id) {
+            notifier.notify(false);
+        }
+    }
+
+    /// Hands a lane to the receiver on behalf of the sender handle that
+    /// connected it.
+    fn attach(&self, lane: Consumer<T>) {
+        let id = {
+            let mut state = self.state.lock().unwrap();
+            state.senders -= 1;
+            match state.receiver_id {
+                0 => state.lanes.push(lane),
+                usize::MAX => {
+                    lane.disconnect();
+                }
+                id => {
+                    lane.connect(id);
+                    state.lanes.push(lane);
+                }
+            }
+            state.receiver_id
+        };
+        Self::notify(id);
+    }
+
+    /// Gives up on a sender handle that never connected.
+    fn release(&self) {
+        let id = {
+            let mut state = self.state.lock().unwrap();
+            state.senders -= 1;
+            state.receiver_id
+        };
+        Self::notify(id);
+    }
+
+    /// Turns away every lane, present and future.
+    fn close(&self) {
+        let lanes = {
+            let mut state = self.state.lock().unwrap();
+            state.receiver_id = usize::MAX;
+            std::mem::take(&mut state.lanes)
+        };
+        for lane in lanes {
+            if !lane.disconnect() {
+                Self::notify(lane.peer_id());
+            }
+        }
+    }
+}
+
+struct Lane<T> {
+    buffer: Consumer<T>,
+    notifier: Arc<SleepNotifier>,
+}
+
+struct Lanes<T> {
+    lanes: Vec<Lane<T>>,
+    next: usize,
+    open: bool,
+}
+
+impl<T: Send + Sized> Lanes<T> {
+    /// Takes over the lanes connected since the last look, and notes whether
+    /// more can still come.
+    fn adopt(&mut self, hub: &Hub<T>) {
+        let mut state = hub.state.lock().unwrap();
+        self.open = state.senders > 0;
+        for buffer in state.lanes.drain(..) {
+            // The producer connected before handing the lane over, so if its
+            // notifier is gone so is the producer
+            let notifier = sys::get_sleep_notifier_for(buffer.peer_id())
+                .or_else(|| sys::get_sleep_notifier_for(usize::MAX))
+                .unwrap();
+            self.lanes.push(Lane { buffer, notifier });
+        }
+    }
+
+    /// Pops from the lanes in turn, starting after the one popped from last.
+    fn pop(&mut self) -> Option<T> {
+        let nr_lanes = self.lanes.len();
+        for i in 0..nr_lanes {
+            let idx = (self.next + i) % nr_lanes;
+            let lane = &self.lanes[idx];
+            if let Some(item) = lane.buffer.try_pop() {
+                lane.notifier.notify(false);
+                self.next = idx + 1;
+                return Some(item);
+            }
+        }
+
+        // Everything was empty, so the lanes whose producer is done can go.
+        // Anything pushed before a disconnect is visible once we see it.
+        self.lanes
+            .retain(|lane| !lane.buffer.producer_disconnected() || lane.buffer.size() > 0);
+        None
+    }
+
+    /// How many receivers the reactor could wake up.
+    fn ready(&self) -> usize {
+        self.lanes
+            .iter()
+            .map(|lane| {
+                if lane.buffer.producer_disconnected() {
+                    lane.buffer.capacity()
+                } else {
+                    lane.buffer.size()
+                }
+            })
+            .sum()
+    }
+}
+
+impl<T: Send + Sized> Clone for SharedMpscSender<T> {
+    fn clone(&self) -> Self {
+        let hub = self.hub.clone().unwrap();
+        hub.state.lock().unwrap().senders += 1;
+        SharedMpscSender { hub: Some(hub) }
+    }
+}
+
+impl<T: 'static + Send + Sized> SharedMpscSender<T> {
+    /// Connects this sender, returning a [`ConnectedSender`] that feeds its
+    /// own lane of the channel.
+    ///
+    /// If the receiver is already gone the returned sender is closed.
+    pub async fn connect(mut self) -> ConnectedSender<T> {
+        let hub = self.hub.take().unwrap();
+        let reactor = crate::executor().reactor();
+        let (producer, consumer) = make(hub.size);
+        producer.connect(reactor.id());
+        hub.attach(consumer);
+        let state = Arc::new(SenderState { buffer: producer });
+        ConnectedSender::register(state, reactor).await
+    }
+}
+
+impl<T: 'static + Send + Sized> SharedMpscReceiver<T> {
+    /// Connects this receiver, returning a [`ConnectedMpscReceiver`] that
+    /// takes data from every sender of this channel.
+    ///
+    /// Senders can connect before or after the receiver does.
+    pub async fn connect(mut self) -> ConnectedMpscReceiver<T> {
+        let hub = self.hub.take().unwrap();
+        let reactor = crate::executor().reactor();
+        {
+            let mut state = hub.state.lock().unwrap();
+            state.receiver_id = reactor.id();
+            for lane in &state.lanes {
+                lane.connect(reactor.id());
+            }
+        }
+
+        let lanes = Rc::new(RefCell::new(Lanes {
+            lanes: Vec::new(),
+            next: 0,
+            open: true,
+        }));
+        let id = reactor.register_shared_channel(Box::new(enclose! { (hub, lanes) move || {
+            let ready = lanes.borrow().ready();
+            let state = hub.state.lock().unwrap();
+            ready + state.lanes.len() + usize::from(state.senders == 0)
+        }}));
+
+        ConnectedMpscReceiver {
+            id,
+            hub,
+            lanes,
+            reactor: Rc::downgrade(&reactor),
+        }
+    }
+}
+
+impl<T: Send + Sized> ConnectedMpscReceiver<T> {
+    /// Receives data from this channel
+    ///
+    /// Values are taken from the senders in turn. Once every sender is gone
+    /// and the channel is drained it returns [`None`]. Otherwise, blocks until
+    /// an item is available and returns it wrapped in [`Some`].
+    ///
+    /// This is also available as a [`Stream`].
+    pub async fn recv(&self) -> Option<T> {
+        future::poll_fn(|cx| self.recv_one(cx)).await
+    }
+
+    fn recv_one(&self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut lanes = self.lanes.borrow_mut();
+        lanes.adopt(&self.hub);
+        if let Some(item) = lanes.pop() {
+            return Poll::Ready(Some(item));
+        }
+        if !lanes.open && lanes.lanes.is_empty() {
+            return Poll::Ready(None);
+        }
+        self.reactor
+            .upgrade()
+            .unwrap()
+            .add_shared_channel_waker(self.id, cx.waker());
+        Poll::Pending
+    }
+}
+
+impl<T: Send + Sized> Stream for ConnectedMpscReceiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.recv_one(cx)
+    }
+}
+
+impl<T: Send + Sized> Drop for SharedMpscSender<T> {
+    fn drop(&mut self) {
+        if let Some(hub) = self.hub.take() {
+            hub.release();
+        }
+    }
+}
+
+impl<T: Send + Sized> Drop for SharedMpscReceiver<T> {
+    fn drop(&mut self) {
+        if let Some(hub) = self.hub.take() {
+            hub.close();
+        }
+    }
+}
+
+impl<T: Send + Sized> Drop for ConnectedMpscReceiver<T> {
+    fn drop(&mut self) {
+        self.hub.close();
+        for lane in self.lanes.borrow().lanes.iter() {
+            if !lane.buffer.disconnect() {
+                lane.notifier.notify(false);
+            }
+        }
+        if let Some(r) = self.reactor.upgrade() {
+            r.unregister_shared_channel(self.id);
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -1130,6 +1766,175 @@ mod test {
         ex1.join().unwrap();
         ex2.join().unwrap();
     }
+
+    #[test]
+    fn batches_cross_executors() {
+        let (sender, receiver) = new_bounded(64);
+
+        let ex1 = LocalExecutorBuilder::default()
+            .spawn(move || async move {
+                let sender = sender.connect().await;
+                sender.send_batch(0..1000).await.unwrap();
+                let stats = sender.notify_stats();
+                assert_eq!(stats.messages(), 1000);
+                // The channel only fits 64 at a time
+                assert!(stats.publishes() >= 1000 / 64);
+                assert!(stats.notifications() <= stats.publishes());
+            })
+            .unwrap();
+
+        let ex2 = LocalExecutorBuilder::default()
+            .spawn(move || async move {
+                let receiver = receiver.connect().await;
+                let mut received = Vec::new();
+                loop {
+                    let batch = receiver.recv_batch(100).await;
+                    if batch.is_empty() {
+                        break;
+                    }
+                    assert!(batch.len() <= 64);
+                    received.extend(batch);
+                }
+                assert!(received.into_iter().eq(0..1000));
+                assert_eq!(receiver.notify_stats().messages(), 1000);
+            })
+            .unwrap();
+
+        ex1.join().unwrap();
+        ex2.join().unwrap();
+    }
+
+    #[test]
+    fn send_batch_hands_back_what_was_not_sent() {
+        let (sender, receiver) = new_bounded(4);
+
+        let ex1 = LocalExecutorBuilder::default()
+            .spawn(move || async move {
+                let sender = sender.connect().await;
+                match sender.send_batch(0..10).await {
+                    Err(GlommioError::Closed(ResourceType::Channel(rest))) => {
+                        assert_eq!(rest, (4..10).collect::<Vec<_>>());
+                    }
+                    other => panic!("unexpected {other:?}"),
+                }
+            })
+            .unwrap();
+
+        let ex2 = LocalExecutorBuilder::default()
+            .spawn(move || async move {
+                let receiver = receiver.connect().await;
+                assert_eq!(receiver.recv_batch(0).await, Vec::<usize>::new());
+                Timer::new(Duration::from_millis(100)).await;
+            })
+            .unwrap();
+
+        ex1.join().unwrap();
+        ex2.join().unwrap();
+    }
+
+    #[test]
+    fn mpsc_takes_from_senders_in_turn() {
+        let (sender, receiver) = new_mpsc_bounded(4);
+        let (filled, lanes_filled) = std::sync::mpsc::channel();
+
+        let producers: Vec<_> = (0..3)
+            .map(|p| {
+                let sender = sender.clone();
+                let filled = filled.clone();
+                LocalExecutorBuilder::default()
+                    .spawn(move || async move {
+                        let sender = sender.connect().await;
+                        for x in 0..4 {
+                            sender.try_send((p, x)).unwrap();
+                        }
+                        filled.send(()).unwrap();
+                    })
+                    .unwrap()
+            })
+            .collect();
+        drop(sender);
+
+        let consumer = LocalExecutorBuilder::default()
+            .spawn(move || async move {
+                let receiver = receiver.connect().await;
+                // Every producer fills its lane before we start draining
+                for _ in 0..3 {
+                    lanes_filled.recv().unwrap();
+                }
+                let received: Vec<(usize, usize)> = receiver.collect().await;
+                assert_eq!(received.len(), 12);
+                for round in received.chunks(3) {
+                    let mut producers: Vec<_> = round.iter().map(|(p, _)| *p).collect();
+                    producers.sort_unstable();
+                    assert_eq!(producers, vec![0, 1, 2]);
+                }
+            })
+            .unwrap();
+
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        consumer.join().unwrap();
+    }
+
+    #[test]
+    fn mpsc_senders_see_the_receiver_go() {
+        let (sender, receiver) = new_mpsc_bounded(1);
+        let late = sender.clone();
+
+        let ex1 = LocalExecutorBuilder::default()
+            .spawn(move || async move {
+                let sender = sender.connect().await;
+                sender.send(1).await.unwrap();
+                while sender.send(2).await.is_ok() {}
+            })
+            .unwrap();
+
+        let ex2 = LocalExecutorBuilder::default()
+            .spawn(move || async move {
+                let receiver = receiver.connect().await;
+                assert_eq!(receiver.recv().await, Some(1));
+            })
+            .unwrap();
+
+        ex1.join().unwrap();
+        ex2.join().unwrap();
+
+        LocalExecutorBuilder::default()
+            .spawn(move || async move {
+                let late = late.connect().await;
+                assert!(matches!(
+                    late.try_send(3),
+                    Err(GlommioError::Closed(ResourceType::Channel(3)))
+                ));
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn mpsc_senders_never_connect() {
+        let (sender, receiver) = new_mpsc_bounded::<usize>(1);
+        let senders = vec![sender.clone(), sender];
+
+        let ex1 = LocalExecutorBuilder::default()
+            .spawn(move || async move {
+                Timer::new(Duration::from_millis(10)).await;
+                drop(senders);
+            })
+            .unwrap();
+
+        let ex2 = LocalExecutorBuilder::default()
+            .spawn(move || async move {
+                let receiver = receiver.connect().await;
+                assert!(receiver.recv().await.is_none());
+            })
+            .unwrap();
+
+        ex1.join().unwrap();
+        ex2.join().unwrap();
+    }
 }
 
 #[cfg(test)]
@@ -234,6 +234,96 @@ impl<T> Buffer<T> {
         None
     }
 
+    /// Returns how many slots from `tail` on are free.
+    ///
+    /// The consumer frees slots in order, so a free slot means every slot
+    /// before it is free as well, and a binary search over the slots finds
+    /// the end of the free run.
+    fn free_run(&self, tail: usize) -> usize {
+        // Slots up to the limit are already known to be free
+        let mut free = self.pcache.limit.load(Ordering::Relaxed).wrapping_sub(tail);
+        let mut unknown = self.capacity;
+        while free < unknown {
+            let mid = free + (unknown - free).div_ceil(2);
+            let slot = &self.buffer_storage[tail.wrapping_add(mid - 1) & self.mask];
+            if slot.has_value.load(Ordering::Acquire) {
+                unknown = mid - 1;
+            } else {
+                free = mid;
+            }
+        }
+        free
+    }
+
+    /// Attempt to push as many values from `items` as fit onto the buffer.
+    ///
+    /// The values become visible to the consumer all at once, and whatever
+    /// did not fit is left in the iterator. Returns how many were pushed.
+    fn try_push_batch<I: Iterator<Item = T>>(&self, items: &mut I) -> usize {
+        if self.consumer_disconnected() {
+            return 0;
+        }
+
+        let tail = self.pcache.tail.load(Ordering::Relaxed);
+        let free = self.free_run(tail);
+        let mut pushed = 0;
+        while pushed < free {
+            let Some(v) = items.next() else {
+                break;
+            };
+            let slot = &self.buffer_storage[tail.wrapping_add(pushed) & self.mask];
+            unsafe {
+                slot.value.get().write(MaybeUninit::new(v));
+            }
+            pushed += 1;
+        }
+
+        // Back to front, so the consumer sees nothing until it can see it all
+        for idx in (0..pushed).rev() {
+            let slot = &self.buffer_storage[tail.wrapping_add(idx) & self.mask];
+            slot.has_value.store(true, Ordering::Release);
+        }
+        self.pcache
+            .limit
+            .store(tail.wrapping_add(free), Ordering::Relaxed);
+        self.pcache
+            .tail
+            .store(tail.wrapping_add(pushed), Ordering::Relaxed);
+        pushed
+    }
+
+    /// Attempt to pop up to `max` values off the buffer into `out`.
+    ///
+    /// Returns how many were popped, which is zero if the buffer was empty.
+    fn try_pop_batch(&self, max: usize, out: &mut Vec<T>) -> usize {
+        // Popped slots are only freed at the end, so going around the ring
+        // would read them again
+        let max = max.min(self.capacity);
+        out.reserve(max);
+
+        let head = self.ccache.head.load(Ordering::Relaxed);
+        let mut popped = 0;
+        while popped < max {
+            let slot = &self.buffer_storage[head.wrapping_add(popped) & self.mask];
+            if !slot.has_value.load(Ordering::Acquire) {
+                break;
+            }
+            out.push(unsafe { slot.value.get().read().assume_init() });
+            popped += 1;
+        }
+
+        // Front to back, as the producer takes a free slot to mean that every
+        // slot before it is free as well
+        for idx in 0..popped {
+            let slot = &self.buffer_storage[head.wrapping_add(idx) & self.mask];
+            slot.has_value.store(false, Ordering::Release);
+        }
+        self.ccache
+            .head
+            .store(head.wrapping_add(popped), Ordering::Relaxed);
+        popped
+    }
+
     /// Disconnects the consumer, and returns whether it was already
     /// disconnected
     pub(crate) fn disconnect_consumer(&self) -> bool {
@@ -377,6 +467,15 @@ impl<T> Producer<T> {
         (*self.buffer).try_push(v)
     }
 
+    /// Attempt to push as many values from `items` as fit onto the queue.
+    ///
+    /// This method does not block. The values pushed become visible to the
+    /// consumer at once, and those that did not fit are left in `items`.
+    /// Returns how many values were pushed.
+    pub fn try_push_batch<I: Iterator<Item = T>>(&self, items: &mut I) -> usize {
+        (*self.buffer).try_push_batch(items)
+    }
+
     /// Disconnects the producer, signaling to the consumer that no new values
     /// are going to be produced.
     ///
@@ -444,6 +543,15 @@ impl<T> Consumer<T> {
     pub fn try_pop(&self) -> Option<T> {
         (*self.buffer).try_pop()
     }
+
+    /// Attempt to pop up to `max` values off the queue, appending them to
+    /// `out`.
+    ///
+    /// This method does not block. Returns how many values were popped, which
+    /// is zero if the queue was empty.
+    pub fn try_pop_batch(&self, max: usize, out: &mut Vec<T>) -> usize {
+        (*self.buffer).try_pop_batch(max, out)
+    }
 }
 
 #[cfg(test)]
@@ -527,6 +635,54 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_batches() {
+        let (p, c) = super::inner_make(10, usize::MAX - 5);
+
+        let mut items = 0..20;
+        assert_eq!(p.try_push_batch(&mut items), 16);
+        assert_eq!(items.next(), Some(16));
+        assert_eq!(p.try_push_batch(&mut items), 0);
+
+        let mut out = Vec::new();
+        assert_eq!(c.try_pop_batch(10, &mut out), 10);
+        assert_eq!(out, (0..10).collect::<Vec<_>>());
+
+        // Room for the popped slots only, and they wrap around
+        assert_eq!(p.try_push_batch(&mut items), 3);
+        assert!(p.try_push(100).is_none());
+        assert_eq!(p.try_push_batch(&mut (200..300)), 6);
+        assert!(p.try_push(300).is_some());
+
+        out.clear();
+        assert_eq!(c.try_pop_batch(100, &mut out), 16);
+        let expected: Vec<_> = (10..16)
+            .chain(17..20)
+            .chain([100])
+            .chain(200..206)
+            .collect();
+        assert_eq!(out, expected);
+        assert_eq!(c.try_pop_batch(100, &mut out), 0);
+    }
+
+    #[test]
+    fn test_threaded_batches() {
+        let (p, c) = super::make(500);
+
+        thread::spawn(move || {
+            let mut items = 0..100000;
+            while !items.is_empty() {
+                p.try_push_batch(&mut (&mut items).take(100));
+            }
+        });
+
+        let mut out = Vec::new();
+        while out.len() < 100000 {
+            c.try_pop_batch(300, &mut out);
+        }
+        assert!(out.into_iter().eq(0..100000));
+    }
+
     fn assert_send_sync<T: Send + Sync>() {}
     #[test]
     fn producer_consumer_are_send() {
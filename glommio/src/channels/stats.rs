@@ -0,0 +1,343 @@
+//! Opt-in instrumentation for channels.
+//!
+//! A channel records nothing until one of its ends is asked to with
+//! `instrument`. From then on, every send and receive updates a
+//! [`ChannelRecorder`] registered with the executor that asked, where
+//! [`ExecutorProxy::channel_stats`](crate::ExecutorProxy::channel_stats)
+//! finds it for as long as the channel lives.
+
+use std::{
+    cell::RefCell,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Weak,
+    },
+    time::{Duration, Instant},
+};
+
+thread_local! {
+    static RECORDERS: RefCell<Vec<Weak<ChannelRecorder>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// What an instrumented channel went through so far.
+///
+/// Channels record nothing until the `instrument` method of one of their ends
+/// is called with a name. From then on, the `stats` method of either end
+/// returns these, and
+/// [`ExecutorProxy::channel_stats`](crate::ExecutorProxy::channel_stats)
+/// collects them on the executor that instrumented the channel, for as long
+/// as it lives. Instrumenting a channel again starts over under the new name.
+///
+/// The two ends of a [`shared_channel`](super::shared_channel) live on
+/// different executors and are instrumented separately, so each only knows
+/// its own half: the sender never receives, and the receiver never sends.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChannelStats {
+    name: String,
+    depth: usize,
+    high_watermark: usize,
+    sent: u64,
+    received: u64,
+    sender_blocked: Duration,
+    receiver_idle: Duration,
+}
+
+impl ChannelStats {
+    /// The name the channel was instrumented under.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// How many messages were queued in the channel as of its last send or
+    /// receive.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// The deepest the channel has been.
+    pub fn high_watermark(&self) -> usize {
+        self.high_watermark
+    }
+
+    /// How many messages were sent.
+    pub fn sent(&self) -> u64 {
+        self.sent
+    }
+
+    /// How many messages were received.
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// How long senders spent waiting for room in the channel.
+    pub fn sender_blocked_time(&self) -> Duration {
+        self.sender_blocked
+    }
+
+    /// How long receivers spent waiting for something to receive.
+    pub fn receiver_idle_time(&self) -> Duration {
+        self.receiver_idle
+    }
+}
+
+/// Accumulates the time spent in intervals that may be opened and closed from
+/// more than one place; only the first opening and the first closing count.
+#[derive(Debug, Default)]
+struct Span {
+    /// When the open interval began, in nanoseconds after the epoch of the
+    /// recorder plus one, or zero if there is none
+    since: AtomicU64,
+    total: AtomicU64,
+}
+
+/// Where an instrumented channel records what it goes through.
+///
+/// Made of atomics so that channels whose state crosses executors can keep
+/// one in it.
+#[derive(Debug)]
+pub(crate) struct ChannelRecorder {
+    name: String,
+    epoch: Instant,
+    depth: AtomicUsize,
+    high_watermark: AtomicUsize,
+    sent: AtomicU64,
+    received: AtomicU64,
+    sender_blocked: Span,
+    receiver_idle: Span,
+}
+
+impl ChannelRecorder {
+    /// Creates a recorder and registers it with the current executor.
+    pub(crate) fn register(name: impl Into<String>) -> Arc<Self> {
+        let recorder = Arc::new(ChannelRecorder {
+            name: name.into(),
+            epoch: Instant::now(),
+            depth: AtomicUsize::new(0),
+            high_watermark: AtomicUsize::new(0),
+            sent: AtomicU64::new(0),
+            received: AtomicU64::new(0),
+            sender_blocked: Span::default(),
+            receiver_idle: Span::default(),
+        });
+        RECORDERS.with(|recorders| {
+            let mut recorders = recorders.borrow_mut();
+            recorders.retain(|r| r.strong_count() > 0);
+            recorders.push(Arc::downgrade(&recorder));
+        });
+        recorder
+    }
+
+    pub(crate) fn sent(&self, messages: usize, depth: usize) {
+        self.sent.fetch_add(messages as u64, Ordering::Relaxed);
+        self.observe(depth);
+        self.close(&self.sender_blocked);
+    }
+
+    pub(crate) fn received(&self, messages: usize, depth: usize) {
+        self.received.fetch_add(messages as u64, Ordering::Relaxed);
+        self.observe(depth);
+        self.close(&self.receiver_idle);
+    }
+
+    pub(crate) fn sender_blocked(&self) {
+        self.open(&self.sender_blocked);
+    }
+
+    pub(crate) fn sender_unblocked(&self) {
+        self.close(&self.sender_blocked);
+    }
+
+    pub(crate) fn receiver_idle(&self) {
+        self.open(&self.receiver_idle);
+    }
+
+    pub(crate) fn receiver_woken(&self) {
+        self.close(&self.receiver_idle);
+    }
+
+    pub(crate) fn stats(&self) -> ChannelStats {
+        ChannelStats {
+            name: self.name.clone(),
+            depth: self.depth.load(Ordering::Relaxed),
+            high_watermark: self.high_watermark.load(Ordering::Relaxed),
+            sent: self.sent.load(Ordering::Relaxed),
+            received: self.received.load(Ordering::Relaxed),
+            sender_blocked: self.elapsed(&self.sender_blocked),
+            receiver_idle: self.elapsed(&self.receiver_idle),
+        }
+    }
+
+    fn observe(&self, depth: usize) {
+        self.depth.store(depth, Ordering::Relaxed);
+        self.high_watermark.fetch_max(depth, Ordering::Relaxed);
+    }
+
+    fn now(&self) -> u64 {
+        self.epoch.elapsed().as_nanos() as u64 + 1
+    }
+
+    fn open(&self, span: &Span) {
+        let _ = span
+            .since
+            .compare_exchange(0, self.now(), Ordering::Relaxed, Ordering::Relaxed);
+    }
+
+    fn close(&self, span: &Span) {
+        let since = span.since.swap(0, Ordering::Relaxed);
+        if since != 0 {
+            span.total
+                .fetch_add(self.now().saturating_sub(since), Ordering::Relaxed);
+        }
+    }
+
+    /// The time accumulated so far, including an interval still open.
+    fn elapsed(&self, span: &Span) -> Duration {
+        let since = span.since.load(Ordering::Relaxed);
+        let open = if since != 0 {
+            self.now().saturating_sub(since)
+        } else {
+            0
+        };
+        Duration::from_nanos(span.total.load(Ordering::Relaxed) + open)
+    }
+}
+
+/// Extends `output` with the stats of every live channel instrumented on the
+/// current executor.
+pub(crate) fn all_channel_stats<V: Extend<ChannelStats>>(mut output: V) -> V {
+    RECORDERS.with(|recorders| {
+        let mut recorders = recorders.borrow_mut();
+        recorders.retain(|r| r.strong_count() > 0);
+        output.extend(
+            recorders
+                .iter()
+                .filter_map(Weak::upgrade)
+                .map(|r| r.stats()),
+        );
+    });
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        channels::{broadcast, local_channel, shared_channel, watch},
+        timer::sleep,
+        LocalExecutor, LocalExecutorBuilder,
+    };
+    use futures_lite::future;
+
+    #[test]
+    fn local_channel_records_traffic_and_blocked_time() {
+        LocalExecutor::default().run(async {
+            let (sender, receiver) = local_channel::new_bounded(1);
+            assert!(sender.stats().is_none());
+            sender.instrument("pipe");
+
+            let consumer = crate::spawn_local(async move {
+                sleep(Duration::from_millis(20)).await;
+                let mut got = Vec::new();
+                while let Some(x) = receiver.recv().await {
+                    got.push(x);
+                }
+                (got, receiver.stats().unwrap())
+            });
+
+            // The second send waits for the consumer to wake up
+            sender.send(1).await.unwrap();
+            sender.send(2).await.unwrap();
+            let stats = sender.stats().unwrap();
+            assert!(stats.sender_blocked_time() >= Duration::from_millis(10));
+            drop(sender);
+
+            let (got, stats) = consumer.await;
+            assert_eq!(got, vec![1, 2]);
+            assert_eq!(stats.name(), "pipe");
+            assert_eq!((stats.sent(), stats.received()), (2, 2));
+            assert_eq!((stats.depth(), stats.high_watermark()), (0, 1));
+        });
+    }
+
+    #[test]
+    fn abandoned_waits_stop_the_clock() {
+        LocalExecutor::default().run(async {
+            let (sender, receiver) = local_channel::new_bounded(1);
+            sender.instrument("pipe");
+
+            sender.try_send(1).unwrap();
+            assert!(future::poll_once(sender.send(2)).await.is_none());
+            let blocked = sender.stats().unwrap().sender_blocked_time();
+            sleep(Duration::from_millis(20)).await;
+            assert_eq!(sender.stats().unwrap().sender_blocked_time(), blocked);
+
+            assert_eq!(receiver.recv().await, Some(1));
+            assert!(future::poll_once(receiver.recv()).await.is_none());
+            let idle = receiver.stats().unwrap().receiver_idle_time();
+            sleep(Duration::from_millis(20)).await;
+            assert_eq!(receiver.stats().unwrap().receiver_idle_time(), idle);
+        });
+    }
+
+    #[test]
+    fn the_executor_reports_the_channels_still_alive() {
+        LocalExecutor::default().run(async {
+            let (sender, receiver) = local_channel::new_unbounded::<u8>();
+            let (bsender, mut breceiver) = broadcast::broadcast(4);
+            let (wsender, mut wreceiver) = watch::watch(0);
+            sender.instrument("local");
+            bsender.instrument("broadcast");
+            wsender.instrument("watch");
+
+            let mut other = bsender.subscribe();
+            bsender.send(1).unwrap();
+            bsender.send(2).unwrap();
+            breceiver.recv().await.unwrap();
+            other.recv().await.unwrap();
+            wsender.send(1).unwrap();
+            wreceiver.changed().await.unwrap();
+            drop((sender, receiver));
+
+            let mut stats = crate::executor().channel_stats(Vec::new());
+            stats.sort_by(|a, b| a.name().cmp(b.name()));
+            let names: Vec<_> = stats.iter().map(|s| s.name()).collect();
+            assert_eq!(names, ["broadcast", "watch"]);
+            assert_eq!((stats[0].sent(), stats[0].received()), (2, 2));
+            assert_eq!(stats[0].high_watermark(), 2);
+            assert_eq!((stats[1].sent(), stats[1].received()), (1, 1));
+            assert_eq!(stats[1].depth(), 0);
+        });
+    }
+
+    #[test]
+    fn each_end_of_a_shared_channel_records_its_half() {
+        let (sender, receiver) = shared_channel::new_bounded(4);
+
+        let producer = LocalExecutorBuilder::default()
+            .spawn(move || async move {
+                let sender = sender.connect().await;
+                sender.instrument("out");
+                for x in 0..3 {
+                    sender.send(x).await.unwrap();
+                }
+                let stats = crate::executor().channel_stats(Vec::new());
+                assert_eq!(stats.len(), 1);
+                assert_eq!((stats[0].sent(), stats[0].received()), (3, 0));
+            })
+            .unwrap();
+
+        let consumer = LocalExecutorBuilder::default()
+            .spawn(move || async move {
+                let receiver = receiver.connect().await;
+                receiver.instrument("in");
+                while receiver.recv().await.is_some() {}
+                let stats = receiver.stats().unwrap();
+                assert_eq!(stats.name(), "in");
+                assert_eq!((stats.sent(), stats.received()), (0, 3));
+            })
+            .unwrap();
+
+        producer.join().unwrap();
+        consumer.join().unwrap();
+    }
+}
@@ -0,0 +1,418 @@
+//! Polls a dynamic, keyed set of streams as one.
+
+use futures_lite::Stream;
+use std::{
+    borrow::Borrow,
+    fmt,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Multiplexes a dynamic set of streams, each under a key, into one stream of
+/// `(key, item)` pairs.
+///
+/// Where [`select!`](crate::select) waits on a fixed set of branches, a
+/// `StreamMap` takes streams in and out as the program runs: a mesh consumer
+/// can hold the receiver of every peer in one map and keep a single task
+/// reading from all of them. Anything that is a [`Stream`] and [`Unpin`] goes
+/// in, such as a [`ConnectedReceiver`], a [`broadcast::Receiver`] or a
+/// [`LocalReceiver`] turned into a stream with [`into_stream`]. Keys are
+/// cloned into every item, and have to be [`Unpin`] as well for the map to be
+/// a stream.
+///
+/// Polling is fair: each poll starts with the stream after the one that
+/// yielded last, so a busy stream cannot starve the others. A stream that
+/// ends is removed from the map, and the map itself ends once it is empty.
+///
+/// The map is only borrowed while it is being polled, so the body of a loop
+/// reading from it is free to [`insert`](Self::insert) and
+/// [`remove`](Self::remove) streams.
+///
+/// # Examples
+/// ```
+/// use futures_lite::StreamExt;
+/// use glommio::{
+///     channels::{local_channel, StreamMap},
+///     LocalExecutor,
+/// };
+///
+/// let ex = LocalExecutor::default();
+/// ex.run(async move {
+///     let (ping, pings) = local_channel::new_unbounded();
+///     let (pong, pongs) = local_channel::new_unbounded();
+///     let mut map = StreamMap::new();
+///     map.insert("ping", pings.into_stream());
+///     map.insert("pong", pongs.into_stream());
+///
+///     ping.try_send(1).unwrap();
+///     pong.try_send(2).unwrap();
+///     drop((ping, pong));
+///
+///     let mut got = map.collect::<Vec<_>>().await;
+///     got.sort();
+///     assert_eq!(got, vec![("ping", 1), ("pong", 2)]);
+/// });
+/// ```
+///
+/// [`ConnectedReceiver`]: super::shared_channel::ConnectedReceiver
+/// [`broadcast::Receiver`]: super::broadcast::Receiver
+/// [`LocalReceiver`]: super::local_channel::LocalReceiver
+/// [`into_stream`]: super::local_channel::LocalReceiver::into_stream
+pub struct StreamMap<K, S> {
+    entries: Vec<(K, S)>,
+    /// Where the next poll starts
+    next: usize,
+}
+
+impl<K, S> StreamMap<K, S> {
+    /// Creates an empty map.
+    pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    /// Creates an empty map with room for `capacity` streams.
+    pub fn with_capacity(capacity: usize) -> Self {
+        StreamMap {
+            entries: Vec::with_capacity(capacity),
+            next: 0,
+        }
+    }
+
+    /// How many streams are in the map.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether the map has no streams.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterates over the keys of the map, in the order they were inserted.
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.entries.iter().map(|(k, _)| k)
+    }
+
+    /// Iterates over the streams of the map, in the order they were inserted.
+    pub fn values(&self) -> impl Iterator<Item = &S> {
+        self.entries.iter().map(|(_, s)| s)
+    }
+
+    /// Iterates over the streams of the map with their keys, in the order
+    /// they were inserted.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &S)> {
+        self.entries.iter().map(|(k, s)| (k, s))
+    }
+
+    /// Iterates mutably over the streams of the map with their keys, in the
+    /// order they were inserted.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut S)> {
+        self.entries.iter_mut().map(|(k, s)| (&*k, s))
+    }
+
+    /// Removes every stream from the map.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+impl<K: Eq, S> StreamMap<K, S> {
+    /// Inserts `stream` under `key`, returning the stream it replaces, if
+    /// any.
+    pub fn insert(&mut self, key: K, stream: S) -> Option<S> {
+        match self.position(&key) {
+            Some(idx) => Some(std::mem::replace(&mut self.entries[idx].1, stream)),
+            None => {
+                self.entries.push((key, stream));
+                None
+            }
+        }
+    }
+
+    /// Removes the stream under `key` and returns it, if there is one.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<S>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        self.position(key).map(|idx| self.entries.remove(idx).1)
+    }
+
+    /// Returns whether there is a stream under `key`.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        self.position(key).is_some()
+    }
+
+    /// Returns the stream under `key`, if there is one.
+    pub fn get<Q>(&self, key: &Q) -> Option<&S>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        self.position(key).map(|idx| &self.entries[idx].1)
+    }
+
+    /// Returns the stream under `key` mutably, if there is one.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut S>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        self.position(key).map(|idx| &mut self.entries[idx].1)
+    }
+
+    fn position<Q>(&self, key: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        self.entries.iter().position(|(k, _)| k.borrow() == key)
+    }
+}
+
+impl<K: Clone, S: Stream + Unpin> StreamMap<K, S> {
+    /// Polls every stream once, starting after the one that yielded last, and
+    /// returns the first item found.
+    ///
+    /// Streams are polled even when an earlier one turns out to be done, so
+    /// that each of them has registered the waker by the time this returns
+    /// [`Poll::Pending`].
+    fn poll_entries(&mut self, cx: &mut Context<'_>) -> Poll<Option<(K, S::Item)>> {
+        let nr_entries = self.entries.len();
+        let mut ended = Vec::new();
+        let mut found = None;
+        for i in 0..nr_entries {
+            let idx = (self.next + i) % nr_entries;
+            match Pin::new(&mut self.entries[idx].1).poll_next(cx) {
+                Poll::Ready(Some(item)) => {
+                    self.next = idx + 1;
+                    found = Some((self.entries[idx].0.clone(), item));
+                    break;
+                }
+                Poll::Ready(None) => ended.push(idx),
+                Poll::Pending => {}
+            }
+        }
+
+        // From the back, so that the indices still to go stay put
+        ended.sort_unstable();
+        for idx in ended.into_iter().rev() {
+            self.entries.remove(idx);
+            if idx < self.next {
+                self.next -= 1;
+            }
+        }
+
+        match found {
+            Some(found) => Poll::Ready(Some(found)),
+            None if self.entries.is_empty() => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<K: Clone + Unpin, S: Stream + Unpin> Stream for StreamMap<K, S> {
+    type Item = (K, S::Item);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_entries(cx)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let mut upper = Some(0);
+        for (_, stream) in &self.entries {
+            upper = upper.zip(stream.size_hint().1).map(|(a, b)| a + b);
+        }
+        (0, upper)
+    }
+}
+
+impl<K, S> Default for StreamMap<K, S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Eq, S> FromIterator<(K, S)> for StreamMap<K, S> {
+    fn from_iter<I: IntoIterator<Item = (K, S)>>(iter: I) -> Self {
+        let mut map = StreamMap::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K: Eq, S> Extend<(K, S)> for StreamMap<K, S> {
+    fn extend<I: IntoIterator<Item = (K, S)>>(&mut self, iter: I) {
+        for (key, stream) in iter {
+            self.insert(key, stream);
+        }
+    }
+}
+
+impl<K: fmt::Debug, S> fmt::Debug for StreamMap<K, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamMap")
+            .field(
+                "keys",
+                &self.entries.iter().map(|(k, _)| k).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        channels::{local_channel, shared_channel},
+        LocalExecutor, LocalExecutorBuilder,
+    };
+    use futures_lite::{future, stream, StreamExt};
+
+    #[test]
+    fn busy_streams_take_turns() {
+        LocalExecutor::default().run(async {
+            let mut map = StreamMap::new();
+            map.insert('a', stream::repeat(1));
+            map.insert('b', stream::repeat(2));
+            map.insert('c', stream::repeat(3));
+
+            let got: Vec<_> = map.take(6).map(|(k, _)| k).collect().await;
+            assert_eq!(got, ['a', 'b', 'c', 'a', 'b', 'c']);
+        });
+    }
+
+    #[test]
+    fn streams_come_and_go_while_reading() {
+        LocalExecutor::default().run(async {
+            let (first, first_rx) = local_channel::new_unbounded();
+            let (second, second_rx) = local_channel::new_unbounded();
+            let mut map = StreamMap::new();
+            map.insert(1, first_rx.into_stream());
+            first.try_send("hello").unwrap();
+
+            let mut handover = Some((second, second_rx));
+            let mut got = Vec::new();
+            while let Some((key, item)) = map.next().await {
+                got.push((key, item));
+                if let Some((second, rx)) = handover.take() {
+                    // Hand over from the first channel to the second, which
+                    // is the only one left to end the loop
+                    assert!(map.remove(&1).is_some());
+                    map.insert(2, rx.into_stream());
+                    second.try_send("world").unwrap();
+                }
+            }
+            assert_eq!(got, vec![(1, "hello"), (2, "world")]);
+            assert!(!first.is_full());
+        });
+    }
+
+    #[test]
+    fn ended_streams_drop_out() {
+        LocalExecutor::default().run(async {
+            let (sender, receiver) = local_channel::new_unbounded::<u8>();
+            let mut map: StreamMap<_, _> = [
+                (0, stream::iter(vec![1, 2]).boxed_local()),
+                (1, receiver.into_stream().boxed_local()),
+            ]
+            .into_iter()
+            .collect();
+
+            assert_eq!(map.next().await, Some((0, 1)));
+            assert_eq!(map.next().await, Some((0, 2)));
+            sender.try_send(3).unwrap();
+            assert_eq!(map.next().await, Some((1, 3)));
+            // The first stream is found to have ended once it is polled again
+            assert!(future::poll_once(map.next()).await.is_none());
+            assert_eq!(map.keys().collect::<Vec<_>>(), [&1]);
+
+            drop(sender);
+            assert_eq!(map.next().await, None);
+            assert!(map.is_empty());
+        });
+    }
+
+    #[test]
+    fn one_task_reads_from_every_peer() {
+        let (senders, receivers): (Vec<_>, Vec<_>) =
+            (0..3).map(|_| shared_channel::new_bounded(4)).unzip();
+
+        let producers: Vec<_> = senders
+            .into_iter()
+            .enumerate()
+            .map(|(peer, sender)| {
+                LocalExecutorBuilder::default()
+                    .spawn(move || async move {
+                        let sender = sender.connect().await;
+                        for x in 0..10 {
+                            sender.send(peer * 100 + x).await.unwrap();
+                        }
+                    })
+                    .unwrap()
+            })
+            .collect();
+
+        let consumer = LocalExecutorBuilder::default()
+            .spawn(move || async move {
+                let mut map = StreamMap::new();
+                for (peer, receiver) in receivers.into_iter().enumerate() {
+                    map.insert(peer, receiver.connect().await);
+                }
+
+                let mut last = [None; 3];
+                while let Some((peer, x)) = map.next().await {
+                    assert_eq!(x / 100, peer);
+                    assert!(last[peer] < Some(x));
+                    last[peer] = Some(x);
+                }
+                assert_eq!(last, [Some(9), Some(109), Some(209)]);
+            })
+            .unwrap();
+
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        consumer.join().unwrap();
+    }
+
+    #[test]
+    fn an_idle_peer_does_not_collect_wakers() {
+        let (idle, idle_rx) = shared_channel::new_bounded::<usize>(4);
+        let (busy, busy_rx) = shared_channel::new_bounded(4);
+        // A sender going away wakes everything, so both stay until we looked
+        let (done, wait_done) = std::sync::mpsc::channel();
+
+        let producer = LocalExecutorBuilder::default()
+            .spawn(move || async move {
+                let idle = idle.connect().await;
+                let busy = busy.connect().await;
+                for x in 0..200 {
+                    busy.send(x).await.unwrap();
+                }
+                wait_done.recv().unwrap();
+                drop((idle, busy));
+            })
+            .unwrap();
+
+        let consumer = LocalExecutorBuilder::default()
+            .spawn(move || async move {
+                let mut map = StreamMap::new();
+                map.insert("idle", idle_rx.connect().await);
+                map.insert("busy", busy_rx.connect().await);
+                for x in 0..200 {
+                    assert_eq!(map.next().await, Some(("busy", x)));
+                }
+                assert!(map.get("idle").unwrap().registered_wakers() <= 1);
+                done.send(()).unwrap();
+            })
+            .unwrap();
+
+        producer.join().unwrap();
+        consumer.join().unwrap();
+    }
+}
@@ -24,7 +24,10 @@
 //! ```
 
 use crate::{
-    channels::storage::{Local, Shared, Storage, StorageExt},
+    channels::{
+        stats::{ChannelRecorder, ChannelStats},
+        storage::{Local, Shared, Storage, StorageExt},
+    },
     error::ResourceType,
     wakers::WakerList,
     GlommioError,
@@ -36,6 +39,7 @@ use std::{
     future::Future,
     marker::PhantomData,
     pin::Pin,
+    sync::Arc,
     task::{Context, Poll},
 };
 
@@ -54,6 +58,7 @@ pub struct State<T> {
     wakers: WakerList,
     sender_gone: bool,
     receivers: usize,
+    recorder: Option<Arc<ChannelRecorder>>,
 }
 
 impl<T> State<T> {
@@ -64,8 +69,19 @@ impl<T> State<T> {
             wakers: WakerList::new(),
             sender_gone: false,
             receivers: 1,
+            recorder: None,
         }
     }
+
+    fn record(&self, f: impl FnOnce(&ChannelRecorder)) {
+        if let Some(recorder) = &self.recorder {
+            f(recorder);
+        }
+    }
+
+    fn stats(&self) -> Option<ChannelStats> {
+        self.recorder.as_ref().map(|recorder| recorder.stats())
+    }
 }
 
 /// Publishes new values. There is exactly one.
@@ -167,6 +183,7 @@ impl<T, S: Storage<State<T>>> Sender<T, S> {
             } else {
                 state.value = value;
                 state.version += 1;
+                state.record(|r| r.sent(1, 1));
                 Ok(())
             };
             (outcome, state.wakers.take())
@@ -187,6 +204,21 @@ impl<T, S: Storage<State<T>>> Sender<T, S> {
     pub fn is_closed(&self) -> bool {
         self.inner.with(|state| state.receivers == 0)
     }
+
+    /// Starts recording the [`ChannelStats`] of this channel under `name`.
+    ///
+    /// Every value seen by every receiver counts as received, and the depth
+    /// is one from a send until a receiver sees it.
+    pub fn instrument(&self, name: impl Into<String>) {
+        let recorder = ChannelRecorder::register(name);
+        self.inner.with(|state| state.recorder = Some(recorder));
+    }
+
+    /// Returns the [`ChannelStats`] of this channel, or [`None`] if it was not
+    /// [instrumented](Self::instrument).
+    pub fn stats(&self) -> Option<ChannelStats> {
+        self.inner.with(|state| state.stats())
+    }
 }
 
 impl<T: Clone, S: Storage<State<T>>> Sender<T, S> {
@@ -236,6 +268,12 @@ impl<T, S: Storage<State<T>>> Receiver<T, S> {
     pub fn with_current<R>(&self, f: impl FnOnce(&T) -> R) -> R {
         self.inner.with(|state| f(&state.value))
     }
+
+    /// Returns the [`ChannelStats`] of this channel, or [`None`] if it was not
+    /// [instrumented](Sender::instrument).
+    pub fn stats(&self) -> Option<ChannelStats> {
+        self.inner.with(|state| state.stats())
+    }
 }
 
 impl<T: Clone, S: Storage<State<T>>> Receiver<T, S> {
@@ -286,13 +324,16 @@ impl<T: Clone, S: Storage<State<T>>> Stream for Receiver<T, S> {
         this.inner.with(|state| {
             if state.version > *seen {
                 *seen = state.version;
+                state.record(|r| r.received(1, 0));
                 return Poll::Ready(Some(state.value.clone()));
             }
 
             if state.sender_gone {
+                state.record(ChannelRecorder::receiver_woken);
                 return Poll::Ready(None);
             }
 
+            state.record(ChannelRecorder::receiver_idle);
             state.wakers.push(cx.waker().clone());
             Poll::Pending
         })
@@ -317,19 +358,31 @@ impl<T, S: Storage<State<T>>> Future for Changed<'_, T, S> {
             // sent is still worth delivering.
             if state.version > *seen {
                 *seen = state.version;
+                state.record(|r| r.received(1, 0));
                 return Poll::Ready(Ok(()));
             }
 
             if state.sender_gone {
+                state.record(ChannelRecorder::receiver_woken);
                 return Poll::Ready(Err(GlommioError::Closed(ResourceType::Channel(()))));
             }
 
+            state.record(ChannelRecorder::receiver_idle);
             state.wakers.push(cx.waker().clone());
             Poll::Pending
         })
     }
 }
 
+impl<T, S: Storage<State<T>>> Drop for Changed<'_, T, S> {
+    /// Stops the idle timer a pending wait leaves open when abandoned.
+    fn drop(&mut self) {
+        self.receiver
+            .inner
+            .with(|state| state.record(ChannelRecorder::receiver_woken));
+    }
+}
+
 impl<T, S: Storage<State<T>> + fmt::Debug> fmt::Debug for Sender<T, S> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Sender")
@@ -0,0 +1,533 @@
+//! Runs [hyper] on a Glommio executor.
+//!
+//! hyper does not depend on a runtime: it asks for one through the traits in
+//! [`hyper::rt`]. This module implements them:
+//!
+//! * [`HyperExecutor`] spawns the tasks hyper needs, such as HTTP/2 streams,
+//!   as local tasks;
+//! * [`TcpStream`] and [`UnixStream`] implement [`hyper::rt::Read`] and
+//!   [`hyper::rt::Write`], so they can be handed to hyper directly;
+//! * [`HyperTimer`] drives hyper's timeouts with [`Timer`].
+//!
+//! On top of those, [`serve_http1`] and [`serve_http2`] accept connections
+//! and serve them, and [`HyperConnector`] opens client connections.
+//!
+//! None of hyper's types need to be `Send` here: every connection lives and
+//! dies on the executor that accepted or opened it.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use glommio::{compat::hyper::serve_http1, net::TcpListener, LocalExecutor};
+//! use hyper::{body::Incoming, service::service_fn, Request, Response};
+//! use std::convert::Infallible;
+//!
+//! async fn hello(_: Request<Incoming>) -> Result<Response<String>, Infallible> {
+//!     Ok(Response::new("world".to_string()))
+//! }
+//!
+//! LocalExecutor::default().run(async {
+//!     let listener = TcpListener::bind("0.0.0.0:8000").unwrap();
+//!     serve_http1(listener, service_fn(hello), 1024).await.unwrap();
+//! });
+//! ```
+//!
+//! [hyper]: https://docs.rs/hyper
+//! [`Timer`]: crate::timer::Timer
+
+use crate::{
+    enclose,
+    net::{stream::RxBuf, TcpListener, TcpStream, UnixStream},
+    sync::Semaphore,
+    timer::Timer,
+    GlommioError, TaskQueueHandle,
+};
+use futures_lite::{ready, AsyncRead, AsyncWrite};
+use hyper::{
+    body::{Body, Incoming},
+    client::conn::{http1, http2},
+    rt::{ReadBufCursor, Sleep},
+    server::conn::{http1 as server_http1, http2 as server_http2},
+    service::HttpService,
+    Uri,
+};
+use std::{
+    error::Error,
+    future::Future,
+    io,
+    mem::ManuallyDrop,
+    pin::Pin,
+    ptr,
+    rc::Rc,
+    slice,
+    task::{Context, Poll},
+    thread::{self, ThreadId},
+    time::{Duration, Instant},
+};
+
+type Result<T> = crate::Result<T, ()>;
+type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Spawns the tasks hyper asks for on the current executor.
+///
+/// By default they go to the task queue that is running when the task is
+/// spawned. [`HyperExecutor::in_task_queue`] sends them to a specific one.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HyperExecutor {
+    task_queue: Option<TaskQueueHandle>,
+}
+
+impl HyperExecutor {
+    /// Creates an executor spawning tasks into the current task queue.
+    pub fn new() -> HyperExecutor {
+        HyperExecutor::default()
+    }
+
+    /// Creates an executor spawning tasks into the task queue identified by
+    /// `handle`.
+    ///
+    /// Spawning panics if that task queue has been removed.
+    pub fn in_task_queue(handle: TaskQueueHandle) -> HyperExecutor {
+        HyperExecutor {
+            task_queue: Some(handle),
+        }
+    }
+}
+
+impl<F> hyper::rt::Executor<F> for HyperExecutor
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    fn execute(&self, fut: F) {
+        let task = match self.task_queue {
+            Some(handle) => crate::spawn_local_into(fut, handle)
+                .expect("the task queue of a HyperExecutor was removed"),
+            None => crate::spawn_local(fut),
+        };
+        task.detach();
+    }
+}
+
+/// Drives hyper's timeouts with Glommio [`Timer`]s.
+///
+/// Set it on hyper's connection builders to enable the options that need a
+/// timer, such as HTTP/1 header read timeouts or HTTP/2 keep-alive.
+///
+/// [`Timer`]: crate::timer::Timer
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HyperTimer;
+
+impl hyper::rt::Timer for HyperTimer {
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Sleep>> {
+        Box::pin(HyperSleep::new(duration))
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Pin<Box<dyn Sleep>> {
+        Box::pin(HyperSleep::new(
+            deadline.saturating_duration_since(Instant::now()),
+        ))
+    }
+
+    fn reset(&self, sleep: &mut Pin<Box<dyn Sleep>>, new_deadline: Instant) {
+        match sleep.as_mut().downcast_mut_pin::<HyperSleep>() {
+            Some(mut sleep) => sleep
+                .timer
+                .reset(new_deadline.saturating_duration_since(Instant::now())),
+            None => *sleep = self.sleep_until(new_deadline),
+        }
+    }
+}
+
+// hyper requires its sleeps to be `Send` and `Sync`, but a `Timer` belongs to
+// the executor that created it. This one is only ever touched on that thread:
+// polling it anywhere else panics, and dropping it anywhere else leaks it.
+struct HyperSleep {
+    timer: ManuallyDrop<Timer>,
+    thread: ThreadId,
+}
+
+// SAFETY: the timer is only accessed on the thread that created it, see above.
+unsafe impl Send for HyperSleep {}
+// SAFETY: no method takes `&self`, so there is nothing to share.
+unsafe impl Sync for HyperSleep {}
+
+impl HyperSleep {
+    fn new(duration: Duration) -> HyperSleep {
+        HyperSleep {
+            timer: ManuallyDrop::new(Timer::new(duration)),
+            thread: thread::current().id(),
+        }
+    }
+}
+
+impl Future for HyperSleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        assert_eq!(
+            self.thread,
+            thread::current().id(),
+            "a hyper timer was polled outside of the executor that created it"
+        );
+        Pin::new(&mut *self.timer).poll(cx).map(drop)
+    }
+}
+
+impl Sleep for HyperSleep {}
+
+impl Drop for HyperSleep {
+    fn drop(&mut self) {
+        if self.thread == thread::current().id() {
+            // SAFETY: the timer is not used after this
+            unsafe { ManuallyDrop::drop(&mut self.timer) }
+        }
+    }
+}
+
+fn poll_read_cursor<S: AsyncRead>(
+    stream: Pin<&mut S>,
+    cx: &mut Context<'_>,
+    mut buf: ReadBufCursor<'_>,
+) -> Poll<io::Result<()>> {
+    // SAFETY: the unfilled part of the cursor is zeroed before it is read into
+    // as a byte slice, and only the bytes the read reports are marked filled.
+    unsafe {
+        let unfilled = buf.as_mut();
+        ptr::write_bytes(unfilled.as_mut_ptr(), 0, unfilled.len());
+        let unfilled = slice::from_raw_parts_mut(unfilled.as_mut_ptr().cast(), unfilled.len());
+        let read = ready!(stream.poll_read(cx, unfilled))?;
+        buf.advance(read);
+    }
+    Poll::Ready(Ok(()))
+}
+
+macro_rules! impl_hyper_io {
+    ($stream:ident) => {
+        impl<B: RxBuf + Unpin> hyper::rt::Read for $stream<B> {
+            fn poll_read(
+                self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: ReadBufCursor<'_>,
+            ) -> Poll<io::Result<()>> {
+                poll_read_cursor(self, cx, buf)
+            }
+        }
+
+        impl<B: RxBuf + Unpin> hyper::rt::Write for $stream<B> {
+            fn poll_write(
+                self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &[u8],
+            ) -> Poll<io::Result<usize>> {
+                AsyncWrite::poll_write(self, cx, buf)
+            }
+
+            fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                AsyncWrite::poll_flush(self, cx)
+            }
+
+            fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                AsyncWrite::poll_close(self, cx)
+            }
+        }
+    };
+}
+
+impl_hyper_io!(TcpStream);
+impl_hyper_io!(UnixStream);
+
+fn hyper_error(err: hyper::Error) -> GlommioError<()> {
+    io::Error::other(err).into()
+}
+
+/// Accepts connections on `listener` and serves HTTP/1 on them with
+/// `service`, serving up to `max_connections` at once.
+///
+/// Each connection is served by its own task, in the current task queue.
+/// Returns only if accepting fails.
+pub async fn serve_http1<S>(listener: TcpListener, service: S, max_connections: usize) -> Result<()>
+where
+    S: HttpService<Incoming> + Clone + 'static,
+    S::Error: Into<BoxError>,
+    S::ResBody: 'static,
+    <S::ResBody as Body>::Error: Into<BoxError>,
+{
+    let mut builder = server_http1::Builder::new();
+    builder.timer(HyperTimer);
+    serve(listener, max_connections, move |stream| {
+        builder.serve_connection(stream, service.clone())
+    })
+    .await
+}
+
+/// Accepts connections on `listener` and serves HTTP/2 on them with
+/// `service`, serving up to `max_connections` at once.
+///
+/// Each connection is served by its own task, in the current task queue, and
+/// so are the streams within it. Returns only if accepting fails.
+pub async fn serve_http2<S>(listener: TcpListener, service: S, max_connections: usize) -> Result<()>
+where
+    S: HttpService<Incoming> + Clone + 'static,
+    S::Error: Into<BoxError>,
+    S::ResBody: 'static,
+    <S::ResBody as Body>::Error: Into<BoxError>,
+{
+    let mut builder = server_http2::Builder::new(HyperExecutor::new());
+    builder.timer(HyperTimer);
+    serve(listener, max_connections, move |stream| {
+        builder.serve_connection(stream, service.clone())
+    })
+    .await
+}
+
+async fn serve<F, C>(listener: TcpListener, max_connections: usize, mut connection: F) -> Result<()>
+where
+    F: FnMut(TcpStream) -> C,
+    C: Future<Output = hyper::Result<()>> + 'static,
+{
+    let permits = Rc::new(Semaphore::new(max_connections as u64));
+    loop {
+        let permit = permits.acquire_static_permit(1).await?;
+        let stream = listener.accept().await?;
+        let peer = stream.peer_addr().ok();
+        let conn = connection(stream);
+        crate::spawn_local(enclose! { (peer) async move {
+            if let Err(err) = conn.await {
+                if !err.is_incomplete_message() {
+                    log::debug!("HTTP connection from {peer:?} failed: {err}");
+                }
+            }
+            drop(permit);
+        }})
+        .detach();
+    }
+}
+
+/// Opens client connections to the authority of HTTP URIs.
+///
+/// Only plain `http` URIs are supported. The connection is driven by a task
+/// spawned into the current task queue, which ends when the returned sender
+/// and all its clones are dropped.
+///
+/// # Examples
+///
+/// ```no_run
+/// use glommio::{compat::hyper::HyperConnector, LocalExecutor};
+/// use hyper::{Request, Uri};
+///
+/// LocalExecutor::default().run(async {
+///     let uri: Uri = "http://127.0.0.1:8000/hello".parse().unwrap();
+///     let mut sender = HyperConnector::new().http1::<String>(&uri).await.unwrap();
+///     let request = Request::builder()
+///         .uri(uri.path())
+///         .header(hyper::header::HOST, "127.0.0.1")
+///         .body(String::new())
+///         .unwrap();
+///     let response = sender.send_request(request).await.unwrap();
+///     println!("{}", response.status());
+/// });
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HyperConnector {
+    connect_timeout: Option<Duration>,
+}
+
+impl HyperConnector {
+    /// Creates a connector that waits for connections as long as the kernel
+    /// does.
+    pub fn new() -> HyperConnector {
+        HyperConnector::default()
+    }
+
+    /// Fails connection attempts that take longer than `timeout`.
+    #[must_use = "The connector must be used to be useful"]
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> HyperConnector {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Connects to the authority of `uri`, on port 80 if it has none.
+    pub async fn connect(&self, uri: &Uri) -> Result<TcpStream> {
+        match uri.scheme_str() {
+            None | Some("http") => {}
+            Some(scheme) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unsupported URI scheme: {scheme}"),
+                )
+                .into())
+            }
+        }
+        let host = uri
+            .host()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "URI has no host"))?;
+        // IPv6 hosts come bracketed, which `ToSocketAddrs` does not expect
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let addr = (host, uri.port_u16().unwrap_or(80));
+        let stream = match self.connect_timeout {
+            Some(timeout) => TcpStream::connect_timeout(addr, timeout).await?,
+            None => TcpStream::connect(addr).await?,
+        };
+        stream.set_nodelay(true)?;
+        Ok(stream)
+    }
+
+    /// Connects to the authority of `uri` and performs an HTTP/1 handshake.
+    pub async fn http1<B>(&self, uri: &Uri) -> Result<http1::SendRequest<B>>
+    where
+        B: Body + 'static,
+        B::Data: Send,
+        B::Error: Into<BoxError>,
+    {
+        let stream = self.connect(uri).await?;
+        let (sender, conn) = http1::handshake(stream).await.map_err(hyper_error)?;
+        drive(conn);
+        Ok(sender)
+    }
+
+    /// Connects to the authority of `uri` and performs an HTTP/2 handshake,
+    /// assuming the server speaks HTTP/2 without negotiating it.
+    pub async fn http2<B>(&self, uri: &Uri) -> Result<http2::SendRequest<B>>
+    where
+        B: Body + Unpin + 'static,
+        B::Data: Send,
+        B::Error: Into<BoxError>,
+    {
+        let stream = self.connect(uri).await?;
+        let mut builder = http2::Builder::new(HyperExecutor::new());
+        builder.timer(HyperTimer);
+        let (sender, conn) = builder.handshake(stream).await.map_err(hyper_error)?;
+        drive(conn);
+        Ok(sender)
+    }
+}
+
+fn drive<C: Future<Output = hyper::Result<()>> + 'static>(conn: C) {
+    crate::spawn_local(async move {
+        if let Err(err) = conn.await {
+            log::debug!("HTTP client connection failed: {err}");
+        }
+    })
+    .detach();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::LocalExecutor;
+    use http_body_util::{BodyExt, Full};
+    use hyper::{body::Bytes, rt::Timer as _, service::service_fn, Request, Response, StatusCode};
+    use std::convert::Infallible;
+
+    async fn echo(
+        req: Request<Incoming>,
+    ) -> std::result::Result<Response<Full<Bytes>>, Infallible> {
+        let path = req.uri().path().to_string();
+        let body = req.into_body().collect().await.unwrap().to_bytes();
+        let mut reply = path.into_bytes();
+        reply.extend_from_slice(&body);
+        Ok(Response::new(Full::new(Bytes::from(reply))))
+    }
+
+    fn request(uri: &Uri, body: &'static str) -> Request<Full<Bytes>> {
+        Request::builder()
+            .uri(uri.path())
+            .header(hyper::header::HOST, uri.authority().unwrap().as_str())
+            .body(Full::new(Bytes::from_static(body.as_bytes())))
+            .unwrap()
+    }
+
+    async fn body_of(response: Response<Incoming>) -> Bytes {
+        assert_eq!(response.status(), StatusCode::OK);
+        response.into_body().collect().await.unwrap().to_bytes()
+    }
+
+    #[test]
+    fn http1_roundtrip() {
+        LocalExecutor::default().run(async {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            crate::spawn_local(serve_http1(listener, service_fn(echo), 4)).detach();
+
+            let uri: Uri = format!("http://{addr}/echo").parse().unwrap();
+            let mut sender = HyperConnector::new()
+                .with_connect_timeout(Duration::from_secs(5))
+                .http1(&uri)
+                .await
+                .unwrap();
+            for body in ["a", "bc"] {
+                let response = sender.send_request(request(&uri, body)).await.unwrap();
+                assert_eq!(body_of(response).await, format!("/echo{body}"));
+            }
+        });
+    }
+
+    #[test]
+    fn http2_roundtrip() {
+        LocalExecutor::default().run(async {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            crate::spawn_local(serve_http2(listener, service_fn(echo), 4)).detach();
+
+            let uri: Uri = format!("http://{addr}/h2").parse().unwrap();
+            let sender = HyperConnector::new().http2(&uri).await.unwrap();
+            // streams of one connection are served concurrently
+            let responses = futures::future::join_all((0..4).map(|_| {
+                let mut sender = sender.clone();
+                let request = request(&uri, "!");
+                async move { body_of(sender.send_request(request).await.unwrap()).await }
+            }))
+            .await;
+            assert!(responses.iter().all(|body| body == "/h2!"));
+        });
+    }
+
+    #[test]
+    fn connector_rejects_other_schemes() {
+        LocalExecutor::default().run(async {
+            let uri: Uri = "https://127.0.0.1/".parse().unwrap();
+            let err = HyperConnector::new().connect(&uri).await.unwrap_err();
+            assert!(err.to_string().contains("unsupported URI scheme"));
+        });
+    }
+
+    #[test]
+    fn unix_stream_serves_http1() {
+        LocalExecutor::default().run(async {
+            let (client, server) = UnixStream::pair().unwrap();
+            crate::spawn_local(async move {
+                server_http1::Builder::new()
+                    .serve_connection(server, service_fn(echo))
+                    .await
+                    .unwrap();
+            })
+            .detach();
+
+            let (mut sender, conn) = http1::handshake(client).await.unwrap();
+            drive(conn);
+            let uri: Uri = "http://localhost/unix".parse().unwrap();
+            let response = sender.send_request(request(&uri, "")).await.unwrap();
+            assert_eq!(body_of(response).await, "/unix");
+        });
+    }
+
+    #[test]
+    fn timer_sleeps_and_resets() {
+        LocalExecutor::default().run(async {
+            let start = Instant::now();
+            HyperTimer.sleep(Duration::from_millis(20)).await;
+            assert!(start.elapsed() >= Duration::from_millis(20));
+
+            let mut sleep = HyperTimer.sleep(Duration::from_secs(60));
+            HyperTimer.reset(&mut sleep, Instant::now() + Duration::from_millis(10));
+            let start = Instant::now();
+            sleep.await;
+            assert!(start.elapsed() < Duration::from_secs(1));
+
+            // a sleep dropped before it fires must not wake anything up
+            drop(HyperTimer.sleep(Duration::from_millis(1)));
+            crate::timer::sleep(Duration::from_millis(5)).await;
+        });
+    }
+}
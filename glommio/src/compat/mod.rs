@@ -0,0 +1,10 @@
+//! Integrations with runtime-agnostic crates of the async ecosystem.
+//!
+//! Each of them is behind a cargo feature of the same name, so that neither it
+//! nor the crate it integrates with is compiled unless asked for.
+
+#[cfg(feature = "hyper")]
+pub mod hyper;
+
+#[cfg(feature = "tokio-compat")]
+pub mod tokio;
@@ -0,0 +1,303 @@
+//! Lets code written against tokio's traits and runtime run on a Glommio
+//! executor.
+//!
+//! Glommio's streams and files implement the `futures` I/O traits, while a
+//! good part of the ecosystem (tonic, tokio-util codecs, rustls' tokio
+//! adapters) wants tokio's. [`TokioIo`] wraps any of them, a
+//! [`TcpStream`](crate::net::TcpStream), a
+//! [`DmaStreamReader`](crate::io::DmaStreamReader), a
+//! [`StreamWriter`](crate::io::StreamWriter)..., and implements the tokio
+//! version of every trait the wrapped type implements. It adds no copy or
+//! buffering of its own.
+//!
+//! Code that calls into tokio's runtime, such as `tokio::time::sleep` or
+//! `tokio::spawn`, panics outside of a tokio context. [`TokioCompat`] provides
+//! one: futures it wraps run on the Glommio executor as usual, but can use a
+//! tokio runtime with one worker thread running in the background, shared by the
+//! whole process. Its timers and sockets are driven by that runtime's thread,
+//! and tasks spawned with `tokio::spawn` run there, so this is meant for
+//! dependencies you cannot change, not for the hot path: prefer
+//! [`timer::sleep`](crate::timer::sleep) and [`spawn_local`](crate::spawn_local)
+//! in your own code. The porting guide in `docs/PORTING_FROM_TOKIO.md` has
+//! more on that.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use glommio::{
+//!     compat::tokio::{TokioCompat, TokioIo},
+//!     net::TcpStream,
+//!     LocalExecutor,
+//! };
+//! use tokio::io::AsyncWriteExt;
+//!
+//! LocalExecutor::default().run(TokioCompat::new(async {
+//!     let mut stream = TokioIo::new(TcpStream::connect("127.0.0.1:10000").await.unwrap());
+//!     // a library calling tokio::time::sleep works here
+//!     tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+//!     stream.write_all(b"hello").await.unwrap();
+//! }));
+//! ```
+
+use futures_lite::{AsyncBufRead, AsyncRead, AsyncSeek, AsyncWrite};
+use pin_project_lite::pin_project;
+use std::{
+    future::Future,
+    io::{self, IoSlice, SeekFrom},
+    pin::Pin,
+    sync::OnceLock,
+    task::{Context, Poll},
+};
+use tokio::{io::ReadBuf, runtime::Runtime};
+
+pin_project! {
+    /// Implements tokio's I/O traits for a type implementing the `futures`
+    /// ones.
+    ///
+    /// See the [module documentation](self).
+    #[derive(Debug)]
+    pub struct TokioIo<T> {
+        #[pin]
+        inner: T,
+        // the target of a seek started but not completed yet
+        seek: Option<SeekFrom>,
+    }
+}
+
+impl<T> TokioIo<T> {
+    /// Wraps `inner`.
+    pub fn new(inner: T) -> TokioIo<T> {
+        TokioIo { inner, seek: None }
+    }
+
+    /// Returns a reference to the wrapped value.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the wrapped value.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Returns the wrapped value.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: AsyncRead> tokio::io::AsyncRead for TokioIo<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        // only zeroes what was never initialized, so at most once per buffer
+        let read = futures_lite::ready!(self
+            .project()
+            .inner
+            .poll_read(cx, buf.initialize_unfilled()))?;
+        buf.advance(read);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: AsyncBufRead> tokio::io::AsyncBufRead for TokioIo<T> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        self.project().inner.poll_fill_buf(cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.project().inner.consume(amt)
+    }
+}
+
+impl<T: AsyncWrite> tokio::io::AsyncWrite for TokioIo<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.project().inner.poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.project().inner.poll_write_vectored(cx, bufs)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().inner.poll_close(cx)
+    }
+}
+
+// tokio splits a seek in two calls where `futures` has one, so the target is
+// kept in between.
+impl<T: AsyncSeek> tokio::io::AsyncSeek for TokioIo<T> {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        *self.project().seek = Some(position);
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        let this = self.project();
+        // without a pending seek, this reports the current position
+        let position = this.seek.unwrap_or(SeekFrom::Current(0));
+        let result = futures_lite::ready!(this.inner.poll_seek(cx, position));
+        *this.seek = None;
+        Poll::Ready(result)
+    }
+}
+
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("glommio-tokio-compat")
+            .enable_all()
+            .build()
+            .expect("failed to start the tokio compatibility runtime")
+    })
+}
+
+pin_project! {
+    /// Runs a future with access to a tokio runtime.
+    ///
+    /// Within the wrapped future, `tokio::time`, `tokio::net` and
+    /// `tokio::spawn` work as they do under tokio. They use a tokio runtime
+    /// with a single worker thread, started the first time a `TokioCompat`
+    /// is polled and shared by the whole process, while the future itself
+    /// keeps running on the Glommio executor that polls it.
+    ///
+    /// Tasks spawned from the future with [`spawn_local`](crate::spawn_local)
+    /// are polled on their own, so they need wrapping too if they use tokio.
+    ///
+    /// See the [module documentation](self).
+    #[derive(Debug)]
+    #[must_use = "futures do nothing unless you `.await` or poll them"]
+    pub struct TokioCompat<F> {
+        #[pin]
+        inner: F,
+    }
+}
+
+impl<F> TokioCompat<F> {
+    /// Wraps `inner`.
+    pub fn new(inner: F) -> TokioCompat<F> {
+        TokioCompat { inner }
+    }
+
+    /// Returns the wrapped future.
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+}
+
+impl<F: Future> Future for TokioCompat<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let _context = runtime().enter();
+        self.project().inner.poll(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        io::{
+            BufferedFile, DmaFile, DmaStreamReaderBuilder, DmaStreamWriterBuilder,
+            StreamReaderBuilder, StreamWriterBuilder,
+        },
+        net::{TcpListener, TcpStream},
+        test_utils::make_tmp_test_directory,
+        LocalExecutor,
+    };
+    use std::time::{Duration, Instant};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+    #[test]
+    fn tcp_stream() {
+        LocalExecutor::default().run(async {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let server = crate::spawn_local(async move {
+                let mut stream = TokioIo::new(listener.accept().await.unwrap().buffered());
+                let mut line = String::new();
+                stream.read_line(&mut line).await.unwrap();
+                stream
+                    .write_all(line.to_uppercase().as_bytes())
+                    .await
+                    .unwrap();
+                stream.shutdown().await.unwrap();
+            });
+
+            let mut stream = TokioIo::new(TcpStream::connect(addr).await.unwrap());
+            stream.write_all(b"hello\n").await.unwrap();
+            let mut reply = Vec::new();
+            stream.read_to_end(&mut reply).await.unwrap();
+            assert_eq!(reply, b"HELLO\n");
+            server.await;
+        });
+    }
+
+    #[test]
+    fn file_streams() {
+        let dir = make_tmp_test_directory("tokio-compat-file-streams");
+        LocalExecutor::default().run(async move {
+            let path = dir.path.join("dma");
+            let file = DmaFile::create(&path).await.unwrap();
+            let mut writer = TokioIo::new(DmaStreamWriterBuilder::new(file).build());
+            writer.write_all(&[7; 10_000]).await.unwrap();
+            writer.shutdown().await.unwrap();
+
+            let file = DmaFile::open(&path).await.unwrap();
+            let mut reader = TokioIo::new(DmaStreamReaderBuilder::new(file).build());
+            let mut contents = Vec::new();
+            reader.read_to_end(&mut contents).await.unwrap();
+            assert_eq!(contents, vec![7; 10_000]);
+            reader.into_inner().close().await.unwrap();
+
+            let path = dir.path.join("buffered");
+            let file = BufferedFile::create(&path).await.unwrap();
+            let mut writer = TokioIo::new(StreamWriterBuilder::new(file).build());
+            writer.write_all(b"0123456789").await.unwrap();
+            writer.shutdown().await.unwrap();
+
+            let file = BufferedFile::open(&path).await.unwrap();
+            let mut reader = TokioIo::new(StreamReaderBuilder::new(file).build());
+            assert_eq!(reader.seek(SeekFrom::Start(4)).await.unwrap(), 4);
+            let mut rest = String::new();
+            reader.read_to_string(&mut rest).await.unwrap();
+            assert_eq!(rest, "456789");
+            assert_eq!(reader.stream_position().await.unwrap(), 10);
+        });
+    }
+
+    #[test]
+    fn tokio_runtime_is_available() {
+        LocalExecutor::default().run(TokioCompat::new(async {
+            let start = Instant::now();
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            assert!(start.elapsed() >= Duration::from_millis(10));
+
+            let spawned = tokio::spawn(async { std::thread::current().name().map(String::from) });
+            assert_eq!(
+                spawned.await.unwrap().as_deref(),
+                Some("glommio-tokio-compat")
+            );
+
+            // and the executor keeps working as usual alongside it
+            assert_eq!(crate::spawn_local(async { 1 }).await, 1);
+        }));
+    }
+}
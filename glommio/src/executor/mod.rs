@@ -35,11 +35,11 @@
 use crate::{
     error::BuilderErrorKind,
     executor::stall::StallDetector,
-    io::DmaBuffer,
+    io::{DmaBuffer, IoRateLimiter},
     parking, reactor,
     sys::{self, blocking::BlockingThreadPool},
     task::{self, waker_fn::dummy_waker},
-    GlommioError, IoRequirements, IoStats, Latency, Reactor, Shares,
+    GlommioError, IoPriority, IoRequirements, IoStats, Latency, Reactor, Shares,
 };
 use ahash::AHashMap;
 use futures_lite::pin;
@@ -1347,11 +1347,47 @@ impl LocalExecutor {
             }
 
             entry.remove();
+            self.reactor
+                .io_scheduler()
+                .set_task_queue_limiter(handle, None);
             return Ok(());
         }
         Err(GlommioError::queue_not_found(handle.index))
     }
 
+    fn set_task_queue_io_limiter(
+        &self,
+        handle: TaskQueueHandle,
+        limiter: Option<IoRateLimiter>,
+    ) -> Result<()> {
+        if self.get_queue(&handle).is_none() {
+            return Err(GlommioError::queue_not_found(handle.index));
+        }
+        self.reactor
+            .io_scheduler()
+            .set_task_queue_limiter(handle, limiter);
+        Ok(())
+    }
+
+    fn set_task_queue_io_priority(
+        &self,
+        handle: TaskQueueHandle,
+        priority: Option<IoPriority>,
+    ) -> Result<()> {
+        let queue = self
+            .get_queue(&handle)
+            .ok_or_else(|| GlommioError::queue_not_found(handle.index))?;
+        let running = self.current_task_queue() == handle;
+        let mut queue = queue.borrow_mut();
+        queue.io_requirements.io_priority = priority;
+        // the reactor only learns about requirements when a task queue starts
+        // running, so tell it now if that already happened
+        if running {
+            self.reactor.inform_io_requirements(queue.io_requirements);
+        }
+        Ok(())
+    }
+
     fn get_queue(&self, handle: &TaskQueueHandle) -> Option<Rc<RefCell<TaskQueue>>> {
         self.queues
             .borrow()
@@ -2479,6 +2515,114 @@ impl ExecutorProxy {
         };
     }
 
+    /// Limits the rate of the file I/O issued by tasks in the task queue
+    /// `handle`, or lifts the limit if `limiter` is `None`.
+    ///
+    /// This is the I/O counterpart of [`Shares`]: it keeps, for instance, a
+    /// background compaction from saturating the device while foreground
+    /// task queues need it. I/O from the task queue waits before being
+    /// submitted for as long as the limiter requires, and that time is
+    /// reported in [`RingIoStats::io_throttled`].
+    ///
+    /// Returns a [`GlommioError`] of type [`QueueErrorKind`] if there is no
+    /// task queue with this handle.
+    ///
+    /// # Examples
+    /// ```
+    /// use glommio::{
+    ///     io::{IoRateLimit, IoRateLimiter},
+    ///     Latency,
+    ///     LocalExecutorBuilder,
+    ///     Shares,
+    /// };
+    ///
+    /// let ex = LocalExecutorBuilder::default()
+    ///     .spawn(|| async move {
+    ///         let background = glommio::executor().create_task_queue(
+    ///             Shares::default(),
+    ///             Latency::NotImportant,
+    ///             "background",
+    ///         );
+    ///         let limit = IoRateLimit::new().with_bytes_per_sec(50 << 20, 1 << 20);
+    ///         glommio::executor()
+    ///             .set_task_queue_io_limiter(background, Some(IoRateLimiter::new(limit)))
+    ///             .unwrap();
+    ///     })
+    ///     .unwrap();
+    ///
+    /// ex.join().unwrap();
+    /// ```
+    ///
+    /// [`Shares`]: crate::Shares
+    /// [`RingIoStats::io_throttled`]: crate::RingIoStats::io_throttled
+    /// [`GlommioError`]: crate::error::GlommioError
+    /// [`QueueErrorKind`]: crate::error::QueueErrorKind
+    pub fn set_task_queue_io_limiter(
+        &self,
+        handle: TaskQueueHandle,
+        limiter: Option<IoRateLimiter>,
+    ) -> Result<()> {
+        #[cfg(any(not(nightly), not(feature = "native-tls")))]
+        return LOCAL_EX.with(|local_ex| local_ex.set_task_queue_io_limiter(handle, limiter));
+
+        #[cfg(all(nightly, feature = "native-tls"))]
+        return unsafe {
+            LOCAL_EX
+                .as_ref()
+                .expect("this thread doesn't have a LocalExecutor running")
+                .set_task_queue_io_limiter(handle, limiter)
+        };
+    }
+
+    /// Sets the [`IoPriority`] of the file I/O issued by the task queue
+    /// identified by `handle`, or leaves it to the kernel if `priority` is
+    /// `None`, which is the default.
+    ///
+    /// The priority applies to I/O submitted from now on.
+    ///
+    /// Returns a [`GlommioError`] of type [`QueueErrorKind`] if there is no
+    /// task queue with this handle.
+    ///
+    /// # Examples
+    /// ```
+    /// use glommio::{IoPriority, Latency, LocalExecutorBuilder, Shares};
+    ///
+    /// let ex = LocalExecutorBuilder::default()
+    ///     .spawn(|| async move {
+    ///         let scrub = glommio::executor().create_task_queue(
+    ///             Shares::default(),
+    ///             Latency::NotImportant,
+    ///             "scrub",
+    ///         );
+    ///         glommio::executor()
+    ///             .set_task_queue_io_priority(scrub, Some(IoPriority::idle()))
+    ///             .unwrap();
+    ///     })
+    ///     .unwrap();
+    ///
+    /// ex.join().unwrap();
+    /// ```
+    ///
+    /// [`IoPriority`]: crate::IoPriority
+    /// [`GlommioError`]: crate::error::GlommioError
+    /// [`QueueErrorKind`]: crate::error::QueueErrorKind
+    pub fn set_task_queue_io_priority(
+        &self,
+        handle: TaskQueueHandle,
+        priority: Option<IoPriority>,
+    ) -> Result<()> {
+        #[cfg(any(not(nightly), not(feature = "native-tls")))]
+        return LOCAL_EX.with(|local_ex| local_ex.set_task_queue_io_priority(handle, priority));
+
+        #[cfg(all(nightly, feature = "native-tls"))]
+        return unsafe {
+            LOCAL_EX
+                .as_ref()
+                .expect("this thread doesn't have a LocalExecutor running")
+                .set_task_queue_io_priority(handle, priority)
+        };
+    }
+
     /// Returns a [`Result`] with its `Ok` value wrapping a [`TaskQueueStats`]
     /// or a [`GlommioError`] of type `[QueueErrorKind`] if there is no task
     /// queue with this handle
@@ -2625,6 +2769,41 @@ impl ExecutorProxy {
         });
     }
 
+    /// Extends `output` with a [`ChannelStats`] for every channel instrumented
+    /// on this executor that is still alive.
+    ///
+    /// Channels record nothing unless instrumented, with
+    /// [`LocalSender::instrument`] for instance. Unlike the other stats, these
+    /// accumulate over the life of each channel rather than since the last
+    /// call.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// use glommio::{channels::local_channel, LocalExecutorBuilder};
+    ///
+    /// let ex = LocalExecutorBuilder::default()
+    ///     .spawn(|| async move {
+    ///         let (sender, _receiver) = local_channel::new_unbounded::<u32>();
+    ///         sender.instrument("backlog");
+    ///         let stats = glommio::executor().channel_stats(Vec::new());
+    ///         assert_eq!(stats.len(), 1);
+    ///         assert_eq!(stats[0].name(), "backlog");
+    ///     })
+    ///     .unwrap();
+    ///
+    /// ex.join().unwrap();
+    /// ```
+    ///
+    /// [`ChannelStats`]: crate::channels::ChannelStats
+    /// [`LocalSender::instrument`]: crate::channels::local_channel::LocalSender::instrument
+    pub fn channel_stats<V>(&self, output: V) -> V
+    where
+        V: Extend<crate::channels::ChannelStats>,
+    {
+        crate::channels::stats::all_channel_stats(output)
+    }
+
     /// Returns an [`IoStats`] struct with information about IO performed by
     /// this executor's reactor
     ///
@@ -3200,6 +3379,19 @@ mod test {
         });
     }
 
+    #[test]
+    fn io_priority_of_invalid_task_queue() {
+        let local_ex = LocalExecutor::default();
+        local_ex.run(async {
+            crate::executor()
+                .set_task_queue_io_priority(
+                    TaskQueueHandle { index: 1 },
+                    Some(IoPriority::best_effort(0)),
+                )
+                .expect_err("there is no such task queue");
+        });
+    }
+
     #[test]
     fn ten_yielding_queues() {
         let local_ex = LocalExecutor::default();
@@ -0,0 +1,253 @@
+//! Crash-consistent replacement of whole files.
+//!
+//! Getting this right by hand takes an anonymous file, a sync, a link, a
+//! rename and a directory sync, in exactly that order. [`AtomicFileWriter`]
+//! does the sequence once so callers don't have to.
+
+use crate::io::{
+    directory::contains_dir, open_options::OpenOptions, Directory, DmaStreamWriter,
+    DmaStreamWriterBuilder,
+};
+use futures_lite::{
+    future::poll_fn,
+    io::{AsyncWrite, AsyncWriteExt},
+};
+use std::{
+    io,
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+type Result<T> = crate::Result<T, ()>;
+
+/// Atomically replaces the file `name` under `dir` with `data`.
+///
+/// This is a convenience wrapper around [`AtomicFileWriter`] for contents that
+/// are already in memory. After it returns successfully the new contents are
+/// durable; if the system crashes before that, `name` holds either its old
+/// contents or the new ones, never a mix of both.
+///
+/// NOTE: `name` must not contain directories and just be a file name
+///
+/// # Examples
+///
+/// ```no_run
+/// use glommio::{
+///     io::{atomic_write, Directory},
+///     LocalExecutor,
+/// };
+///
+/// let ex = LocalExecutor::default();
+/// ex.run(async {
+///     let dir = Directory::open("/var/lib/myservice").await.unwrap();
+///     atomic_write(&dir, "manifest", b"generation = 2\n")
+///         .await
+///         .unwrap();
+/// });
+/// ```
+pub async fn atomic_write<P: AsRef<Path>>(dir: &Directory, name: P, data: &[u8]) -> Result<()> {
+    let mut writer = AtomicFileWriter::create(dir, name).await?;
+    writer.write_all(data).await?;
+    writer.commit().await
+}
+
+#[derive(Debug)]
+/// Writes a file that only becomes visible, under its final name, once it is
+/// completely written and durable.
+///
+/// The contents go to an anonymous file created with
+/// [`OpenOptions::tmpfile_linkable`] in the target [`Directory`], through a
+/// [`DmaStreamWriter`]. Nothing is visible in the directory until [`commit`]
+/// is called, which:
+///
+/// * flushes the remaining buffers and issues `fdatasync` on the file,
+/// * links it into the directory under a temporary name,
+/// * renames that name over the target, replacing any file already there,
+/// * and finally syncs the [`Directory`], so the rename itself is durable.
+///
+/// Should the system crash at any point the target holds either its previous
+/// contents or the new ones. Dropping the writer without committing discards
+/// everything written to it and leaves the target untouched.
+///
+/// A crash between the link and the rename can leave a `.<name>.<inode>.tmp`
+/// file behind in the directory. Applications that care can remove those on
+/// startup.
+///
+/// [`OpenOptions::tmpfile_linkable`]: OpenOptions::tmpfile_linkable
+/// [`commit`]: AtomicFileWriter::commit
+pub struct AtomicFileWriter {
+    writer: DmaStreamWriter,
+    dir: Directory,
+    name: PathBuf,
+}
+
+impl AtomicFileWriter {
+    /// Starts writing a new version of the file `name` under `dir`.
+    ///
+    /// NOTE: `name` must not contain directories and just be a file name
+    pub async fn create<P: AsRef<Path>>(dir: &Directory, name: P) -> Result<AtomicFileWriter> {
+        if contains_dir(name.as_ref()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Path cannot contain directories",
+            )
+            .into());
+        }
+
+        let path = dir.path_required("create atomic file")?.to_path_buf();
+        let file = OpenOptions::new()
+            .write(true)
+            .tmpfile(true)
+            .tmpfile_linkable(true)
+            .dma_open(&path)
+            .await?;
+
+        // The sync is issued by `commit` instead, after the padding added by
+        // the last Direct I/O write has been truncated away: syncing before
+        // that would leave the final file size out of the durable state.
+        let writer = DmaStreamWriterBuilder::new(file)
+            .with_sync_on_close_disabled(true)
+            .build();
+
+        Ok(AtomicFileWriter {
+            writer,
+            dir: dir.try_clone()?,
+            name: name.as_ref().to_owned(),
+        })
+    }
+
+    /// Makes everything written so far durable and atomically replaces the
+    /// target file with it.
+    pub async fn commit(mut self) -> Result<()> {
+        let builder = poll_fn(|cx| self.writer.poll_seal(cx)).await?;
+        let file = builder.file;
+        file.fdatasync().await?;
+
+        let dir_path = self.dir.path_required("commit atomic file")?.to_path_buf();
+        let mut staging = std::ffi::OsString::from(".");
+        staging.push(&self.name);
+        staging.push(format!(".{}.tmp", file.inode()));
+
+        file.link_at(self.dir.as_raw_fd(), &staging).await?;
+        if let Err(err) =
+            crate::io::rename(dir_path.join(&staging), dir_path.join(&self.name)).await
+        {
+            let _ = crate::io::remove(dir_path.join(&staging)).await;
+            return Err(err);
+        }
+        self.dir.sync().await?;
+
+        file.close_rc().await?;
+        self.dir.close().await
+    }
+
+    /// Acquires the current position of this [`AtomicFileWriter`], which is
+    /// the size the file will have once committed.
+    pub fn current_pos(&self) -> u64 {
+        self.writer.current_pos()
+    }
+}
+
+impl AsyncWrite for AtomicFileWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.writer).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.writer).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.writer).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::make_test_directories;
+
+    macro_rules! atomic_file_test {
+        ( $name:ident, $dir:ident, $code:block) => {
+            #[test]
+            fn $name() {
+                for dir in make_test_directories(&format!("atomic-dma-{}", stringify!($name))) {
+                    let $dir = dir.path.clone();
+                    test_executor!(async move { $code });
+                }
+            }
+        };
+    }
+
+    fn dir_entries(path: &Path) -> Vec<String> {
+        let mut entries: Vec<_> = std::fs::read_dir(path)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    atomic_file_test!(write_new_file, path, {
+        let dir = Directory::open(&path).await.unwrap();
+        atomic_write(&dir, "config", b"hello").await.unwrap();
+
+        assert_eq!(std::fs::read(path.join("config")).unwrap(), b"hello");
+        assert_eq!(dir_entries(&path), vec!["config"]);
+        dir.close().await.unwrap();
+    });
+
+    atomic_file_test!(replace_existing_file, path, {
+        let dir = Directory::open(&path).await.unwrap();
+        std::fs::write(path.join("config"), vec![b'x'; 10000]).unwrap();
+
+        atomic_write(&dir, "config", b"short").await.unwrap();
+
+        assert_eq!(std::fs::read(path.join("config")).unwrap(), b"short");
+        assert_eq!(dir_entries(&path), vec!["config"]);
+        dir.close().await.unwrap();
+    });
+
+    atomic_file_test!(nothing_visible_until_commit, path, {
+        let dir = Directory::open(&path).await.unwrap();
+        std::fs::write(path.join("config"), b"old").unwrap();
+
+        let mut writer = AtomicFileWriter::create(&dir, "config").await.unwrap();
+        writer.write_all(&[7u8; 9000]).await.unwrap();
+        assert_eq!(writer.current_pos(), 9000);
+        assert_eq!(std::fs::read(path.join("config")).unwrap(), b"old");
+        assert_eq!(dir_entries(&path), vec!["config"]);
+
+        writer.commit().await.unwrap();
+        assert_eq!(std::fs::read(path.join("config")).unwrap(), vec![7u8; 9000]);
+        dir.close().await.unwrap();
+    });
+
+    atomic_file_test!(drop_without_commit_keeps_old, path, {
+        let dir = Directory::open(&path).await.unwrap();
+        std::fs::write(path.join("config"), b"old").unwrap();
+
+        let mut writer = AtomicFileWriter::create(&dir, "config").await.unwrap();
+        writer.write_all(b"new").await.unwrap();
+        drop(writer);
+
+        assert_eq!(std::fs::read(path.join("config")).unwrap(), b"old");
+        assert_eq!(dir_entries(&path), vec!["config"]);
+        dir.close().await.unwrap();
+    });
+
+    atomic_file_test!(name_with_directory_rejected, path, {
+        let dir = Directory::open(&path).await.unwrap();
+        let err = AtomicFileWriter::create(&dir, "a/b").await.unwrap_err();
+        assert!(
+            matches!(err, crate::GlommioError::IoError(ref e) if e.kind() == io::ErrorKind::InvalidInput)
+        );
+        dir.close().await.unwrap();
+    });
+}
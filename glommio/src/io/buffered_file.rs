@@ -5,11 +5,16 @@
 //
 
 use crate::{
-    io::{glommio_file::GlommioFile, read_result::ReadResult, OpenOptions},
+    io::{
+        glommio_file::{FileAdvice, GlommioFile, SyncRangeFlags},
+        read_result::ReadResult,
+        OpenOptions,
+    },
     GlommioError,
 };
 use std::{
     cell::Ref,
+    ffi::{OsStr, OsString},
     os::unix::io::{AsRawFd, FromRawFd, RawFd},
     path::{Path, PathBuf},
 };
@@ -193,6 +198,59 @@ impl BufferedFile {
         self.file.fdatasync().await
     }
 
+    /// Tells the kernel how the range `[offset, offset + len)` of this file
+    /// will be accessed, through `posix_fadvise(2)`. A `len` of zero extends
+    /// the range to the end of the file.
+    ///
+    /// Note: this syscall might be issued in a background thread depending on
+    /// the system's capabilities.
+    pub async fn fadvise(&self, offset: u64, len: u64, advice: FileAdvice) -> Result<()> {
+        self.file.fadvise(offset, len, advice).await
+    }
+
+    /// Starts and/or waits for writeback of the range `[offset, offset + len)`
+    /// of this file, through `sync_file_range(2)`. A `len` of zero extends the
+    /// range to the end of the file.
+    ///
+    /// This allows flushing a file incrementally, so that a final
+    /// [`fdatasync`] has little left to do. It is not a substitute for it:
+    /// it neither flushes the device's cache nor persists metadata, so it
+    /// provides no durability guarantees of its own.
+    ///
+    /// Note: this syscall might be issued in a background thread depending on
+    /// the system's capabilities.
+    ///
+    /// [`fdatasync`]: BufferedFile::fdatasync
+    pub async fn sync_range(&self, offset: u64, len: u64, flags: SyncRangeFlags) -> Result<()> {
+        self.file.sync_range(offset, len, flags).await
+    }
+
+    /// Returns the value of the extended attribute `name` of this file, or
+    /// `None` if it has no such attribute.
+    ///
+    /// Note: this syscall might be issued in a background thread depending on
+    /// the system's capabilities.
+    pub async fn get_xattr<N: AsRef<OsStr>>(&self, name: N) -> Result<Option<Vec<u8>>> {
+        self.file.get_xattr(name.as_ref()).await
+    }
+
+    /// Sets the extended attribute `name` of this file to `value`, creating
+    /// it if needed.
+    ///
+    /// Note: this syscall might be issued in a background thread depending on
+    /// the system's capabilities.
+    pub async fn set_xattr<N: AsRef<OsStr>>(&self, name: N, value: &[u8]) -> Result<()> {
+        self.file.set_xattr(name.as_ref(), value).await
+    }
+
+    /// Returns the names of the extended attributes of this file.
+    ///
+    /// Note: this syscall is always issued in a background thread, as
+    /// io_uring has no equivalent.
+    pub async fn list_xattrs(&self) -> Result<Vec<OsString>> {
+        self.file.list_xattr().await
+    }
+
     /// Erases a range from the file without changing the size. Check the man
     /// page for [`fallocate`] for a list of the supported filesystems.
     /// Partial blocks are zeroed while whole blocks are simply unmapped
@@ -366,6 +424,23 @@ mod test {
         assert_eq!(stats.all_rings().file_buffered_writes(), (1, 6));
     });
 
+    buffered_file_test!(advise_and_sync_range, path, _k, {
+        let file = BufferedFile::create(path.join("testfile")).await.unwrap();
+        file.write_at(vec![1; 8192], 0).await.unwrap();
+
+        file.sync_range(0, 0, SyncRangeFlags::WRITE | SyncRangeFlags::WAIT_AFTER)
+            .await
+            .unwrap();
+        file.fadvise(0, 8192, FileAdvice::DontNeed).await.unwrap();
+        file.close().await.unwrap();
+
+        let reader = BufferedFile::open(path.join("testfile")).await.unwrap();
+        reader.fadvise(0, 0, FileAdvice::WillNeed).await.unwrap();
+        let rb = reader.read_at(0, 8192).await.unwrap();
+        assert_eq!(&*rb, &[1; 8192][..]);
+        reader.close().await.unwrap();
+    });
+
     buffered_file_test!(write_past_end, path, _k, {
         let writer = BufferedFile::create(path.join("testfile")).await.unwrap();
 
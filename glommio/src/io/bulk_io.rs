@@ -1,6 +1,7 @@
 use crate::io::{
     dma_file::{align_down, align_up},
-    DmaFile, ReadResult, ScheduledSource,
+    read_result::ReadSource,
+    DmaFile, ReadResult,
 };
 use core::task::{Context, Poll};
 use futures_lite::{ready, Stream, StreamExt};
@@ -287,18 +288,18 @@ impl<V: IoVec + Unpin, S: Stream<Item = V> + Unpin> Stream for CoalescedReads<V,
 }
 
 #[derive(Debug)]
-pub(crate) struct OrderedBulkIo<U: IoVec + Unpin, S: Stream<Item = (ScheduledSource, U)> + Unpin> {
+pub(crate) struct OrderedBulkIo<U: IoVec + Unpin, S: Stream<Item = (ReadSource, U)> + Unpin> {
     file: Rc<DmaFile>,
     iovs: S,
 
-    inflight: VecDeque<(ScheduledSource, U)>,
+    inflight: VecDeque<(ReadSource, U)>,
     concurrency_cap: usize,
     inflight_memory: usize,
     memory_cap: usize,
     terminated: bool,
 }
 
-impl<U: IoVec + Unpin, S: Stream<Item = (ScheduledSource, U)> + Unpin> OrderedBulkIo<U, S> {
+impl<U: IoVec + Unpin, S: Stream<Item = (ReadSource, U)> + Unpin> OrderedBulkIo<U, S> {
     pub(crate) fn new(file: Rc<DmaFile>, concurrency: usize, iovs: S) -> OrderedBulkIo<U, S> {
         assert!(concurrency > 0);
         OrderedBulkIo {
@@ -336,10 +337,8 @@ impl<U: IoVec + Unpin, S: Stream<Item = (ScheduledSource, U)> + Unpin> OrderedBu
     }
 }
 
-impl<U: IoVec + Unpin, S: Stream<Item = (ScheduledSource, U)> + Unpin> Stream
-    for OrderedBulkIo<U, S>
-{
-    type Item = (ScheduledSource, U);
+impl<U: IoVec + Unpin, S: Stream<Item = (ReadSource, U)> + Unpin> Stream for OrderedBulkIo<U, S> {
+    type Item = (ReadSource, U);
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let this = self.get_mut();
@@ -358,7 +357,7 @@ impl<U: IoVec + Unpin, S: Stream<Item = (ScheduledSource, U)> + Unpin> Stream
         // poll the local buffer for a fulfilled source, if any, and replace it with a
         // new one from the underlying stream
         let poll_buffer = |this: &mut Self, cx: &mut Context<'_>| {
-            if this.inflight.back_mut().unwrap().0.result().is_some() {
+            if this.inflight.back_mut().unwrap().0.is_ready() {
                 // we have a source with a result in the buffer so we take it out and replace it
                 // with a new from the stream, if any, to keep the buffer full
                 let ret = this.inflight.pop_back().unwrap();
@@ -374,7 +373,7 @@ impl<U: IoVec + Unpin, S: Stream<Item = (ScheduledSource, U)> + Unpin> Stream
                     .back_mut()
                     .unwrap()
                     .0
-                    .add_waiter_many(cx.waker().clone());
+                    .add_waiter(cx.waker().clone());
                 Poll::Pending
             }
         };
@@ -425,15 +424,13 @@ impl<V: IoVec + Unpin> IoVec for ReadManyArgs<V> {
 ///
 /// See [`DmaFile::read_many`] for more information
 #[derive(Debug)]
-pub struct ReadManyResult<
-    V: IoVec + Unpin,
-    S: Stream<Item = (ScheduledSource, ReadManyArgs<V>)> + Unpin,
-> {
+pub struct ReadManyResult<V: IoVec + Unpin, S: Stream<Item = (ReadSource, ReadManyArgs<V>)> + Unpin>
+{
     pub(crate) inner: OrderedBulkIo<ReadManyArgs<V>, S>,
-    pub(crate) current: Option<(ScheduledSource, ReadManyArgs<V>)>,
+    pub(crate) current: Option<(ReadSource, ReadManyArgs<V>)>,
 }
 
-impl<V: IoVec + Unpin, S: Stream<Item = (ScheduledSource, ReadManyArgs<V>)> + Unpin>
+impl<V: IoVec + Unpin, S: Stream<Item = (ReadSource, ReadManyArgs<V>)> + Unpin>
     ReadManyResult<V, S>
 {
     /// Set the amount of IO concurrency of this stream, i.e., the number of IO
@@ -476,7 +473,7 @@ impl<V: IoVec + Unpin, S: Stream<Item = (ScheduledSource, ReadManyArgs<V>)> + Un
     }
 }
 
-impl<V: IoVec + Unpin, S: Stream<Item = (ScheduledSource, ReadManyArgs<V>)> + Unpin> Stream
+impl<V: IoVec + Unpin, S: Stream<Item = (ReadSource, ReadManyArgs<V>)> + Unpin> Stream
     for ReadManyResult<V, S>
 {
     type Item = super::Result<(V, ReadResult)>;
@@ -485,13 +482,17 @@ impl<V: IoVec + Unpin, S: Stream<Item = (ScheduledSource, ReadManyArgs<V>)> + Un
         if let Some((source, args)) = &mut self.current {
             if let Some(io) = args.user_reads.pop_front() {
                 let (pos, size) = (io.pos(), io.size());
+                let mut offset = (pos - args.system_read.pos()) as usize;
+                let mut size = size;
+                if let ReadSource::Mapped(_, range) = source {
+                    // mappings end at the end of the file, whereas an I/O buffer
+                    // is always as large as what was asked for
+                    offset = offset.min(range.len());
+                    size = size.min(range.len() - offset);
+                }
                 return Poll::Ready(Some(Ok((
                     io,
-                    ReadResult::from_sliced_buffer(
-                        source.clone(),
-                        (pos - args.system_read.pos()) as usize,
-                        size,
-                    ),
+                    ReadResult::from_sliced_buffer(source.clone(), offset, size),
                 ))));
             }
         }
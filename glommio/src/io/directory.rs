@@ -142,9 +142,13 @@ impl Directory {
     pub fn path(&self) -> Option<Ref<'_, Path>> {
         self.file.path()
     }
+
+    pub(super) fn path_required(&self, op: &'static str) -> Result<Ref<'_, Path>> {
+        self.file.path_required(op)
+    }
 }
 
-fn contains_dir(path: &Path) -> bool {
+pub(super) fn contains_dir(path: &Path) -> bool {
     let mut iter = path.components();
     match iter.next() {
         Some(std::path::Component::Normal(_)) => iter.next().is_some(),
@@ -9,17 +9,19 @@ use crate::{
             CoalescedReads, IoVec, MergedBufferLimit, OrderedBulkIo, ReadAmplificationLimit,
             ReadManyArgs, ReadManyResult,
         },
-        glommio_file::GlommioFile,
+        glommio_file::{FileAdvice, GlommioFile, SyncRangeFlags},
+        mmap::FileMapping,
         open_options::OpenOptions,
-        read_result::ReadResult,
-        ScheduledSource,
+        rate_limit::{IoRateLimiter, Throttled},
+        read_result::{ReadResult, ReadSource},
     },
     sys::{self, sysfs, DirectIo, DmaBuffer, DmaSource, PollableStatus},
 };
 use futures_lite::{Stream, StreamExt};
 use nix::sys::statfs::*;
 use std::{
-    cell::Ref,
+    cell::{Ref, RefCell},
+    ffi::{OsStr, OsString},
     io,
     os::{
         fd::BorrowedFd,
@@ -142,6 +144,7 @@ pub struct DmaFile {
     max_sectors_size: usize,
     max_segment_size: usize,
     pollable: PollableStatus,
+    rate_limiter: RefCell<Option<IoRateLimiter>>,
 }
 
 impl DmaFile {
@@ -239,6 +242,7 @@ impl DmaFile {
             max_sectors_size,
             max_segment_size,
             pollable,
+            rate_limiter: Default::default(),
         })
     }
 
@@ -338,6 +342,7 @@ impl DmaFile {
             max_sectors_size: self.max_sectors_size,
             max_segment_size: self.max_segment_size,
             pollable: self.pollable,
+            rate_limiter: self.rate_limiter.clone(),
         })
     }
 
@@ -383,6 +388,7 @@ impl DmaFile {
     /// [`alloc_dma_buffer`]: struct.DmaFile.html#method.alloc_dma_buffer
     /// [man page]: https://man7.org/linux/man-pages/man2/open.2.html
     pub async fn write_at(&self, buf: DmaBuffer, pos: u64) -> Result<usize> {
+        self.throttle(buf.len()).await;
         let source = self.file.reactor.upgrade().unwrap().write_dma(
             self.as_raw_fd(),
             DmaSource::Owned(buf),
@@ -443,6 +449,7 @@ impl DmaFile {
     /// });
     /// ```
     pub async fn write_rc_at(&self, buf: Rc<DmaBuffer>, pos: u64) -> Result<usize> {
+        self.throttle(buf.len()).await;
         let source = self.file.reactor.upgrade().unwrap().write_dma(
             self.as_raw_fd(),
             DmaSource::Shared(buf),
@@ -457,6 +464,7 @@ impl DmaFile {
     /// The position must be aligned to for Direct I/O. In most platforms
     /// that means 512 bytes.
     pub async fn read_at_aligned(&self, pos: u64, size: usize) -> Result<ReadResult> {
+        self.throttle(size).await;
         let source = self.file.reactor.upgrade().unwrap().read_dma(
             self.as_raw_fd(),
             pos,
@@ -481,6 +489,7 @@ impl DmaFile {
         let b = (pos - eff_pos) as usize;
 
         let eff_size = self.align_up((size + b) as u64) as usize;
+        self.throttle(eff_size).await;
         let source = self.file.reactor.upgrade().unwrap().read_dma(
             self.as_raw_fd(),
             eff_pos,
@@ -517,7 +526,24 @@ impl DmaFile {
         iovs: S,
         buffer_limit: MergedBufferLimit,
         read_amp_limit: ReadAmplificationLimit,
-    ) -> ReadManyResult<V, impl Stream<Item = (ScheduledSource, ReadManyArgs<V>)>>
+    ) -> ReadManyResult<V, impl Stream<Item = (ReadSource, ReadManyArgs<V>)>>
+    where
+        V: IoVec + Unpin,
+        S: Stream<Item = V> + Unpin,
+    {
+        self.read_many_from(iovs, buffer_limit, read_amp_limit, None)
+    }
+
+    // `read_many`, optionally serving the reads from a mapping of the file
+    // instead of the ring. There is a single stream type either way, so
+    // callers that may or may not have a mapping need not be generic over it.
+    pub(super) fn read_many_from<V, S>(
+        self: &Rc<DmaFile>,
+        iovs: S,
+        buffer_limit: MergedBufferLimit,
+        read_amp_limit: ReadAmplificationLimit,
+        mapping: Option<Rc<FileMapping>>,
+    ) -> ReadManyResult<V, impl Stream<Item = (ReadSource, ReadManyArgs<V>)>>
     where
         V: IoVec + Unpin,
         S: Stream<Item = V> + Unpin,
@@ -538,18 +564,30 @@ impl DmaFile {
 
         let file = self.clone();
         let reactor = file.file.reactor.upgrade().unwrap();
-        let it = CoalescedReads::new(
-            max_merged_buffer_size,
-            max_read_amp,
-            Some(self.o_direct_alignment),
-            iovs,
-        )
-        .map(move |iov| {
-            let fd = file.as_raw_fd();
-            let pollable = file.pollable;
-            let scheduler = file.file.scheduler.borrow();
+        // mapped reads have no alignment requirements
+        let alignment = mapping.is_none().then_some(self.o_direct_alignment);
+        let coalesced = CoalescedReads::new(max_merged_buffer_size, max_read_amp, alignment, iovs);
+        // mapped reads do not reach the device, so only the others are limited
+        let throttled = Throttled::new(
+            coalesced,
+            self.rate_limiter.borrow().clone(),
+            mapping.is_none(),
+            |iov| iov.size() as u64,
+        );
+        let it = throttled.map(move |iov| {
+            let source = match &mapping {
+                Some(mapping) => ReadSource::mapped(mapping, iov.pos(), iov.size()),
+                None => {
+                    let fd = file.as_raw_fd();
+                    let pollable = file.pollable;
+                    let scheduler = file.file.scheduler.borrow();
+                    reactor
+                        .read_dma(fd, iov.pos(), iov.size(), pollable, scheduler.as_ref())
+                        .into()
+                }
+            };
             (
-                reactor.read_dma(fd, iov.pos(), iov.size(), pollable, scheduler.as_ref()),
+                source,
                 ReadManyArgs {
                     user_reads: iov.coalesced_user_iovecs,
                     system_read: (iov.pos, iov.size),
@@ -562,6 +600,36 @@ impl DmaFile {
         }
     }
 
+    /// Limits the rate of the I/O issued on this file, or lifts the limit if
+    /// `limiter` is `None`.
+    ///
+    /// This applies to reads and writes issued from now on, including those of
+    /// streams built from this file, on top of any limit on the task queue
+    /// issuing them. See [`IoRateLimiter`] for details. The limiter is not
+    /// carried over to an [`OwnedDmaFile`].
+    pub fn set_rate_limiter(&self, limiter: Option<IoRateLimiter>) {
+        self.rate_limiter.replace(limiter);
+    }
+
+    /// Returns the limiter set with [`DmaFile::set_rate_limiter`], if any.
+    pub fn rate_limiter(&self) -> Option<IoRateLimiter> {
+        self.rate_limiter.borrow().clone()
+    }
+
+    // Waits for the limiters that apply to I/O on this file, if any, to allow
+    // `bytes` more of it.
+    async fn throttle(&self, bytes: usize) {
+        let delay = self
+            .file
+            .reactor
+            .upgrade()
+            .unwrap()
+            .throttle(self.rate_limiter.borrow().as_ref(), bytes as u64);
+        if !delay.is_zero() {
+            crate::timer::sleep(delay).await;
+        }
+    }
+
     /// Copies a file range from one file to another in kernel space. This is going to have the same performance
     /// characteristic as splice except if both files are on the same filesystem and the filesystem supports reflinks.
     /// In that case, the underlying disk blocks will be CoW linked instead of actually performing a copy.
@@ -595,6 +663,62 @@ impl DmaFile {
         self.file.fdatasync().await
     }
 
+    /// Tells the kernel how the range `[offset, offset + len)` of this file
+    /// will be accessed, through `posix_fadvise(2)`. A `len` of zero extends
+    /// the range to the end of the file.
+    ///
+    /// As this is a DMA file, reads and writes bypass the page cache, so
+    /// this mostly matters to other, buffered, users of the same file.
+    ///
+    /// Note: this syscall might be issued in a background thread depending on
+    /// the system's capabilities.
+    pub async fn fadvise(&self, offset: u64, len: u64, advice: FileAdvice) -> Result<()> {
+        self.file.fadvise(offset, len, advice).await
+    }
+
+    /// Starts and/or waits for writeback of the range `[offset, offset + len)`
+    /// of this file, through `sync_file_range(2)`. A `len` of zero extends the
+    /// range to the end of the file.
+    ///
+    /// This allows flushing a file incrementally, so that a final
+    /// [`fdatasync`] has little left to do. It is not a substitute for it:
+    /// it neither flushes the device's cache nor persists metadata, so it
+    /// provides no durability guarantees of its own.
+    ///
+    /// Note: this syscall might be issued in a background thread depending on
+    /// the system's capabilities.
+    ///
+    /// [`fdatasync`]: DmaFile::fdatasync
+    pub async fn sync_range(&self, offset: u64, len: u64, flags: SyncRangeFlags) -> Result<()> {
+        self.file.sync_range(offset, len, flags).await
+    }
+
+    /// Returns the value of the extended attribute `name` of this file, or
+    /// `None` if it has no such attribute.
+    ///
+    /// Note: this syscall might be issued in a background thread depending on
+    /// the system's capabilities.
+    pub async fn get_xattr<N: AsRef<OsStr>>(&self, name: N) -> Result<Option<Vec<u8>>> {
+        self.file.get_xattr(name.as_ref()).await
+    }
+
+    /// Sets the extended attribute `name` of this file to `value`, creating
+    /// it if needed.
+    ///
+    /// Note: this syscall might be issued in a background thread depending on
+    /// the system's capabilities.
+    pub async fn set_xattr<N: AsRef<OsStr>>(&self, name: N, value: &[u8]) -> Result<()> {
+        self.file.set_xattr(name.as_ref(), value).await
+    }
+
+    /// Returns the names of the extended attributes of this file.
+    ///
+    /// Note: this syscall is always issued in a background thread, as
+    /// io_uring has no equivalent.
+    pub async fn list_xattrs(&self) -> Result<Vec<OsString>> {
+        self.file.list_xattr().await
+    }
+
     /// Returns the alignment required for I/O operations. Typical values will
     /// be 512 (NVME drive is configured in slower compat mode) or 4096
     /// (typical TLC native alignment).
@@ -659,6 +783,12 @@ impl DmaFile {
         self.file.rename(new_path).await
     }
 
+    // Gives a name to this file, which is how an `O_TMPFILE` file becomes
+    // visible. Not public: the only user is the atomic writer.
+    pub(super) async fn link_at<P: AsRef<Path>>(&self, dir: RawFd, new_path: P) -> Result<()> {
+        self.file.link_at(dir, new_path).await
+    }
+
     /// Remove this file.
     ///
     /// The file does not have to be closed to be removed. Removing removes
@@ -1085,6 +1215,7 @@ impl From<OwnedDmaFile> for DmaFile {
             max_sectors_size: value.max_sectors_size,
             max_segment_size: value.max_segment_size,
             pollable: value.pollable,
+            rate_limiter: Default::default(),
         }
     }
 }
@@ -1180,6 +1311,7 @@ impl WeakDmaFile {
 #[cfg(test)]
 pub(crate) mod test {
     use super::*;
+    use crate::io::IoRateLimit;
     use crate::{
         enclose,
         sync::Semaphore,
@@ -1188,6 +1320,8 @@ pub(crate) mod test {
         timer::{sleep, try_timeout as timeout},
         ByteSliceMutExt,
         GlommioError,
+        IoPriority,
+        IoPriorityClass,
         Latency,
         LocalExecutor,
         ResourceType,
@@ -1196,7 +1330,13 @@ pub(crate) mod test {
     use futures::join;
     use futures_lite::{stream, StreamExt};
     use rand::{rng, seq::SliceRandom};
-    use std::{cell::RefCell, convert::TryInto, ops::Deref, path::PathBuf, time::Duration};
+    use std::{
+        cell::RefCell,
+        convert::TryInto,
+        ops::Deref,
+        path::PathBuf,
+        time::{Duration, Instant},
+    };
 
     macro_rules! dma_file_test {
         ( $name:ident, $dir:ident, $kind:ident, $code:block) => {
@@ -1346,6 +1486,266 @@ pub(crate) mod test {
         new_file.close().await.expect("failed to close file");
     });
 
+    dma_file_test!(file_fadvise_and_sync_range, path, _k, {
+        let new_file = DmaFile::create(path.join("testfile"))
+            .await
+            .expect("failed to create file");
+        let mut buf = new_file.alloc_dma_buffer(4096);
+        buf.memset(7);
+        new_file.write_at(buf, 0).await.expect("failed to write");
+
+        new_file
+            .fadvise(0, 0, FileAdvice::Random)
+            .await
+            .expect("fadvise failed");
+        // larger than the ring can express, so it takes the blocking path
+        new_file
+            .fadvise(0, (1 << 32) + 4096, FileAdvice::DontNeed)
+            .await
+            .expect("fadvise failed");
+        new_file
+            .sync_range(
+                0,
+                4096,
+                SyncRangeFlags::WAIT_BEFORE | SyncRangeFlags::WRITE | SyncRangeFlags::WAIT_AFTER,
+            )
+            .await
+            .expect("sync_file_range failed");
+        // larger than the ring can express, so it takes the blocking path
+        new_file
+            .sync_range(0, 1 << 33, SyncRangeFlags::WRITE)
+            .await
+            .expect("sync_file_range failed");
+
+        new_file.close().await.expect("failed to close file");
+    });
+
+    dma_file_test!(file_xattrs, path, _k, {
+        let new_file = DmaFile::create(path.join("testfile"))
+            .await
+            .expect("failed to create file");
+
+        match new_file.set_xattr("user.glommio.small", b"v1").await {
+            Err(GlommioError::EnhancedIoError { source, .. })
+                if source.raw_os_error() == Some(libc::EOPNOTSUPP) =>
+            {
+                // user xattrs need a filesystem that has them
+                new_file.close().await.unwrap();
+                return;
+            }
+            res => res.expect("failed to set xattr"),
+        }
+        // larger than the first buffer tried, to go through a size query
+        let large = vec![b'x'; 1000];
+        new_file
+            .set_xattr("user.glommio.large", &large)
+            .await
+            .expect("failed to set xattr");
+
+        assert_eq!(
+            new_file.get_xattr("user.glommio.small").await.unwrap(),
+            Some(b"v1".to_vec())
+        );
+        assert_eq!(
+            new_file.get_xattr("user.glommio.large").await.unwrap(),
+            Some(large)
+        );
+        assert_eq!(
+            new_file.get_xattr("user.glommio.missing").await.unwrap(),
+            None
+        );
+
+        let mut names = new_file.list_xattrs().await.unwrap();
+        names.retain(|name| name.to_str().unwrap().starts_with("user.glommio."));
+        names.sort();
+        assert_eq!(names, vec!["user.glommio.large", "user.glommio.small"]);
+
+        new_file
+            .get_xattr("user.with\0nul")
+            .await
+            .expect_err("names cannot contain NUL bytes");
+
+        new_file.close().await.expect("failed to close file");
+    });
+
+    dma_file_test!(file_rate_limited, path, _k, {
+        let new_file = Rc::new(
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .dma_open(path.join("testfile"))
+                .await
+                .expect("failed to create file"),
+        );
+        // 20 operations per second, with no burst
+        let limiter = IoRateLimiter::new(IoRateLimit::new().with_ops_per_sec(20, 1));
+        new_file.set_rate_limiter(Some(limiter.clone()));
+        assert!(new_file.rate_limiter().is_some());
+
+        let start = Instant::now();
+        for i in 0..4 {
+            let buf = new_file.alloc_dma_buffer(4096);
+            new_file.write_at(buf, i * 4096).await.unwrap();
+        }
+        let iovs = (0..4).map(|i| (i * 4096, 4096));
+        let reads = new_file
+            .read_many(
+                stream::iter(iovs),
+                MergedBufferLimit::NoMerging,
+                ReadAmplificationLimit::NoAmplification,
+            )
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(reads.len(), 4);
+        // the first write is allowed right away, the other 7 operations wait
+        assert!(start.elapsed() >= Duration::from_millis(350));
+
+        let (throttled, throttled_for) = crate::executor().io_stats().all_rings().io_throttled();
+        assert_eq!(throttled, 7);
+        assert!(throttled_for >= Duration::from_millis(300));
+
+        new_file.set_rate_limiter(None);
+        new_file.read_at(0, 4096).await.unwrap();
+        // executor stats reset when read, so anything here is new
+        let (throttled, _) = crate::executor().io_stats().all_rings().io_throttled();
+        assert_eq!(throttled, 0);
+
+        new_file.close_rc().await.expect("failed to close file");
+    });
+
+    dma_file_test!(task_queue_rate_limited, path, _k, {
+        let new_file = Rc::new(
+            DmaFile::create(path.join("testfile"))
+                .await
+                .expect("failed to create file"),
+        );
+        let tq = crate::executor().create_task_queue(
+            Shares::default(),
+            Latency::NotImportant,
+            "limited",
+        );
+        crate::executor()
+            .set_task_queue_io_limiter(
+                tq,
+                Some(IoRateLimiter::new(
+                    IoRateLimit::new().with_bytes_per_sec(40960, 4096),
+                )),
+            )
+            .unwrap();
+
+        let start = Instant::now();
+        crate::spawn_local_into(
+            enclose! { (new_file) async move {
+                for i in 0..3 {
+                    let buf = new_file.alloc_dma_buffer(4096);
+                    new_file.write_at(buf, i * 4096).await.unwrap();
+                }
+            }},
+            tq,
+        )
+        .unwrap()
+        .await;
+        // 4 KiB are allowed right away, the other 8 KiB take 200ms
+        assert!(start.elapsed() >= Duration::from_millis(190));
+        let stats = crate::executor().task_queue_io_stats(tq).unwrap();
+        assert_eq!(stats.all_rings().io_throttled().0, 2);
+
+        // I/O from other task queues is not limited
+        let throttled = || crate::executor().io_stats().all_rings().io_throttled().0;
+        throttled();
+        let buf = new_file.alloc_dma_buffer(4096);
+        new_file.write_at(buf, 0).await.unwrap();
+        assert_eq!(throttled(), 0);
+
+        crate::executor()
+            .set_task_queue_io_limiter(tq, None)
+            .unwrap();
+        new_file.close_rc().await.expect("failed to close file");
+    });
+
+    dma_file_test!(task_queue_io_priority, path, _k, {
+        assert_eq!(IoPriority::realtime(0).to_ioprio(), 1 << 13);
+        assert_eq!(IoPriority::best_effort(4).to_ioprio(), (2 << 13) | 4);
+        assert_eq!(IoPriority::idle().to_ioprio(), 3 << 13);
+
+        let new_file = Rc::new(
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .dma_open(path.join("testfile"))
+                .await
+                .expect("failed to create file"),
+        );
+        let prioritized = |name, priority| {
+            let tq =
+                crate::executor().create_task_queue(Shares::default(), Latency::NotImportant, name);
+            crate::executor()
+                .set_task_queue_io_priority(tq, Some(priority))
+                .unwrap();
+            tq
+        };
+        let best_effort = prioritized("best-effort", IoPriority::best_effort(2));
+        let idle = prioritized("idle", IoPriority::idle());
+
+        for tq in [best_effort, idle] {
+            crate::spawn_local_into(
+                enclose! { (new_file) async move {
+                    let buf = new_file.alloc_dma_buffer(4096);
+                    new_file.write_at(buf, 0).await.unwrap();
+                    new_file.read_at(0, 4096).await.unwrap();
+                }},
+                tq,
+            )
+            .unwrap()
+            .await;
+        }
+        // I/O from task queues without a priority is not attributed to a class
+        new_file.read_at(0, 4096).await.unwrap();
+
+        let stats = crate::executor().io_stats().all_rings();
+        assert_eq!(
+            stats
+                .io_latency_us_for_class(IoPriorityClass::Realtime)
+                .count(),
+            0
+        );
+        assert!(
+            stats
+                .io_latency_us_for_class(IoPriorityClass::BestEffort)
+                .count()
+                > 0
+        );
+        let idle_stats = crate::executor()
+            .task_queue_io_stats(idle)
+            .unwrap()
+            .all_rings();
+        let idle_count = idle_stats
+            .io_latency_us_for_class(IoPriorityClass::Idle)
+            .count();
+        assert!(idle_count > 0);
+        assert_eq!(idle_count, idle_stats.io_latency_us().count());
+
+        // the running task queue picks up its new priority right away
+        crate::executor()
+            .set_task_queue_io_priority(
+                crate::executor().current_task_queue(),
+                Some(IoPriority::best_effort(7)),
+            )
+            .unwrap();
+        new_file.read_at(0, 4096).await.unwrap();
+        let stats = crate::executor().io_stats().all_rings();
+        assert_eq!(
+            stats
+                .io_latency_us_for_class(IoPriorityClass::BestEffort)
+                .count(),
+            1
+        );
+
+        new_file.close_rc().await.expect("failed to close file");
+    });
+
     dma_file_test!(file_path, path, _k, {
         let new_file = DmaFile::create(path.join("testfile"))
             .await
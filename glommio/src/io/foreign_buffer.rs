@@ -0,0 +1,205 @@
+//! Buffers handed to another executor without copying them.
+
+use std::{
+    fmt,
+    ops::Deref,
+    ptr::{self, NonNull},
+};
+
+use crate::{
+    io::ReadResult,
+    sys::{DmaBuffer, Parked},
+};
+
+/// The bytes of a [`DmaBuffer`] or a [`ReadResult`], handed over to another
+/// executor without copying them.
+///
+/// Buffers come from the allocator of the executor that did the I/O, which
+/// only that executor may touch, so they cannot be sent themselves. Turning
+/// one into a `ForeignBuffer` leaves it parked where it is, and what travels
+/// is a read-only view of its bytes, which can go through a
+/// [`shared_channel`] like any other message. Dropping the view on another
+/// executor queues the buffer to be freed by its owner, which does so the next
+/// time it polls for events, waking up for it if it was asleep. If the owner
+/// is gone by then, the buffer is leaked.
+///
+/// # Examples
+///
+/// ```
+/// use glommio::{io::ForeignBuffer, LocalExecutor, LocalExecutorBuilder, Placement};
+///
+/// LocalExecutor::default().run(async {
+///     let mut buffer = glommio::allocate_dma_buffer(4096);
+///     buffer.as_bytes_mut().fill(7);
+///     let buffer = ForeignBuffer::from(buffer);
+///
+///     LocalExecutorBuilder::new(Placement::Unbound)
+///         .spawn(move || async move { assert!(buffer.iter().all(|b| *b == 7)) })
+///         .unwrap()
+///         .join()
+///         .unwrap();
+/// });
+/// ```
+///
+/// [`shared_channel`]: crate::channels::shared_channel
+pub struct ForeignBuffer {
+    data: NonNull<u8>,
+    len: usize,
+    owner: Parked,
+}
+
+// SAFETY: the bytes are only ever read, and stay put for as long as the
+// parked buffer they belong to, which goes back to its executor to be dropped
+unsafe impl Send for ForeignBuffer {}
+unsafe impl Sync for ForeignBuffer {}
+
+impl ForeignBuffer {
+    fn new(data: *const u8, len: usize, owner: Parked) -> ForeignBuffer {
+        ForeignBuffer {
+            data: NonNull::new(data as *mut u8).unwrap_or(NonNull::dangling()),
+            len,
+            owner,
+        }
+    }
+
+    /// Returns the bytes of the buffer.
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.data.as_ptr(), self.len) }
+    }
+
+    /// Returns the number of bytes in the buffer.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Indicates whether the buffer is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the id of the executor the buffer is freed by.
+    pub fn owner_id(&self) -> usize {
+        self.owner.executor_id()
+    }
+}
+
+impl fmt::Debug for ForeignBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ForeignBuffer")
+            .field("len", &self.len)
+            .field("owner_id", &self.owner_id())
+            .finish()
+    }
+}
+
+impl Deref for ForeignBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl AsRef<[u8]> for ForeignBuffer {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl From<DmaBuffer> for ForeignBuffer {
+    /// Parks the buffer on the current executor, which must be the one it was
+    /// allocated by.
+    fn from(buffer: DmaBuffer) -> Self {
+        let (data, len) = (buffer.as_ptr(), buffer.len());
+        ForeignBuffer::new(data, len, Parked::new(Box::new(buffer)))
+    }
+}
+
+impl From<ReadResult> for ForeignBuffer {
+    /// Parks the result on the current executor, which must be the one that
+    /// read it.
+    fn from(result: ReadResult) -> Self {
+        let (data, len) = if result.is_empty() {
+            (ptr::null(), 0)
+        } else {
+            (result.as_ptr(), result.len())
+        };
+        ForeignBuffer::new(data, len, Parked::new(Box::new(result)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{rc::Rc, time::Duration};
+
+    use super::*;
+    use crate::{
+        io::OpenOptions, test_utils::make_tmp_test_directory, timer::sleep, LocalExecutor,
+        LocalExecutorBuilder,
+    };
+
+    fn on_another_executor<F: FnOnce() + Send + 'static>(f: F) {
+        LocalExecutorBuilder::default()
+            .spawn(move || async move { f() })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn a_buffer_dropped_elsewhere_returns_to_its_allocator() {
+        LocalExecutor::default().run(async {
+            let mut buffer = crate::allocate_dma_buffer(4096);
+            buffer.as_bytes_mut().fill(42);
+            let addr = buffer.as_ptr();
+
+            let buffer = ForeignBuffer::from(buffer);
+            assert_eq!(buffer.owner_id(), crate::executor().id());
+            on_another_executor(move || {
+                assert_eq!(buffer.len(), 4096);
+                assert!(buffer.iter().all(|b| *b == 42));
+            });
+
+            // Freed once the reactor got to the queue, after which the same
+            // block is handed out again
+            let mut attempts = 0;
+            loop {
+                let buffer = crate::allocate_dma_buffer(4096);
+                if buffer.as_ptr() == addr {
+                    break;
+                }
+                attempts += 1;
+                assert!(attempts < 1000, "the buffer was never freed");
+                sleep(Duration::from_millis(1)).await;
+            }
+        });
+    }
+
+    #[test]
+    fn read_results_cross_executors() {
+        let dir = make_tmp_test_directory("read_results_cross_executors");
+        let path = dir.path.join("file");
+
+        LocalExecutor::default().run(async move {
+            let file = OpenOptions::new()
+                .create(true)
+                .read(true)
+                .write(true)
+                .dma_open(&path)
+                .await
+                .unwrap();
+            let file = Rc::new(file);
+            let mut buffer = file.alloc_dma_buffer(4096);
+            buffer.as_bytes_mut().fill(3);
+            file.write_at(buffer, 0).await.unwrap();
+
+            let read = ForeignBuffer::from(file.read_at(1, 10).await.unwrap());
+            let empty = ForeignBuffer::from(file.read_at(4096, 10).await.unwrap());
+            on_another_executor(move || {
+                assert_eq!(read.as_bytes(), &[3; 10]);
+                assert!(empty.is_empty());
+            });
+            file.close_rc().await.unwrap();
+        });
+    }
+}
@@ -7,17 +7,22 @@
 use crate::{
     io::sched::FileScheduler,
     reactor::Reactor,
-    sys::{self, Statx},
+    sys::{self, Source, SourceType, Statx},
     GlommioError, ResourceType,
 };
 use log::debug;
 use std::{
     cell::{Ref, RefCell},
     convert::TryInto,
+    ffi::{CString, OsStr, OsString},
+    future::Future,
     io,
     os::{
         fd::{BorrowedFd, IntoRawFd, OwnedFd},
-        unix::io::{AsFd, AsRawFd, FromRawFd, RawFd},
+        unix::{
+            ffi::{OsStrExt, OsStringExt},
+            io::{AsFd, AsRawFd, FromRawFd, RawFd},
+        },
     },
     path::{Path, PathBuf},
     rc::{Rc, Weak},
@@ -30,6 +35,71 @@ pub(super) type Device = u64;
 pub(super) type Inode = u64;
 pub(super) type Identity = (Device, Inode);
 
+/// Access pattern hint for a range of a file, given to the kernel through
+/// `posix_fadvise(2)`.
+///
+/// Mostly useful on a [`BufferedFile`], where it drives readahead and what
+/// stays in the page cache.
+///
+/// [`BufferedFile`]: super::BufferedFile
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileAdvice {
+    /// No particular pattern. Resets any earlier advice.
+    Normal,
+    /// The range will be read from start to end, so readahead can be more
+    /// aggressive.
+    Sequential,
+    /// The range will be read in random order, so readahead is disabled.
+    Random,
+    /// The range will be accessed only once.
+    NoReuse,
+    /// The range will be needed soon, so the kernel starts reading it into
+    /// the page cache right away.
+    WillNeed,
+    /// The range will not be needed soon, so its clean pages can be dropped
+    /// from the page cache.
+    DontNeed,
+}
+
+impl FileAdvice {
+    fn as_raw(self) -> libc::c_int {
+        match self {
+            FileAdvice::Normal => libc::POSIX_FADV_NORMAL,
+            FileAdvice::Sequential => libc::POSIX_FADV_SEQUENTIAL,
+            FileAdvice::Random => libc::POSIX_FADV_RANDOM,
+            FileAdvice::NoReuse => libc::POSIX_FADV_NOREUSE,
+            FileAdvice::WillNeed => libc::POSIX_FADV_WILLNEED,
+            FileAdvice::DontNeed => libc::POSIX_FADV_DONTNEED,
+        }
+    }
+}
+
+bitflags::bitflags! {
+    /// What a `sync_file_range(2)` call waits for and starts, see
+    /// [`DmaFile::sync_range`].
+    ///
+    /// [`DmaFile::sync_range`]: super::DmaFile::sync_range
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct SyncRangeFlags: libc::c_uint {
+        /// Wait for writeback already in flight on the range to finish before
+        /// doing anything else.
+        const WAIT_BEFORE = libc::SYNC_FILE_RANGE_WAIT_BEFORE;
+        /// Start writeback of the dirty pages in the range.
+        const WRITE = libc::SYNC_FILE_RANGE_WRITE;
+        /// Wait for the writeback of the range to finish.
+        const WAIT_AFTER = libc::SYNC_FILE_RANGE_WAIT_AFTER;
+    }
+}
+
+fn xattr_name(name: &OsStr) -> io::Result<CString> {
+    CString::new(name.as_bytes()).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "xattr name contains a NUL byte",
+        )
+    })
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum AdvisoryLockState {
     Unlocked = 0,
@@ -531,6 +601,25 @@ impl GlommioFile {
         Ok(())
     }
 
+    pub(crate) async fn link_at<P: AsRef<Path>>(&self, dir: RawFd, new_path: P) -> Result<()> {
+        let source = self
+            .reactor
+            .upgrade()
+            .unwrap()
+            .link_at(self.as_raw_fd(), dir, new_path.as_ref())
+            .await;
+
+        source.collect_rw().await.map_err(|source| {
+            GlommioError::create_enhanced(
+                source,
+                "Linking",
+                Some(new_path.as_ref()),
+                Some(self.as_raw_fd()),
+            )
+        })?;
+        Ok(())
+    }
+
     pub(crate) async fn fdatasync(&self) -> Result<()> {
         let source = self.reactor.upgrade().unwrap().fdatasync(self.as_raw_fd());
         source.collect_rw().await.map_err(|source| {
@@ -559,6 +648,123 @@ impl GlommioFile {
         Ok(())
     }
 
+    pub(crate) async fn fadvise(&self, offset: u64, len: u64, advice: FileAdvice) -> Result<()> {
+        let source = self
+            .reactor
+            .upgrade()
+            .unwrap()
+            .fadvise(self.as_raw_fd(), offset, len, advice.as_raw())
+            .await;
+        source
+            .collect_rw()
+            .await
+            .map_err(|source| self.enhance(source, "Advising"))?;
+        Ok(())
+    }
+
+    pub(crate) async fn sync_range(
+        &self,
+        offset: u64,
+        len: u64,
+        flags: SyncRangeFlags,
+    ) -> Result<()> {
+        let source = self
+            .reactor
+            .upgrade()
+            .unwrap()
+            .sync_file_range(self.as_raw_fd(), offset, len, flags.bits())
+            .await;
+        source
+            .collect_rw()
+            .await
+            .map_err(|source| self.enhance(source, "Syncing range"))?;
+        Ok(())
+    }
+
+    pub(crate) async fn get_xattr(&self, name: &OsStr) -> Result<Option<Vec<u8>>> {
+        let name = xattr_name(name)?;
+        let reactor = self.reactor.upgrade().unwrap();
+        self.read_xattr_buffer("Getting xattr", |len| {
+            reactor.get_xattr(self.as_raw_fd(), name.clone(), len)
+        })
+        .await
+    }
+
+    pub(crate) async fn set_xattr(&self, name: &OsStr, value: &[u8]) -> Result<()> {
+        let name = xattr_name(name)?;
+        let source = self
+            .reactor
+            .upgrade()
+            .unwrap()
+            .set_xattr(self.as_raw_fd(), name, value.to_vec(), 0)
+            .await;
+        source
+            .collect_rw()
+            .await
+            .map_err(|source| self.enhance(source, "Setting xattr"))?;
+        Ok(())
+    }
+
+    pub(crate) async fn list_xattr(&self) -> Result<Vec<OsString>> {
+        let reactor = self.reactor.upgrade().unwrap();
+        let names = self
+            .read_xattr_buffer("Listing xattrs", |len| {
+                reactor.list_xattr(self.as_raw_fd(), len)
+            })
+            .await?
+            .unwrap_or_default();
+        Ok(names
+            .split(|b| *b == 0)
+            .filter(|name| !name.is_empty())
+            .map(|name| OsString::from_vec(name.to_vec()))
+            .collect())
+    }
+
+    // The xattr calls that fill a buffer fail with ERANGE if it is too small,
+    // and report the size they need when given an empty one. Starts with a
+    // buffer that fits most values and asks for the size only if that fails.
+    async fn read_xattr_buffer<F, Fut>(&self, op: &'static str, call: F) -> Result<Option<Vec<u8>>>
+    where
+        F: Fn(usize) -> Fut,
+        Fut: Future<Output = Source>,
+    {
+        let mut len = 256;
+        loop {
+            let source = call(len).await;
+            match source.collect_rw().await {
+                Ok(size) => {
+                    let mut value = match source.extract_source_type() {
+                        SourceType::Xattr(_, value) => value,
+                        _ => unreachable!("xattr calls use xattr sources"),
+                    };
+                    value.truncate(size);
+                    return Ok(Some(value));
+                }
+                Err(err) if err.raw_os_error() == Some(libc::ENODATA) => return Ok(None),
+                Err(err) if err.raw_os_error() == Some(libc::ERANGE) => {
+                    // the value may change between the two calls, so loop
+                    // until it fits
+                    let size = call(0)
+                        .await
+                        .collect_rw()
+                        .await
+                        .map_err(|source| self.enhance(source, op))?;
+                    len = size.max(1);
+                }
+                Err(err) => return Err(self.enhance(err, op)),
+            }
+        }
+    }
+
+    fn enhance(&self, source: io::Error, op: &'static str) -> GlommioError<()> {
+        GlommioError::create_enhanced(
+            source,
+            op,
+            self.path.borrow().as_ref(),
+            Some(self.as_raw_fd()),
+        )
+    }
+
     // Retrieve file metadata, backed by the statx(2) syscall
     pub(crate) async fn statx(&self) -> Result<Statx> {
         let source = self.reactor.upgrade().unwrap().statx(self.as_raw_fd());
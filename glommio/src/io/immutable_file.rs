@@ -5,14 +5,17 @@
 //
 use crate::io::{
     bulk_io::{MergedBufferLimit, ReadAmplificationLimit, ReadManyArgs},
+    mmap::{FileMapping, MmapAdvice},
     open_options::OpenOptions,
-    DmaStreamReaderBuilder, DmaStreamWriter, DmaStreamWriterBuilder, IoVec, ReadManyResult,
-    ReadResult, ScheduledSource,
+    read_result::ReadSource,
+    DmaFile, DmaStreamReaderBuilder, DmaStreamWriter, DmaStreamWriterBuilder, IoVec,
+    ReadManyResult, ReadResult,
 };
 use futures_lite::{future::poll_fn, io::AsyncWrite, Stream};
 use std::{
     cell::Ref,
     io,
+    os::unix::io::AsRawFd,
     path::Path,
     pin::Pin,
     rc::Rc,
@@ -43,6 +46,7 @@ where
     flush_disabled: bool,
     pre_allocate: Option<u64>,
     hint_extent_size: Option<usize>,
+    mmap: Option<MmapAdvice>,
     path: P,
 }
 
@@ -55,6 +59,7 @@ where
 /// [`ImmutableFileBuilder`]: ImmutableFileBuilder
 pub struct ImmutableFilePreSealSink {
     writer: DmaStreamWriter,
+    mmap: Option<MmapAdvice>,
 }
 
 #[derive(Debug, Clone)]
@@ -80,6 +85,7 @@ pub struct ImmutableFilePreSealSink {
 pub struct ImmutableFile {
     stream_builder: DmaStreamReaderBuilder,
     size: u64,
+    mapping: Option<Rc<FileMapping>>,
 }
 
 impl<P> ImmutableFileBuilder<P>
@@ -111,6 +117,7 @@ where
             flush_disabled: false,
             pre_allocate: None,
             hint_extent_size: None,
+            mmap: None,
         }
     }
 
@@ -183,6 +190,32 @@ where
         self
     }
 
+    /// Memory-maps the file once it is sealed, or opened with
+    /// [`build_existing`], and serves [`read_at`] and [`read_many`] from the
+    /// mapping instead of issuing Direct I/O.
+    ///
+    /// Reads then cost a copy-free slice of the page cache, which suits small,
+    /// hot, randomly accessed files such as indexes and bloom filters. The
+    /// flip side is that a read of a page not in memory blocks the executor
+    /// while the page fault is served. Glommio takes the fault at read time,
+    /// so it is charged to the reading task queue and shows up in
+    /// [`RingIoStats::mapped_prefault_latency_us`] and in the stall detector.
+    ///
+    /// `advice` is passed to the kernel through `madvise(2)`. Sequential
+    /// access through [`stream_reader`] still uses Direct I/O. Empty files
+    /// are never mapped.
+    ///
+    /// [`build_existing`]: ImmutableFileBuilder::build_existing
+    /// [`read_at`]: ImmutableFile::read_at
+    /// [`read_many`]: ImmutableFile::read_many
+    /// [`stream_reader`]: ImmutableFile::stream_reader
+    /// [`RingIoStats::mapped_prefault_latency_us`]: crate::RingIoStats::mapped_prefault_latency_us
+    #[must_use = "The builder must be built to be useful"]
+    pub fn with_memory_map(mut self, advice: Option<MmapAdvice>) -> Self {
+        self.mmap = advice;
+        self
+    }
+
     /// Builds an [`ImmutableFilePreSealSink`] with the properties defined by
     /// this builder.
     ///
@@ -213,7 +246,10 @@ where
             .with_write_behind(self.concurrency)
             .build();
 
-        Ok(ImmutableFilePreSealSink { writer })
+        Ok(ImmutableFilePreSealSink {
+            writer,
+            mmap: self.mmap,
+        })
     }
 
     /// Builds an [`ImmutableFile`] with the properties defined by this
@@ -235,6 +271,7 @@ where
         );
         file.attach_scheduler();
         let size = file.file_size().await?;
+        let mapping = map_file(&file, size, self.mmap)?;
         let stream_builder = DmaStreamReaderBuilder::from_rc(file)
             .with_buffer_size(self.buffer_size)
             .with_read_ahead(self.concurrency);
@@ -242,6 +279,7 @@ where
         Ok(ImmutableFile {
             stream_builder,
             size,
+            mapping,
         })
     }
 }
@@ -256,9 +294,11 @@ impl ImmutableFilePreSealSink {
         stream_builder.file.attach_scheduler();
 
         let size = stream_builder.file.file_size().await?;
+        let mapping = map_file(&stream_builder.file, size, self.mmap)?;
         Ok(ImmutableFile {
             stream_builder,
             size,
+            mapping,
         })
     }
 
@@ -373,8 +413,17 @@ impl ImmutableFile {
     /// It is not necessary to respect the `O_DIRECT` alignment of the file, and
     /// this API will internally convert the positions and sizes to match,
     /// at a cost.
+    ///
+    /// If the file is memory-mapped, the read is served from the mapping.
     pub async fn read_at(&self, pos: u64, size: usize) -> Result<ReadResult> {
-        self.stream_builder.file.read_at(pos, size).await
+        match &self.mapping {
+            Some(mapping) => {
+                let source = ReadSource::mapped(mapping, pos, size);
+                let len = source.result().unwrap()?;
+                Ok(ReadResult::from_sliced_buffer(source, 0, len))
+            }
+            None => self.stream_builder.file.read_at(pos, size).await,
+        }
     }
 
     /// Submit many reads and process the results in a stream-like fashion via a
@@ -397,14 +446,17 @@ impl ImmutableFile {
         iovs: S,
         buffer_limit: MergedBufferLimit,
         read_amp_limit: ReadAmplificationLimit,
-    ) -> ReadManyResult<V, impl Stream<Item = (ScheduledSource, ReadManyArgs<V>)>>
+    ) -> ReadManyResult<V, impl Stream<Item = (ReadSource, ReadManyArgs<V>)>>
     where
         V: IoVec + Unpin,
         S: Stream<Item = V> + Unpin,
     {
-        self.stream_builder
-            .file
-            .read_many(iovs, buffer_limit, read_amp_limit)
+        self.stream_builder.file.read_many_from(
+            iovs,
+            buffer_limit,
+            read_amp_limit,
+            self.mapping.clone(),
+        )
     }
 
     /// Rename this file.
@@ -442,6 +494,24 @@ impl ImmutableFile {
     }
 }
 
+fn map_file(
+    file: &DmaFile,
+    size: u64,
+    advice: Option<MmapAdvice>,
+) -> Result<Option<Rc<FileMapping>>> {
+    match advice {
+        Some(advice) if size > 0 => {
+            let mapping = enhanced_try!(
+                FileMapping::new(file.as_raw_fd(), size as usize, advice),
+                "Memory-mapping",
+                file
+            )?;
+            Ok(Some(Rc::new(mapping)))
+        }
+        _ => Ok(None),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -577,4 +647,82 @@ mod test {
 
         stream.close().await.unwrap();
     });
+
+    immutable_file_test!(mapped_read_at, path, {
+        let fname = path.join("testfile");
+        let mut immutable = ImmutableFileBuilder::new(fname)
+            .with_memory_map(Some(MmapAdvice::Random))
+            .build_sink()
+            .await
+            .unwrap();
+        immutable.write_all(&[0, 1, 2, 3, 4, 5]).await.unwrap();
+        let stream = immutable.seal().await.unwrap();
+        assert!(stream.mapping.is_some());
+
+        let _ = crate::executor().io_stats();
+        let buf = stream.read_at(1, 4).await.unwrap();
+        assert_eq!(&*buf, &[1, 2, 3, 4]);
+        let buf = stream.read_at(4, 100).await.unwrap();
+        assert_eq!(&*buf, &[4, 5]);
+        let buf = stream.read_at(100, 4).await.unwrap();
+        assert_eq!(buf.len(), 0);
+
+        let stats = crate::executor().io_stats();
+        assert_eq!(stats.all_rings().file_reads().0, 0);
+        assert_eq!(stats.all_rings().file_mapped_reads(), (3, 6));
+        assert_eq!(stats.all_rings().mapped_prefault_latency_us().count(), 3);
+
+        stream.close().await.unwrap();
+    });
+
+    immutable_file_test!(mapped_read_many, path, {
+        let fname = path.join("testfile");
+        let mut immutable = ImmutableFileBuilder::new(&fname)
+            .build_sink()
+            .await
+            .unwrap();
+        let data: Vec<u8> = (0..8192u32).map(|x| x as u8).collect();
+        immutable.write_all(&data).await.unwrap();
+        immutable.seal().await.unwrap().close().await.unwrap();
+
+        let stream = ImmutableFileBuilder::new(&fname)
+            .with_memory_map(Some(MmapAdvice::Normal))
+            .build_existing()
+            .await
+            .unwrap();
+        assert!(stream.mapping.is_some());
+
+        {
+            let iovs = vec![(0, 4), (2, 4), (4096, 16), (8190, 10)];
+            let bufs: Vec<_> = stream
+                .read_many(
+                    stream::iter(iovs.into_iter()),
+                    MergedBufferLimit::Custom(8192),
+                    ReadAmplificationLimit::NoAmplification,
+                )
+                .map(|x| x.unwrap())
+                .collect()
+                .await;
+            assert_eq!(bufs.len(), 4);
+            assert_eq!(&*bufs[0].1, &data[0..4]);
+            assert_eq!(&*bufs[1].1, &data[2..6]);
+            assert_eq!(&*bufs[2].1, &data[4096..4112]);
+            assert_eq!(&*bufs[3].1, &data[8190..8192]);
+        }
+
+        stream.close().await.unwrap();
+    });
+
+    immutable_file_test!(mapped_empty_file, path, {
+        let fname = path.join("testfile");
+        let immutable = ImmutableFileBuilder::new(fname)
+            .with_memory_map(Some(MmapAdvice::WillNeed))
+            .build_sink()
+            .await
+            .unwrap();
+        let stream = immutable.seal().await.unwrap();
+        assert!(stream.mapping.is_none());
+        assert_eq!(stream.read_at(0, 10).await.unwrap().len(), 0);
+        stream.close().await.unwrap();
+    });
 }
@@ -0,0 +1,120 @@
+//! Read-only memory mappings of sealed files.
+//!
+//! An [`ImmutableFile`](super::ImmutableFile) never changes once sealed, so it
+//! can be mapped once and served straight from the page cache, without a ring
+//! round-trip or a buffer allocation per read.
+
+use std::{
+    hint::black_box,
+    io,
+    os::unix::io::RawFd,
+    ptr::NonNull,
+    time::{Duration, Instant},
+};
+
+/// Access pattern hint given to the kernel, through `madvise(2)`, for a
+/// memory-mapped [`ImmutableFile`](super::ImmutableFile).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MmapAdvice {
+    /// No particular pattern. The kernel uses its default readahead.
+    #[default]
+    Normal,
+    /// Pages are accessed in random order, so readahead is wasted. Suits
+    /// bloom filters and hash indexes.
+    Random,
+    /// Pages are accessed from start to end and can be read ahead
+    /// aggressively, and dropped soon after.
+    Sequential,
+    /// The whole file will be needed soon. The kernel starts reading it in
+    /// right away, so later reads are less likely to fault.
+    WillNeed,
+}
+
+impl MmapAdvice {
+    fn as_raw(self) -> libc::c_int {
+        match self {
+            MmapAdvice::Normal => libc::MADV_NORMAL,
+            MmapAdvice::Random => libc::MADV_RANDOM,
+            MmapAdvice::Sequential => libc::MADV_SEQUENTIAL,
+            MmapAdvice::WillNeed => libc::MADV_WILLNEED,
+        }
+    }
+}
+
+/// A shared, read-only mapping of a whole file.
+///
+/// Public only because it appears, unnameable, in the bounds of
+/// [`ReadManyResult`](super::ReadManyResult).
+#[derive(Debug)]
+pub struct FileMapping {
+    addr: NonNull<u8>,
+    len: usize,
+    page_size: usize,
+}
+
+impl FileMapping {
+    pub(crate) fn new(fd: RawFd, len: usize, advice: MmapAdvice) -> io::Result<FileMapping> {
+        assert!(len > 0, "cannot map an empty file");
+        let addr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                fd,
+                0,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let mapping = FileMapping {
+            addr: NonNull::new(addr as *mut u8).unwrap(),
+            len,
+            page_size: unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize,
+        };
+        // a hint, so failing to apply it is not worth failing the mapping
+        unsafe {
+            libc::madvise(addr, len, advice.as_raw());
+        }
+        Ok(mapping)
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.addr.as_ptr(), self.len) }
+    }
+
+    /// Clamps `pos..pos + size` to the end of the mapping.
+    pub(crate) fn clamp(&self, pos: u64, size: usize) -> (usize, usize) {
+        let start = (pos as usize).min(self.len);
+        (start, size.min(self.len - start))
+    }
+
+    /// Touches every page in `start..start + len`, so that any page fault
+    /// is taken here, while the reading task runs, rather than wherever the
+    /// bytes end up being dereferenced. That charges the fault to the task
+    /// queue that asked for the data, which is what the stall detector and
+    /// the preemption timer measure.
+    ///
+    /// Returns the time it took, which is close to zero for resident pages.
+    pub(crate) fn prefault(&self, start: usize, len: usize) -> Duration {
+        if len == 0 {
+            return Duration::ZERO;
+        }
+        let began = Instant::now();
+        let bytes = self.as_bytes();
+        let first = start - start % self.page_size;
+        for offset in (first..start + len).step_by(self.page_size) {
+            black_box(unsafe { std::ptr::read_volatile(&bytes[offset]) });
+        }
+        began.elapsed()
+    }
+}
+
+impl Drop for FileMapping {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.addr.as_ptr() as *mut libc::c_void, self.len);
+        }
+    }
+}
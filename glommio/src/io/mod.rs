@@ -92,7 +92,31 @@
 //! instead of the lower level [`DmaFile`]. [`ImmutableFile`]s can be accessed
 //! both randomly or sequentially.
 //!
+//! Small, hot files that are read at random, like indexes and bloom filters,
+//! can also be memory-mapped once sealed, see
+//! [`ImmutableFileBuilder::with_memory_map`]. Reads are then served straight
+//! from the page cache, at the cost of blocking the executor on page faults.
+//!
+//! Rate limiting
+//! =============
+//!
+//! An [`IoRateLimiter`] caps the bandwidth and operation rate of the Direct I/O
+//! issued by a task queue or on a given [`DmaFile`], so background work such
+//! as compactions does not starve latency-sensitive foreground I/O.
+//!
+//! Atomic replacement
+//! ==================
+//!
+//! Files like configs and manifests are usually rewritten as a whole, and a
+//! crash midway must not leave a torn file behind. [`AtomicFileWriter`] and its
+//! in-memory shortcut [`atomic_write`] write the new contents aside and
+//! atomically swap them in once they are durable.
+//!
 //! [`ImmutableFile`]: struct.ImmutableFile.html
+//! [`IoRateLimiter`]: struct.IoRateLimiter.html
+//! [`ImmutableFileBuilder::with_memory_map`]: struct.ImmutableFileBuilder.html#method.with_memory_map
+//! [`AtomicFileWriter`]: struct.AtomicFileWriter.html
+//! [`atomic_write`]: fn.atomic_write.html
 //! [`BufferedFile`]: struct.BufferedFile.html
 //! [`DmaFile`]: struct.DmaFile.html
 //! [`DmaBuffer`]: struct.DmaBuffer.html
@@ -121,15 +145,19 @@ macro_rules! enhanced_try {
     }};
 }
 
+mod atomic_file;
 mod buffered_file;
 mod buffered_file_stream;
 mod bulk_io;
 mod directory;
 mod dma_file;
 mod dma_file_stream;
+mod foreign_buffer;
 mod glommio_file;
 mod immutable_file;
+mod mmap;
 mod open_options;
+mod rate_limit;
 mod read_result;
 mod sched;
 mod stat;
@@ -156,6 +184,7 @@ pub async fn remove<P: AsRef<Path>>(path: P) -> Result<()> {
 
 pub(crate) use self::sched::{FileScheduler, IoScheduler, ScheduledSource};
 pub use self::{
+    atomic_file::{atomic_write, AtomicFileWriter},
     buffered_file::BufferedFile,
     buffered_file_stream::{
         stdin, StreamReader, StreamReaderBuilder, StreamWriter, StreamWriterBuilder,
@@ -168,8 +197,12 @@ pub use self::{
     dma_file_stream::{
         DmaStreamReader, DmaStreamReaderBuilder, DmaStreamWriter, DmaStreamWriterBuilder,
     },
+    foreign_buffer::ForeignBuffer,
+    glommio_file::{FileAdvice, SyncRangeFlags},
     immutable_file::{ImmutableFile, ImmutableFileBuilder, ImmutableFilePreSealSink},
+    mmap::MmapAdvice,
     open_options::OpenOptions,
+    rate_limit::{IoRateLimit, IoRateLimiter},
     read_result::ReadResult,
     stat::Stat,
 };
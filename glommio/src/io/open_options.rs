@@ -142,7 +142,7 @@ impl OpenOptions {
     /// system supports it. The path provided to [`dma_open`](#method.dma_open) is the
     /// path of the directory to parent the file. This also requires [`write`](#method.write)
     /// to have been set. See [`tmpfile_linkable`](#method.tmpfile_linkable) if you want to
-    /// be able to later create a name for this file using `linkat` (not yet implemented).
+    /// be able to later create a name for this file using `linkat`.
     pub fn tmpfile(&mut self, tmpfile: bool) -> &mut Self {
         self.tmpfile = tmpfile;
         self
@@ -150,8 +150,8 @@ impl OpenOptions {
 
     /// When [`tmpfile`](#method.tmpfile) is set to true, this controls whether the temporary file
     /// may be put into the filesystem as a named file at a later date using `linkat`.
-    /// For now, since `linkat` is not implemented within glommio, you'll need to link
-    /// it by hand.
+    /// [`AtomicFileWriter`](crate::io::AtomicFileWriter) is built on this to replace
+    /// files atomically; for anything else, you'll need to link it by hand.
     pub fn tmpfile_linkable(&mut self, linkable: bool) -> &mut Self {
         self.tmpfile_linkable = linkable;
         self
@@ -0,0 +1,333 @@
+//! Token-bucket limits on the bandwidth and operation rate of file I/O.
+//!
+//! [`Shares`](crate::Shares) only divide CPU time between task queues: a
+//! background task queue doing little but I/O can still saturate the device.
+//! An [`IoRateLimiter`] caps how fast I/O is submitted instead, either for a
+//! whole task queue or for an individual [`DmaFile`](super::DmaFile).
+
+use crate::timer::Timer;
+use futures_lite::{ready, Stream};
+use std::{
+    cell::RefCell,
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+/// A sustained rate and how much of it may be used at once after a period of
+/// inactivity.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Rate {
+    per_sec: u64,
+    burst: u64,
+}
+
+impl Rate {
+    fn new(per_sec: u64, burst: u64) -> Rate {
+        assert!(per_sec > 0, "a rate limit must allow some I/O");
+        Rate { per_sec, burst }
+    }
+
+    /// How long `units` take at this rate.
+    fn cost(&self, units: u64) -> Duration {
+        let nanos = units as u128 * 1_000_000_000 / self.per_sec as u128;
+        Duration::from_nanos(nanos.min(u64::MAX as u128) as u64)
+    }
+}
+
+/// The limits enforced by an [`IoRateLimiter`].
+///
+/// Each limit is a token bucket: I/O flows at up to the given rate, and up to
+/// `burst` of the unused allowance is kept for later, so that an idle
+/// workload can briefly go faster than the rate when it resumes. A default
+/// `IoRateLimit` has no limits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IoRateLimit {
+    bytes: Option<Rate>,
+    ops: Option<Rate>,
+}
+
+impl IoRateLimit {
+    /// Creates an `IoRateLimit` with no limits.
+    pub fn new() -> IoRateLimit {
+        IoRateLimit::default()
+    }
+
+    /// Limits bandwidth to `bytes_per_sec`, allowing bursts of up to
+    /// `burst_bytes`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bytes_per_sec` is zero.
+    #[must_use]
+    pub fn with_bytes_per_sec(mut self, bytes_per_sec: u64, burst_bytes: u64) -> Self {
+        self.bytes = Some(Rate::new(bytes_per_sec, burst_bytes));
+        self
+    }
+
+    /// Limits the number of operations to `ops_per_sec`, allowing bursts of up
+    /// to `burst_ops`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `ops_per_sec` is zero.
+    #[must_use]
+    pub fn with_ops_per_sec(mut self, ops_per_sec: u64, burst_ops: u64) -> Self {
+        self.ops = Some(Rate::new(ops_per_sec, burst_ops));
+        self
+    }
+
+    /// Returns the bandwidth limit and its burst, in bytes, if any.
+    pub fn bytes_per_sec(&self) -> Option<(u64, u64)> {
+        self.bytes.map(|rate| (rate.per_sec, rate.burst))
+    }
+
+    /// Returns the operation rate limit and its burst, if any.
+    pub fn ops_per_sec(&self) -> Option<(u64, u64)> {
+        self.ops.map(|rate| (rate.per_sec, rate.burst))
+    }
+}
+
+/// One token bucket, kept as the time at which it will be full again rather
+/// than as a count of tokens, so that it needs no refilling.
+#[derive(Debug, Default)]
+struct Bucket {
+    full_at: Option<Instant>,
+}
+
+impl Bucket {
+    /// Takes `units` out of the bucket and returns when the operation may
+    /// proceed, which is once the bucket holds that many.
+    ///
+    /// The tokens are taken right away, so operations are served in the
+    /// order they asked. An operation larger than the burst proceeds once the
+    /// bucket is full and leaves it in debt, so it is never starved: it just
+    /// makes the ones after it wait longer.
+    fn take(&mut self, rate: &Rate, units: u64, now: Instant) -> Instant {
+        let full_at = self.full_at.map_or(now, |at| at.max(now));
+        let allowed_at = (full_at + rate.cost(units.min(rate.burst)))
+            .checked_sub(rate.cost(rate.burst))
+            .map_or(now, |at| at.max(now));
+        self.full_at = Some(full_at + rate.cost(units));
+        allowed_at
+    }
+}
+
+#[derive(Debug, Default)]
+struct LimiterState {
+    limit: IoRateLimit,
+    bytes: Bucket,
+    ops: Bucket,
+}
+
+/// Limits the rate at which file I/O is submitted.
+///
+/// A limiter can be attached to a task queue, with
+/// [`ExecutorProxy::set_task_queue_io_limiter`], to limit all the file I/O
+/// issued by tasks in that queue, and to individual files, with
+/// [`DmaFile::set_rate_limiter`]. I/O subject to both waits for both. Clones
+/// share the same budget, so a single limiter can cap a group of files or
+/// task queues together.
+///
+/// I/O that would exceed the limits waits before it is submitted. The time
+/// spent waiting is reported in [`RingIoStats::io_throttled`].
+///
+/// # Examples
+///
+/// ```no_run
+/// use glommio::{
+///     io::{DmaFile, IoRateLimit, IoRateLimiter},
+///     Latency,
+///     LocalExecutor,
+///     Shares,
+/// };
+///
+/// let ex = LocalExecutor::default();
+/// ex.run(async {
+///     let compaction = glommio::executor().create_task_queue(
+///         Shares::default(),
+///         Latency::NotImportant,
+///         "compaction",
+///     );
+///     // 100 MiB/s and 1000 IOPS, with bursts of up to 4 MiB and 64 operations
+///     let limit = IoRateLimit::new()
+///         .with_bytes_per_sec(100 << 20, 4 << 20)
+///         .with_ops_per_sec(1000, 64);
+///     glommio::executor()
+///         .set_task_queue_io_limiter(compaction, Some(IoRateLimiter::new(limit)))
+///         .unwrap();
+/// });
+/// ```
+///
+/// [`ExecutorProxy::set_task_queue_io_limiter`]: crate::ExecutorProxy::set_task_queue_io_limiter
+/// [`DmaFile::set_rate_limiter`]: super::DmaFile::set_rate_limiter
+/// [`RingIoStats::io_throttled`]: crate::RingIoStats::io_throttled
+#[derive(Clone, Debug, Default)]
+pub struct IoRateLimiter {
+    state: Rc<RefCell<LimiterState>>,
+}
+
+impl IoRateLimiter {
+    /// Creates a limiter enforcing `limit`.
+    pub fn new(limit: IoRateLimit) -> IoRateLimiter {
+        IoRateLimiter {
+            state: Rc::new(RefCell::new(LimiterState {
+                limit,
+                ..Default::default()
+            })),
+        }
+    }
+
+    /// Returns the limits currently enforced.
+    pub fn limit(&self) -> IoRateLimit {
+        self.state.borrow().limit
+    }
+
+    /// Replaces the limits enforced, for every clone of this limiter. I/O
+    /// already waiting is not affected.
+    pub fn set_limit(&self, limit: IoRateLimit) {
+        self.state.borrow_mut().limit = limit;
+    }
+
+    /// Accounts for an operation of `bytes` and returns when it may be
+    /// submitted.
+    pub(crate) fn reserve(&self, bytes: u64, now: Instant) -> Instant {
+        let state = &mut *self.state.borrow_mut();
+        let mut allowed_at = now;
+        if let Some(rate) = &state.limit.bytes {
+            allowed_at = allowed_at.max(state.bytes.take(rate, bytes, now));
+        }
+        if let Some(rate) = &state.limit.ops {
+            allowed_at = allowed_at.max(state.ops.take(rate, 1, now));
+        }
+        allowed_at
+    }
+}
+
+/// Holds back the items of a stream of I/O requests until the limiters that
+/// apply to them allow them to be submitted.
+#[derive(Debug)]
+pub(crate) struct Throttled<S: Stream> {
+    inner: S,
+    limiter: Option<IoRateLimiter>,
+    enabled: bool,
+    size: fn(&S::Item) -> u64,
+    held: Option<(S::Item, Timer)>,
+}
+
+impl<S: Stream> Throttled<S> {
+    pub(crate) fn new(
+        inner: S,
+        limiter: Option<IoRateLimiter>,
+        enabled: bool,
+        size: fn(&S::Item) -> u64,
+    ) -> Self {
+        Throttled {
+            inner,
+            limiter,
+            enabled,
+            size,
+            held: None,
+        }
+    }
+}
+
+impl<S: Stream + Unpin> Stream for Throttled<S>
+where
+    S::Item: Unpin,
+{
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        if let Some((_, timer)) = &mut this.held {
+            ready!(Pin::new(timer).poll(cx));
+            return Poll::Ready(this.held.take().map(|(item, _)| item));
+        }
+
+        let item = ready!(Pin::new(&mut this.inner).poll_next(cx));
+        match item {
+            Some(item) if this.enabled => {
+                let delay = crate::executor()
+                    .reactor()
+                    .throttle(this.limiter.as_ref(), (this.size)(&item));
+                if delay.is_zero() {
+                    Poll::Ready(Some(item))
+                } else {
+                    this.held = Some((item, Timer::new(delay)));
+                    self.poll_next(cx)
+                }
+            }
+            item => Poll::Ready(item),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bursts_then_paces() {
+        let limiter = IoRateLimiter::new(IoRateLimit::new().with_bytes_per_sec(1000, 500));
+        let now = Instant::now();
+
+        // the burst goes through right away
+        assert_eq!(limiter.reserve(250, now), now);
+        assert_eq!(limiter.reserve(250, now), now);
+        // and once it is used up, it takes 250ms for 250 bytes to be allowed
+        assert_eq!(limiter.reserve(250, now), now + Duration::from_millis(250));
+        assert_eq!(limiter.reserve(250, now), now + Duration::from_millis(500));
+
+        // being idle for long enough refills the burst, but no more than it
+        let later = now + Duration::from_secs(10);
+        assert_eq!(limiter.reserve(500, later), later);
+        assert!(limiter.reserve(1, later) > later);
+    }
+
+    #[test]
+    fn large_operations_are_not_starved() {
+        let limiter = IoRateLimiter::new(IoRateLimit::new().with_bytes_per_sec(1000, 100));
+        let now = Instant::now();
+
+        assert_eq!(limiter.reserve(10_000, now), now);
+        // the debt is paid by whoever comes next
+        assert_eq!(limiter.reserve(1, now), now + Duration::from_millis(9_901));
+    }
+
+    #[test]
+    fn ops_and_bytes_limit_together() {
+        let limit = IoRateLimit::new()
+            .with_bytes_per_sec(1 << 30, 0)
+            .with_ops_per_sec(10, 1);
+        let limiter = IoRateLimiter::new(limit);
+        let now = Instant::now();
+
+        assert_eq!(limiter.reserve(4096, now), now);
+        assert_eq!(limiter.reserve(4096, now), now + Duration::from_millis(100));
+        assert_eq!(limiter.reserve(4096, now), now + Duration::from_millis(200));
+        assert_eq!(limiter.limit().ops_per_sec(), Some((10, 1)));
+        assert_eq!(limiter.limit().bytes_per_sec(), Some((1 << 30, 0)));
+    }
+
+    #[test]
+    fn clones_share_the_budget() {
+        let limiter = IoRateLimiter::new(IoRateLimit::new().with_ops_per_sec(1, 0));
+        let clone = limiter.clone();
+        let now = Instant::now();
+
+        assert_eq!(limiter.reserve(0, now), now);
+        assert_eq!(clone.reserve(0, now), now + Duration::from_secs(1));
+
+        clone.set_limit(IoRateLimit::new());
+        assert_eq!(limiter.reserve(0, now), now);
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_rate_rejected() {
+        let _ = IoRateLimit::new().with_ops_per_sec(0, 10);
+    }
+}
@@ -2,8 +2,8 @@
 // under the mit/apache-2.0 license, at your convenience
 //
 // this product includes software developed at datadog (https://www.datadoghq.com/). copyright 2020 datadog, inc.
-use crate::io::ScheduledSource;
-use std::{num::NonZeroUsize, ptr::NonNull};
+use crate::io::{mmap::FileMapping, ScheduledSource};
+use std::{num::NonZeroUsize, ops::Range, ptr::NonNull, rc::Rc, task::Waker};
 
 #[derive(Default, Clone, Debug)]
 /// ReadResult encapsulates a buffer, returned by read operations like
@@ -20,9 +20,69 @@ impl core::ops::Deref for ReadResult {
     }
 }
 
+/// Where the bytes of a read live: either the buffer of an I/O request, or a
+/// range of a memory-mapped file.
+///
+/// Public only because it appears, unnameable, in the bounds of
+/// [`ReadManyResult`](super::ReadManyResult).
+#[derive(Clone, Debug)]
+pub enum ReadSource {
+    Scheduled(ScheduledSource),
+    Mapped(Rc<FileMapping>, Range<usize>),
+}
+
+impl ReadSource {
+    pub(crate) fn mapped(mapping: &Rc<FileMapping>, pos: u64, size: usize) -> ReadSource {
+        let (start, len) = mapping.clamp(pos, size);
+        let fault_time = mapping.prefault(start, len);
+        crate::executor()
+            .reactor()
+            .sys
+            .record_mapped_read(len, fault_time);
+        ReadSource::Mapped(mapping.clone(), start..start + len)
+    }
+
+    /// Whether the bytes can be accessed. Mapped memory always can be.
+    pub(crate) fn is_ready(&self) -> bool {
+        match self {
+            ReadSource::Scheduled(source) => source.result().is_some(),
+            ReadSource::Mapped(..) => true,
+        }
+    }
+
+    pub(crate) fn add_waiter(&self, waker: Waker) {
+        if let ReadSource::Scheduled(source) = self {
+            source.add_waiter_many(waker);
+        }
+    }
+
+    pub(crate) fn result(&self) -> Option<std::io::Result<usize>> {
+        match self {
+            ReadSource::Scheduled(source) => source.result(),
+            ReadSource::Mapped(_, range) => Some(Ok(range.len())),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// For I/O requests, the request must have completed.
+    pub(crate) unsafe fn as_bytes(&self) -> &[u8] {
+        match self {
+            ReadSource::Scheduled(source) => source.as_bytes(),
+            ReadSource::Mapped(mapping, range) => &mapping.as_bytes()[range.clone()],
+        }
+    }
+}
+
+impl From<ScheduledSource> for ReadSource {
+    fn from(source: ScheduledSource) -> Self {
+        ReadSource::Scheduled(source)
+    }
+}
+
 #[derive(Clone, Debug)]
 struct ReadResultInner {
-    buffer: ScheduledSource,
+    buffer: ReadSource,
     mem: NonNull<u8>,
 
     // This (usage of `NonZeroUsize`) is probably needed to make sure that rustc
@@ -47,7 +107,12 @@ impl ReadResult {
         Self(None)
     }
 
-    pub(crate) fn from_sliced_buffer(buffer: ScheduledSource, offset: usize, len: usize) -> Self {
+    pub(crate) fn from_sliced_buffer(
+        buffer: impl Into<ReadSource>,
+        offset: usize,
+        len: usize,
+    ) -> Self {
+        let buffer = buffer.into();
         Self(
             NonZeroUsize::new(len)
                 .map(|len| unsafe {
@@ -1,13 +1,15 @@
 use crate::{
-    io::glommio_file::Identity,
+    io::{glommio_file::Identity, IoRateLimiter},
     sys::{Reactor, Source},
-    IoRequirements,
+    IoRequirements, TaskQueueHandle,
 };
+use ahash::AHashMap;
 use intrusive_collections::{intrusive_adapter, Bound, KeyAdapter, RBTree, RBTreeLink};
 use std::{
     cell::{Cell, RefCell},
     ops::{Deref, Range},
     rc::{Rc, Weak},
+    time::{Duration, Instant},
 };
 
 #[derive(Debug)]
@@ -16,6 +18,9 @@ pub(crate) struct IoScheduler {
     current_requirements: Cell<IoRequirements>,
 
     file_schedulers: RefCell<RBTree<FileSchedulerAdapter>>,
+
+    /// Rate limits on the file I/O issued from each task queue.
+    task_queue_limiters: RefCell<AHashMap<TaskQueueHandle, IoRateLimiter>>,
 }
 
 impl IoScheduler {
@@ -23,6 +28,7 @@ impl IoScheduler {
         IoScheduler {
             current_requirements: Cell::new(Default::default()),
             file_schedulers: RefCell::new(Default::default()),
+            task_queue_limiters: RefCell::new(Default::default()),
         }
     }
 
@@ -34,6 +40,42 @@ impl IoScheduler {
         self.current_requirements.set(req);
     }
 
+    pub(crate) fn set_task_queue_limiter(
+        &self,
+        handle: TaskQueueHandle,
+        limiter: Option<IoRateLimiter>,
+    ) {
+        let mut limiters = self.task_queue_limiters.borrow_mut();
+        match limiter {
+            Some(limiter) => limiters.insert(handle, limiter),
+            None => limiters.remove(&handle),
+        };
+    }
+
+    /// Accounts for `bytes` of file I/O about to be issued by the current
+    /// task queue, against a file limited by `file`, and returns how long the
+    /// I/O has to wait before it is submitted.
+    pub(crate) fn reserve(&self, file: Option<&IoRateLimiter>, bytes: u64) -> Duration {
+        let limiters = self.task_queue_limiters.borrow();
+        let task_queue = if limiters.is_empty() {
+            None
+        } else {
+            limiters.get(&crate::executor().current_task_queue())
+        };
+        if file.is_none() && task_queue.is_none() {
+            return Duration::ZERO;
+        }
+
+        let now = Instant::now();
+        let allowed_at = file
+            .into_iter()
+            .chain(task_queue)
+            .map(|limiter| limiter.reserve(bytes, now))
+            .max()
+            .unwrap();
+        allowed_at - now
+    }
+
     pub(super) fn get_file_scheduler(self: &Rc<Self>, identity: Identity) -> FileScheduler {
         let mut borrow = self.file_schedulers.borrow_mut();
         let file = if let Some(file) = borrow.find(&identity).clone_pointer() {
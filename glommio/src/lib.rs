@@ -487,6 +487,8 @@ macro_rules! make_shared_var_mut {
 
 mod byte_slice_ext;
 pub mod channels;
+#[cfg(any(feature = "hyper", feature = "tokio-compat"))]
+pub mod compat;
 pub mod controllers;
 mod error;
 mod executor;
@@ -588,9 +590,115 @@ pub enum Latency {
     NotImportant,
 }
 
+/// The scheduling classes of the kernel's block layer.
+///
+/// See [`IoPriority`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum IoPriorityClass {
+    /// Served before any other class. Setting it requires `CAP_SYS_ADMIN` or
+    /// `CAP_SYS_NICE`: without either, I/O carrying it fails with `EPERM`.
+    Realtime,
+    /// The class every process is in by default.
+    BestEffort,
+    /// Only served when no other class has I/O pending.
+    Idle,
+}
+
+impl IoPriorityClass {
+    // Index of the class in per-class stats.
+    pub(crate) fn index(self) -> usize {
+        match self {
+            IoPriorityClass::Realtime => 0,
+            IoPriorityClass::BestEffort => 1,
+            IoPriorityClass::Idle => 2,
+        }
+    }
+}
+
+/// An attribute of a [`TaskQueue`], set with
+/// [`ExecutorProxy::set_task_queue_io_priority`].
+///
+/// [`Latency`] decides which ring a task queue's I/O goes to, but the kernel
+/// is not told about it. An `IoPriority` is carried by every file read and
+/// write the task queue issues, in the `ioprio` field of the submission, so
+/// that the I/O schedulers that honour it (BFQ, and mq-deadline for the class)
+/// can order requests from different task queues. Within a class, lower
+/// levels are served first. Non-pollable file I/O in the
+/// [`IoPriorityClass::Realtime`] class also goes to the latency ring, so that
+/// its completions are noticed as soon as possible.
+///
+/// [`TaskQueue`]: struct.TaskQueueHandle.html
+/// [`ExecutorProxy::set_task_queue_io_priority`]: crate::ExecutorProxy::set_task_queue_io_priority
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct IoPriority {
+    class: IoPriorityClass,
+    level: u8,
+}
+
+impl IoPriority {
+    /// The highest level, i.e. the one with the lowest value.
+    pub const HIGHEST_LEVEL: u8 = 0;
+    /// The lowest level, i.e. the one with the highest value.
+    pub const LOWEST_LEVEL: u8 = 7;
+
+    /// Creates a priority in `class` at `level`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `level` is above [`IoPriority::LOWEST_LEVEL`].
+    pub fn new(class: IoPriorityClass, level: u8) -> IoPriority {
+        assert!(
+            level <= Self::LOWEST_LEVEL,
+            "I/O priority levels go from {} to {}",
+            Self::HIGHEST_LEVEL,
+            Self::LOWEST_LEVEL
+        );
+        IoPriority { class, level }
+    }
+
+    /// Creates a priority in the [`IoPriorityClass::Realtime`] class.
+    pub fn realtime(level: u8) -> IoPriority {
+        IoPriority::new(IoPriorityClass::Realtime, level)
+    }
+
+    /// Creates a priority in the [`IoPriorityClass::BestEffort`] class.
+    pub fn best_effort(level: u8) -> IoPriority {
+        IoPriority::new(IoPriorityClass::BestEffort, level)
+    }
+
+    /// Creates a priority in the [`IoPriorityClass::Idle`] class. Levels
+    /// carry no meaning in that class.
+    pub fn idle() -> IoPriority {
+        IoPriority::new(IoPriorityClass::Idle, 0)
+    }
+
+    /// The class of this priority.
+    pub fn class(&self) -> IoPriorityClass {
+        self.class
+    }
+
+    /// The level of this priority within its class.
+    pub fn level(&self) -> u8 {
+        self.level
+    }
+
+    // The value of the `ioprio` field, as built by `IOPRIO_PRIO_VALUE` in
+    // `linux/ioprio.h`.
+    pub(crate) fn to_ioprio(self) -> u16 {
+        const IOPRIO_CLASS_SHIFT: u16 = 13;
+        let class: u16 = match self.class {
+            IoPriorityClass::Realtime => 1,
+            IoPriorityClass::BestEffort => 2,
+            IoPriorityClass::Idle => 3,
+        };
+        (class << IOPRIO_CLASS_SHIFT) | self.level as u16
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub(crate) struct IoRequirements {
     latency_req: Latency,
+    io_priority: Option<IoPriority>,
     _io_handle: usize,
 }
 
@@ -598,6 +706,7 @@ impl Default for IoRequirements {
     fn default() -> Self {
         Self {
             latency_req: Latency::NotImportant,
+            io_priority: None,
             _io_handle: 0,
         }
     }
@@ -607,9 +716,20 @@ impl IoRequirements {
     fn new(latency: Latency, handle: usize) -> Self {
         Self {
             latency_req: latency,
+            io_priority: None,
             _io_handle: handle,
         }
     }
+
+    pub(crate) fn io_priority(&self) -> Option<IoPriority> {
+        self.io_priority
+    }
+
+    // The `ioprio` of file I/O issued under these requirements. Zero leaves it
+    // to the kernel, which uses the priority of the thread.
+    pub(crate) fn ioprio(&self) -> u16 {
+        self.io_priority.map_or(0, IoPriority::to_ioprio)
+    }
 }
 
 /// Stores information about IO performed in a specific ring
@@ -628,11 +748,17 @@ pub struct RingIoStats {
     pub(crate) file_bytes_written: u64,
     pub(crate) file_buffered_writes: u64,
     pub(crate) file_buffered_bytes_written: u64,
+    pub(crate) file_mapped_reads: u64,
+    pub(crate) file_mapped_bytes_read: u64,
+    pub(crate) io_throttled: u64,
+    pub(crate) io_throttled_us: u64,
 
     // Distributions
     pub(crate) pre_reactor_io_scheduler_latency_us: sketches_ddsketch::DDSketch,
     pub(crate) io_latency_us: sketches_ddsketch::DDSketch,
     pub(crate) post_reactor_io_scheduler_latency_us: sketches_ddsketch::DDSketch,
+    pub(crate) mapped_prefault_latency_us: sketches_ddsketch::DDSketch,
+    pub(crate) io_latency_by_class_us: [sketches_ddsketch::DDSketch; 3],
 }
 
 impl Default for RingIoStats {
@@ -650,6 +776,10 @@ impl Default for RingIoStats {
             file_bytes_written: 0,
             file_buffered_writes: 0,
             file_buffered_bytes_written: 0,
+            file_mapped_reads: 0,
+            file_mapped_bytes_read: 0,
+            io_throttled: 0,
+            io_throttled_us: 0,
             pre_reactor_io_scheduler_latency_us: sketches_ddsketch::DDSketch::new(
                 sketches_ddsketch::Config::new(0.01, 2048, 1.0e-9),
             ),
@@ -659,6 +789,12 @@ impl Default for RingIoStats {
             post_reactor_io_scheduler_latency_us: sketches_ddsketch::DDSketch::new(
                 sketches_ddsketch::Config::new(0.01, 2048, 1.0e-9),
             ),
+            mapped_prefault_latency_us: sketches_ddsketch::DDSketch::new(
+                sketches_ddsketch::Config::new(0.01, 2048, 1.0e-9),
+            ),
+            io_latency_by_class_us: std::array::from_fn(|_| {
+                sketches_ddsketch::DDSketch::new(sketches_ddsketch::Config::new(0.01, 2048, 1.0e-9))
+            }),
         }
     }
 }
@@ -681,6 +817,10 @@ impl Debug for RingIoStats {
                 "file_buffered_bytes_written",
                 &self.file_buffered_bytes_written,
             )
+            .field("file_mapped_reads", &self.file_mapped_reads)
+            .field("file_mapped_bytes_read", &self.file_mapped_bytes_read)
+            .field("io_throttled", &self.io_throttled)
+            .field("io_throttled_us", &self.io_throttled_us)
             .finish_non_exhaustive()
     }
 }
@@ -742,6 +882,29 @@ impl RingIoStats {
         (self.file_buffered_writes, self.file_buffered_bytes_written)
     }
 
+    /// Memory-mapped file read stats
+    ///
+    /// Returns the number of reads served from the mapping of an
+    /// [`ImmutableFile`](crate::io::ImmutableFile) as well as bytes read.
+    /// These never reach the ring, so they are not part of [`file_reads`].
+    ///
+    /// [`file_reads`]: RingIoStats::file_reads
+    pub fn file_mapped_reads(&self) -> (u64, u64) {
+        (self.file_mapped_reads, self.file_mapped_bytes_read)
+    }
+
+    /// I/O rate limiting stats
+    ///
+    /// Returns the number of file I/O operations that had to wait for an
+    /// [`IoRateLimiter`](crate::io::IoRateLimiter) before being submitted, as
+    /// well as the total time they waited.
+    pub fn io_throttled(&self) -> (u64, Duration) {
+        (
+            self.io_throttled,
+            Duration::from_micros(self.io_throttled_us),
+        )
+    }
+
     /// The pre-reactor IO scheduler latency
     ///
     /// Returns a distribution of measures tracking the time between the moment
@@ -759,6 +922,17 @@ impl RingIoStats {
         &self.io_latency_us
     }
 
+    /// The IO latency of a priority class
+    ///
+    /// Returns the part of [`io_latency_us`] recorded for sources issued by
+    /// task queues with an [`IoPriority`] in `class`. I/O from task queues
+    /// without one is not recorded here.
+    ///
+    /// [`io_latency_us`]: RingIoStats::io_latency_us
+    pub fn io_latency_us_for_class(&self, class: IoPriorityClass) -> &DDSketch {
+        &self.io_latency_by_class_us[class.index()]
+    }
+
     /// The post-reactor IO scheduler latency
     ///
     /// Returns a distribution of measures tracking the time between the moment
@@ -767,6 +941,16 @@ impl RingIoStats {
     pub fn post_reactor_io_scheduler_latency_us(&self) -> &DDSketch {
         &self.post_reactor_io_scheduler_latency_us
     }
+
+    /// The time memory-mapped reads spent faulting pages in
+    ///
+    /// Returns a distribution of measures tracking how long each read served
+    /// from a mapping took to touch its pages. Reads of resident pages record
+    /// close to zero; the rest is time the executor thread was blocked on the
+    /// device, and is also visible to the stall detector.
+    pub fn mapped_prefault_latency_us(&self) -> &DDSketch {
+        &self.mapped_prefault_latency_us
+    }
 }
 
 impl<'a> Sum<&'a RingIoStats> for RingIoStats {
@@ -784,6 +968,10 @@ impl<'a> Sum<&'a RingIoStats> for RingIoStats {
             a.file_bytes_written += b.file_bytes_written;
             a.file_buffered_writes += b.file_buffered_writes;
             a.file_buffered_bytes_written += b.file_buffered_bytes_written;
+            a.file_mapped_reads += b.file_mapped_reads;
+            a.file_mapped_bytes_read += b.file_mapped_bytes_read;
+            a.io_throttled += b.io_throttled;
+            a.io_throttled_us += b.io_throttled_us;
             a.pre_reactor_io_scheduler_latency_us
                 .merge(&b.pre_reactor_io_scheduler_latency_us)
                 .unwrap();
@@ -791,6 +979,16 @@ impl<'a> Sum<&'a RingIoStats> for RingIoStats {
             a.post_reactor_io_scheduler_latency_us
                 .merge(&b.post_reactor_io_scheduler_latency_us)
                 .unwrap();
+            a.mapped_prefault_latency_us
+                .merge(&b.mapped_prefault_latency_us)
+                .unwrap();
+            for (a, b) in a
+                .io_latency_by_class_us
+                .iter_mut()
+                .zip(&b.io_latency_by_class_us)
+            {
+                a.merge(b).unwrap();
+            }
             a
         })
     }
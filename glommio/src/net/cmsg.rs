@@ -0,0 +1,245 @@
+//! Control messages, or ancillary data, on Unix sockets.
+//!
+//! Data sent on a Unix socket can carry file descriptors (`SCM_RIGHTS`),
+//! which the receiving process gets as new descriptors for the same open
+//! files, and the credentials of the sender (`SCM_CREDENTIALS`), which the
+//! kernel checks. [`UnixStream`] and [`UnixDatagram`] send them with
+//! `send_with_control` and receive them with `recv_with_control`, or with
+//! `send_with_fds` and `recv_with_fds` when only descriptors are involved.
+//!
+//! On a stream, control messages are attached to the bytes they were sent
+//! with, and a read never returns bytes sent with control messages together
+//! with bytes sent before them. So send at least one byte with them, and
+//! read them with `recv_with_control` rather than through [`AsyncRead`].
+//!
+//! [`UnixStream`]: super::UnixStream
+//! [`UnixDatagram`]: super::UnixDatagram
+//! [`AsyncRead`]: futures_lite::io::AsyncRead
+
+use crate::{
+    sys::{self, SourceType},
+    ByteSliceMutExt,
+};
+use nix::sys::socket::MsgFlags;
+use std::{
+    io, mem,
+    os::unix::io::{FromRawFd, OwnedFd, RawFd},
+    time::Duration,
+};
+
+// The most descriptors Linux passes in one message
+const SCM_MAX_FD: usize = 253;
+
+// Room for as many descriptors as can be passed, and credentials
+const RECV_CONTROL_LEN: usize = unsafe {
+    libc::CMSG_SPACE((SCM_MAX_FD * mem::size_of::<RawFd>()) as u32)
+        + libc::CMSG_SPACE(mem::size_of::<libc::ucred>() as u32)
+} as usize;
+
+/// The credentials of a process, as passed in an `SCM_CREDENTIALS` control
+/// message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnixCredentials {
+    pid: libc::pid_t,
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+}
+
+impl UnixCredentials {
+    /// Returns the credentials of the current process, which an
+    /// unprivileged process can always send.
+    pub fn current() -> UnixCredentials {
+        unsafe {
+            UnixCredentials {
+                pid: libc::getpid(),
+                uid: libc::getuid(),
+                gid: libc::getgid(),
+            }
+        }
+    }
+
+    /// Creates credentials to send. Anything but the sender's own needs
+    /// privileges, or sending fails.
+    pub fn new(pid: libc::pid_t, uid: libc::uid_t, gid: libc::gid_t) -> UnixCredentials {
+        UnixCredentials { pid, uid, gid }
+    }
+
+    /// The process ID.
+    pub fn pid(&self) -> libc::pid_t {
+        self.pid
+    }
+
+    /// The user ID.
+    pub fn uid(&self) -> libc::uid_t {
+        self.uid
+    }
+
+    /// The group ID.
+    pub fn gid(&self) -> libc::gid_t {
+        self.gid
+    }
+
+    fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(mem::size_of::<libc::ucred>());
+        bytes.extend_from_slice(&self.pid.to_ne_bytes());
+        bytes.extend_from_slice(&self.uid.to_ne_bytes());
+        bytes.extend_from_slice(&self.gid.to_ne_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<UnixCredentials> {
+        let field = |i: usize| Some(<[u8; 4]>::try_from(bytes.get(i * 4..i * 4 + 4)?).unwrap());
+        Some(UnixCredentials {
+            pid: libc::pid_t::from_ne_bytes(field(0)?),
+            uid: libc::uid_t::from_ne_bytes(field(1)?),
+            gid: libc::gid_t::from_ne_bytes(field(2)?),
+        })
+    }
+}
+
+/// A control message to send on a Unix socket.
+#[derive(Clone, Copy, Debug)]
+pub enum ControlMessage<'a> {
+    /// File descriptors to pass (`SCM_RIGHTS`). They stay open here.
+    Rights(&'a [RawFd]),
+    /// Credentials for the receiver to check (`SCM_CREDENTIALS`).
+    Credentials(UnixCredentials),
+}
+
+/// A control message received on a Unix socket.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ControlMessageOwned {
+    /// File descriptors passed by the sender (`SCM_RIGHTS`), closed when
+    /// dropped.
+    Rights(Vec<OwnedFd>),
+    /// The credentials of the sender (`SCM_CREDENTIALS`), only received
+    /// with [`set_pass_credentials`] enabled.
+    ///
+    /// [`set_pass_credentials`]: super::UnixStream::set_pass_credentials
+    Credentials(UnixCredentials),
+    /// A control message of another kind, as its level, type and data.
+    Other(i32, i32, Vec<u8>),
+}
+
+fn encode(messages: &[ControlMessage<'_>]) -> Vec<u8> {
+    let mut control = Vec::new();
+    for message in messages {
+        match message {
+            // an empty one is an error
+            ControlMessage::Rights([]) => {}
+            ControlMessage::Rights(fds) => {
+                let data: Vec<u8> = fds.iter().flat_map(|fd| fd.to_ne_bytes()).collect();
+                sys::push_control_message(&mut control, libc::SOL_SOCKET, libc::SCM_RIGHTS, &data);
+            }
+            ControlMessage::Credentials(credentials) => sys::push_control_message(
+                &mut control,
+                libc::SOL_SOCKET,
+                libc::SCM_CREDENTIALS,
+                &credentials.to_bytes(),
+            ),
+        }
+    }
+    control
+}
+
+// Takes ownership of every descriptor received, so none leaks
+fn decode(control: &[u8]) -> Vec<ControlMessageOwned> {
+    sys::control_messages(control)
+        .map(|(level, kind, data)| match (level, kind) {
+            (libc::SOL_SOCKET, libc::SCM_RIGHTS) => ControlMessageOwned::Rights(
+                data.chunks_exact(mem::size_of::<RawFd>())
+                    .map(|fd| unsafe {
+                        OwnedFd::from_raw_fd(RawFd::from_ne_bytes(fd.try_into().unwrap()))
+                    })
+                    .collect(),
+            ),
+            (libc::SOL_SOCKET, libc::SCM_CREDENTIALS) => UnixCredentials::from_bytes(data)
+                .map(ControlMessageOwned::Credentials)
+                .unwrap_or_else(|| ControlMessageOwned::Other(level, kind, data.to_vec())),
+            _ => ControlMessageOwned::Other(level, kind, data.to_vec()),
+        })
+        .collect()
+}
+
+/// Gathers the descriptors of all `Rights` messages.
+pub(crate) fn into_fds(messages: Vec<ControlMessageOwned>) -> Vec<OwnedFd> {
+    messages
+        .into_iter()
+        .flat_map(|message| match message {
+            ControlMessageOwned::Rights(fds) => fds,
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
+pub(crate) async fn send_with_control(
+    fd: RawFd,
+    buf: &[u8],
+    messages: &[ControlMessage<'_>],
+    timeout: Option<Duration>,
+) -> io::Result<usize> {
+    let control = encode(messages);
+    match sys::sendmsg_control_syscall(fd, buf, &control, libc::MSG_DONTWAIT) {
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+        result => return result,
+    }
+
+    let reactor = crate::executor().reactor();
+    let mut dma = reactor.alloc_dma_buffer(buf.len());
+    assert_eq!(dma.write_at(0, buf), buf.len());
+    let source = reactor.rushed_sendmsg(fd, dma, None, control, timeout)?;
+    source.collect_rw().await
+}
+
+pub(crate) async fn recv_with_control(
+    fd: RawFd,
+    buf: &mut [u8],
+    timeout: Option<Duration>,
+) -> io::Result<(usize, Vec<ControlMessageOwned>)> {
+    let mut control = vec![0; RECV_CONTROL_LEN];
+    let flags = MsgFlags::MSG_CMSG_CLOEXEC;
+    match sys::recvmsg_control_syscall(
+        fd,
+        buf,
+        &mut control,
+        (flags | MsgFlags::MSG_DONTWAIT).bits(),
+    ) {
+        Ok((size, control_len)) => return Ok((size, decode(&control[..control_len]))),
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+        Err(err) => return Err(err),
+    }
+
+    let source = crate::executor().reactor().rushed_recvmsg(
+        fd,
+        buf.len(),
+        flags,
+        RECV_CONTROL_LEN,
+        timeout,
+    )?;
+    let size = source.collect_rw().await?;
+    match source.extract_source_type() {
+        SourceType::SockRecvMsg(src, _iov, hdr, _addr, control) => {
+            buf[..size].copy_from_slice(&src.unwrap().as_bytes()[..size]);
+            let control = &control[..hdr.msg_controllen.min(control.len())];
+            Ok((size, decode(control)))
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode_decode_roundtrip() {
+        let credentials = UnixCredentials::current();
+        let control = encode(&[ControlMessage::Credentials(credentials)]);
+        match &decode(&control)[..] {
+            [ControlMessageOwned::Credentials(decoded)] => assert_eq!(*decoded, credentials),
+            other => panic!("unexpected control messages {other:?}"),
+        }
+        assert!(decode(&[]).is_empty());
+    }
+}
@@ -4,13 +4,15 @@
 // This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
 //
 use crate::{
-    sys::{DmaBuffer, Source, SourceType},
+    sys::{self, DmaBuffer, Source, SourceType},
     ByteSliceMutExt, Reactor,
 };
-use nix::sys::socket::{MsgFlags, SockaddrLike};
+use nix::sys::socket::{MsgFlags, SockaddrLike, SockaddrStorage};
 use std::{
     cell::Cell,
-    io,
+    io::{self, IoSliceMut},
+    mem::{self, MaybeUninit},
+    net::SocketAddr,
     os::unix::io::{AsRawFd, FromRawFd, RawFd},
     rc::{Rc, Weak},
     time::Duration,
@@ -18,8 +20,181 @@ use std::{
 
 const DEFAULT_BUFFER_SIZE: usize = 8192;
 
+// Room for a GRO segment size and a TOS byte or traffic class
+const RECV_CONTROL_LEN: usize =
+    2 * unsafe { libc::CMSG_SPACE(mem::size_of::<libc::c_int>() as u32) } as usize;
+
 type Result<T> = crate::Result<T, ()>;
 
+/// A datagram to send with `send_many`, on a [`UdpSocket`] or a
+/// [`UnixDatagram`].
+///
+/// [`UdpSocket`]: super::UdpSocket
+/// [`UnixDatagram`]: super::UnixDatagram
+#[derive(Clone, Copy, Debug)]
+pub struct Transmit<'a, A = SocketAddr> {
+    contents: &'a [u8],
+    destination: Option<A>,
+    segment_size: Option<usize>,
+}
+
+impl<'a, A> Transmit<'a, A> {
+    /// Creates a datagram with `contents`, for the peer the socket is
+    /// connected to.
+    pub fn new(contents: &'a [u8]) -> Transmit<'a, A> {
+        Transmit {
+            contents,
+            destination: None,
+            segment_size: None,
+        }
+    }
+
+    /// Sends the datagram to `destination` rather than to the connected peer.
+    pub fn with_destination(mut self, destination: A) -> Transmit<'a, A> {
+        self.destination = Some(destination);
+        self
+    }
+
+    /// Sends the contents as several datagrams of `segment_size` bytes, the
+    /// last one possibly shorter, split by the kernel or the network card
+    /// rather than with one system call each (UDP generic segmentation
+    /// offload, or `UDP_SEGMENT`).
+    ///
+    /// Only UDP sockets support this. Linux splits the contents into at most
+    /// 64 datagrams, and they must fit in a single 64 KiB IP packet.
+    pub fn with_segment_size(mut self, segment_size: usize) -> Transmit<'a, A> {
+        self.segment_size = Some(segment_size);
+        self
+    }
+
+    pub(crate) fn segment_size(&self) -> Option<usize> {
+        self.segment_size
+    }
+
+    pub(crate) fn map_destination<B>(&self, f: impl FnOnce(&A) -> B) -> Transmit<'a, B> {
+        Transmit {
+            contents: self.contents,
+            destination: self.destination.as_ref().map(f),
+            segment_size: self.segment_size,
+        }
+    }
+
+    pub(crate) fn try_map_destination<B, E>(
+        &self,
+        f: impl FnOnce(&A) -> std::result::Result<B, E>,
+    ) -> std::result::Result<Transmit<'a, B>, E> {
+        Ok(Transmit {
+            contents: self.contents,
+            destination: self.destination.as_ref().map(f).transpose()?,
+            segment_size: self.segment_size,
+        })
+    }
+}
+
+/// Describes a datagram received with `recv_many`, on a [`UdpSocket`] or a
+/// [`UnixDatagram`].
+///
+/// [`UdpSocket`]: super::UdpSocket
+/// [`UnixDatagram`]: super::UnixDatagram
+#[derive(Clone, Copy, Debug)]
+pub struct RecvMeta<A = SocketAddr> {
+    len: usize,
+    addr: A,
+    segment_size: Option<usize>,
+    tos: Option<u8>,
+}
+
+impl<A> RecvMeta<A> {
+    /// Returns the number of bytes received into the buffer.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether the datagram was empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the address the datagram came from.
+    pub fn addr(&self) -> &A {
+        &self.addr
+    }
+
+    /// Returns the size of the datagrams the kernel coalesced into the
+    /// buffer, if it did: the buffer then holds one datagram every
+    /// `segment_size` bytes, the last one possibly shorter.
+    ///
+    /// This only happens with [`UdpSocket::set_gro`] enabled.
+    ///
+    /// [`UdpSocket::set_gro`]: super::UdpSocket::set_gro
+    pub fn segment_size(&self) -> Option<usize> {
+        self.segment_size
+    }
+
+    /// Returns the TOS byte, or the IPv6 traffic class, the datagram was sent
+    /// with, if [`UdpSocket::set_recv_tos`] enabled reporting it.
+    ///
+    /// [`UdpSocket::set_recv_tos`]: super::UdpSocket::set_recv_tos
+    pub fn tos(&self) -> Option<u8> {
+        self.tos
+    }
+
+    /// Returns the ECN codepoint of the datagram, the two low bits of its
+    /// [`tos`](RecvMeta::tos).
+    pub fn ecn(&self) -> Option<u8> {
+        self.tos.map(|tos| tos & 0b11)
+    }
+
+    pub(crate) fn map_addr<B>(self, f: impl FnOnce(A) -> B) -> RecvMeta<B> {
+        RecvMeta {
+            len: self.len,
+            addr: f(self.addr),
+            segment_size: self.segment_size,
+            tos: self.tos,
+        }
+    }
+}
+
+fn recv_meta<T>(len: usize, addr: T, control: &[u8]) -> RecvMeta<T> {
+    let mut meta = RecvMeta {
+        len,
+        addr,
+        segment_size: None,
+        tos: None,
+    };
+    let int = |data: &[u8]| {
+        data.get(..4)
+            .map(|x| i32::from_ne_bytes(x.try_into().unwrap()))
+    };
+    for (level, kind, data) in sys::control_messages(control) {
+        match (level, kind) {
+            (libc::SOL_UDP, libc::UDP_GRO) => meta.segment_size = int(data).map(|x| x as usize),
+            (libc::IPPROTO_IP, libc::IP_TOS) => meta.tos = data.first().copied(),
+            (libc::IPPROTO_IPV6, libc::IPV6_TCLASS) => meta.tos = int(data).map(|x| x as u8),
+            _ => {}
+        }
+    }
+    meta
+}
+
+fn gso_control(segment_size: Option<usize>) -> Vec<u8> {
+    let mut control = Vec::new();
+    if let Some(size) = segment_size {
+        let size = size as u16;
+        sys::push_control_message(
+            &mut control,
+            libc::SOL_UDP,
+            libc::UDP_SEGMENT,
+            &size.to_ne_bytes(),
+        );
+    }
+    control
+}
+
+fn to_storage(addr: &impl SockaddrLike) -> SockaddrStorage {
+    unsafe { SockaddrStorage::from_raw(addr.as_ptr(), Some(addr.len())) }.unwrap()
+}
+
 #[derive(Debug)]
 pub struct GlommioDatagram<S: AsRawFd + FromRawFd + From<socket2::Socket>> {
     pub(crate) reactor: Weak<Reactor>,
@@ -128,11 +303,12 @@ impl<S: AsRawFd + FromRawFd + From<socket2::Socket>> GlommioDatagram<S> {
             self.socket.as_raw_fd(),
             buf.len(),
             flags,
+            0,
             self.read_timeout.get(),
         )?;
         let sz = source.collect_rw().await?;
         match source.extract_source_type() {
-            SourceType::SockRecvMsg(mut src, _iov, hdr, addr) => {
+            SourceType::SockRecvMsg(mut src, _iov, hdr, addr, _control) => {
                 let mut src = src.take().unwrap();
                 src.trim_to_size(sz);
                 buf[0..sz].copy_from_slice(&src.as_bytes()[0..sz]);
@@ -194,7 +370,8 @@ impl<S: AsRawFd + FromRawFd + From<socket2::Socket>> GlommioDatagram<S> {
         let source = self.reactor.upgrade().unwrap().rushed_sendmsg(
             self.socket.as_raw_fd(),
             dma,
-            sockaddr,
+            Some(to_storage(&sockaddr)),
+            Vec::new(),
             self.write_timeout.get(),
         )?;
         let ret = source.collect_rw().await?;
@@ -231,6 +408,97 @@ impl<S: AsRawFd + FromRawFd + From<socket2::Socket>> GlommioDatagram<S> {
         }
     }
 
+    pub(crate) async fn send_many<T: SockaddrLike>(
+        &self,
+        transmits: &[Transmit<'_, T>],
+    ) -> io::Result<usize> {
+        if transmits
+            .iter()
+            .filter_map(|t| t.segment_size)
+            .any(|size| size == 0 || size > u16::MAX as usize)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the segment size must be between 1 and 65535",
+            ));
+        }
+        let mut sent = 0;
+        while sent < transmits.len() {
+            let result = match self.yolo_sendmmsg(&transmits[sent..]) {
+                Some(result) => result,
+                None => self.sendmsg_blocking(&transmits[sent]).await.map(|_| 1),
+            };
+            match result {
+                Ok(count) => sent += count,
+                // like sendmmsg, report what was sent and lose the error
+                Err(_) if sent > 0 => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(sent)
+    }
+
+    async fn sendmsg_blocking<T: SockaddrLike>(
+        &self,
+        transmit: &Transmit<'_, T>,
+    ) -> io::Result<usize> {
+        let mut dma = self.allocate_buffer(transmit.contents.len());
+        assert_eq!(dma.write_at(0, transmit.contents), transmit.contents.len());
+        let source = self.reactor.upgrade().unwrap().rushed_sendmsg(
+            self.socket.as_raw_fd(),
+            dma,
+            transmit.destination.as_ref().map(to_storage),
+            gso_control(transmit.segment_size),
+            self.write_timeout.get(),
+        )?;
+        let ret = source.collect_rw().await?;
+        self.tx_yolo.set(true);
+        Ok(ret)
+    }
+
+    pub(crate) async fn recv_many<T: SockaddrLike>(
+        &self,
+        bufs: &mut [IoSliceMut<'_>],
+    ) -> io::Result<Vec<RecvMeta<T>>> {
+        let Some((first, rest)) = bufs.split_first_mut() else {
+            return Ok(Vec::new());
+        };
+        if let Some(result) = self.yolo_recvmmsg(first, rest) {
+            return result;
+        }
+        let mut received = vec![self.recvmsg_blocking(first).await?];
+        // plus whatever else arrived meanwhile; an error would only hide
+        // what was received
+        if let Some((next, rest)) = rest.split_first_mut() {
+            if let Some(Ok(more)) = self.yolo_recvmmsg(next, rest) {
+                received.extend(more);
+            }
+        }
+        Ok(received)
+    }
+
+    async fn recvmsg_blocking<T: SockaddrLike>(&self, buf: &mut [u8]) -> io::Result<RecvMeta<T>> {
+        let source = self.reactor.upgrade().unwrap().rushed_recvmsg(
+            self.socket.as_raw_fd(),
+            buf.len(),
+            MsgFlags::empty(),
+            RECV_CONTROL_LEN,
+            self.read_timeout.get(),
+        )?;
+        let sz = source.collect_rw().await?;
+        match source.extract_source_type() {
+            SourceType::SockRecvMsg(mut src, _iov, hdr, addr, control) => {
+                let src = src.take().unwrap();
+                buf[0..sz].copy_from_slice(&src.as_bytes()[0..sz]);
+                let addr = unsafe { sys::ssptr_to_sockaddr(addr, hdr.msg_namelen as _)? };
+                self.rx_yolo.set(true);
+                let control = &control[..hdr.msg_controllen.min(control.len())];
+                Ok(recv_meta(sz, addr, control))
+            }
+            _ => unreachable!(),
+        }
+    }
+
     fn allocate_buffer(&self, size: usize) -> DmaBuffer {
         self.reactor.upgrade().unwrap().alloc_dma_buffer(size)
     }
@@ -290,4 +558,126 @@ impl<S: AsRawFd + FromRawFd + From<socket2::Socket>> GlommioDatagram<S> {
             None
         })
     }
+
+    fn yolo_sendmmsg<T: SockaddrLike>(
+        &self,
+        transmits: &[Transmit<'_, T>],
+    ) -> Option<io::Result<usize>> {
+        if self.tx_yolo.get() {
+            let controls: Vec<_> = transmits
+                .iter()
+                .map(|t| gso_control(t.segment_size))
+                .collect();
+            let mut iovs: Vec<_> = transmits
+                .iter()
+                .map(|t| libc::iovec {
+                    iov_base: t.contents.as_ptr() as *mut libc::c_void,
+                    iov_len: t.contents.len(),
+                })
+                .collect();
+            let mut msgs: Vec<_> = transmits
+                .iter()
+                .zip(iovs.iter_mut())
+                .zip(controls.iter())
+                .map(|((transmit, iov), control)| {
+                    let mut hdr = unsafe { mem::zeroed::<libc::msghdr>() };
+                    if let Some(addr) = &transmit.destination {
+                        hdr.msg_name = addr.as_ptr() as *mut libc::c_void;
+                        hdr.msg_namelen = addr.len();
+                    }
+                    hdr.msg_iov = iov as *mut libc::iovec;
+                    hdr.msg_iovlen = 1;
+                    if !control.is_empty() {
+                        hdr.msg_control = control.as_ptr() as *mut libc::c_void;
+                        hdr.msg_controllen = control.len() as _;
+                    }
+                    libc::mmsghdr {
+                        msg_hdr: hdr,
+                        msg_len: 0,
+                    }
+                })
+                .collect();
+            match sys::sendmmsg_syscall(
+                self.socket.as_raw_fd(),
+                &mut msgs,
+                MsgFlags::MSG_DONTWAIT.bits(),
+            ) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => None,
+                result => Some(result),
+            }
+        } else {
+            None
+        }
+        .or_else(|| {
+            self.tx_yolo.set(false);
+            None
+        })
+    }
+
+    // Takes the first buffer apart so there is always at least one.
+    fn yolo_recvmmsg<T: SockaddrLike>(
+        &self,
+        first: &mut [u8],
+        rest: &mut [IoSliceMut<'_>],
+    ) -> Option<io::Result<Vec<RecvMeta<T>>>> {
+        if self.rx_yolo.get() {
+            let count = rest.len() + 1;
+            let mut names =
+                vec![MaybeUninit::<nix::sys::socket::sockaddr_storage>::uninit(); count];
+            let mut controls = vec![0; count * RECV_CONTROL_LEN];
+            let mut iovs: Vec<_> = std::iter::once(first)
+                .chain(rest.iter_mut().map(|buf| &mut **buf))
+                .map(|buf| libc::iovec {
+                    iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+                    iov_len: buf.len(),
+                })
+                .collect();
+            let mut msgs: Vec<_> = iovs
+                .iter_mut()
+                .zip(names.iter_mut())
+                .zip(controls.chunks_mut(RECV_CONTROL_LEN))
+                .map(|((iov, name), control)| {
+                    let mut hdr = unsafe { mem::zeroed::<libc::msghdr>() };
+                    hdr.msg_name = name.as_mut_ptr() as *mut libc::c_void;
+                    hdr.msg_namelen = mem::size_of_val(name) as libc::socklen_t;
+                    hdr.msg_iov = iov as *mut libc::iovec;
+                    hdr.msg_iovlen = 1;
+                    hdr.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+                    hdr.msg_controllen = control.len() as _;
+                    libc::mmsghdr {
+                        msg_hdr: hdr,
+                        msg_len: 0,
+                    }
+                })
+                .collect();
+            match sys::recvmmsg_syscall(
+                self.socket.as_raw_fd(),
+                &mut msgs,
+                MsgFlags::MSG_DONTWAIT.bits(),
+            ) {
+                Ok(received) => Some(
+                    msgs[..received]
+                        .iter()
+                        .zip(names)
+                        .zip(controls.chunks(RECV_CONTROL_LEN))
+                        .map(|((msg, name), control)| {
+                            let hdr = &msg.msg_hdr;
+                            let addr =
+                                unsafe { sys::ssptr_to_sockaddr(name, hdr.msg_namelen as _)? };
+                            let control = &control[..hdr.msg_controllen.min(control.len())];
+                            Ok(recv_meta(msg.msg_len as usize, addr, control))
+                        })
+                        .collect(),
+                ),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => None,
+                Err(err) => Some(Err(err)),
+            }
+        } else {
+            None
+        }
+        .or_else(|| {
+            self.rx_yolo.set(false);
+            None
+        })
+    }
 }
@@ -0,0 +1,306 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::Path,
+    time::Duration,
+};
+
+const DNS_PORT: u16 = 53;
+
+/// Where and how a [`Resolver`] looks names up.
+///
+/// [`ResolverConfig::system`] reads it from `/etc/resolv.conf` and
+/// `/etc/hosts`, the way the C library does; the `with_*` methods build one
+/// by hand.
+///
+/// [`Resolver`]: super::Resolver
+#[derive(Clone, Debug)]
+pub struct ResolverConfig {
+    nameservers: Vec<SocketAddr>,
+    search: Vec<String>,
+    ndots: usize,
+    timeout: Duration,
+    attempts: usize,
+    hosts: HashMap<String, Vec<IpAddr>>,
+}
+
+impl Default for ResolverConfig {
+    fn default() -> Self {
+        ResolverConfig {
+            nameservers: Vec::new(),
+            search: Vec::new(),
+            ndots: 1,
+            timeout: Duration::from_secs(5),
+            attempts: 2,
+            hosts: HashMap::new(),
+        }
+    }
+}
+
+impl ResolverConfig {
+    /// Creates a configuration with no name server, no search domain and no
+    /// host entry, so that every lookup falls back to `getaddrinfo`.
+    pub fn new() -> ResolverConfig {
+        ResolverConfig::default()
+    }
+
+    /// Reads the configuration of the system from `/etc/resolv.conf` and
+    /// `/etc/hosts`.
+    ///
+    /// Either file may be missing. Without a name server in
+    /// `/etc/resolv.conf`, the one on the local host is queried, as the C
+    /// library does.
+    pub fn system() -> io::Result<ResolverConfig> {
+        ResolverConfig::from_files("/etc/resolv.conf", "/etc/hosts")
+    }
+
+    /// Reads the configuration from a file in the `resolv.conf(5)` format and
+    /// a file in the `hosts(5)` format. Either file may be missing.
+    pub fn from_files<R: AsRef<Path>, H: AsRef<Path>>(
+        resolv_conf: R,
+        hosts: H,
+    ) -> io::Result<ResolverConfig> {
+        let mut config = match read_optional(resolv_conf.as_ref())? {
+            Some(resolv_conf) => ResolverConfig::parse_resolv_conf(&resolv_conf),
+            None => ResolverConfig::new(),
+        };
+        if config.nameservers.is_empty() {
+            config = config.with_nameserver(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), DNS_PORT));
+        }
+        if let Some(hosts) = read_optional(hosts.as_ref())? {
+            config.parse_hosts(&hosts);
+        }
+        Ok(config)
+    }
+
+    /// Adds a name server, queried in the order added.
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_nameserver(mut self, addr: SocketAddr) -> ResolverConfig {
+        self.nameservers.push(addr);
+        self
+    }
+
+    /// Adds a domain to search for names with fewer dots than
+    /// [`with_ndots`] sets.
+    ///
+    /// [`with_ndots`]: ResolverConfig::with_ndots
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_search_domain<S: Into<String>>(mut self, domain: S) -> ResolverConfig {
+        let domain = normalize(&domain.into());
+        if !domain.is_empty() {
+            self.search.push(domain);
+        }
+        self
+    }
+
+    /// Sets how many dots a name needs to be looked up as is before the
+    /// search domains are tried. Defaults to 1.
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_ndots(mut self, ndots: usize) -> ResolverConfig {
+        self.ndots = ndots;
+        self
+    }
+
+    /// Sets how long to wait for the answer of a name server. Defaults to 5
+    /// seconds.
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_timeout(mut self, timeout: Duration) -> ResolverConfig {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets how many times every name server is queried before giving up.
+    /// Defaults to 2.
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_attempts(mut self, attempts: usize) -> ResolverConfig {
+        self.attempts = attempts.max(1);
+        self
+    }
+
+    /// Adds an address for `name`, returned without asking any name server.
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_host<S: AsRef<str>>(mut self, name: S, addr: IpAddr) -> ResolverConfig {
+        self.add_host(name.as_ref(), addr);
+        self
+    }
+
+    /// The name servers, in the order they are queried.
+    pub fn nameservers(&self) -> &[SocketAddr] {
+        &self.nameservers
+    }
+
+    /// The search domains, in the order they are tried.
+    pub fn search_domains(&self) -> &[String] {
+        &self.search
+    }
+
+    /// How many dots a name needs to be looked up as is first.
+    pub fn ndots(&self) -> usize {
+        self.ndots
+    }
+
+    /// How long to wait for the answer of a name server.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// How many times every name server is queried.
+    pub fn attempts(&self) -> usize {
+        self.attempts
+    }
+
+    pub(super) fn host(&self, name: &str) -> Option<&[IpAddr]> {
+        self.hosts.get(name).map(Vec::as_slice)
+    }
+
+    // The names to query for `name`, in order, following the search list
+    pub(super) fn candidates(&self, name: &str) -> Vec<String> {
+        if let Some(absolute) = name.strip_suffix('.') {
+            return vec![absolute.to_string()];
+        }
+        let searched = self.search.iter().map(|domain| format!("{name}.{domain}"));
+        if name.matches('.').count() >= self.ndots {
+            std::iter::once(name.to_string()).chain(searched).collect()
+        } else {
+            searched.chain(std::iter::once(name.to_string())).collect()
+        }
+    }
+
+    fn add_host(&mut self, name: &str, addr: IpAddr) {
+        let addrs = self.hosts.entry(normalize(name)).or_default();
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+
+    // Later `search` and `domain` lines replace earlier ones, as in the C
+    // library; unknown lines and options are ignored
+    fn parse_resolv_conf(contents: &str) -> ResolverConfig {
+        let mut config = ResolverConfig::new();
+        for line in contents.lines() {
+            let mut words = line
+                .split(['#', ';'])
+                .next()
+                .unwrap_or_default()
+                .split_whitespace();
+            match words.next() {
+                Some("nameserver") => {
+                    if let Some(ip) = words.next().and_then(|ip| ip.parse::<IpAddr>().ok()) {
+                        config.nameservers.push(SocketAddr::new(ip, DNS_PORT));
+                    }
+                }
+                Some("search") | Some("domain") => {
+                    config.search = words.map(normalize).filter(|d| !d.is_empty()).collect();
+                }
+                Some("options") => {
+                    for option in words {
+                        let (name, value) = option.split_once(':').unwrap_or((option, ""));
+                        let value = value.parse::<usize>().ok();
+                        match (name, value) {
+                            ("ndots", Some(ndots)) => config.ndots = ndots.min(15),
+                            ("timeout", Some(secs)) => {
+                                config.timeout = Duration::from_secs(secs.clamp(1, 30) as u64)
+                            }
+                            ("attempts", Some(attempts)) => config.attempts = attempts.clamp(1, 5),
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        config
+    }
+
+    fn parse_hosts(&mut self, contents: &str) {
+        for line in contents.lines() {
+            let mut words = line
+                .split('#')
+                .next()
+                .unwrap_or_default()
+                .split_whitespace();
+            let Some(addr) = words.next().and_then(|addr| addr.parse::<IpAddr>().ok()) else {
+                continue;
+            };
+            for name in words {
+                self.add_host(name, addr);
+            }
+        }
+    }
+}
+
+fn read_optional(path: &Path) -> io::Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Names compare without case and without the final dot.
+pub(super) fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_resolv_conf() {
+        let config = ResolverConfig::parse_resolv_conf(
+            "# generated\n\
+             nameserver 10.0.0.1\n\
+             nameserver 2001:db8::1 ; secondary\n\
+             nameserver fe80::1%eth0\n\
+             domain ignored.example\n\
+             search corp.example lab.example.\n\
+             options ndots:2 timeout:1 attempts:9 rotate\n",
+        );
+        assert_eq!(
+            config.nameservers(),
+            &[
+                "10.0.0.1:53".parse().unwrap(),
+                "[2001:db8::1]:53".parse().unwrap()
+            ]
+        );
+        assert_eq!(config.search_domains(), &["corp.example", "lab.example"]);
+        assert_eq!(config.ndots(), 2);
+        assert_eq!(config.timeout(), Duration::from_secs(1));
+        assert_eq!(config.attempts(), 5);
+    }
+
+    #[test]
+    fn parses_hosts() {
+        let mut config = ResolverConfig::new();
+        config.parse_hosts(
+            "127.0.0.1 localhost\n\
+             ::1 localhost ip6-localhost # loopback\n\
+             10.1.2.3 Db.Example db\n\
+             not-an-address ignored\n",
+        );
+        assert_eq!(
+            config.host("localhost").unwrap(),
+            &[
+                "127.0.0.1".parse::<IpAddr>().unwrap(),
+                "::1".parse().unwrap()
+            ]
+        );
+        assert_eq!(
+            config.host("db.example").unwrap(),
+            &["10.1.2.3".parse::<IpAddr>().unwrap()]
+        );
+        assert!(config.host("ignored").is_none());
+    }
+
+    #[test]
+    fn search_list_order() {
+        let config = ResolverConfig::new()
+            .with_search_domain("corp.example")
+            .with_ndots(1);
+        assert_eq!(config.candidates("db"), ["db.corp.example", "db"]);
+        assert_eq!(config.candidates("db.eu"), ["db.eu", "db.eu.corp.example"]);
+        assert_eq!(config.candidates("db."), ["db"]);
+    }
+}
@@ -0,0 +1,604 @@
+//! Asynchronous name resolution.
+//!
+//! [`std::net::ToSocketAddrs`] resolves names with `getaddrinfo`, which
+//! blocks the calling thread until the name servers answer; on an executor
+//! that stalls every task of the shard. The [`Resolver`] queries the name
+//! servers itself over a [`UdpSocket`], after looking in the hosts file, and
+//! keeps the answers of every executor for as long as their TTL allows. Only
+//! when it gets no answer does it ask `getaddrinfo`, on the blocking thread
+//! pool.
+//!
+//! [`TcpStream::connect`], [`UdpSocket::connect`] and [`UdpSocket::send_to`]
+//! take a [`ToSocketAddrs`], which resolves through the resolver of the
+//! executor.
+//!
+//! [`UdpSocket`]: super::UdpSocket
+//! [`TcpStream::connect`]: super::TcpStream::connect
+//! [`UdpSocket::connect`]: super::UdpSocket::connect
+//! [`UdpSocket::send_to`]: super::UdpSocket::send_to
+
+mod config;
+mod wire;
+
+pub use self::config::ResolverConfig;
+
+use crate::net::UdpSocket;
+use futures_lite::future::zip;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    rc::Rc,
+    time::{Duration, Instant, SystemTime},
+};
+use wire::Record;
+
+type Result<T> = crate::Result<T, ()>;
+
+thread_local!(static RESOLVER: RefCell<Option<Rc<Resolver>>> = const { RefCell::new(None) });
+
+#[derive(Debug)]
+struct CacheEntry {
+    addrs: Vec<IpAddr>,
+    expires: Instant,
+}
+
+// What querying the name servers for one name gave
+enum Answer {
+    Found(Vec<Record>),
+    // the name does not exist, or has no address
+    Missing,
+}
+
+/// Resolves names to addresses without blocking the executor.
+///
+/// A name is looked up in the hosts entries first, then in the cache, and
+/// then by querying each name server in turn for its IPv4 and IPv6
+/// addresses, along the search list of the configuration. Answers stay in the
+/// cache for their TTL. If no name server answers, or there is none, the name
+/// is resolved with `getaddrinfo` on the blocking thread pool instead.
+///
+/// Every executor has its own resolver, returned by [`Resolver::current`],
+/// which [`TcpStream::connect`] and [`UdpSocket::send_to`] use.
+///
+/// # Examples
+///
+/// ```no_run
+/// use glommio::{net::Resolver, LocalExecutor};
+///
+/// let ex = LocalExecutor::default();
+/// ex.run(async move {
+///     let addrs = Resolver::current().lookup_ip("example.com").await.unwrap();
+///     println!("example.com is at {addrs:?}");
+/// });
+/// ```
+///
+/// [`TcpStream::connect`]: super::TcpStream::connect
+/// [`UdpSocket::send_to`]: super::UdpSocket::send_to
+#[derive(Debug)]
+pub struct Resolver {
+    config: ResolverConfig,
+    cache: RefCell<HashMap<String, CacheEntry>>,
+}
+
+impl Resolver {
+    /// Creates a resolver with its own, empty cache.
+    pub fn new(config: ResolverConfig) -> Resolver {
+        Resolver {
+            config,
+            cache: Default::default(),
+        }
+    }
+
+    /// Returns the resolver of this executor.
+    ///
+    /// Unless [`set_current`] replaced it, it is created on first use from
+    /// [`ResolverConfig::system`], or with no name server if that fails.
+    ///
+    /// [`set_current`]: Resolver::set_current
+    pub fn current() -> Rc<Resolver> {
+        RESOLVER.with(|resolver| {
+            resolver
+                .borrow_mut()
+                .get_or_insert_with(|| {
+                    let config = ResolverConfig::system().unwrap_or_default();
+                    Rc::new(Resolver::new(config))
+                })
+                .clone()
+        })
+    }
+
+    /// Makes `resolver` the resolver of this executor, returned by
+    /// [`current`] from now on.
+    ///
+    /// [`current`]: Resolver::current
+    pub fn set_current(resolver: Resolver) {
+        RESOLVER.with(|current| *current.borrow_mut() = Some(Rc::new(resolver)));
+    }
+
+    /// The configuration of this resolver.
+    pub fn config(&self) -> &ResolverConfig {
+        &self.config
+    }
+
+    /// Forgets every cached answer.
+    pub fn clear_cache(&self) {
+        self.cache.borrow_mut().clear();
+    }
+
+    /// Resolves `host` to the socket addresses with `port`, IPv4 addresses
+    /// first.
+    pub async fn lookup(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>> {
+        let addrs = self.lookup_ip(host).await?;
+        Ok(addrs
+            .into_iter()
+            .map(|ip| SocketAddr::new(ip, port))
+            .collect())
+    }
+
+    /// Resolves `host` to its addresses, IPv4 addresses first.
+    ///
+    /// A name that does not exist is a [`NotFound`] error. An IP address
+    /// resolves to itself.
+    ///
+    /// [`NotFound`]: io::ErrorKind::NotFound
+    pub async fn lookup_ip(&self, host: &str) -> Result<Vec<IpAddr>> {
+        let host = host
+            .strip_prefix('[')
+            .and_then(|host| host.strip_suffix(']'))
+            .unwrap_or(host);
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(vec![ip]);
+        }
+        let name = config::normalize(host);
+        if let Some(addrs) = self.config.host(&name) {
+            return Ok(addrs.to_vec());
+        }
+        if let Some(addrs) = self.cached(&name) {
+            return Ok(addrs);
+        }
+
+        if !self.config.nameservers().is_empty() {
+            let mut failed = false;
+            for candidate in self.config.candidates(&host.to_ascii_lowercase()) {
+                match self.query_both(&candidate).await {
+                    Ok(Answer::Found(records)) => {
+                        let addrs = self.cache_answer(&name, records);
+                        return Ok(addrs);
+                    }
+                    Ok(Answer::Missing) => {}
+                    Err(_) => failed = true,
+                }
+            }
+            if !failed {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no address found for {host}"),
+                )
+                .into());
+            }
+        }
+        Ok(getaddrinfo(host.to_string()).await?)
+    }
+
+    fn cached(&self, name: &str) -> Option<Vec<IpAddr>> {
+        let mut cache = self.cache.borrow_mut();
+        match cache.get(name) {
+            Some(entry) if entry.expires > Instant::now() => Some(entry.addrs.clone()),
+            Some(_) => {
+                cache.remove(name);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn cache_answer(&self, name: &str, records: Vec<Record>) -> Vec<IpAddr> {
+        let ttl = records.iter().map(|record| record.ttl).min().unwrap_or(0);
+        let mut addrs: Vec<IpAddr> = Vec::with_capacity(records.len());
+        for record in records {
+            if !addrs.contains(&record.addr) {
+                addrs.push(record.addr);
+            }
+        }
+        addrs.sort_by_key(IpAddr::is_ipv6);
+        if ttl > 0 {
+            self.cache.borrow_mut().insert(
+                name.to_string(),
+                CacheEntry {
+                    addrs: addrs.clone(),
+                    expires: Instant::now() + Duration::from_secs(ttl as u64),
+                },
+            );
+        }
+        addrs
+    }
+
+    // Asks for the IPv4 and IPv6 addresses of `name` at once. One failing is
+    // fine if the other found addresses.
+    async fn query_both(&self, name: &str) -> io::Result<Answer> {
+        let (v4, v6) = zip(
+            self.query(name, wire::TYPE_A),
+            self.query(name, wire::TYPE_AAAA),
+        )
+        .await;
+        match (v4, v6) {
+            (Ok(Answer::Found(mut v4)), Ok(Answer::Found(v6))) => {
+                v4.extend(v6);
+                Ok(Answer::Found(v4))
+            }
+            (Ok(Answer::Found(records)), _) | (_, Ok(Answer::Found(records))) => {
+                Ok(Answer::Found(records))
+            }
+            (Err(err), _) | (_, Err(err)) => Err(err),
+            (Ok(Answer::Missing), Ok(Answer::Missing)) => Ok(Answer::Missing),
+        }
+    }
+
+    // Tries every name server in turn, as many rounds as configured
+    async fn query(&self, name: &str, qtype: u16) -> io::Result<Answer> {
+        let query = wire::query(random_id(), name, qtype)?;
+        let mut last_err = io::Error::new(io::ErrorKind::TimedOut, "no name server answered");
+        for _ in 0..self.config.attempts() {
+            for nameserver in self.config.nameservers() {
+                match self.exchange(*nameserver, &query).await {
+                    Ok(response) if response.rcode == wire::RCODE_NAME_ERROR => {
+                        return Ok(Answer::Missing)
+                    }
+                    Ok(response) if response.rcode != 0 => {
+                        last_err = io::Error::other(format!(
+                            "name server {nameserver} failed with code {}",
+                            response.rcode
+                        ));
+                    }
+                    // partial answers are left to `getaddrinfo`, which
+                    // retries over TCP
+                    Ok(response) if response.truncated => {
+                        last_err = io::Error::other(format!(
+                            "name server {nameserver} truncated its answer"
+                        ));
+                    }
+                    Ok(response) if response.records.is_empty() => return Ok(Answer::Missing),
+                    Ok(response) => return Ok(Answer::Found(response.records)),
+                    Err(err) => last_err = err,
+                }
+            }
+        }
+        Err(last_err)
+    }
+
+    async fn exchange(&self, nameserver: SocketAddr, query: &[u8]) -> io::Result<wire::Response> {
+        let local: SocketAddr = match nameserver {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect_addr(nameserver).await?;
+        socket.send(query).await?;
+        let id = u16::from_be_bytes([query[0], query[1]]);
+        let receive = async {
+            let mut buf = [0; wire::MAX_UDP_LEN];
+            loop {
+                let len = socket.recv(&mut buf).await?;
+                // stray answers to earlier queries are dropped
+                match wire::parse(&buf[..len]) {
+                    Ok(response) if response.id == id => return Ok(response),
+                    _ => continue,
+                }
+            }
+        };
+        Ok(crate::timer::try_timeout(self.config.timeout(), receive).await?)
+    }
+}
+
+fn random_id() -> u16 {
+    let mut id = [0u8; 2];
+    let filled = unsafe { libc::getrandom(id.as_mut_ptr().cast(), id.len(), 0) };
+    if filled != id.len() as isize {
+        // ids only need to be hard to guess, and this is rare enough
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH);
+        return now.unwrap_or_default().subsec_nanos() as u16 ^ std::process::id() as u16;
+    }
+    u16::from_ne_bytes(id)
+}
+
+async fn getaddrinfo(host: String) -> io::Result<Vec<IpAddr>> {
+    crate::executor()
+        .spawn_blocking(move || {
+            use std::net::ToSocketAddrs as _;
+            let mut addrs: Vec<IpAddr> = Vec::new();
+            for addr in (host.as_str(), 0).to_socket_addrs()? {
+                if !addrs.contains(&addr.ip()) {
+                    addrs.push(addr.ip());
+                }
+            }
+            Ok(addrs)
+        })
+        .await
+}
+
+/// Resolves `addr` with the resolver of this executor.
+pub(crate) async fn resolve<A: ToSocketAddrs + ?Sized>(addr: &A) -> Result<Vec<SocketAddr>> {
+    let addrs = match addr.to_target() {
+        sealed::Target::Addrs(addrs) => addrs,
+        sealed::Target::Host(host, port) => Resolver::current().lookup(host, port).await?,
+        sealed::Target::Invalid(err) => return Err(err.into()),
+    };
+    if addrs.is_empty() {
+        return Err(io::Error::other("empty address").into());
+    }
+    Ok(addrs)
+}
+
+/// Values that are, or resolve to, socket addresses, without blocking.
+///
+/// Implemented for the same types as [`std::net::ToSocketAddrs`]. Host names,
+/// as in `"example.com:80"` or `("example.com", 80)`, are resolved with the
+/// [`Resolver`] of the executor.
+pub trait ToSocketAddrs: sealed::ToTarget {}
+
+impl<T: sealed::ToTarget + ?Sized> ToSocketAddrs for T {}
+
+mod sealed {
+    use super::*;
+
+    #[derive(Debug)]
+    pub enum Target<'a> {
+        Addrs(Vec<SocketAddr>),
+        Host(&'a str, u16),
+        Invalid(io::Error),
+    }
+
+    pub trait ToTarget {
+        fn to_target(&self) -> Target<'_>;
+    }
+
+    impl ToTarget for SocketAddr {
+        fn to_target(&self) -> Target<'_> {
+            Target::Addrs(vec![*self])
+        }
+    }
+
+    impl ToTarget for SocketAddrV4 {
+        fn to_target(&self) -> Target<'_> {
+            Target::Addrs(vec![(*self).into()])
+        }
+    }
+
+    impl ToTarget for SocketAddrV6 {
+        fn to_target(&self) -> Target<'_> {
+            Target::Addrs(vec![(*self).into()])
+        }
+    }
+
+    impl ToTarget for (IpAddr, u16) {
+        fn to_target(&self) -> Target<'_> {
+            Target::Addrs(vec![(*self).into()])
+        }
+    }
+
+    impl ToTarget for (Ipv4Addr, u16) {
+        fn to_target(&self) -> Target<'_> {
+            Target::Addrs(vec![(*self).into()])
+        }
+    }
+
+    impl ToTarget for (Ipv6Addr, u16) {
+        fn to_target(&self) -> Target<'_> {
+            Target::Addrs(vec![(*self).into()])
+        }
+    }
+
+    impl ToTarget for (&str, u16) {
+        fn to_target(&self) -> Target<'_> {
+            host_target(self.0, self.1)
+        }
+    }
+
+    impl ToTarget for (String, u16) {
+        fn to_target(&self) -> Target<'_> {
+            host_target(&self.0, self.1)
+        }
+    }
+
+    impl ToTarget for str {
+        fn to_target(&self) -> Target<'_> {
+            if let Ok(addr) = self.parse::<SocketAddr>() {
+                return Target::Addrs(vec![addr]);
+            }
+            let parsed = self
+                .rsplit_once(':')
+                .and_then(|(host, port)| Some((host, port.parse::<u16>().ok()?)));
+            match parsed {
+                Some((host, port)) => host_target(host, port),
+                None => Target::Invalid(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "invalid socket address",
+                )),
+            }
+        }
+    }
+
+    impl ToTarget for String {
+        fn to_target(&self) -> Target<'_> {
+            self.as_str().to_target()
+        }
+    }
+
+    impl ToTarget for [SocketAddr] {
+        fn to_target(&self) -> Target<'_> {
+            Target::Addrs(self.to_vec())
+        }
+    }
+
+    impl<T: ToTarget + ?Sized> ToTarget for &T {
+        fn to_target(&self) -> Target<'_> {
+            (**self).to_target()
+        }
+    }
+
+    fn host_target(host: &str, port: u16) -> Target<'_> {
+        match host.parse::<IpAddr>() {
+            Ok(ip) => Target::Addrs(vec![SocketAddr::new(ip, port)]),
+            Err(_) => Target::Host(host, port),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{enclose, net::TcpListener};
+    use std::cell::Cell;
+
+    // A name server answering with the addresses of `zone`, or that the name
+    // does not exist; counts the queries it gets
+    fn nameserver(zone: Vec<(&'static str, Record)>) -> (SocketAddr, Rc<Cell<usize>>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let queries = Rc::new(Cell::new(0));
+        crate::spawn_local(enclose! { (queries) async move {
+            let mut buf = [0; wire::MAX_UDP_LEN];
+            loop {
+                let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+                queries.set(queries.get() + 1);
+                let query = &buf[..len];
+                let (name, qtype) = question(query);
+                let records: Vec<Record> = zone
+                    .iter()
+                    .filter(|(owner, record)| {
+                        *owner == name && record.addr.is_ipv4() == (qtype == wire::TYPE_A)
+                    })
+                    .map(|(_, record)| *record)
+                    .collect();
+                let known = zone.iter().any(|(owner, _)| *owner == name);
+                let rcode = if known { 0 } else { wire::RCODE_NAME_ERROR };
+                let response = wire::test::response(query, rcode, &records);
+                socket.send_to(&response, peer).await.unwrap();
+            }
+        }})
+        .detach();
+        (addr, queries)
+    }
+
+    fn question(query: &[u8]) -> (String, u16) {
+        let mut labels = Vec::new();
+        let mut pos = 12;
+        while query[pos] != 0 {
+            let len = query[pos] as usize;
+            labels.push(std::str::from_utf8(&query[pos + 1..pos + 1 + len]).unwrap());
+            pos += 1 + len;
+        }
+        (
+            labels.join("."),
+            u16::from_be_bytes([query[pos + 1], query[pos + 2]]),
+        )
+    }
+
+    fn record(addr: &str, ttl: u32) -> Record {
+        Record {
+            addr: addr.parse().unwrap(),
+            ttl,
+        }
+    }
+
+    #[test]
+    fn caches_answers_for_their_ttl() {
+        test_executor!(async move {
+            let (addr, queries) = nameserver(vec![
+                ("db.example", record("2001:db8::1", 60)),
+                ("db.example", record("192.0.2.1", 60)),
+                ("fleeting.example", record("192.0.2.2", 0)),
+            ]);
+            let resolver = Resolver::new(ResolverConfig::new().with_nameserver(addr));
+
+            let expected: Vec<IpAddr> =
+                vec!["192.0.2.1".parse().unwrap(), "2001:db8::1".parse().unwrap()];
+            assert_eq!(resolver.lookup_ip("db.example").await.unwrap(), expected);
+            assert_eq!(queries.get(), 2);
+            assert_eq!(resolver.lookup_ip("DB.example.").await.unwrap(), expected);
+            assert_eq!(queries.get(), 2);
+
+            for _ in 0..2 {
+                resolver.lookup_ip("fleeting.example").await.unwrap();
+            }
+            assert_eq!(queries.get(), 6);
+
+            resolver.clear_cache();
+            resolver.lookup_ip("db.example").await.unwrap();
+            assert_eq!(queries.get(), 8);
+        });
+    }
+
+    #[test]
+    fn searches_hosts_and_domains() {
+        test_executor!(async move {
+            let (addr, queries) = nameserver(vec![("db.corp.example", record("192.0.2.1", 60))]);
+            let resolver = Resolver::new(
+                ResolverConfig::new()
+                    .with_nameserver(addr)
+                    .with_search_domain("corp.example")
+                    .with_host("gateway", "10.0.0.1".parse().unwrap()),
+            );
+
+            let db = resolver.lookup("db", 5432).await.unwrap();
+            assert_eq!(db, vec!["192.0.2.1:5432".parse().unwrap()]);
+            let gateway = resolver.lookup_ip("Gateway").await.unwrap();
+            assert_eq!(gateway, vec!["10.0.0.1".parse::<IpAddr>().unwrap()]);
+            assert_eq!(
+                resolver.lookup_ip("[::1]").await.unwrap(),
+                vec![IpAddr::from(Ipv6Addr::LOCALHOST)]
+            );
+            assert_eq!(queries.get(), 2);
+
+            let err = resolver.lookup_ip("missing").await.unwrap_err();
+            assert_eq!(io::Error::from(err).kind(), io::ErrorKind::NotFound);
+        });
+    }
+
+    #[test]
+    fn falls_back_to_getaddrinfo() {
+        test_executor!(async move {
+            // never answers
+            let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let resolver = Resolver::new(
+                ResolverConfig::new()
+                    .with_nameserver(silent.local_addr().unwrap())
+                    .with_timeout(Duration::from_millis(10))
+                    .with_attempts(1),
+            );
+            let addrs = resolver.lookup_ip("localhost").await.unwrap();
+            assert!(addrs.iter().all(IpAddr::is_loopback), "{addrs:?}");
+            assert!(!addrs.is_empty());
+        });
+    }
+
+    #[test]
+    fn connect_resolves_with_the_executor_resolver() {
+        test_executor!(async move {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let port = listener.local_addr().unwrap().port();
+            Resolver::set_current(Resolver::new(
+                ResolverConfig::new().with_host("service.test", Ipv4Addr::LOCALHOST.into()),
+            ));
+            let accept = crate::spawn_local(async move {
+                for _ in 0..2 {
+                    listener.accept().await.unwrap();
+                }
+            });
+
+            crate::net::TcpStream::connect(("service.test", port))
+                .await
+                .unwrap();
+            let addr = format!("service.test:{port}");
+            crate::net::TcpStream::connect(&addr).await.unwrap();
+            accept.await;
+
+            let err = crate::net::TcpStream::connect("service.test")
+                .await
+                .unwrap_err();
+            assert_eq!(io::Error::from(err).kind(), io::ErrorKind::InvalidInput);
+        });
+    }
+}
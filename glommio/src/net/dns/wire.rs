@@ -0,0 +1,203 @@
+//! The DNS message format (RFC 1035), as far as looking up addresses needs
+//! it.
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+pub(super) const TYPE_A: u16 = 1;
+pub(super) const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+const HEADER_LEN: usize = 12;
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_TRUNCATED: u16 = 0x0200;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+
+pub(super) const RCODE_NAME_ERROR: u8 = 3;
+
+/// The largest message sent over UDP without extensions.
+pub(super) const MAX_UDP_LEN: usize = 512;
+
+/// An address record of an answer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Record {
+    pub(super) addr: IpAddr,
+    pub(super) ttl: u32,
+}
+
+/// The parts of a response a lookup looks at.
+#[derive(Debug)]
+pub(super) struct Response {
+    pub(super) id: u16,
+    pub(super) truncated: bool,
+    pub(super) rcode: u8,
+    pub(super) records: Vec<Record>,
+}
+
+fn malformed() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "malformed DNS response")
+}
+
+/// Encodes a recursive query for the records of type `qtype` of `name`.
+pub(super) fn query(id: u16, name: &str, qtype: u16) -> io::Result<Vec<u8>> {
+    let mut msg = Vec::with_capacity(HEADER_LEN + name.len() + 6);
+    msg.extend_from_slice(&id.to_be_bytes());
+    msg.extend_from_slice(&FLAG_RECURSION_DESIRED.to_be_bytes());
+    // one question, no records
+    msg.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    encode_name(&mut msg, name)?;
+    msg.extend_from_slice(&qtype.to_be_bytes());
+    msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(msg)
+}
+
+pub(super) fn encode_name(msg: &mut Vec<u8>, name: &str) -> io::Result<()> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("invalid name: {name}"));
+    if name.len() > 253 {
+        return Err(invalid());
+    }
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        if label.len() > 63 {
+            return Err(invalid());
+        }
+        msg.push(label.len() as u8);
+        msg.extend_from_slice(label.as_bytes());
+    }
+    msg.push(0);
+    Ok(())
+}
+
+/// Decodes a response, keeping the A and AAAA records of its answer.
+pub(super) fn parse(msg: &[u8]) -> io::Result<Response> {
+    let u16_at = |pos: usize| -> io::Result<u16> {
+        msg.get(pos..pos + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .ok_or_else(malformed)
+    };
+    let id = u16_at(0)?;
+    let flags = u16_at(2)?;
+    if flags & FLAG_RESPONSE == 0 {
+        return Err(malformed());
+    }
+    let questions = u16_at(4)?;
+    let answers = u16_at(6)?;
+
+    let mut pos = HEADER_LEN;
+    for _ in 0..questions {
+        pos = skip_name(msg, pos)? + 4;
+    }
+    let mut records = Vec::new();
+    for _ in 0..answers {
+        pos = skip_name(msg, pos)?;
+        let kind = u16_at(pos)?;
+        let class = u16_at(pos + 2)?;
+        let ttl = (u16_at(pos + 4)? as u32) << 16 | u16_at(pos + 6)? as u32;
+        let len = u16_at(pos + 8)? as usize;
+        let data = msg.get(pos + 10..pos + 10 + len).ok_or_else(malformed)?;
+        pos += 10 + len;
+        let addr = match (kind, class, len) {
+            (TYPE_A, CLASS_IN, 4) => {
+                IpAddr::from(Ipv4Addr::from(<[u8; 4]>::try_from(data).unwrap()))
+            }
+            (TYPE_AAAA, CLASS_IN, 16) => {
+                IpAddr::from(Ipv6Addr::from(<[u8; 16]>::try_from(data).unwrap()))
+            }
+            // aliases, and whatever else the server added
+            _ => continue,
+        };
+        records.push(Record { addr, ttl });
+    }
+
+    Ok(Response {
+        id,
+        truncated: flags & FLAG_TRUNCATED != 0,
+        rcode: (flags & 0xf) as u8,
+        records,
+    })
+}
+
+// Returns the position after the name at `pos`
+fn skip_name(msg: &[u8], mut pos: usize) -> io::Result<usize> {
+    loop {
+        let len = *msg.get(pos).ok_or_else(malformed)?;
+        match len {
+            0 => return Ok(pos + 1),
+            // a pointer to a name elsewhere ends this one
+            len if len & 0xc0 == 0xc0 => return Ok(pos + 2),
+            len if len & 0xc0 == 0 => pos += 1 + len as usize,
+            _ => return Err(malformed()),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(super) mod test {
+    use super::*;
+
+    /// Encodes the response of a server to `query`, with `records` as its
+    /// answer.
+    pub(crate) fn response(query: &[u8], rcode: u8, records: &[Record]) -> Vec<u8> {
+        let question_end = skip_name(query, HEADER_LEN).unwrap() + 4;
+        let qtype = u16::from_be_bytes([query[question_end - 4], query[question_end - 3]]);
+        let mut msg = query[..question_end].to_vec();
+        msg[2] = 0x81;
+        msg[3] = 0x80 | rcode;
+        msg[6..8].copy_from_slice(&(records.len() as u16).to_be_bytes());
+        for record in records {
+            // points back at the name of the question
+            msg.extend_from_slice(&[0xc0, HEADER_LEN as u8]);
+            let data = match record.addr {
+                IpAddr::V4(ip) => ip.octets().to_vec(),
+                IpAddr::V6(ip) => ip.octets().to_vec(),
+            };
+            let kind = if data.len() == 4 { TYPE_A } else { TYPE_AAAA };
+            assert_eq!(kind, qtype);
+            msg.extend_from_slice(&kind.to_be_bytes());
+            msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+            msg.extend_from_slice(&record.ttl.to_be_bytes());
+            msg.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            msg.extend_from_slice(&data);
+        }
+        msg
+    }
+
+    #[test]
+    fn query_and_response_roundtrip() {
+        let query = query(0x1234, "www.example.com", TYPE_A).unwrap();
+        assert_eq!(&query[12..29], b"\x03www\x07example\x03com\x00");
+
+        let record = Record {
+            addr: "192.0.2.1".parse().unwrap(),
+            ttl: 300,
+        };
+        let parsed = parse(&response(&query, 0, &[record])).unwrap();
+        assert_eq!(parsed.id, 0x1234);
+        assert_eq!(parsed.rcode, 0);
+        assert!(!parsed.truncated);
+        assert_eq!(parsed.records, vec![record]);
+
+        let parsed = parse(&response(&query, RCODE_NAME_ERROR, &[])).unwrap();
+        assert_eq!(parsed.rcode, RCODE_NAME_ERROR);
+        assert!(parsed.records.is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_messages() {
+        // a query is not a response
+        let query = query(1, "example.com", TYPE_AAAA).unwrap();
+        assert!(parse(&query).is_err());
+        let mut truncated = response(
+            &query,
+            0,
+            &[Record {
+                addr: "2001:db8::1".parse().unwrap(),
+                ttl: 1,
+            }],
+        );
+        truncated.pop();
+        assert!(parse(&truncated).is_err());
+        assert!(encode_name(&mut Vec::new(), &"a".repeat(64)).is_err());
+    }
+}
@@ -0,0 +1,465 @@
+//! Handing listening sockets over to another process.
+//!
+//! Every shard of a server usually binds its own [`TcpListener`] to the same
+//! address, and the kernel spreads the connections among them with
+//! `SO_REUSEPORT`. Each of those listeners has its own queue of connections
+//! not yet accepted, and closing it resets them. So when a new binary takes
+//! over, the old process passes the listeners themselves to it over a Unix
+//! socket instead of closing them: the queues move with them, and no
+//! connection is refused or dropped while both processes run.
+//!
+//! [`TcpListener::into_handover`] and [`TcpListener::from_handover`] do that
+//! for a single listener over a connected [`UnixStream`]. [`PoolHandover`]
+//! does it for a whole pool: every shard of the old process offers its
+//! listener, they all go in one message once the last shard does, and every
+//! shard of the new process receives its share of them.
+//!
+//! The handover message carries a short header and the descriptors, and the
+//! receiver acknowledges it once it owns them. Until the acknowledgement
+//! arrives the old process keeps its listeners, and gets them back if the
+//! handover fails, so it can keep serving.
+//!
+//! [`TcpListener`]: super::TcpListener
+//! [`TcpListener::into_handover`]: super::TcpListener::into_handover
+//! [`TcpListener::from_handover`]: super::TcpListener::from_handover
+
+use crate::{
+    net::{TcpListener, UnixListener, UnixStream},
+    GlommioError,
+};
+use futures_lite::future::poll_fn;
+use socket2::{Domain, SockRef, Type};
+use std::{
+    fmt, io,
+    os::unix::io::{FromRawFd, IntoRawFd, OwnedFd, RawFd},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    task::{Poll, Waker},
+};
+
+type Result<T> = crate::Result<T, ()>;
+
+const MAGIC: &[u8; 8] = b"GLMHNDV1";
+const HEADER_LEN: usize = MAGIC.len() + 4;
+const ACK: u8 = 1;
+
+// The most descriptors Linux passes in one message
+const MAX_LISTENERS: usize = 253;
+
+/// A listener that could not be handed over, and why.
+///
+/// The listener was not passed on and still accepts connections, so the
+/// process can keep serving with it.
+#[derive(Debug)]
+pub struct HandoverError {
+    listener: TcpListener,
+    error: GlommioError<()>,
+}
+
+impl HandoverError {
+    pub(crate) fn new(listener: TcpListener, error: GlommioError<()>) -> HandoverError {
+        HandoverError { listener, error }
+    }
+
+    /// Why the handover failed.
+    pub fn error(&self) -> &GlommioError<()> {
+        &self.error
+    }
+
+    /// Returns the listener, to keep serving with it.
+    pub fn into_listener(self) -> TcpListener {
+        self.listener
+    }
+
+    /// Returns the listener and why the handover failed.
+    pub fn into_parts(self) -> (TcpListener, GlommioError<()>) {
+        (self.listener, self.error)
+    }
+}
+
+impl fmt::Display for HandoverError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the listener could not be handed over: {}", self.error)
+    }
+}
+
+impl std::error::Error for HandoverError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+fn invalid_data(msg: String) -> GlommioError<()> {
+    io::Error::new(io::ErrorKind::InvalidData, msg).into()
+}
+
+/// Sends `fds` in one handover message and waits until they are
+/// acknowledged.
+pub(crate) async fn send_listeners(stream: &UnixStream, fds: &[RawFd]) -> Result<()> {
+    if fds.len() > MAX_LISTENERS {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("at most {MAX_LISTENERS} listeners can be handed over at once"),
+        )
+        .into());
+    }
+    let mut header = [0; HEADER_LEN];
+    header[..MAGIC.len()].copy_from_slice(MAGIC);
+    header[MAGIC.len()..].copy_from_slice(&(fds.len() as u32).to_le_bytes());
+    stream.send_with_fds(&header, fds).await?;
+
+    let mut ack = [0; 1];
+    match stream.recv_with_fds(&mut ack).await? {
+        (1, _) if ack[0] == ACK => Ok(()),
+        (0, _) => Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "the receiving process closed the connection before acknowledging the handover",
+        )
+        .into()),
+        _ => Err(invalid_data("unexpected handover acknowledgement".into())),
+    }
+}
+
+/// Receives one handover message, checks that it carries `expected`
+/// listening TCP sockets, if given, and acknowledges it.
+pub(crate) async fn recv_listeners(
+    stream: &UnixStream,
+    expected: Option<usize>,
+) -> Result<Vec<OwnedFd>> {
+    let mut header = [0; HEADER_LEN];
+    let (read, fds) = stream.recv_with_fds(&mut header).await?;
+    if read != HEADER_LEN || &header[..MAGIC.len()] != MAGIC {
+        return Err(invalid_data("not a listener handover message".into()));
+    }
+    let count = u32::from_le_bytes(header[MAGIC.len()..].try_into().unwrap()) as usize;
+    if count != fds.len() {
+        return Err(invalid_data(format!(
+            "the handover announced {count} listeners but carried {}",
+            fds.len()
+        )));
+    }
+    if let Some(expected) = expected.filter(|expected| *expected != count) {
+        return Err(invalid_data(format!(
+            "expected {expected} listeners in the handover, got {count}"
+        )));
+    }
+    for fd in &fds {
+        let socket = SockRef::from(fd);
+        let tcp = matches!(socket.domain()?, Domain::IPV4 | Domain::IPV6)
+            && socket.r#type()? == Type::STREAM;
+        if !tcp || !socket.is_listener()? {
+            return Err(invalid_data(
+                "the handover carried something else than a TCP listener".into(),
+            ));
+        }
+    }
+    stream.send_with_fds(&[ACK], &[]).await?;
+    Ok(fds)
+}
+
+/// Hands the listeners of every shard of a [`LocalExecutorPoolBuilder`] pool
+/// over to another process.
+///
+/// Create one before building the pool and clone it into every shard. The
+/// same type serves both sides, each process using it once:
+///
+/// * In the process being replaced, every shard calls [`send`] with its
+///   listener. Once the last one does, the listeners of all shards are sent
+///   together to the Unix socket at the path given, and every `send`
+///   completes when the new process has acknowledged them.
+///
+/// * In the process taking over, every shard calls [`receive`]. The first one
+///   binds the Unix socket at the path given and waits for the listeners, and
+///   then each shard gets its share of them.
+///
+/// The two pools need not have the same number of shards. Listener `i` goes
+/// to the `i % shards`-th shard to call [`receive`], so a shard may get more
+/// than one listener and must accept connections on all of them, or none, in
+/// which case it can [`bind`] a new one to the same address.
+///
+/// A handover happens once: create a new `PoolHandover` to try again after a
+/// failure. [`send`] must not be cancelled before it completes, since the
+/// listener offered is passed on even then.
+///
+/// # Examples
+///
+/// ```no_run
+/// use glommio::{
+///     net::{PoolHandover, TcpListener},
+///     LocalExecutorPoolBuilder,
+///     PoolPlacement,
+/// };
+///
+/// // In the new process, started while the old one is still serving
+/// let handover = PoolHandover::new("/run/server/handover.sock", 4);
+/// LocalExecutorPoolBuilder::new(PoolPlacement::Unbound(4))
+///     .on_all_shards(move || async move {
+///         let mut listeners = handover.receive().await.unwrap();
+///         if listeners.is_empty() {
+///             listeners.push(TcpListener::bind("0.0.0.0:8000").unwrap());
+///         }
+///         // accept connections on every listener
+///     })
+///     .unwrap()
+///     .join_all();
+/// ```
+///
+/// [`LocalExecutorPoolBuilder`]: crate::LocalExecutorPoolBuilder
+/// [`send`]: PoolHandover::send
+/// [`receive`]: PoolHandover::receive
+/// [`bind`]: TcpListener::bind
+#[derive(Clone, Debug)]
+pub struct PoolHandover {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    path: PathBuf,
+    shards: usize,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    // The listeners offered so far, in the process being replaced
+    offered: Vec<RawFd>,
+    // How many shards called `receive`, in the process taking over
+    claimed: usize,
+    // The listeners received and not yet claimed by their shard
+    received: Vec<Option<OwnedFd>>,
+    // Set once the handover succeeded or failed; errors are kept as their
+    // kind and message, since every shard gets a copy
+    outcome: Option<std::result::Result<(), (io::ErrorKind, String)>>,
+    wakers: Vec<Waker>,
+}
+
+impl PoolHandover {
+    /// Creates a handover for a pool of `shards` executors, through the Unix
+    /// socket at `path`.
+    ///
+    /// Nothing may exist at `path` when the process taking over starts
+    /// receiving, and the socket is removed once the listeners arrived.
+    pub fn new<P: AsRef<Path>>(path: P, shards: usize) -> PoolHandover {
+        assert!(shards > 0, "a pool has at least one shard");
+        PoolHandover {
+            shared: Arc::new(Shared {
+                path: path.as_ref().to_path_buf(),
+                shards,
+                state: Default::default(),
+            }),
+        }
+    }
+
+    /// Offers the listener of this shard, in the process being replaced.
+    ///
+    /// Completes once the listeners of every shard were handed over, at which
+    /// point this one is closed here and the shard should stop accepting
+    /// connections and finish serving those it has. If the handover fails,
+    /// every shard gets its listener back.
+    pub async fn send(&self, listener: TcpListener) -> std::result::Result<(), HandoverError> {
+        let fds = {
+            let mut state = self.shared.state.lock().unwrap();
+            if state.offered.len() == self.shared.shards {
+                let err = io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "every shard of the pool already offered its listener",
+                );
+                return Err(HandoverError::new(listener, err.into()));
+            }
+            state.offered.push(listener.raw_fd());
+            (state.offered.len() == self.shared.shards).then(|| state.offered.clone())
+        };
+        if let Some(fds) = fds {
+            let result = async {
+                let stream = UnixStream::connect(&self.shared.path).await?;
+                send_listeners(&stream, &fds).await
+            }
+            .await;
+            self.finish(result.map(|_| Vec::new()));
+        }
+
+        match self.outcome().await {
+            Ok(()) => Ok(()),
+            Err(err) => Err(HandoverError::new(listener, err.into())),
+        }
+    }
+
+    /// Receives the share of the listeners of this shard, in the process
+    /// taking over.
+    ///
+    /// Each shard gets a different share, which may be empty. Completes once
+    /// the process being replaced handed its listeners over.
+    pub async fn receive(&self) -> Result<Vec<TcpListener>> {
+        let slot = {
+            let mut state = self.shared.state.lock().unwrap();
+            state.claimed += 1;
+            state.claimed - 1
+        };
+        if slot >= self.shared.shards {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "every shard of the pool already received its listeners",
+            )
+            .into());
+        }
+        if slot == 0 {
+            let result = async {
+                let listener = UnixListener::bind(&self.shared.path)?;
+                let stream = listener.accept().await;
+                let _ = std::fs::remove_file(&self.shared.path);
+                recv_listeners(&stream?, None).await
+            }
+            .await;
+            self.finish(result);
+        }
+
+        self.outcome().await?;
+        let fds: Vec<OwnedFd> = {
+            let mut state = self.shared.state.lock().unwrap();
+            state
+                .received
+                .iter_mut()
+                .skip(slot)
+                .step_by(self.shared.shards)
+                .filter_map(Option::take)
+                .collect()
+        };
+        Ok(fds
+            .into_iter()
+            .map(|fd| unsafe { TcpListener::from_raw_fd(fd.into_raw_fd()) })
+            .collect())
+    }
+
+    fn finish(&self, result: Result<Vec<OwnedFd>>) {
+        let mut state = self.shared.state.lock().unwrap();
+        match result {
+            Ok(fds) => {
+                state.received = fds.into_iter().map(Some).collect();
+                state.outcome = Some(Ok(()));
+            }
+            Err(err) => {
+                let err = io::Error::from(err);
+                state.outcome = Some(Err((err.kind(), err.to_string())));
+            }
+        }
+        for waker in state.wakers.drain(..) {
+            waker.wake();
+        }
+    }
+
+    async fn outcome(&self) -> io::Result<()> {
+        poll_fn(|cx| {
+            let mut state = self.shared.state.lock().unwrap();
+            match &state.outcome {
+                Some(Ok(())) => Poll::Ready(Ok(())),
+                Some(Err((kind, msg))) => Poll::Ready(Err(io::Error::new(*kind, msg.clone()))),
+                None => {
+                    state.wakers.push(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{test_utils::make_tmp_test_directory, LocalExecutorPoolBuilder, PoolPlacement};
+    use futures_lite::{AsyncReadExt, AsyncWriteExt};
+    use std::io::{Read, Write};
+
+    #[test]
+    fn queued_connections_survive_the_handover() {
+        test_executor!(async move {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            // waits in the queue of the listener until someone accepts it
+            let mut client = std::net::TcpStream::connect(addr).unwrap();
+
+            let (old, new) = UnixStream::pair().unwrap();
+            let receiver =
+                crate::spawn_local(async move { TcpListener::from_handover(&new).await.unwrap() });
+            listener.into_handover(&old).await.unwrap();
+            let listener = receiver.await;
+            assert_eq!(listener.local_addr().unwrap(), addr);
+
+            let mut stream = listener.accept().await.unwrap();
+            client.write_all(b"ping").unwrap();
+            let mut buf = [0; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"ping");
+            stream.write_all(b"pong").await.unwrap();
+            client.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"pong");
+        });
+    }
+
+    #[test]
+    fn a_failed_handover_returns_the_listener() {
+        test_executor!(async move {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let (old, new) = UnixStream::pair().unwrap();
+            drop(new);
+
+            let err = listener.into_handover(&old).await.unwrap_err();
+            let listener = err.into_listener();
+            assert_eq!(listener.local_addr().unwrap(), addr);
+            let client =
+                crate::spawn_local(
+                    async move { crate::net::TcpStream::connect(addr).await.unwrap() },
+                );
+            listener.accept().await.unwrap();
+            client.await;
+        });
+    }
+
+    #[test]
+    fn hands_every_shard_over() {
+        let dir = make_tmp_test_directory("handover-every-shard");
+        let path = dir.path.join("handover.sock");
+        // reserves a port that every shard of the old pool binds to
+        let reserved = socket2::Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+        reserved.set_reuse_port(true).unwrap();
+        let any: std::net::SocketAddr = "127.0.0.1:0".parse().unwrap();
+        reserved.bind(&any.into()).unwrap();
+        let addr = reserved.local_addr().unwrap().as_socket().unwrap();
+
+        let new = PoolHandover::new(&path, 2);
+        let receivers = LocalExecutorPoolBuilder::new(PoolPlacement::Unbound(2))
+            .on_all_shards(move || async move {
+                let listeners = new.receive().await.unwrap();
+                listeners
+                    .iter()
+                    .map(|listener| listener.local_addr().unwrap())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap();
+        while !path.exists() {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        let old = PoolHandover::new(&path, 3);
+        let senders = LocalExecutorPoolBuilder::new(PoolPlacement::Unbound(3))
+            .on_all_shards(move || async move {
+                let listener = TcpListener::bind(addr).unwrap();
+                old.send(listener).await.unwrap();
+            })
+            .unwrap();
+        for sent in senders.join_all() {
+            sent.unwrap();
+        }
+
+        let mut received: Vec<_> = receivers
+            .join_all()
+            .into_iter()
+            .map(|addrs| addrs.unwrap())
+            .collect();
+        received.sort_by_key(Vec::len);
+        assert_eq!(received, vec![vec![addr], vec![addr, addr]]);
+        assert!(!path.exists());
+    }
+}
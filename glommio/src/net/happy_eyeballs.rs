@@ -0,0 +1,471 @@
+//! Racing connection attempts to several addresses, as RFC 8305 ("Happy
+//! Eyeballs") describes.
+//!
+//! A name often resolves to both IPv6 and IPv4 addresses, and one of the two
+//! may be unreachable without the network saying so: a connection attempt
+//! over it only fails when it times out. Trying the addresses one after the
+//! other then costs the whole timeout before the working ones get a chance.
+//! Instead, the addresses are tried alternating between families, each
+//! attempt starting when the one before fails or after a short delay, and the
+//! first to connect wins; the others are cancelled.
+
+use crate::{
+    net::{
+        dns::{self, ToSocketAddrs},
+        TcpSocketBuilder, TcpStream,
+    },
+    sys::Source,
+    timer::Timer,
+};
+use futures_lite::future::poll_fn;
+use nix::sys::socket::SockaddrStorage;
+use socket2::Socket;
+use std::{
+    collections::VecDeque,
+    future::Future,
+    io,
+    net::SocketAddr,
+    os::unix::io::AsRawFd,
+    pin::Pin,
+    task::Poll,
+    time::{Duration, Instant},
+};
+
+type Result<T> = crate::Result<T, ()>;
+
+/// How a connection attempt ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttemptOutcome {
+    /// It connected, and its connection was returned.
+    Connected,
+    /// It failed with an error of this kind.
+    Failed(io::ErrorKind),
+    /// Its timeout expired before it connected.
+    TimedOut,
+    /// Another attempt connected first.
+    Cancelled,
+}
+
+/// One connection attempt of a [`ConnectReport`].
+#[derive(Clone, Debug)]
+pub struct ConnectAttempt {
+    addr: SocketAddr,
+    started: Duration,
+    duration: Duration,
+    outcome: AttemptOutcome,
+}
+
+impl ConnectAttempt {
+    /// The address this attempt connected to, or tried to.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// When this attempt started, counted from the start of the first one.
+    pub fn started(&self) -> Duration {
+        self.started
+    }
+
+    /// How long this attempt lasted.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// How this attempt ended.
+    pub fn outcome(&self) -> AttemptOutcome {
+        self.outcome
+    }
+}
+
+/// How [`HappyEyeballs::connect`] got its connection.
+#[derive(Clone, Debug)]
+pub struct ConnectReport {
+    addr: SocketAddr,
+    elapsed: Duration,
+    attempts: Vec<ConnectAttempt>,
+}
+
+impl ConnectReport {
+    /// The address connected to.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// How long connecting took, from the first attempt on.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// Every attempt made, in the order they started.
+    pub fn attempts(&self) -> &[ConnectAttempt] {
+        &self.attempts
+    }
+}
+
+/// Connects to the first of the addresses of a host to accept, racing the
+/// attempts as RFC 8305 describes.
+///
+/// The addresses are tried alternating between IPv6 and IPv4, starting with
+/// IPv6. Each attempt starts as soon as the previous one failed, or after the
+/// [attempt delay] if it is still going; the first to connect is returned,
+/// and the attempts still going are cancelled. [`TcpStream::connect`] and
+/// [`TcpStream::connect_timeout`] connect this way with the default settings.
+///
+/// # Examples
+///
+/// ```no_run
+/// use glommio::{net::HappyEyeballs, LocalExecutor};
+/// use std::time::Duration;
+///
+/// let ex = LocalExecutor::default();
+/// ex.run(async move {
+///     let (stream, report) = HappyEyeballs::new()
+///         .with_attempt_delay(Duration::from_millis(100))
+///         .with_timeout(Duration::from_secs(5))
+///         .connect("example.com:80")
+///         .await
+///         .unwrap();
+///     println!("connected to {} in {:?}", report.addr(), report.elapsed());
+/// });
+/// ```
+///
+/// [attempt delay]: HappyEyeballs::with_attempt_delay
+#[derive(Clone, Debug)]
+pub struct HappyEyeballs {
+    attempt_delay: Duration,
+    attempt_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    socket: TcpSocketBuilder,
+}
+
+impl Default for HappyEyeballs {
+    fn default() -> Self {
+        HappyEyeballs {
+            attempt_delay: Duration::from_millis(250),
+            attempt_timeout: None,
+            timeout: None,
+            socket: TcpSocketBuilder::new(),
+        }
+    }
+}
+
+impl HappyEyeballs {
+    /// Creates the default settings: a delay of 250ms between attempts, and
+    /// no timeout.
+    pub fn new() -> HappyEyeballs {
+        HappyEyeballs::default()
+    }
+
+    /// Sets how long an attempt may go on before the next one starts, kept
+    /// between the 10ms and 2s RFC 8305 allows.
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_attempt_delay(mut self, delay: Duration) -> HappyEyeballs {
+        self.attempt_delay = delay.clamp(Duration::from_millis(10), Duration::from_secs(2));
+        self
+    }
+
+    /// Fails each attempt that takes longer than `timeout`.
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_attempt_timeout(mut self, timeout: Duration) -> HappyEyeballs {
+        self.attempt_timeout = Some(timeout);
+        self
+    }
+
+    /// Fails the connection if no attempt connected within `timeout`. Name
+    /// resolution does not count.
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_timeout(mut self, timeout: Duration) -> HappyEyeballs {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Creates the socket of every attempt with the options of `builder`.
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_socket(mut self, builder: TcpSocketBuilder) -> HappyEyeballs {
+        self.socket = builder;
+        self
+    }
+
+    /// Connects to the first address of `addr` to accept, resolving host
+    /// names with the [`Resolver`] of the executor.
+    ///
+    /// If no attempt connects, returns the error of the last one to fail.
+    ///
+    /// [`Resolver`]: super::Resolver
+    pub async fn connect<A: ToSocketAddrs>(&self, addr: A) -> Result<(TcpStream, ConnectReport)> {
+        let addrs = interleave(dns::resolve(&addr).await?);
+        Ok(self.race(addrs).await?)
+    }
+
+    async fn race(&self, addrs: Vec<SocketAddr>) -> io::Result<(TcpStream, ConnectReport)> {
+        let reactor = crate::executor().reactor();
+        let start = Instant::now();
+        let deadline = self.timeout.map(|timeout| start + timeout);
+        let mut pending = VecDeque::from(addrs);
+        let mut running: Vec<Running> = Vec::new();
+        let mut attempts: Vec<ConnectAttempt> = Vec::new();
+        let mut last_err = None;
+        let mut delay = Timer::new(self.attempt_delay);
+        let mut start_next = true;
+
+        let result = poll_fn(|cx| loop {
+            if std::mem::take(&mut start_next) {
+                while let Some(addr) = pending.pop_front() {
+                    let now = Instant::now();
+                    let left = deadline.map(|deadline| deadline.saturating_duration_since(now));
+                    if left == Some(Duration::ZERO) {
+                        pending.clear();
+                        last_err = Some(timed_out());
+                        break;
+                    }
+                    let timeout = match (left, self.attempt_timeout) {
+                        (Some(left), Some(timeout)) => Some(left.min(timeout)),
+                        (left, timeout) => left.or(timeout),
+                    };
+                    let mut attempt = ConnectAttempt {
+                        addr,
+                        started: now - start,
+                        duration: Duration::ZERO,
+                        outcome: AttemptOutcome::Cancelled,
+                    };
+                    match self.socket.connecting_socket(&addr) {
+                        Ok(socket) => {
+                            let fd = socket.as_raw_fd();
+                            let source = match timeout {
+                                Some(timeout) => reactor.connect_timeout(
+                                    fd,
+                                    SockaddrStorage::from(addr),
+                                    timeout,
+                                ),
+                                None => reactor.connect(fd, SockaddrStorage::from(addr)),
+                            };
+                            running.push(Running {
+                                socket,
+                                source,
+                                attempt: attempts.len(),
+                            });
+                            attempts.push(attempt);
+                            delay.reset(self.attempt_delay);
+                            break;
+                        }
+                        // no socket of this family, or an option it refused: on to the
+                        // next address
+                        Err(err) => {
+                            attempt.outcome = AttemptOutcome::Failed(err.kind());
+                            attempts.push(attempt);
+                            last_err = Some(err);
+                        }
+                    }
+                }
+            }
+
+            let mut failed = false;
+            let mut i = 0;
+            while i < running.len() {
+                match running[i].source.poll_collect_rw(cx) {
+                    Poll::Pending => i += 1,
+                    Poll::Ready(Ok(_)) => return Poll::Ready(Ok(running.swap_remove(i))),
+                    Poll::Ready(Err(err)) => {
+                        let lost = running.swap_remove(i);
+                        let attempt = &mut attempts[lost.attempt];
+                        attempt.duration = start.elapsed() - attempt.started;
+                        // the link timeout cancels the connection attempt
+                        let err = match err.raw_os_error() {
+                            Some(libc::ECANCELED) => timed_out(),
+                            _ => err,
+                        };
+                        attempt.outcome = match err.kind() {
+                            io::ErrorKind::TimedOut => AttemptOutcome::TimedOut,
+                            kind => AttemptOutcome::Failed(kind),
+                        };
+                        last_err = Some(err);
+                        failed = true;
+                    }
+                }
+            }
+
+            if pending.is_empty() {
+                if running.is_empty() {
+                    return Poll::Ready(Err(last_err.take().unwrap_or_else(timed_out)));
+                }
+                return Poll::Pending;
+            }
+            // a failure, or the delay running out, starts the next attempt
+            if failed || running.is_empty() || Pin::new(&mut delay).poll(cx).is_ready() {
+                start_next = true;
+                continue;
+            }
+            return Poll::Pending;
+        })
+        .await;
+
+        let elapsed = start.elapsed();
+        // dropping the others cancels them, and closes their sockets
+        for lost in running {
+            let attempt = &mut attempts[lost.attempt];
+            attempt.duration = elapsed - attempt.started;
+        }
+        let won = result?;
+        let attempt = &mut attempts[won.attempt];
+        attempt.duration = elapsed - attempt.started;
+        attempt.outcome = AttemptOutcome::Connected;
+        let report = ConnectReport {
+            addr: attempt.addr,
+            elapsed,
+            attempts,
+        };
+        Ok((TcpStream::from_socket(won.socket), report))
+    }
+}
+
+// An attempt that has not finished yet
+struct Running {
+    socket: Socket,
+    source: Source,
+    attempt: usize,
+}
+
+fn timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "connection timed out")
+}
+
+/// Orders `addrs` alternating between IPv6 and IPv4, starting with IPv6,
+/// and otherwise as they were.
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let (v6, v4): (Vec<_>, Vec<_>) = addrs.into_iter().partition(SocketAddr::is_ipv6);
+    let mut v6 = v6.into_iter();
+    let mut v4 = v4.into_iter();
+    let mut ordered = Vec::with_capacity(v6.len() + v4.len());
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => return ordered,
+            (first, second) => ordered.extend(first.into_iter().chain(second)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::net::TcpListener;
+    use socket2::{Domain, Type};
+
+    // An address where connection attempts hang: the queue of its listener is
+    // full, so its SYNs get no answer
+    fn blackhole() -> (Vec<Socket>, SocketAddr) {
+        let listener = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+        let any: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        listener.bind(&any.into()).unwrap();
+        listener.listen(0).unwrap();
+        let addr = listener.local_addr().unwrap().as_socket().unwrap();
+        let mut sockets = vec![listener];
+        for _ in 0..4 {
+            let filler = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+            filler.set_nonblocking(true).unwrap();
+            let _ = filler.connect(&addr.into());
+            sockets.push(filler);
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        (sockets, addr)
+    }
+
+    #[test]
+    fn interleaves_families_starting_with_ipv6() {
+        let addrs: Vec<SocketAddr> = ["1.1.1.1:1", "2.2.2.2:1", "[::1]:1", "3.3.3.3:1", "[::2]:1"]
+            .iter()
+            .map(|addr| addr.parse().unwrap())
+            .collect();
+        let ordered: Vec<String> = interleave(addrs).iter().map(ToString::to_string).collect();
+        assert_eq!(
+            ordered,
+            ["[::1]:1", "1.1.1.1:1", "[::2]:1", "2.2.2.2:1", "3.3.3.3:1"]
+        );
+    }
+
+    fn listener() -> (SocketAddr, crate::Task<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = crate::spawn_local(async move {
+            listener.accept().await.unwrap();
+        });
+        (addr, accept)
+    }
+
+    #[test]
+    fn a_failed_attempt_starts_the_next_at_once() {
+        test_executor!(async move {
+            // nothing listens there once the listener is gone
+            let refused = TcpListener::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap();
+            let (addr, accept) = listener();
+
+            let (_stream, report) = HappyEyeballs::new()
+                .with_attempt_delay(Duration::from_secs(2))
+                .connect(&[refused, addr][..])
+                .await
+                .unwrap();
+            accept.await;
+
+            assert_eq!(report.addr(), addr);
+            let attempts = report.attempts();
+            assert_eq!(attempts.len(), 2);
+            assert_eq!(
+                attempts[0].outcome(),
+                AttemptOutcome::Failed(io::ErrorKind::ConnectionRefused)
+            );
+            assert_eq!(attempts[1].outcome(), AttemptOutcome::Connected);
+            assert!(attempts[1].started() < Duration::from_secs(1));
+        });
+    }
+
+    #[test]
+    fn a_slow_attempt_is_raced_and_cancelled() {
+        test_executor!(async move {
+            let (_sockets, slow) = blackhole();
+            let (addr, accept) = listener();
+
+            let (_stream, report) = HappyEyeballs::new()
+                .with_attempt_delay(Duration::from_millis(20))
+                .connect(&[slow, addr][..])
+                .await
+                .unwrap();
+            accept.await;
+
+            assert_eq!(report.addr(), addr);
+            let attempts = report.attempts();
+            assert_eq!(attempts[0].addr(), slow);
+            assert_eq!(attempts[0].outcome(), AttemptOutcome::Cancelled);
+            assert_eq!(attempts[1].outcome(), AttemptOutcome::Connected);
+            assert!(attempts[1].started() >= Duration::from_millis(20));
+            assert!(attempts[0].duration() >= attempts[1].started());
+        });
+    }
+
+    #[test]
+    fn attempts_and_connections_time_out() {
+        test_executor!(async move {
+            let (_sockets, slow) = blackhole();
+            let (addr, accept) = listener();
+
+            let (_stream, report) = HappyEyeballs::new()
+                .with_attempt_delay(Duration::from_secs(2))
+                .with_attempt_timeout(Duration::from_millis(30))
+                .connect(&[slow, addr][..])
+                .await
+                .unwrap();
+            accept.await;
+            let attempts = report.attempts();
+            assert_eq!(attempts[0].outcome(), AttemptOutcome::TimedOut);
+            assert!(attempts[1].started() < Duration::from_secs(1));
+
+            let err = HappyEyeballs::new()
+                .with_timeout(Duration::from_millis(30))
+                .connect(slow)
+                .await
+                .unwrap_err();
+            assert_eq!(io::Error::from(err).kind(), io::ErrorKind::TimedOut);
+        });
+    }
+}
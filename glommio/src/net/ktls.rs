@@ -0,0 +1,652 @@
+//! Kernel TLS: handing a session's record encryption to the kernel.
+
+use crate::{
+    net::TcpStream,
+    reactor::Reactor,
+    sys::{self, Source, SourceType},
+};
+use futures_lite::{
+    future::poll_fn,
+    io::{AsyncRead, AsyncWrite},
+    ready,
+};
+use nix::sys::socket::MsgFlags;
+use std::{
+    fmt, io,
+    net::{Shutdown, SocketAddr},
+    os::unix::io::{AsRawFd, RawFd},
+    pin::Pin,
+    rc::{Rc, Weak},
+    task::{Context, Poll},
+};
+
+type Result<T> = crate::Result<T, ()>;
+
+// From linux/tls.h
+const TLS_TX: libc::c_int = 1;
+const TLS_RX: libc::c_int = 2;
+const TLS_SET_RECORD_TYPE: libc::c_int = 1;
+const TLS_GET_RECORD_TYPE: libc::c_int = 2;
+const TLS_1_2_VERSION: u16 = 0x0303;
+const TLS_1_3_VERSION: u16 = 0x0304;
+const TLS_CIPHER_AES_GCM_128: u16 = 51;
+const TLS_CIPHER_AES_GCM_256: u16 = 52;
+const TLS_CIPHER_CHACHA20_POLY1305: u16 = 54;
+
+// Record content types
+const ALERT: u8 = 21;
+const HANDSHAKE: u8 = 22;
+const APPLICATION_DATA: u8 = 23;
+
+// Handshake messages that can follow the handshake in TLS 1.3
+const NEW_SESSION_TICKET: u8 = 4;
+const KEY_UPDATE: u8 = 24;
+
+const WARNING: u8 = 1;
+const CLOSE_NOTIFY: u8 = 0;
+
+// The largest plaintext a record can carry, so a control record is always
+// received whole
+const MAX_RECORD_LEN: usize = 1 << 14;
+
+// Room for the record type of a control record
+const CONTROL_LEN: usize = unsafe { libc::CMSG_SPACE(1) } as usize;
+
+/// The version of TLS a session negotiated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KtlsVersion {
+    /// TLS 1.2
+    Tls12,
+    /// TLS 1.3
+    Tls13,
+}
+
+/// The cipher a session negotiated, with the key and IV for one direction.
+///
+/// The IV is the full 12-byte nonce base: for TLS 1.2 that is the 4-byte
+/// implicit salt followed by the 8-byte explicit part.
+#[derive(Clone)]
+pub enum KtlsCipher {
+    /// AES-128 in GCM mode.
+    Aes128Gcm {
+        /// The traffic key.
+        key: [u8; 16],
+        /// The nonce base.
+        iv: [u8; 12],
+    },
+    /// AES-256 in GCM mode.
+    Aes256Gcm {
+        /// The traffic key.
+        key: [u8; 32],
+        /// The nonce base.
+        iv: [u8; 12],
+    },
+    /// ChaCha20 with Poly1305.
+    Chacha20Poly1305 {
+        /// The traffic key.
+        key: [u8; 32],
+        /// The nonce base.
+        iv: [u8; 12],
+    },
+}
+
+// Keys stay out of logs
+impl fmt::Debug for KtlsCipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            KtlsCipher::Aes128Gcm { .. } => "Aes128Gcm",
+            KtlsCipher::Aes256Gcm { .. } => "Aes256Gcm",
+            KtlsCipher::Chacha20Poly1305 { .. } => "Chacha20Poly1305",
+        })
+    }
+}
+
+/// What the kernel needs to take over one direction of a TLS session.
+#[derive(Clone, Debug)]
+pub struct KtlsSecrets {
+    version: KtlsVersion,
+    cipher: KtlsCipher,
+    sequence: u64,
+}
+
+impl KtlsSecrets {
+    /// Creates the secrets of one direction. `sequence` is the sequence
+    /// number of the next record in that direction.
+    pub fn new(version: KtlsVersion, cipher: KtlsCipher, sequence: u64) -> KtlsSecrets {
+        KtlsSecrets {
+            version,
+            cipher,
+            sequence,
+        }
+    }
+
+    /// The TLS version the secrets are for.
+    pub fn version(&self) -> KtlsVersion {
+        self.version
+    }
+
+    // The `tls12_crypto_info_*` struct for the cipher. Its fields are all
+    // byte arrays after the two u16 of the header, so there is no padding.
+    fn crypto_info(&self) -> Vec<u8> {
+        let version = match self.version {
+            KtlsVersion::Tls12 => TLS_1_2_VERSION,
+            KtlsVersion::Tls13 => TLS_1_3_VERSION,
+        };
+        let mut info = Vec::with_capacity(56);
+        let mut header = |cipher: u16| {
+            info.extend_from_slice(&version.to_ne_bytes());
+            info.extend_from_slice(&cipher.to_ne_bytes());
+        };
+        // the GCM structs split the nonce base into an 8-byte IV and a
+        // 4-byte salt, and put the key in between
+        let (cipher, key, iv): (u16, &[u8], &[u8; 12]) = match &self.cipher {
+            KtlsCipher::Aes128Gcm { key, iv } => (TLS_CIPHER_AES_GCM_128, key, iv),
+            KtlsCipher::Aes256Gcm { key, iv } => (TLS_CIPHER_AES_GCM_256, key, iv),
+            KtlsCipher::Chacha20Poly1305 { key, iv } => (TLS_CIPHER_CHACHA20_POLY1305, key, iv),
+        };
+        header(cipher);
+        if cipher == TLS_CIPHER_CHACHA20_POLY1305 {
+            info.extend_from_slice(iv);
+            info.extend_from_slice(key);
+        } else {
+            info.extend_from_slice(&iv[4..]);
+            info.extend_from_slice(key);
+            info.extend_from_slice(&iv[..4]);
+        }
+        info.extend_from_slice(&self.sequence.to_be_bytes());
+        info
+    }
+}
+
+// Attaches the kernel's TLS layer. Data passes through untouched until keys
+// are installed.
+pub(crate) fn set_tls_ulp(fd: RawFd) -> io::Result<()> {
+    sys::setsockopt_bytes(fd, libc::SOL_TCP, libc::TCP_ULP, b"tls")
+}
+
+/// A [`TcpStream`] whose TLS records are encrypted and decrypted by the
+/// kernel, produced by [`TcpStream::into_ktls`].
+///
+/// Once a TLS handshake completes, the kernel can encrypt and decrypt the
+/// records of the session given its keys (kTLS). Data then moves with plain
+/// `send` and `recv`, through the ring like on any other socket, and
+/// transfers from files can go straight to the socket without passing
+/// through user space to be encrypted.
+///
+/// [`TcpStream::into_ktls`] installs the keys for each direction, as
+/// [`KtlsSecrets`], and returns this. The keys come from the library that ran
+/// the handshake; with the `rustls` feature, `TlsStream::into_ktls` extracts
+/// and installs them in one go.
+///
+/// Records other than application data still reach user space, as control
+/// messages. The stream ends at a `close_notify` alert, reports any other
+/// alert as an error, and drops TLS 1.3 session tickets. A TLS 1.3 key update
+/// from the peer stops reception until the next keys are installed with
+/// [`set_rx_secrets`](Self::set_rx_secrets): deriving them needs the
+/// session's traffic secrets, which only the TLS library has. Like a
+/// `TlsStream`, closing it sends a `close_notify` alert, and a peer closing
+/// the connection without one is reported as an
+/// [`UnexpectedEof`](io::ErrorKind::UnexpectedEof) error.
+///
+/// This needs the kernel's `tls` module. Call [`TcpStream::set_tls_ulp`]
+/// before the handshake to find out whether it is there, while falling back
+/// to TLS in user space is still possible.
+#[derive(Debug)]
+pub struct KtlsStream {
+    stream: TcpStream,
+    reactor: Weak<Reactor>,
+    version: KtlsVersion,
+
+    // plaintext received but not returned yet, from `rx_pos` on
+    rx_buf: Vec<u8>,
+    rx_pos: usize,
+    rx_source: Option<Source>,
+    // a close_notify was received
+    rx_closed: bool,
+    // a key update was received, and the next keys not installed yet
+    rx_expired: bool,
+    tx_update_requested: bool,
+
+    // waits for room to send a control record
+    tx_source: Option<Source>,
+    close_notify_sent: bool,
+}
+
+impl KtlsStream {
+    pub(crate) fn new(stream: TcpStream, tx: &KtlsSecrets, rx: &KtlsSecrets) -> Result<KtlsStream> {
+        if tx.version != rx.version {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the secrets of both directions must be for the same TLS version",
+            )
+            .into());
+        }
+        let fd = stream.as_raw_fd();
+        match set_tls_ulp(fd) {
+            // set already, with `TcpStream::set_tls_ulp`
+            Err(err) if err.raw_os_error() == Some(libc::EEXIST) => {}
+            result => result?,
+        }
+        sys::setsockopt_bytes(fd, libc::SOL_TLS, TLS_TX, &tx.crypto_info())?;
+        sys::setsockopt_bytes(fd, libc::SOL_TLS, TLS_RX, &rx.crypto_info())?;
+        Ok(KtlsStream {
+            stream,
+            reactor: Rc::downgrade(&crate::executor().reactor()),
+            version: tx.version,
+            rx_buf: Vec::new(),
+            rx_pos: 0,
+            rx_source: None,
+            rx_closed: false,
+            rx_expired: false,
+            tx_update_requested: false,
+            tx_source: None,
+            close_notify_sent: false,
+        })
+    }
+
+    // Plaintext the TLS library had decrypted before the switch, returned
+    // before anything the kernel receives.
+    #[cfg(feature = "rustls")]
+    pub(crate) fn set_pending(&mut self, plaintext: Vec<u8>) {
+        self.rx_buf = plaintext;
+        self.rx_pos = 0;
+    }
+
+    /// Returns the TLS version of the session.
+    pub fn version(&self) -> KtlsVersion {
+        self.version
+    }
+
+    /// Returns a reference to the underlying stream.
+    ///
+    /// Reading from or writing to it directly bypasses the handling of
+    /// control records.
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    /// Returns the socket address of the remote peer of this connection.
+    pub fn peer_addr(&self) -> Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    /// Returns the socket address of the local half of this connection.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.stream.local_addr()
+    }
+
+    /// Returns whether reading stopped at a TLS 1.3 key update from the peer.
+    ///
+    /// Reads fail until the next keys are installed with
+    /// [`set_rx_secrets`](KtlsStream::set_rx_secrets).
+    pub fn rx_secrets_expired(&self) -> bool {
+        self.rx_expired
+    }
+
+    /// Returns whether the peer asked for the keys of this side to be
+    /// updated as well, with [`update_tx_secrets`](KtlsStream::update_tx_secrets).
+    pub fn tx_update_requested(&self) -> bool {
+        self.tx_update_requested
+    }
+
+    /// Installs the keys the peer switched to with a key update.
+    pub fn set_rx_secrets(&mut self, secrets: &KtlsSecrets) -> Result<()> {
+        sys::setsockopt_bytes(
+            self.stream.as_raw_fd(),
+            libc::SOL_TLS,
+            TLS_RX,
+            &secrets.crypto_info(),
+        )?;
+        self.rx_expired = false;
+        Ok(())
+    }
+
+    /// Sends a TLS 1.3 key update, then switches to `secrets` for what is
+    /// sent after it.
+    pub async fn update_tx_secrets(&mut self, secrets: &KtlsSecrets) -> Result<()> {
+        // update_not_requested: the peer's keys are its own business
+        let message = [KEY_UPDATE, 0, 0, 1, 0];
+        poll_fn(|cx| self.poll_send_record(cx, HANDSHAKE, &message)).await?;
+        sys::setsockopt_bytes(
+            self.stream.as_raw_fd(),
+            libc::SOL_TLS,
+            TLS_TX,
+            &secrets.crypto_info(),
+        )?;
+        self.tx_update_requested = false;
+        Ok(())
+    }
+
+    /// Shuts down the read, write, or both halves of this connection.
+    ///
+    /// Shutting down the write half sends a `close_notify` alert first.
+    pub async fn shutdown(&mut self, how: Shutdown) -> Result<()> {
+        if how != Shutdown::Read {
+            poll_fn(|cx| self.poll_close_notify(cx)).await?;
+        }
+        self.stream.shutdown(how).await
+    }
+
+    fn poll_close_notify(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if !self.close_notify_sent {
+            ready!(self.poll_send_record(cx, ALERT, &[WARNING, CLOSE_NOTIFY]))?;
+            self.close_notify_sent = true;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    // Sends `data` as a single record of type `record_type`.
+    fn poll_send_record(
+        &mut self,
+        cx: &mut Context<'_>,
+        record_type: u8,
+        data: &[u8],
+    ) -> Poll<io::Result<()>> {
+        if let Some(source) = &self.tx_source {
+            if source.result().is_none() {
+                source.add_waiter_single(cx.waker());
+                return Poll::Pending;
+            }
+            self.tx_source = None;
+        }
+        let mut control = Vec::with_capacity(CONTROL_LEN);
+        sys::push_control_message(
+            &mut control,
+            libc::SOL_TLS,
+            TLS_SET_RECORD_TYPE,
+            &[record_type],
+        );
+        // not through the ring, which asks for zero-copy sends: kTLS
+        // sockets refuse those
+        let fd = self.stream.as_raw_fd();
+        match sys::sendmsg_control_syscall(fd, data, &control, libc::MSG_DONTWAIT) {
+            Ok(sent) if sent == data.len() => Poll::Ready(Ok(())),
+            Ok(_) => Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                "a TLS control record was sent in part",
+            ))),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                let source = self.reactor.upgrade().unwrap().poll_write_ready(fd);
+                source.add_waiter_single(cx.waker());
+                self.tx_source = Some(source);
+                Poll::Pending
+            }
+            Err(err) => Poll::Ready(Err(err)),
+        }
+    }
+
+    // Receives the next record, or as much of it as fits, into `rx_buf` and
+    // returns its type.
+    fn poll_recv_record(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<u8>> {
+        let fd = self.stream.as_raw_fd();
+        if self.rx_source.is_none() {
+            let mut control = [0; CONTROL_LEN];
+            self.rx_buf.resize(MAX_RECORD_LEN, 0);
+            match sys::recvmsg_control_syscall(
+                fd,
+                &mut self.rx_buf,
+                &mut control,
+                libc::MSG_DONTWAIT,
+            ) {
+                Ok((size, control_len)) => {
+                    self.rx_buf.truncate(size);
+                    return Poll::Ready(Ok(record_type(&control[..control_len])));
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    self.rx_buf.clear();
+                    self.rx_source = Some(self.reactor.upgrade().unwrap().rushed_recvmsg(
+                        fd,
+                        MAX_RECORD_LEN,
+                        MsgFlags::empty(),
+                        CONTROL_LEN,
+                        self.stream.read_timeout(),
+                    )?);
+                }
+                Err(err) => {
+                    self.rx_buf.clear();
+                    return Poll::Ready(Err(err));
+                }
+            }
+        }
+
+        let result = ready!(self.rx_source.as_ref().unwrap().poll_collect_rw(cx));
+        let source = self.rx_source.take().unwrap();
+        let size = result?;
+        match source.extract_source_type() {
+            SourceType::SockRecvMsg(buf, _iov, hdr, _addr, control) => {
+                self.rx_buf.clear();
+                self.rx_buf
+                    .extend_from_slice(&buf.unwrap().as_bytes()[..size]);
+                let control_len = hdr.msg_controllen.min(control.len());
+                Poll::Ready(Ok(record_type(&control[..control_len])))
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn handle_alert(&mut self) -> io::Result<()> {
+        match self.rx_buf[..] {
+            [_, CLOSE_NOTIFY] => {
+                self.rx_closed = true;
+                Ok(())
+            }
+            [_, description] => Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                format!("received TLS alert {description}"),
+            )),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "malformed TLS alert",
+            )),
+        }
+    }
+
+    fn handle_handshake(&mut self) -> io::Result<()> {
+        let mut messages = &self.rx_buf[..];
+        while let [kind, a, b, c, rest @ ..] = messages {
+            let len = u32::from_be_bytes([0, *a, *b, *c]) as usize;
+            if rest.len() < len {
+                break;
+            }
+            let (body, next) = rest.split_at(len);
+            match (self.version, *kind, body) {
+                // there is no session cache to keep it in
+                (KtlsVersion::Tls13, NEW_SESSION_TICKET, _) => {}
+                (KtlsVersion::Tls13, KEY_UPDATE, [requested]) => {
+                    self.rx_expired = true;
+                    self.tx_update_requested |= *requested == 1;
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unexpected TLS handshake message {kind}"),
+                    ));
+                }
+            }
+            messages = next;
+        }
+        if messages.is_empty() {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "malformed TLS handshake record",
+            ))
+        }
+    }
+}
+
+fn record_type(control: &[u8]) -> u8 {
+    // the kernel only says when it is not application data
+    sys::control_messages(control)
+        .find(|(level, kind, _)| *level == libc::SOL_TLS && *kind == TLS_GET_RECORD_TYPE)
+        .and_then(|(_, _, data)| data.first().copied())
+        .unwrap_or(APPLICATION_DATA)
+}
+
+impl AsRawFd for KtlsStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+}
+
+impl AsyncRead for KtlsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            if this.rx_pos < this.rx_buf.len() {
+                let size = buf.len().min(this.rx_buf.len() - this.rx_pos);
+                buf[..size].copy_from_slice(&this.rx_buf[this.rx_pos..this.rx_pos + size]);
+                this.rx_pos += size;
+                return Poll::Ready(Ok(size));
+            }
+            if this.rx_closed || buf.is_empty() {
+                return Poll::Ready(Ok(0));
+            }
+            if this.rx_expired {
+                return Poll::Ready(Err(io::Error::other(
+                    "the peer updated its TLS keys, and the next ones are not installed",
+                )));
+            }
+
+            let record_type = ready!(this.poll_recv_record(cx))?;
+            this.rx_pos = 0;
+            match record_type {
+                APPLICATION_DATA if this.rx_buf.is_empty() => {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "connection closed without a TLS close_notify",
+                    )));
+                }
+                APPLICATION_DATA => continue,
+                ALERT => this.handle_alert()?,
+                HANDSHAKE => this.handle_handshake()?,
+                other => {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unexpected TLS record type {other}"),
+                    )));
+                }
+            }
+            // control records are not data
+            this.rx_buf.clear();
+        }
+    }
+}
+
+impl AsyncWrite for KtlsStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_close_notify(cx))?;
+        Pin::new(&mut this.stream).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::net::TcpListener;
+    use futures_lite::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn crypto_info_layout() {
+        let iv: [u8; 12] = std::array::from_fn(|i| i as u8);
+        let secrets = KtlsSecrets::new(
+            KtlsVersion::Tls12,
+            KtlsCipher::Aes128Gcm {
+                key: [0xaa; 16],
+                iv,
+            },
+            0x0102,
+        );
+        let info = secrets.crypto_info();
+        // tls12_crypto_info_aes_gcm_128
+        assert_eq!(info.len(), 40);
+        assert_eq!(&info[..4], &[0x03, 0x03, 51, 0]);
+        assert_eq!(&info[4..12], &iv[4..]);
+        assert_eq!(&info[12..28], &[0xaa; 16]);
+        assert_eq!(&info[28..32], &iv[..4]);
+        assert_eq!(&info[32..], &[0, 0, 0, 0, 0, 0, 1, 2]);
+
+        let secrets = KtlsSecrets::new(
+            KtlsVersion::Tls13,
+            KtlsCipher::Chacha20Poly1305 {
+                key: [0xbb; 32],
+                iv,
+            },
+            7,
+        );
+        let info = secrets.crypto_info();
+        // tls12_crypto_info_chacha20_poly1305
+        assert_eq!(info.len(), 56);
+        assert_eq!(&info[..4], &[0x04, 0x03, 54, 0]);
+        assert_eq!(&info[4..16], &iv);
+        assert_eq!(&info[16..48], &[0xbb; 32]);
+        assert_eq!(&info[48..], &7u64.to_be_bytes());
+    }
+
+    #[test]
+    fn record_type_from_control_messages() {
+        assert_eq!(record_type(&[]), APPLICATION_DATA);
+        let mut control = Vec::new();
+        sys::push_control_message(&mut control, libc::SOL_SOCKET, libc::SCM_RIGHTS, &[0; 4]);
+        sys::push_control_message(&mut control, libc::SOL_TLS, TLS_GET_RECORD_TYPE, &[ALERT]);
+        assert_eq!(record_type(&control), ALERT);
+    }
+
+    fn secrets(byte: u8) -> KtlsSecrets {
+        let cipher = KtlsCipher::Aes256Gcm {
+            key: [byte; 32],
+            iv: [byte; 12],
+        };
+        KtlsSecrets::new(KtlsVersion::Tls13, cipher, 0)
+    }
+
+    #[test]
+    fn loopback_with_installed_keys() {
+        test_executor!(async move {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let client = TcpStream::connect(addr).await.unwrap();
+            let server = listener.accept().await.unwrap();
+            match client.set_tls_ulp() {
+                Err(crate::GlommioError::IoError(err)) if err.kind() == io::ErrorKind::NotFound => {
+                    // no `tls` module in this kernel
+                    return;
+                }
+                result => result.unwrap(),
+            }
+
+            let mut client = client.into_ktls(&secrets(1), &secrets(2)).unwrap();
+            let mut server = server.into_ktls(&secrets(2), &secrets(1)).unwrap();
+            assert_eq!(client.version(), KtlsVersion::Tls13);
+
+            client.write_all(b"ping").await.unwrap();
+            let mut buf = [0; 4];
+            server.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"ping");
+
+            // the close_notify alert is read as the end of the stream
+            client.close().await.unwrap();
+            let mut rest = Vec::new();
+            server.read_to_end(&mut rest).await.unwrap();
+            assert!(rest.is_empty());
+        });
+    }
+}
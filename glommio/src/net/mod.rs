@@ -112,13 +112,30 @@ fn yolo_sendmsg(
     }
 }
 
+mod cmsg;
 mod datagram;
-mod stream;
+mod dns;
+mod handover;
+mod happy_eyeballs;
+mod ktls;
+pub(crate) mod stream;
+mod tcp_options;
 mod tcp_socket;
+#[cfg(feature = "rustls")]
+mod tls;
 mod udp_socket;
 mod unix;
+#[cfg(feature = "rustls")]
+pub use self::tls::{TlsAcceptor, TlsConnector, TlsStream};
 pub use self::{
+    cmsg::{ControlMessage, ControlMessageOwned, UnixCredentials},
+    datagram::{RecvMeta, Transmit},
+    dns::{Resolver, ResolverConfig, ToSocketAddrs},
+    handover::{HandoverError, PoolHandover},
+    happy_eyeballs::{AttemptOutcome, ConnectAttempt, ConnectReport, HappyEyeballs},
+    ktls::{KtlsCipher, KtlsSecrets, KtlsStream, KtlsVersion},
     stream::{Buffered, Preallocated},
+    tcp_options::{TcpInfo, TcpSocketBuilder, TcpState},
     tcp_socket::{AcceptedTcpStream, TcpListener, TcpStream},
     udp_socket::UdpSocket,
     unix::{AcceptedUnixStream, UnixDatagram, UnixListener, UnixStream},
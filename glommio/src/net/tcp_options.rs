@@ -0,0 +1,907 @@
+//! Configuring TCP sockets before they bind or connect, and reading what the
+//! kernel knows about a connection.
+//!
+//! Most options can still be changed once a [`TcpListener`] or a
+//! [`TcpStream`] exists, through their getters and setters, but some only
+//! matter before: `SO_REUSEPORT` and `SO_BINDTODEVICE` before the socket
+//! binds, the receive buffer size before the window scale is agreed on, and
+//! `TCP_FASTOPEN_CONNECT` before the connection starts. The
+//! [`TcpSocketBuilder`] sets them all at the right time.
+
+use crate::{
+    net::{dns::ToSocketAddrs, HappyEyeballs, TcpListener, TcpStream},
+    sys,
+};
+use nix::sys::socket::{getsockopt, setsockopt, sockopt};
+use socket2::{Domain, Protocol, SockAddr, SockRef, Socket, Type};
+use std::{
+    io, mem,
+    net::{self, SocketAddr},
+    os::fd::{AsFd, AsRawFd},
+    time::Duration,
+};
+
+type Result<T> = crate::Result<T, ()>;
+
+/// Sets the options of TCP sockets before they bind or connect.
+///
+/// Options not set are left to the defaults of the system, except that
+/// [`bind`] sets `SO_REUSEPORT` unless [`with_reuse_port`] says otherwise,
+/// as [`TcpListener::bind`] does. The same builder can create any number of
+/// sockets.
+///
+/// # Examples
+///
+/// ```no_run
+/// use glommio::{net::TcpSocketBuilder, LocalExecutor};
+/// use std::time::Duration;
+///
+/// let ex = LocalExecutor::default();
+/// ex.run(async move {
+///     let builder = TcpSocketBuilder::new()
+///         .with_keepalive(true)
+///         .with_keepalive_idle(Duration::from_secs(30))
+///         .with_user_timeout(Duration::from_secs(60))
+///         .with_recv_buffer_size(1 << 20);
+///
+///     let listener = builder
+///         .clone()
+///         .with_reuse_port(false)
+///         .bind("127.0.0.1:8000")
+///         .unwrap();
+///     let stream = builder.connect("127.0.0.1:8000").await.unwrap();
+/// });
+/// ```
+///
+/// [`bind`]: TcpSocketBuilder::bind
+/// [`with_reuse_port`]: TcpSocketBuilder::with_reuse_port
+#[derive(Clone, Debug, Default)]
+pub struct TcpSocketBuilder {
+    reuse_port: Option<bool>,
+    reuse_address: Option<bool>,
+    backlog: Option<u32>,
+    device: Option<String>,
+    mark: Option<u32>,
+    tos: Option<u32>,
+    ttl: Option<u32>,
+    send_buffer_size: Option<usize>,
+    recv_buffer_size: Option<usize>,
+    keepalive: Option<bool>,
+    keepalive_idle: Option<Duration>,
+    keepalive_interval: Option<Duration>,
+    keepalive_count: Option<u32>,
+    user_timeout: Option<Duration>,
+    linger: Option<Option<Duration>>,
+    nodelay: Option<bool>,
+    cork: Option<bool>,
+    quickack: Option<bool>,
+    fastopen: Option<u32>,
+    fastopen_connect: Option<bool>,
+}
+
+impl TcpSocketBuilder {
+    /// Creates a builder that leaves every option to its default.
+    pub fn new() -> TcpSocketBuilder {
+        TcpSocketBuilder::default()
+    }
+
+    /// Sets `SO_REUSEPORT`, which lets several sockets bind to the same
+    /// address and the kernel balance connections between them. [`bind`]
+    /// sets it unless this turns it off.
+    ///
+    /// [`bind`]: TcpSocketBuilder::bind
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_reuse_port(mut self, reuse: bool) -> TcpSocketBuilder {
+        self.reuse_port = Some(reuse);
+        self
+    }
+
+    /// Sets `SO_REUSEADDR`, which lets a socket bind to an address still
+    /// held by connections in the `TIME_WAIT` state.
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_reuse_address(mut self, reuse: bool) -> TcpSocketBuilder {
+        self.reuse_address = Some(reuse);
+        self
+    }
+
+    /// Sets how many connections may wait to be accepted by a listener.
+    /// Defaults to 1024.
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_backlog(mut self, backlog: u32) -> TcpSocketBuilder {
+        self.backlog = Some(backlog);
+        self
+    }
+
+    /// Sets `SO_BINDTODEVICE`, so that only packets of the network interface
+    /// named `interface` are sent and received.
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_device<S: Into<String>>(mut self, interface: S) -> TcpSocketBuilder {
+        self.device = Some(interface.into());
+        self
+    }
+
+    /// Sets `SO_MARK`, the mark routing and filtering rules can match
+    /// packets against. Needs the `CAP_NET_ADMIN` capability.
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_mark(mut self, mark: u32) -> TcpSocketBuilder {
+        self.mark = Some(mark);
+        self
+    }
+
+    /// Sets the type of service field of the packets sent: `IP_TOS` for
+    /// IPv4, `IPV6_TCLASS` for IPv6.
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_tos(mut self, tos: u32) -> TcpSocketBuilder {
+        self.tos = Some(tos);
+        self
+    }
+
+    /// Sets the time-to-live of the packets sent: `IP_TTL` for IPv4,
+    /// `IPV6_UNICAST_HOPS` for IPv6.
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_ttl(mut self, ttl: u32) -> TcpSocketBuilder {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Sets `SO_SNDBUF`, the size of the send buffer. The kernel doubles it
+    /// to leave room for its bookkeeping.
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_send_buffer_size(mut self, size: usize) -> TcpSocketBuilder {
+        self.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Sets `SO_RCVBUF`, the size of the receive buffer. The kernel doubles
+    /// it to leave room for its bookkeeping.
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_recv_buffer_size(mut self, size: usize) -> TcpSocketBuilder {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Sets `SO_KEEPALIVE`, which probes idle connections to find out
+    /// whether the peer is still there.
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_keepalive(mut self, keepalive: bool) -> TcpSocketBuilder {
+        self.keepalive = Some(keepalive);
+        self
+    }
+
+    /// Sets `TCP_KEEPIDLE`, how long a connection stays idle before the
+    /// first keepalive probe, in whole seconds.
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_keepalive_idle(mut self, idle: Duration) -> TcpSocketBuilder {
+        self.keepalive_idle = Some(idle);
+        self
+    }
+
+    /// Sets `TCP_KEEPINTVL`, the time between keepalive probes, in whole
+    /// seconds.
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_keepalive_interval(mut self, interval: Duration) -> TcpSocketBuilder {
+        self.keepalive_interval = Some(interval);
+        self
+    }
+
+    /// Sets `TCP_KEEPCNT`, how many keepalive probes go unanswered before
+    /// the connection is dropped.
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_keepalive_count(mut self, count: u32) -> TcpSocketBuilder {
+        self.keepalive_count = Some(count);
+        self
+    }
+
+    /// Sets `TCP_USER_TIMEOUT`, how long sent data may stay unacknowledged
+    /// before the connection is dropped, in milliseconds.
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_user_timeout(mut self, timeout: Duration) -> TcpSocketBuilder {
+        self.user_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets `SO_LINGER`. With `Some`, closing the socket waits up to that
+    /// long for the data still queued to be sent, and `Some(Duration::ZERO)`
+    /// resets the connection instead of closing it gracefully.
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_linger(mut self, linger: Option<Duration>) -> TcpSocketBuilder {
+        self.linger = Some(linger);
+        self
+    }
+
+    /// Sets `TCP_NODELAY`, which sends small segments at once instead of
+    /// coalescing them.
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_nodelay(mut self, nodelay: bool) -> TcpSocketBuilder {
+        self.nodelay = Some(nodelay);
+        self
+    }
+
+    /// Sets `TCP_CORK`, which holds partial segments back until it is
+    /// cleared or 200ms passed.
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_cork(mut self, cork: bool) -> TcpSocketBuilder {
+        self.cork = Some(cork);
+        self
+    }
+
+    /// Sets `TCP_QUICKACK`, which acknowledges segments at once instead of
+    /// delaying the acknowledgements. The kernel may clear it again.
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_quickack(mut self, quickack: bool) -> TcpSocketBuilder {
+        self.quickack = Some(quickack);
+        self
+    }
+
+    /// Sets `TCP_FASTOPEN` on listeners, accepting data in the SYN of up to
+    /// `queue_len` connections not yet established. Zero turns it off.
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_fastopen(mut self, queue_len: u32) -> TcpSocketBuilder {
+        self.fastopen = Some(queue_len);
+        self
+    }
+
+    /// Sets `TCP_FASTOPEN_CONNECT` on connecting sockets, sending the first
+    /// data written in the SYN when the server allows it.
+    #[must_use = "The builder must be used to be useful"]
+    pub fn with_fastopen_connect(mut self, fastopen: bool) -> TcpSocketBuilder {
+        self.fastopen_connect = Some(fastopen);
+        self
+    }
+
+    /// Creates a TCP listener bound to the specified address, with the
+    /// options of this builder.
+    ///
+    /// Like [`TcpListener::bind`], binding with port number 0 requests an
+    /// available port from the OS.
+    pub fn bind<A: net::ToSocketAddrs>(&self, addr: A) -> Result<TcpListener> {
+        let addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::other("empty address"))?;
+        let socket = self.socket(&addr)?;
+        socket.set_reuse_port(self.reuse_port.unwrap_or(true))?;
+        if let Some(queue_len) = self.fastopen {
+            set_fastopen(&socket, queue_len)?;
+        }
+        socket.bind(&SockAddr::from(addr))?;
+        let backlog = self.backlog.unwrap_or(1024).min(i32::MAX as u32) as i32;
+        socket.listen(backlog)?;
+        Ok(TcpListener::from_socket(socket))
+    }
+
+    /// Creates a TCP connection to the specified address, with the options of
+    /// this builder.
+    ///
+    /// Connects as [`TcpStream::connect`] does, racing the addresses of the
+    /// host. To change how they are raced, pass this builder to
+    /// [`HappyEyeballs::with_socket`].
+    pub async fn connect<A: ToSocketAddrs>(&self, addr: A) -> Result<TcpStream> {
+        let (stream, _) = HappyEyeballs::new()
+            .with_socket(self.clone())
+            .connect(addr)
+            .await?;
+        Ok(stream)
+    }
+
+    /// Creates a TCP connection to the specified address with a timeout, with
+    /// the options of this builder.
+    ///
+    /// It is an error to pass a zero `Duration` to this function, as to
+    /// [`TcpStream::connect_timeout`].
+    pub async fn connect_timeout<A: ToSocketAddrs>(
+        &self,
+        addr: A,
+        duration: Duration,
+    ) -> Result<TcpStream> {
+        if duration.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot set a 0 duration timeout",
+            )
+            .into());
+        }
+        let (stream, _) = HappyEyeballs::new()
+            .with_socket(self.clone())
+            .with_timeout(duration)
+            .connect(addr)
+            .await?;
+        Ok(stream)
+    }
+
+    /// Creates a socket to connect to `addr` with.
+    pub(super) fn connecting_socket(&self, addr: &SocketAddr) -> io::Result<Socket> {
+        let socket = self.socket(addr)?;
+        if let Some(reuse) = self.reuse_port {
+            socket.set_reuse_port(reuse)?;
+        }
+        if let Some(fastopen) = self.fastopen_connect {
+            setsockopt(&socket, sockopt::TcpFastOpenConnect, &fastopen).map_err(io::Error::from)?;
+        }
+        Ok(socket)
+    }
+
+    // The options that apply the same to listeners and connections
+    fn socket(&self, addr: &SocketAddr) -> io::Result<Socket> {
+        let domain = if addr.is_ipv6() {
+            Domain::IPV6
+        } else {
+            Domain::IPV4
+        };
+        let socket = Socket::new(domain, Type::STREAM, Some(Protocol::TCP))?;
+        if let Some(reuse) = self.reuse_address {
+            socket.set_reuse_address(reuse)?;
+        }
+        if let Some(interface) = &self.device {
+            socket.bind_device(Some(interface.as_bytes()))?;
+        }
+        if let Some(mark) = self.mark {
+            socket.set_mark(mark)?;
+        }
+        if let Some(tos) = self.tos {
+            set_tos(&socket, tos)?;
+        }
+        if let Some(ttl) = self.ttl {
+            if addr.is_ipv6() {
+                socket.set_unicast_hops_v6(ttl)?;
+            } else {
+                socket.set_ttl_v4(ttl)?;
+            }
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if let Some(keepalive) = self.keepalive {
+            socket.set_keepalive(keepalive)?;
+        }
+        if let Some(idle) = self.keepalive_idle {
+            set_keepalive_idle(&socket, idle)?;
+        }
+        if let Some(interval) = self.keepalive_interval {
+            set_keepalive_interval(&socket, interval)?;
+        }
+        if let Some(count) = self.keepalive_count {
+            set_keepalive_count(&socket, count)?;
+        }
+        if let Some(timeout) = self.user_timeout {
+            socket.set_tcp_user_timeout(Some(timeout))?;
+        }
+        if let Some(linger) = self.linger {
+            socket.set_linger(linger)?;
+        }
+        if let Some(nodelay) = self.nodelay {
+            socket.set_tcp_nodelay(nodelay)?;
+        }
+        if let Some(cork) = self.cork {
+            socket.set_tcp_cork(cork)?;
+        }
+        if let Some(quickack) = self.quickack {
+            socket.set_tcp_quickack(quickack)?;
+        }
+        Ok(socket)
+    }
+}
+
+// The options socket2 leaves out, or sets together with others, shared by
+// the builder, `TcpListener` and `TcpStream`
+
+fn secs(duration: Duration) -> u32 {
+    duration.as_secs().min(u32::MAX as u64) as u32
+}
+
+pub(super) fn set_keepalive_idle<F: AsFd>(fd: &F, idle: Duration) -> io::Result<()> {
+    Ok(setsockopt(fd, sockopt::TcpKeepIdle, &secs(idle))?)
+}
+
+pub(super) fn keepalive_idle<F: AsFd>(fd: &F) -> io::Result<Duration> {
+    let secs = getsockopt(fd, sockopt::TcpKeepIdle)?;
+    Ok(Duration::from_secs(secs as u64))
+}
+
+pub(super) fn set_keepalive_interval<F: AsFd>(fd: &F, interval: Duration) -> io::Result<()> {
+    Ok(setsockopt(fd, sockopt::TcpKeepInterval, &secs(interval))?)
+}
+
+pub(super) fn keepalive_interval<F: AsFd>(fd: &F) -> io::Result<Duration> {
+    let secs = getsockopt(fd, sockopt::TcpKeepInterval)?;
+    Ok(Duration::from_secs(secs as u64))
+}
+
+pub(super) fn set_keepalive_count<F: AsFd>(fd: &F, count: u32) -> io::Result<()> {
+    Ok(setsockopt(fd, sockopt::TcpKeepCount, &count)?)
+}
+
+pub(super) fn keepalive_count<F: AsFd>(fd: &F) -> io::Result<u32> {
+    Ok(getsockopt(fd, sockopt::TcpKeepCount)?)
+}
+
+pub(super) fn set_fastopen<F: AsFd>(fd: &F, queue_len: u32) -> io::Result<()> {
+    let queue_len = queue_len.min(i32::MAX as u32) as libc::c_int;
+    sys::setsockopt_bytes(
+        fd.as_fd().as_raw_fd(),
+        libc::SOL_TCP,
+        libc::TCP_FASTOPEN,
+        &queue_len.to_ne_bytes(),
+    )
+}
+
+pub(super) fn fastopen<F: AsFd>(fd: &F) -> io::Result<u32> {
+    let mut queue_len = [0u8; mem::size_of::<libc::c_int>()];
+    sys::getsockopt_bytes(
+        fd.as_fd().as_raw_fd(),
+        libc::SOL_TCP,
+        libc::TCP_FASTOPEN,
+        &mut queue_len,
+    )?;
+    Ok(libc::c_int::from_ne_bytes(queue_len) as u32)
+}
+
+pub(super) fn set_tos<F: AsFd>(fd: &F, tos: u32) -> io::Result<()> {
+    let socket = SockRef::from(fd);
+    if socket.local_addr()?.is_ipv6() {
+        socket.set_tclass_v6(tos)
+    } else {
+        socket.set_tos_v4(tos)
+    }
+}
+
+pub(super) fn tos<F: AsFd>(fd: &F) -> io::Result<u32> {
+    let socket = SockRef::from(fd);
+    if socket.local_addr()?.is_ipv6() {
+        socket.tclass_v6()
+    } else {
+        socket.tos_v4()
+    }
+}
+
+pub(super) fn bind_device<F: AsFd>(fd: &F, interface: Option<&str>) -> io::Result<()> {
+    SockRef::from(fd).bind_device(interface.map(str::as_bytes))
+}
+
+pub(super) fn device<F: AsFd>(fd: &F) -> io::Result<Option<String>> {
+    let device = SockRef::from(fd).device()?;
+    Ok(device.map(|name| String::from_utf8_lossy(&name).into_owned()))
+}
+
+/// The state of a TCP connection, as [`TcpInfo::state`] reports it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TcpState {
+    /// Data flows both ways.
+    Established,
+    /// A SYN was sent, and no answer came back yet.
+    SynSent,
+    /// A SYN was received and answered.
+    SynRecv,
+    /// Closed here, waiting for the FIN to be acknowledged.
+    FinWait1,
+    /// Closed here and acknowledged, waiting for the FIN of the peer.
+    FinWait2,
+    /// Closed both ways, waiting for late segments to expire.
+    TimeWait,
+    /// Closed.
+    Close,
+    /// Closed by the peer, not yet here.
+    CloseWait,
+    /// Closed by the peer then here, waiting for the last acknowledgement.
+    LastAck,
+    /// Listening for connections.
+    Listen,
+    /// Closed both ways at once, waiting for the acknowledgement.
+    Closing,
+    /// A state this version does not know about.
+    Unknown(u8),
+}
+
+impl From<u8> for TcpState {
+    fn from(state: u8) -> Self {
+        match state {
+            1 => TcpState::Established,
+            2 => TcpState::SynSent,
+            3 => TcpState::SynRecv,
+            4 => TcpState::FinWait1,
+            5 => TcpState::FinWait2,
+            6 => TcpState::TimeWait,
+            7 => TcpState::Close,
+            8 => TcpState::CloseWait,
+            9 => TcpState::LastAck,
+            10 => TcpState::Listen,
+            11 => TcpState::Closing,
+            other => TcpState::Unknown(other),
+        }
+    }
+}
+
+// `struct tcp_info` of the kernel, as of 5.4. The one in libc stops at
+// `tcpi_total_retrans`, as glibc's does.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct RawTcpInfo {
+    state: u8,
+    ca_state: u8,
+    retransmits: u8,
+    probes: u8,
+    backoff: u8,
+    options: u8,
+    wscale: u8,
+    app_limited_fastopen_fail: u8,
+    rto: u32,
+    ato: u32,
+    snd_mss: u32,
+    rcv_mss: u32,
+    unacked: u32,
+    sacked: u32,
+    lost: u32,
+    retrans: u32,
+    fackets: u32,
+    last_data_sent: u32,
+    last_ack_sent: u32,
+    last_data_recv: u32,
+    last_ack_recv: u32,
+    pmtu: u32,
+    rcv_ssthresh: u32,
+    rtt: u32,
+    rttvar: u32,
+    snd_ssthresh: u32,
+    snd_cwnd: u32,
+    advmss: u32,
+    reordering: u32,
+    rcv_rtt: u32,
+    rcv_space: u32,
+    total_retrans: u32,
+    pacing_rate: u64,
+    max_pacing_rate: u64,
+    bytes_acked: u64,
+    bytes_received: u64,
+    segs_out: u32,
+    segs_in: u32,
+    notsent_bytes: u32,
+    min_rtt: u32,
+    data_segs_in: u32,
+    data_segs_out: u32,
+    delivery_rate: u64,
+    busy_time: u64,
+    rwnd_limited: u64,
+    sndbuf_limited: u64,
+    delivered: u32,
+    delivered_ce: u32,
+    bytes_sent: u64,
+    bytes_retrans: u64,
+    dsack_dups: u32,
+    reord_seen: u32,
+}
+
+/// What the kernel knows about a TCP connection at one point in time, read
+/// with `TCP_INFO`.
+///
+/// Older kernels report fewer fields; those they leave out read as zero.
+///
+/// # Examples
+///
+/// ```no_run
+/// use glommio::{net::TcpStream, LocalExecutor};
+///
+/// let ex = LocalExecutor::default();
+/// ex.run(async move {
+///     let stream = TcpStream::connect("127.0.0.1:10000").await.unwrap();
+///     let info = stream.tcp_info().unwrap();
+///     println!("rtt {:?}, cwnd {}", info.rtt(), info.snd_cwnd());
+/// });
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct TcpInfo {
+    raw: RawTcpInfo,
+}
+
+fn micros(us: u32) -> Duration {
+    Duration::from_micros(us as u64)
+}
+
+fn millis(ms: u32) -> Duration {
+    Duration::from_millis(ms as u64)
+}
+
+impl TcpInfo {
+    pub(super) fn read<F: AsFd>(fd: &F) -> io::Result<TcpInfo> {
+        let mut raw = RawTcpInfo::default();
+        // the kernel writes as much of the structure as it knows, and the
+        // rest stays zeroed
+        let bytes = unsafe {
+            std::slice::from_raw_parts_mut(
+                &mut raw as *mut RawTcpInfo as *mut u8,
+                mem::size_of::<RawTcpInfo>(),
+            )
+        };
+        sys::getsockopt_bytes(fd.as_fd().as_raw_fd(), libc::SOL_TCP, libc::TCP_INFO, bytes)?;
+        Ok(TcpInfo { raw })
+    }
+
+    /// The state of the connection.
+    pub fn state(&self) -> TcpState {
+        TcpState::from(self.raw.state)
+    }
+
+    /// How many times in a row the segment now in flight was retransmitted.
+    pub fn retransmits(&self) -> u8 {
+        self.raw.retransmits
+    }
+
+    /// The retransmission timeout.
+    pub fn rto(&self) -> Duration {
+        micros(self.raw.rto)
+    }
+
+    /// The delay before a delayed acknowledgement is sent.
+    pub fn ato(&self) -> Duration {
+        micros(self.raw.ato)
+    }
+
+    /// The largest segment sent, in bytes.
+    pub fn snd_mss(&self) -> u32 {
+        self.raw.snd_mss
+    }
+
+    /// The largest segment received, as far as it can be told, in bytes.
+    pub fn rcv_mss(&self) -> u32 {
+        self.raw.rcv_mss
+    }
+
+    /// How many segments were sent and not acknowledged yet.
+    pub fn unacked(&self) -> u32 {
+        self.raw.unacked
+    }
+
+    /// How many segments in flight are considered lost.
+    pub fn lost(&self) -> u32 {
+        self.raw.lost
+    }
+
+    /// How many segments in flight were retransmitted.
+    pub fn retrans(&self) -> u32 {
+        self.raw.retrans
+    }
+
+    /// How many segments were retransmitted over the whole connection.
+    pub fn total_retrans(&self) -> u32 {
+        self.raw.total_retrans
+    }
+
+    /// How long ago data was last sent.
+    pub fn last_data_sent(&self) -> Duration {
+        millis(self.raw.last_data_sent)
+    }
+
+    /// How long ago data was last received.
+    pub fn last_data_recv(&self) -> Duration {
+        millis(self.raw.last_data_recv)
+    }
+
+    /// How long ago an acknowledgement was last received.
+    pub fn last_ack_recv(&self) -> Duration {
+        millis(self.raw.last_ack_recv)
+    }
+
+    /// The path MTU, in bytes.
+    pub fn pmtu(&self) -> u32 {
+        self.raw.pmtu
+    }
+
+    /// The smoothed round-trip time.
+    pub fn rtt(&self) -> Duration {
+        micros(self.raw.rtt)
+    }
+
+    /// How much the round-trip time varies.
+    pub fn rtt_var(&self) -> Duration {
+        micros(self.raw.rttvar)
+    }
+
+    /// The smallest round-trip time seen.
+    pub fn min_rtt(&self) -> Duration {
+        micros(self.raw.min_rtt)
+    }
+
+    /// The congestion window, in segments.
+    pub fn snd_cwnd(&self) -> u32 {
+        self.raw.snd_cwnd
+    }
+
+    /// The slow start threshold, in segments.
+    pub fn snd_ssthresh(&self) -> u32 {
+        self.raw.snd_ssthresh
+    }
+
+    /// The round-trip time the receiving side estimates.
+    pub fn rcv_rtt(&self) -> Duration {
+        micros(self.raw.rcv_rtt)
+    }
+
+    /// How much the receiver advertises it can take, in bytes.
+    pub fn rcv_space(&self) -> u32 {
+        self.raw.rcv_space
+    }
+
+    /// The pacing rate, in bytes per second.
+    pub fn pacing_rate(&self) -> u64 {
+        self.raw.pacing_rate
+    }
+
+    /// The delivery rate last measured, in bytes per second.
+    pub fn delivery_rate(&self) -> u64 {
+        self.raw.delivery_rate
+    }
+
+    /// How many bytes were sent, retransmissions included.
+    pub fn bytes_sent(&self) -> u64 {
+        self.raw.bytes_sent
+    }
+
+    /// How many bytes were retransmitted.
+    pub fn bytes_retrans(&self) -> u64 {
+        self.raw.bytes_retrans
+    }
+
+    /// How many bytes the peer acknowledged.
+    pub fn bytes_acked(&self) -> u64 {
+        self.raw.bytes_acked
+    }
+
+    /// How many bytes were received.
+    pub fn bytes_received(&self) -> u64 {
+        self.raw.bytes_received
+    }
+
+    /// How many segments were sent.
+    pub fn segs_out(&self) -> u32 {
+        self.raw.segs_out
+    }
+
+    /// How many segments were received.
+    pub fn segs_in(&self) -> u32 {
+        self.raw.segs_in
+    }
+
+    /// How many bytes were written and not sent yet.
+    pub fn notsent_bytes(&self) -> u32 {
+        self.raw.notsent_bytes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures_lite::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn raw_tcp_info_matches_the_kernel_layout() {
+        assert_eq!(mem::offset_of!(RawTcpInfo, rto), 8);
+        assert_eq!(mem::offset_of!(RawTcpInfo, pacing_rate), 104);
+        assert_eq!(mem::offset_of!(RawTcpInfo, delivery_rate), 160);
+        assert_eq!(mem::offset_of!(RawTcpInfo, bytes_sent), 200);
+        assert_eq!(mem::size_of::<RawTcpInfo>(), 224);
+    }
+
+    #[test]
+    fn builder_sets_options_before_bind_and_connect() {
+        test_executor!(async move {
+            let builder = TcpSocketBuilder::new()
+                .with_keepalive(true)
+                .with_keepalive_idle(Duration::from_secs(42))
+                .with_keepalive_interval(Duration::from_secs(7))
+                .with_keepalive_count(3)
+                .with_user_timeout(Duration::from_millis(1500))
+                .with_linger(Some(Duration::from_secs(2)))
+                .with_nodelay(true)
+                .with_tos(0x10)
+                .with_recv_buffer_size(64 * 1024);
+
+            let listener = builder
+                .clone()
+                .with_reuse_port(false)
+                .bind("127.0.0.1:0")
+                .unwrap();
+            assert!(!listener.reuse_port().unwrap());
+            assert!(listener.keepalive().unwrap());
+            let addr = listener.local_addr().unwrap();
+
+            let stream = builder.connect(addr).await.unwrap();
+            assert!(stream.keepalive().unwrap());
+            assert_eq!(stream.keepalive_idle().unwrap(), Duration::from_secs(42));
+            assert_eq!(stream.keepalive_interval().unwrap(), Duration::from_secs(7));
+            assert_eq!(stream.keepalive_count().unwrap(), 3);
+            assert_eq!(
+                stream.user_timeout().unwrap(),
+                Some(Duration::from_millis(1500))
+            );
+            assert_eq!(stream.linger().unwrap(), Some(Duration::from_secs(2)));
+            assert!(stream.nodelay().unwrap());
+            assert_eq!(stream.tos().unwrap(), 0x10);
+            assert!(stream.recv_buffer_size().unwrap() >= 64 * 1024);
+            assert!(!stream.reuse_port().unwrap());
+
+            // accepted connections inherit what the listener had
+            let accepted = listener.accept().await.unwrap();
+            assert!(accepted.keepalive().unwrap());
+            assert_eq!(accepted.keepalive_idle().unwrap(), Duration::from_secs(42));
+        });
+    }
+
+    #[test]
+    fn bind_sets_reuse_port_by_default() {
+        test_executor!(async move {
+            let listener = TcpSocketBuilder::new().bind("127.0.0.1:0").unwrap();
+            assert!(listener.reuse_port().unwrap());
+            let addr = listener.local_addr().unwrap();
+            let second = TcpSocketBuilder::new().bind(addr).unwrap();
+            assert_eq!(second.local_addr().unwrap(), addr);
+
+            let exclusive = TcpSocketBuilder::new()
+                .with_reuse_port(false)
+                .bind("127.0.0.1:0")
+                .unwrap();
+            let err = TcpSocketBuilder::new()
+                .bind(exclusive.local_addr().unwrap())
+                .unwrap_err();
+            assert_eq!(io::Error::from(err).kind(), io::ErrorKind::AddrInUse);
+        });
+    }
+
+    #[test]
+    fn setters_change_options_of_existing_sockets() {
+        test_executor!(async move {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.set_fastopen(16).unwrap();
+            assert_eq!(listener.fastopen().unwrap(), 16);
+            listener.set_send_buffer_size(32 * 1024).unwrap();
+            assert!(listener.send_buffer_size().unwrap() >= 32 * 1024);
+
+            let stream = TcpStream::connect(listener.local_addr().unwrap())
+                .await
+                .unwrap();
+            stream.set_keepalive(true).unwrap();
+            stream.set_keepalive_idle(Duration::from_secs(5)).unwrap();
+            assert_eq!(stream.keepalive_idle().unwrap(), Duration::from_secs(5));
+            stream.set_user_timeout(None).unwrap();
+            assert_eq!(stream.user_timeout().unwrap(), None);
+            stream.set_linger(None).unwrap();
+            assert_eq!(stream.linger().unwrap(), None);
+            stream.set_cork(true).unwrap();
+            assert!(stream.cork().unwrap());
+            stream.set_cork(false).unwrap();
+            assert!(!stream.cork().unwrap());
+            stream.set_quickack(true).unwrap();
+            stream.set_tos(0x08).unwrap();
+            assert_eq!(stream.tos().unwrap(), 0x08);
+            assert_eq!(stream.device().unwrap(), None);
+        });
+    }
+
+    #[test]
+    fn tcp_info_of_a_connection() {
+        test_executor!(async move {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let mut stream = TcpStream::connect(listener.local_addr().unwrap())
+                .await
+                .unwrap();
+            let mut accepted = listener.accept().await.unwrap();
+
+            stream.write_all(b"ping").await.unwrap();
+            let mut buf = [0u8; 4];
+            accepted.read_exact(&mut buf).await.unwrap();
+
+            let info = stream.tcp_info().unwrap();
+            assert_eq!(info.state(), TcpState::Established);
+            assert!(info.snd_mss() > 0);
+            assert!(info.snd_cwnd() > 0);
+            assert!(info.segs_out() > 0);
+            let info = accepted.tcp_info().unwrap();
+            assert_eq!(info.bytes_received(), 4);
+
+            drop(stream);
+            crate::timer::sleep(Duration::from_millis(10)).await;
+            assert_eq!(accepted.tcp_info().unwrap().state(), TcpState::CloseWait);
+        });
+    }
+}
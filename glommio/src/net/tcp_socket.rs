@@ -6,8 +6,12 @@
 use super::stream::GlommioStream;
 use crate::{
     net::{
+        dns::ToSocketAddrs,
+        handover::{self, HandoverError},
+        ktls::{self, KtlsSecrets, KtlsStream},
         stream::{Buffered, NonBuffered, Preallocated, RxBuf},
-        yolo_accept,
+        tcp_options::{self, TcpInfo},
+        yolo_accept, HappyEyeballs, TcpSocketBuilder, UnixStream,
     },
     reactor::Reactor,
     sys::Source,
@@ -19,13 +23,12 @@ use futures_lite::{
     ready,
     stream::{self, Stream},
 };
-use nix::sys::socket::SockaddrStorage;
 use pin_project_lite::pin_project;
-use socket2::{Domain, Protocol, Socket, Type};
+use socket2::{SockRef, Socket};
 use std::{
     cell::RefCell,
     io,
-    net::{self, Shutdown, SocketAddr, ToSocketAddrs},
+    net::{self, Shutdown, SocketAddr},
     os::{
         fd::AsFd,
         unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd},
@@ -105,7 +108,8 @@ impl TcpListener {
     ///
     /// This method sets the ReusePort option in the bound socket, so it is
     /// designed to be called from multiple executors to achieve
-    /// parallelism.
+    /// parallelism. To bind without it, or with other options, use a
+    /// [`TcpSocketBuilder`].
     ///
     /// # Examples
     ///
@@ -118,30 +122,16 @@ impl TcpListener {
     ///     println!("Listening on {}", listener.local_addr().unwrap());
     /// });
     /// ```
-    pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<TcpListener> {
-        let addr = addr
-            .to_socket_addrs()
-            .unwrap()
-            .next()
-            .ok_or_else(|| io::Error::other("empty address"))?;
-
-        let domain = if addr.is_ipv6() {
-            Domain::IPV6
-        } else {
-            Domain::IPV4
-        };
-        let sk = Socket::new(domain, Type::STREAM, Some(Protocol::TCP))?;
-        let addr = socket2::SockAddr::from(addr);
-        sk.set_reuse_port(true)?;
-        sk.bind(&addr)?;
-        sk.listen(1024)?;
-        let listener = sk.into();
+    pub fn bind<A: net::ToSocketAddrs>(addr: A) -> Result<TcpListener> {
+        TcpSocketBuilder::new().bind(addr)
+    }
 
-        Ok(TcpListener {
+    pub(super) fn from_socket(socket: Socket) -> TcpListener {
+        TcpListener {
             reactor: Rc::downgrade(&crate::executor().reactor()),
-            listener,
+            listener: socket.into(),
             current_source: Default::default(),
-        })
+        }
     }
 
     /// Accepts a new incoming TCP connection and allows the result to be sent
@@ -330,6 +320,168 @@ impl TcpListener {
     pub fn set_ttl(&self, ttl: u32) -> Result<()> {
         Ok(self.listener.set_ttl(ttl)?)
     }
+
+    /// Gets the `SO_REUSEPORT` option of this socket, which [`bind`] sets
+    /// unless a [`TcpSocketBuilder`] turned it off.
+    ///
+    /// [`bind`]: TcpListener::bind
+    pub fn reuse_port(&self) -> Result<bool> {
+        Ok(SockRef::from(&self.listener).reuse_port()?)
+    }
+
+    /// Sets the `TCP_FASTOPEN` option of this socket, accepting data in the
+    /// SYN of up to `queue_len` connections not yet established. Zero turns
+    /// it off.
+    pub fn set_fastopen(&self, queue_len: u32) -> Result<()> {
+        Ok(tcp_options::set_fastopen(&self.listener, queue_len)?)
+    }
+
+    /// Gets the `TCP_FASTOPEN` option of this socket.
+    ///
+    /// For more information about this option, see
+    /// [`TcpListener::set_fastopen`].
+    pub fn fastopen(&self) -> Result<u32> {
+        Ok(tcp_options::fastopen(&self.listener)?)
+    }
+
+    /// Sets the `SO_KEEPALIVE` option of this socket, which the accepted
+    /// connections inherit.
+    ///
+    /// For more information about this option, see
+    /// [`TcpStream::set_keepalive`].
+    pub fn set_keepalive(&self, keepalive: bool) -> Result<()> {
+        Ok(SockRef::from(&self.listener).set_keepalive(keepalive)?)
+    }
+
+    /// Gets the `SO_KEEPALIVE` option of this socket.
+    pub fn keepalive(&self) -> Result<bool> {
+        Ok(SockRef::from(&self.listener).keepalive()?)
+    }
+
+    /// Sets the `SO_SNDBUF` option of this socket, which the accepted
+    /// connections inherit.
+    pub fn set_send_buffer_size(&self, size: usize) -> Result<()> {
+        Ok(SockRef::from(&self.listener).set_send_buffer_size(size)?)
+    }
+
+    /// Gets the `SO_SNDBUF` option of this socket.
+    pub fn send_buffer_size(&self) -> Result<usize> {
+        Ok(SockRef::from(&self.listener).send_buffer_size()?)
+    }
+
+    /// Sets the `SO_RCVBUF` option of this socket, which the accepted
+    /// connections inherit.
+    pub fn set_recv_buffer_size(&self, size: usize) -> Result<()> {
+        Ok(SockRef::from(&self.listener).set_recv_buffer_size(size)?)
+    }
+
+    /// Gets the `SO_RCVBUF` option of this socket.
+    pub fn recv_buffer_size(&self) -> Result<usize> {
+        Ok(SockRef::from(&self.listener).recv_buffer_size()?)
+    }
+
+    /// Sets the type of service field of the packets sent, which the
+    /// accepted connections inherit: `IP_TOS` for IPv4, `IPV6_TCLASS` for
+    /// IPv6.
+    pub fn set_tos(&self, tos: u32) -> Result<()> {
+        Ok(tcp_options::set_tos(&self.listener, tos)?)
+    }
+
+    /// Gets the type of service field of the packets sent.
+    pub fn tos(&self) -> Result<u32> {
+        Ok(tcp_options::tos(&self.listener)?)
+    }
+
+    /// Sets the `SO_MARK` option of this socket, which the accepted
+    /// connections inherit. Needs the `CAP_NET_ADMIN` capability.
+    pub fn set_mark(&self, mark: u32) -> Result<()> {
+        Ok(SockRef::from(&self.listener).set_mark(mark)?)
+    }
+
+    /// Gets the `SO_MARK` option of this socket.
+    pub fn mark(&self) -> Result<u32> {
+        Ok(SockRef::from(&self.listener).mark()?)
+    }
+
+    /// Gets the network interface this socket is bound to with
+    /// `SO_BINDTODEVICE`, if any.
+    pub fn device(&self) -> Result<Option<String>> {
+        Ok(tcp_options::device(&self.listener)?)
+    }
+
+    /// Hands this listener over to another process, through a connected
+    /// Unix socket.
+    ///
+    /// Completes once the other process, receiving with [`from_handover`],
+    /// acknowledged it. The listener is then closed here, but the socket and
+    /// the connections waiting in its queue live on in the other process. If
+    /// the handover fails the listener is returned in the error, still
+    /// listening.
+    ///
+    /// To hand over the listeners of every shard of a pool, use
+    /// [`PoolHandover`].
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use glommio::{
+    ///     net::{TcpListener, UnixStream},
+    ///     LocalExecutor,
+    /// };
+    ///
+    /// let ex = LocalExecutor::default();
+    /// ex.run(async move {
+    ///     let listener = TcpListener::bind("127.0.0.1:8000").unwrap();
+    ///     let stream = UnixStream::connect("/tmp/handover.sock").await.unwrap();
+    ///     if let Err(err) = listener.into_handover(&stream).await {
+    ///         // keep serving with it
+    ///         let listener = err.into_listener();
+    ///     }
+    /// });
+    /// ```
+    ///
+    /// [`from_handover`]: TcpListener::from_handover
+    /// [`PoolHandover`]: super::PoolHandover
+    pub async fn into_handover(
+        self,
+        stream: &UnixStream,
+    ) -> std::result::Result<(), HandoverError> {
+        match handover::send_listeners(stream, &[self.raw_fd()]).await {
+            Ok(()) => Ok(()),
+            Err(err) => Err(HandoverError::new(self, err)),
+        }
+    }
+
+    /// Receives a listener handed over by another process with
+    /// [`into_handover`], through a connected Unix socket.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use glommio::{
+    ///     net::{TcpListener, UnixListener},
+    ///     LocalExecutor,
+    /// };
+    ///
+    /// let ex = LocalExecutor::default();
+    /// ex.run(async move {
+    ///     let handover = UnixListener::bind("/tmp/handover.sock").unwrap();
+    ///     let stream = handover.accept().await.unwrap();
+    ///     let listener = TcpListener::from_handover(&stream).await.unwrap();
+    ///     println!("Took over {}", listener.local_addr().unwrap());
+    /// });
+    /// ```
+    ///
+    /// [`into_handover`]: TcpListener::into_handover
+    pub async fn from_handover(stream: &UnixStream) -> Result<TcpListener> {
+        let fd = handover::recv_listeners(stream, Some(1))
+            .await?
+            .pop()
+            .unwrap();
+        Ok(unsafe { TcpListener::from_raw_fd(fd.into_raw_fd()) })
+    }
+
+    pub(crate) fn raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -453,21 +605,39 @@ impl TcpStream<NonBuffered> {
             fd: self.into_raw_fd(),
         }
     }
-}
 
-fn make_tcp_socket(addr: &SocketAddr) -> io::Result<Socket> {
-    let domain = if addr.is_ipv6() {
-        Domain::IPV6
-    } else {
-        Domain::IPV4
-    };
-    let socket = Socket::new(domain, Type::STREAM, Some(Protocol::TCP))?;
-    Ok(socket)
+    /// Attaches the kernel's TLS layer to this stream, without installing
+    /// any keys yet, so data still passes through untouched.
+    ///
+    /// [`into_ktls`](TcpStream::into_ktls) does this too. Calling it before
+    /// the handshake finds out early whether kTLS is available: it fails
+    /// with [`NotFound`](io::ErrorKind::NotFound) if the kernel has no `tls`
+    /// module, while TLS in user space is still an option.
+    pub fn set_tls_ulp(&self) -> Result<()> {
+        Ok(ktls::set_tls_ulp(self.as_raw_fd())?)
+    }
+
+    /// Hands the encryption of a TLS session established over this stream
+    /// to the kernel.
+    ///
+    /// `tx` and `rx` are the secrets of each direction, as negotiated by
+    /// the handshake. No TLS record may have been received past the
+    /// handshake and left unprocessed, or it is lost.
+    ///
+    /// See [`KtlsStream`] for what happens to records other than
+    /// application data.
+    pub fn into_ktls(self, tx: &KtlsSecrets, rx: &KtlsSecrets) -> Result<KtlsStream> {
+        KtlsStream::new(self, tx, rx)
+    }
 }
 
 impl TcpStream {
     /// Creates a TCP connection to the specified address.
     ///
+    /// Host names are resolved with the [`Resolver`] of the executor, and
+    /// the addresses are raced as [`HappyEyeballs`] describes, so that an
+    /// unreachable address family does not hold the connection up.
+    ///
     /// # Examples
     ///
     /// ```no_run
@@ -478,21 +648,18 @@ impl TcpStream {
     ///     TcpStream::connect("127.0.0.1:10000").await.unwrap();
     /// })
     /// ```
+    ///
+    /// [`Resolver`]: super::Resolver
     pub async fn connect<A: ToSocketAddrs>(addr: A) -> Result<TcpStream> {
-        let addr = addr.to_socket_addrs()?.next().unwrap();
-        let socket = make_tcp_socket(&addr)?;
-        let reactor = crate::executor().reactor();
-        let source = reactor.connect(socket.as_raw_fd(), SockaddrStorage::from(addr));
-        source.collect_rw().await?;
-
-        Ok(TcpStream {
-            stream: GlommioStream::from(socket),
-        })
+        let (stream, _) = HappyEyeballs::new().connect(addr).await?;
+        Ok(stream)
     }
 
     /// Creates a TCP connection to the specified address with a timeout.
     ///
-    /// It is an error to pass a zero `Duration` to this function.
+    /// It is an error to pass a zero `Duration` to this function. Host names
+    /// are resolved and addresses raced as by [`connect`], and the timeout
+    /// bounds every attempt together.
     ///
     /// Timeouts are implemented using `io_uring`'s `IORING_OP_LINK_TIMEOUT`.
     ///
@@ -510,6 +677,8 @@ impl TcpStream {
     ///         .unwrap();
     /// })
     /// ```
+    ///
+    /// [`connect`]: TcpStream::connect
     pub async fn connect_timeout<A: ToSocketAddrs>(
         addr: A,
         duration: Duration,
@@ -522,28 +691,17 @@ impl TcpStream {
             .into());
         }
 
-        let addr = addr.to_socket_addrs()?.next().unwrap();
-        let socket = make_tcp_socket(&addr)?;
-        let reactor = crate::executor().reactor();
-        let source =
-            reactor.connect_timeout(socket.as_raw_fd(), SockaddrStorage::from(addr), duration);
-
-        // connect_timeout submits two sqes to io_uring: a connect sqe soft-linked
-        // with a LINK_TIMEOUT sqe. If the timeout fires, the connect sqe fails with
-        // ECANCELED. We map that error to TimedOut to match the standard library's API.
-        source
-            .collect_rw()
-            .await
-            .map_err(|err| match err.raw_os_error() {
-                Some(libc::ECANCELED) => {
-                    io::Error::new(io::ErrorKind::TimedOut, "connection timed out")
-                }
-                _ => err,
-            })?;
+        let (stream, _) = HappyEyeballs::new()
+            .with_timeout(duration)
+            .connect(addr)
+            .await?;
+        Ok(stream)
+    }
 
-        Ok(TcpStream {
+    pub(super) fn from_socket(socket: Socket) -> TcpStream {
+        TcpStream {
             stream: GlommioStream::from(socket),
-        })
+        }
     }
 
     /// Creates a buffered TCP connection with default receive buffer.
@@ -712,6 +870,220 @@ impl<B: RxBuf> TcpStream<B> {
         Ok(self.stream.stream().set_ttl(ttl)?)
     }
 
+    /// Sets the `SO_KEEPALIVE` option on this socket.
+    ///
+    /// If set, probes are sent over the connection once it went idle for a
+    /// while, and it is dropped if the peer stops answering them. How long
+    /// and how many is up to [`set_keepalive_idle`],
+    /// [`set_keepalive_interval`] and [`set_keepalive_count`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use glommio::{net::TcpStream, LocalExecutor};
+    /// use std::time::Duration;
+    ///
+    /// let ex = LocalExecutor::default();
+    /// ex.run(async move {
+    ///     let stream = TcpStream::connect("127.0.0.1:10000").await.unwrap();
+    ///     stream.set_keepalive(true).unwrap();
+    ///     stream.set_keepalive_idle(Duration::from_secs(30)).unwrap();
+    ///     stream.set_keepalive_interval(Duration::from_secs(5)).unwrap();
+    ///     stream.set_keepalive_count(3).unwrap();
+    /// });
+    /// ```
+    ///
+    /// [`set_keepalive_idle`]: TcpStream::set_keepalive_idle
+    /// [`set_keepalive_interval`]: TcpStream::set_keepalive_interval
+    /// [`set_keepalive_count`]: TcpStream::set_keepalive_count
+    pub fn set_keepalive(&self, keepalive: bool) -> Result<()> {
+        Ok(self.socket().set_keepalive(keepalive)?)
+    }
+
+    /// Gets the `SO_KEEPALIVE` option on this socket.
+    pub fn keepalive(&self) -> Result<bool> {
+        Ok(self.socket().keepalive()?)
+    }
+
+    /// Sets the `TCP_KEEPIDLE` option on this socket, how long the
+    /// connection stays idle before the first keepalive probe, in whole
+    /// seconds.
+    pub fn set_keepalive_idle(&self, idle: Duration) -> Result<()> {
+        Ok(tcp_options::set_keepalive_idle(self.stream.stream(), idle)?)
+    }
+
+    /// Gets the `TCP_KEEPIDLE` option on this socket.
+    pub fn keepalive_idle(&self) -> Result<Duration> {
+        Ok(tcp_options::keepalive_idle(self.stream.stream())?)
+    }
+
+    /// Sets the `TCP_KEEPINTVL` option on this socket, the time between
+    /// keepalive probes, in whole seconds.
+    pub fn set_keepalive_interval(&self, interval: Duration) -> Result<()> {
+        Ok(tcp_options::set_keepalive_interval(
+            self.stream.stream(),
+            interval,
+        )?)
+    }
+
+    /// Gets the `TCP_KEEPINTVL` option on this socket.
+    pub fn keepalive_interval(&self) -> Result<Duration> {
+        Ok(tcp_options::keepalive_interval(self.stream.stream())?)
+    }
+
+    /// Sets the `TCP_KEEPCNT` option on this socket, how many keepalive
+    /// probes go unanswered before the connection is dropped.
+    pub fn set_keepalive_count(&self, count: u32) -> Result<()> {
+        Ok(tcp_options::set_keepalive_count(
+            self.stream.stream(),
+            count,
+        )?)
+    }
+
+    /// Gets the `TCP_KEEPCNT` option on this socket.
+    pub fn keepalive_count(&self) -> Result<u32> {
+        Ok(tcp_options::keepalive_count(self.stream.stream())?)
+    }
+
+    /// Sets the `TCP_USER_TIMEOUT` option on this socket, how long sent
+    /// data may stay unacknowledged before the connection is dropped.
+    /// [`None`] leaves it to the system.
+    pub fn set_user_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        Ok(self.socket().set_tcp_user_timeout(timeout)?)
+    }
+
+    /// Gets the `TCP_USER_TIMEOUT` option on this socket.
+    pub fn user_timeout(&self) -> Result<Option<Duration>> {
+        Ok(self.socket().tcp_user_timeout()?)
+    }
+
+    /// Sets the `SO_LINGER` option on this socket.
+    ///
+    /// With `Some`, closing the socket waits up to that long for the data
+    /// still queued to be sent, and `Some(Duration::ZERO)` resets the
+    /// connection instead of closing it gracefully.
+    pub fn set_linger(&self, linger: Option<Duration>) -> Result<()> {
+        Ok(self.socket().set_linger(linger)?)
+    }
+
+    /// Gets the `SO_LINGER` option on this socket.
+    pub fn linger(&self) -> Result<Option<Duration>> {
+        Ok(self.socket().linger()?)
+    }
+
+    /// Sets the `SO_SNDBUF` option on this socket, the size of the send
+    /// buffer. The kernel doubles it to leave room for its bookkeeping.
+    pub fn set_send_buffer_size(&self, size: usize) -> Result<()> {
+        Ok(self.socket().set_send_buffer_size(size)?)
+    }
+
+    /// Gets the `SO_SNDBUF` option on this socket.
+    pub fn send_buffer_size(&self) -> Result<usize> {
+        Ok(self.socket().send_buffer_size()?)
+    }
+
+    /// Sets the `SO_RCVBUF` option on this socket, the size of the receive
+    /// buffer. The kernel doubles it to leave room for its bookkeeping.
+    pub fn set_recv_buffer_size(&self, size: usize) -> Result<()> {
+        Ok(self.socket().set_recv_buffer_size(size)?)
+    }
+
+    /// Gets the `SO_RCVBUF` option on this socket.
+    pub fn recv_buffer_size(&self) -> Result<usize> {
+        Ok(self.socket().recv_buffer_size()?)
+    }
+
+    /// Sets the `TCP_CORK` option on this socket.
+    ///
+    /// If set, partial segments are held back until the option is cleared or
+    /// 200ms passed, so that a header and a body written apart go out
+    /// together.
+    pub fn set_cork(&self, cork: bool) -> Result<()> {
+        Ok(self.socket().set_tcp_cork(cork)?)
+    }
+
+    /// Gets the `TCP_CORK` option on this socket.
+    pub fn cork(&self) -> Result<bool> {
+        Ok(self.socket().tcp_cork()?)
+    }
+
+    /// Sets the `TCP_QUICKACK` option on this socket, which acknowledges
+    /// segments at once instead of delaying the acknowledgements. The kernel
+    /// may clear it again, so it is set again where it matters.
+    pub fn set_quickack(&self, quickack: bool) -> Result<()> {
+        Ok(self.socket().set_tcp_quickack(quickack)?)
+    }
+
+    /// Gets the `TCP_QUICKACK` option on this socket.
+    pub fn quickack(&self) -> Result<bool> {
+        Ok(self.socket().tcp_quickack()?)
+    }
+
+    /// Sets the type of service field of the packets sent: `IP_TOS` for
+    /// IPv4, `IPV6_TCLASS` for IPv6.
+    pub fn set_tos(&self, tos: u32) -> Result<()> {
+        Ok(tcp_options::set_tos(self.stream.stream(), tos)?)
+    }
+
+    /// Gets the type of service field of the packets sent.
+    pub fn tos(&self) -> Result<u32> {
+        Ok(tcp_options::tos(self.stream.stream())?)
+    }
+
+    /// Sets the `SO_MARK` option on this socket, the mark routing and
+    /// filtering rules can match packets against. Needs the `CAP_NET_ADMIN`
+    /// capability.
+    pub fn set_mark(&self, mark: u32) -> Result<()> {
+        Ok(self.socket().set_mark(mark)?)
+    }
+
+    /// Gets the `SO_MARK` option on this socket.
+    pub fn mark(&self) -> Result<u32> {
+        Ok(self.socket().mark()?)
+    }
+
+    /// Sets the `SO_BINDTODEVICE` option on this socket, so that only packets
+    /// of the network interface named `interface` are sent and received.
+    /// [`None`] removes the binding.
+    pub fn bind_device(&self, interface: Option<&str>) -> Result<()> {
+        Ok(tcp_options::bind_device(self.stream.stream(), interface)?)
+    }
+
+    /// Gets the network interface this socket is bound to with
+    /// `SO_BINDTODEVICE`, if any.
+    pub fn device(&self) -> Result<Option<String>> {
+        Ok(tcp_options::device(self.stream.stream())?)
+    }
+
+    /// Gets the `SO_REUSEPORT` option on this socket.
+    pub fn reuse_port(&self) -> Result<bool> {
+        Ok(self.socket().reuse_port()?)
+    }
+
+    /// Reads what the kernel knows about this connection with `TCP_INFO`:
+    /// its state, round-trip times, congestion window, retransmissions and
+    /// byte counts.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use glommio::{net::TcpStream, LocalExecutor};
+    ///
+    /// let ex = LocalExecutor::default();
+    /// ex.run(async move {
+    ///     let stream = TcpStream::connect("127.0.0.1:10000").await.unwrap();
+    ///     let info = stream.tcp_info().unwrap();
+    ///     println!("{:?}, rtt {:?}", info.state(), info.rtt());
+    /// });
+    /// ```
+    pub fn tcp_info(&self) -> Result<TcpInfo> {
+        Ok(TcpInfo::read(self.stream.stream())?)
+    }
+
+    fn socket(&self) -> SockRef<'_> {
+        SockRef::from(self.stream.stream())
+    }
+
     /// Receives data on the socket from the remote address to which it is
     /// connected, without removing that data from the queue.
     ///
@@ -0,0 +1,447 @@
+//! TLS over Glommio streams, using [rustls].
+//!
+//! [rustls]: https://docs.rs/rustls
+
+use crate::net::{stream::RxBuf, KtlsCipher, KtlsSecrets, KtlsStream, KtlsVersion, TcpStream};
+use futures_lite::{
+    future::poll_fn,
+    io::{AsyncRead, AsyncWrite},
+    ready,
+};
+use rustls::{
+    pki_types::ServerName, ClientConfig, ClientConnection, Connection, ConnectionTrafficSecrets,
+    HandshakeKind, ProtocolVersion, ServerConfig, ServerConnection,
+};
+use std::{
+    io::{self, IoSlice, Read, Write},
+    net::{Shutdown, SocketAddr},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+type Result<T> = crate::Result<T, ()>;
+
+/// Starts TLS connections as a client.
+///
+/// Wraps a rustls [`ClientConfig`] and runs the handshake over an already
+/// connected stream, usually a [`TcpStream`], producing a [`TlsStream`].
+/// Everything negotiable is set in the configuration: trusted roots, client
+/// certificates, ALPN protocols and session resumption, which rustls enables
+/// by default with an in-memory cache. Reuse one connector for many
+/// connections so that they share that cache.
+///
+/// # Examples
+///
+/// ```no_run
+/// use futures_lite::AsyncWriteExt;
+/// use glommio::{
+///     net::{TcpStream, TlsConnector},
+///     LocalExecutor,
+/// };
+/// use rustls::{ClientConfig, RootCertStore};
+/// use std::sync::Arc;
+///
+/// LocalExecutor::default().run(async {
+///     let roots = RootCertStore::empty(); // add your trust anchors
+///     let mut config = ClientConfig::builder()
+///         .with_root_certificates(roots)
+///         .with_no_client_auth();
+///     config.alpn_protocols = vec![b"http/1.1".to_vec()];
+///     let connector = TlsConnector::new(Arc::new(config));
+///
+///     let stream = TcpStream::connect("example.com:443").await.unwrap();
+///     let mut stream = connector
+///         .connect("example.com", stream.buffered())
+///         .await
+///         .unwrap();
+///     stream.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+/// });
+/// ```
+#[derive(Clone, Debug)]
+pub struct TlsConnector {
+    config: Arc<ClientConfig>,
+}
+
+impl TlsConnector {
+    /// Creates a connector using `config` for all its connections.
+    pub fn new(config: Arc<ClientConfig>) -> TlsConnector {
+        TlsConnector { config }
+    }
+
+    /// Runs a client handshake over `stream`.
+    ///
+    /// `domain` is sent as SNI and checked against the server's
+    /// certificate. It can also be an IP address, which is checked but not
+    /// sent.
+    pub async fn connect<S>(&self, domain: &str, stream: S) -> Result<TlsStream<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let name = ServerName::try_from(domain.to_owned())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let conn = ClientConnection::new(self.config.clone(), name).map_err(tls_error)?;
+        TlsStream::handshake(stream, conn.into()).await
+    }
+}
+
+impl From<Arc<ClientConfig>> for TlsConnector {
+    fn from(config: Arc<ClientConfig>) -> TlsConnector {
+        TlsConnector::new(config)
+    }
+}
+
+/// Accepts TLS connections as a server.
+///
+/// Wraps a rustls [`ServerConfig`] and runs the handshake over an accepted
+/// stream, producing a [`TlsStream`]. The configuration holds the
+/// certificate, or a resolver that picks one from the SNI name the client
+/// sent, the ALPN protocols to choose from and the session cache used for
+/// resumption, in memory by default. Reuse one acceptor for many connections
+/// so that they share that cache.
+#[derive(Clone, Debug)]
+pub struct TlsAcceptor {
+    config: Arc<ServerConfig>,
+}
+
+impl TlsAcceptor {
+    /// Creates an acceptor using `config` for all its connections.
+    pub fn new(config: Arc<ServerConfig>) -> TlsAcceptor {
+        TlsAcceptor { config }
+    }
+
+    /// Runs a server handshake over `stream`.
+    pub async fn accept<S>(&self, stream: S) -> Result<TlsStream<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let conn = ServerConnection::new(self.config.clone()).map_err(tls_error)?;
+        TlsStream::handshake(stream, conn.into()).await
+    }
+}
+
+impl From<Arc<ServerConfig>> for TlsAcceptor {
+    fn from(config: Arc<ServerConfig>) -> TlsAcceptor {
+        TlsAcceptor::new(config)
+    }
+}
+
+/// A stream encrypted with TLS, produced by a [`TlsConnector`] or a
+/// [`TlsAcceptor`] once the handshake completes.
+///
+/// Reads and writes go through [`AsyncRead`] and [`AsyncWrite`]. Closing
+/// the stream sends a `close_notify` alert before closing the wrapped one.
+/// A peer closing the connection without sending one is reported as an
+/// [`UnexpectedEof`](io::ErrorKind::UnexpectedEof) error rather than as the
+/// end of the stream, since the data may have been truncated.
+///
+/// TLS records are read through the wrapped stream, so a
+/// [`buffered`](TcpStream::buffered) stream keeps its receive buffer, and its
+/// read and write timeouts also bound the handshake.
+#[derive(Debug)]
+pub struct TlsStream<S = TcpStream> {
+    io: S,
+    conn: Connection,
+}
+
+impl<S> TlsStream<S> {
+    /// Returns a reference to the wrapped stream.
+    pub fn get_ref(&self) -> &S {
+        &self.io
+    }
+
+    /// Returns a mutable reference to the wrapped stream.
+    ///
+    /// Reading from or writing to it directly corrupts the TLS session.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.io
+    }
+
+    /// Returns the rustls connection state, for anything not exposed here.
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+
+    /// Returns the wrapped stream and the rustls connection state.
+    pub fn into_inner(self) -> (S, Connection) {
+        (self.io, self.conn)
+    }
+
+    /// Returns the protocol agreed on with ALPN, if any.
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.conn.alpn_protocol()
+    }
+
+    /// Returns the server name the client asked for with SNI.
+    ///
+    /// This is always `None` on the connecting side.
+    pub fn server_name(&self) -> Option<&str> {
+        match &self.conn {
+            Connection::Client(_) => None,
+            Connection::Server(conn) => conn.server_name(),
+        }
+    }
+
+    /// Returns whether the handshake resumed an earlier session.
+    pub fn is_resumed(&self) -> bool {
+        self.conn.handshake_kind() == Some(HandshakeKind::Resumed)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> TlsStream<S> {
+    async fn handshake(io: S, conn: Connection) -> Result<TlsStream<S>> {
+        let mut stream = TlsStream { io, conn };
+        poll_fn(|cx| stream.poll_handshake(cx)).await?;
+        Ok(stream)
+    }
+
+    fn poll_handshake(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.conn.is_handshaking() {
+            if self.conn.wants_write() {
+                ready!(self.poll_flush_tls(cx))?;
+            } else if ready!(self.poll_read_tls(cx))? == 0 {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed during the TLS handshake",
+                )));
+            }
+        }
+        // the last flight, and the session tickets a server sends right after
+        self.poll_flush_tls(cx)
+    }
+
+    /// Reads and processes TLS records, returning how many bytes were read
+    /// from the wrapped stream: 0 means it reached its end.
+    fn poll_read_tls(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        let mut io = SyncIo {
+            io: &mut self.io,
+            cx,
+        };
+        let read = match self.conn.read_tls(&mut io) {
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Poll::Pending,
+            result => result?,
+        };
+        if let Err(err) = self.conn.process_new_packets() {
+            // rustls queued an alert telling the peer why; send it if we can
+            let _ = self.poll_write_tls(cx);
+            return Poll::Ready(Err(tls_error(err)));
+        }
+        Poll::Ready(Ok(read))
+    }
+
+    fn poll_write_tls(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        let mut io = SyncIo {
+            io: &mut self.io,
+            cx,
+        };
+        match self.conn.write_tls(&mut io) {
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => Poll::Pending,
+            result => Poll::Ready(result),
+        }
+    }
+
+    fn poll_flush_tls(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.conn.wants_write() {
+            if ready!(self.poll_write_tls(cx))? == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+        }
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+}
+
+impl<B: RxBuf + Unpin> TlsStream<TcpStream<B>> {
+    /// Returns the socket address of the remote peer of this TLS connection.
+    pub fn peer_addr(&self) -> Result<SocketAddr> {
+        self.io.peer_addr()
+    }
+
+    /// Returns the socket address of the local half of this TLS connection.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.io.local_addr()
+    }
+
+    /// Shuts down the read, write, or both halves of this connection.
+    ///
+    /// Shutting down the write half sends a `close_notify` alert first, so
+    /// the peer can tell the end of the stream from a truncation.
+    pub async fn shutdown(&mut self, how: Shutdown) -> Result<()> {
+        if how != Shutdown::Read {
+            self.conn.send_close_notify();
+            poll_fn(|cx| self.poll_flush_tls(cx)).await?;
+        }
+        self.io.shutdown(how).await
+    }
+}
+
+impl TlsStream<TcpStream> {
+    /// Hands the encryption of this session to the kernel, returning a
+    /// [`KtlsStream`].
+    ///
+    /// The configuration must set `enable_secret_extraction`, and the
+    /// conversion is best made right after the handshake: it fails while
+    /// TLS records are still waiting to be sent. Plaintext rustls already
+    /// decrypted is returned first by the new stream. Session tickets the
+    /// server sends afterwards are dropped, so the session cannot be resumed
+    /// by a client that converted.
+    ///
+    /// The stream is consumed either way; call
+    /// [`TcpStream::set_tls_ulp`] before the handshake to find out whether
+    /// the kernel supports TLS at all.
+    pub fn into_ktls(self) -> Result<KtlsStream> {
+        let TlsStream { io, mut conn } = self;
+        if conn.wants_write() {
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "TLS records are still waiting to be sent",
+            )
+            .into());
+        }
+        let version = match conn.protocol_version() {
+            Some(ProtocolVersion::TLSv1_2) => KtlsVersion::Tls12,
+            Some(ProtocolVersion::TLSv1_3) => KtlsVersion::Tls13,
+            _ => return Err(unsupported("TLS version").into()),
+        };
+        let mut pending = Vec::new();
+        match conn.reader().read_to_end(&mut pending) {
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+            result => {
+                result?;
+            }
+        }
+        let secrets = conn.dangerous_extract_secrets().map_err(tls_error)?;
+        let (tx_sequence, tx) = secrets.tx;
+        let (rx_sequence, rx) = secrets.rx;
+        let tx = KtlsSecrets::new(version, ktls_cipher(tx)?, tx_sequence);
+        let rx = KtlsSecrets::new(version, ktls_cipher(rx)?, rx_sequence);
+        let mut stream = io.into_ktls(&tx, &rx)?;
+        stream.set_pending(pending);
+        Ok(stream)
+    }
+}
+
+fn ktls_cipher(secrets: ConnectionTrafficSecrets) -> io::Result<KtlsCipher> {
+    let cipher = match secrets {
+        ConnectionTrafficSecrets::Aes128Gcm { key, iv } => KtlsCipher::Aes128Gcm {
+            key: key.as_ref().try_into().map_err(|_| unsupported("key"))?,
+            iv: iv.as_ref().try_into().map_err(|_| unsupported("IV"))?,
+        },
+        ConnectionTrafficSecrets::Aes256Gcm { key, iv } => KtlsCipher::Aes256Gcm {
+            key: key.as_ref().try_into().map_err(|_| unsupported("key"))?,
+            iv: iv.as_ref().try_into().map_err(|_| unsupported("IV"))?,
+        },
+        ConnectionTrafficSecrets::Chacha20Poly1305 { key, iv } => KtlsCipher::Chacha20Poly1305 {
+            key: key.as_ref().try_into().map_err(|_| unsupported("key"))?,
+            iv: iv.as_ref().try_into().map_err(|_| unsupported("IV"))?,
+        },
+        _ => return Err(unsupported("cipher suite")),
+    };
+    Ok(cipher)
+}
+
+fn unsupported(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("kTLS does not support this {what}"),
+    )
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for TlsStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            match this.conn.reader().read(buf) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                result => return Poll::Ready(result),
+            }
+            // once the wrapped stream ends, the reader reports it instead
+            ready!(this.poll_read_tls(cx))?;
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for TlsStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let mut written = 0;
+        while written < buf.len() {
+            written += this.conn.writer().write(&buf[written..])?;
+            while this.conn.wants_write() {
+                match this.poll_write_tls(cx) {
+                    Poll::Ready(Ok(0)) => {
+                        return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+                    }
+                    Poll::Ready(Ok(_)) => {}
+                    Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                    // rustls holds on to what it encrypted, so that much is
+                    // written as far as the caller is concerned
+                    Poll::Pending if written > 0 => return Poll::Ready(Ok(written)),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+        }
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_flush_tls(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.conn.send_close_notify();
+        ready!(this.poll_flush_tls(cx))?;
+        Pin::new(&mut this.io).poll_close(cx)
+    }
+}
+
+fn tls_error(err: rustls::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+/// rustls reads and writes records through `std::io`; this turns a pending
+/// poll of the wrapped stream into a `WouldBlock` error for it.
+struct SyncIo<'a, 'b, S> {
+    io: &'a mut S,
+    cx: &'a mut Context<'b>,
+}
+
+impl<S: AsyncRead + Unpin> Read for SyncIo<'_, '_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match Pin::new(&mut *self.io).poll_read(self.cx, buf) {
+            Poll::Ready(result) => result,
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> Write for SyncIo<'_, '_, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match Pin::new(&mut *self.io).poll_write(self.cx, buf) {
+            Poll::Ready(result) => result,
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        match Pin::new(&mut *self.io).poll_write_vectored(self.cx, bufs) {
+            Poll::Ready(result) => result,
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match Pin::new(&mut *self.io).poll_flush(self.cx) {
+            Poll::Ready(result) => result,
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
@@ -3,12 +3,15 @@
 //
 // This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
 //
-use super::datagram::GlommioDatagram;
-use nix::sys::socket::{SockaddrLike, SockaddrStorage};
+use super::{
+    datagram::{GlommioDatagram, RecvMeta, Transmit},
+    dns::{self, ToSocketAddrs},
+};
+use nix::sys::socket::{getsockopt, setsockopt, sockopt, SockaddrLike, SockaddrStorage};
 use socket2::{Domain, Protocol, Socket, Type};
 use std::{
-    io,
-    net::{self, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, ToSocketAddrs},
+    io::{self, IoSliceMut},
+    net::{self, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
     os::unix::io::{AsRawFd, FromRawFd, RawFd},
     time::Duration,
 };
@@ -79,7 +82,7 @@ impl UdpSocket {
     ///     println!("Listening on {}", listener.local_addr().unwrap());
     /// });
     /// ```
-    pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<UdpSocket> {
+    pub fn bind<A: net::ToSocketAddrs>(addr: A) -> Result<UdpSocket> {
         let addr = addr
             .to_socket_addrs()
             .unwrap()
@@ -104,7 +107,8 @@ impl UdpSocket {
     /// [`recv`] methods to be used to send data and also applies filters to
     /// only receive data from the specified address.
     ///
-    /// If `addr` yields multiple addresses, connect will be attempted with each
+    /// Host names are resolved with the [`Resolver`] of the executor. If
+    /// `addr` yields multiple addresses, connect will be attempted with each
     /// of the addresses until the underlying OS function returns no error.
     /// Note that usually, a successful connect call does not specify that
     /// there is a remote server listening on the port, rather, such an
@@ -134,13 +138,11 @@ impl UdpSocket {
     ///
     /// [`send`]: UdpSocket::send
     /// [`recv`]: UdpSocket::recv
+    /// [`Resolver`]: super::Resolver
     pub async fn connect<A: ToSocketAddrs>(&self, addr: A) -> Result<()> {
-        let iter = addr.to_socket_addrs()?;
         let mut err = io::Error::other("No Valid addresses");
-        for addr in iter {
-            let reactor = self.socket.reactor.upgrade().unwrap();
-            let source = reactor.connect(self.socket.as_raw_fd(), SockaddrStorage::from(addr));
-            match source.collect_rw().await {
+        for addr in dns::resolve(&addr).await? {
+            match self.connect_addr(addr).await {
                 Ok(_) => return Ok(()),
                 Err(x) => {
                     err = x;
@@ -150,6 +152,12 @@ impl UdpSocket {
         Err(err.into())
     }
 
+    pub(crate) async fn connect_addr(&self, addr: SocketAddr) -> io::Result<()> {
+        let reactor = self.socket.reactor.upgrade().unwrap();
+        let source = reactor.connect(self.socket.as_raw_fd(), SockaddrStorage::from(addr));
+        source.collect_rw().await.map(drop)
+    }
+
     /// Sets the buffer size used on the receive path
     pub fn set_buffer_size(&mut self, buffer_size: usize) {
         self.socket.rx_buf_size = buffer_size;
@@ -598,9 +606,10 @@ impl UdpSocket {
 
     /// Sends data on the socket to the given address. On success, returns the
     /// number of bytes written. Address type can be any implementor of
-    /// [`ToSocketAddrs`] trait. See its documentation for concrete examples.
-    /// It is possible for `addr` to yield multiple addresses, but send_to will
-    /// only send data to the first address yielded by `addr`.
+    /// [`ToSocketAddrs`] trait, with host names resolved by the [`Resolver`]
+    /// of the executor. It is possible for `addr` to yield multiple
+    /// addresses, but send_to will only send data to the first address
+    /// yielded by `addr`.
     ///
     /// # Examples
     ///
@@ -622,14 +631,10 @@ impl UdpSocket {
     /// })
     /// ```
     ///
-    /// [`ToSocketAddrs`]: https://doc.rust-lang.org/stable/std/net/trait.ToSocketAddrs.html
+    /// [`ToSocketAddrs`]: super::ToSocketAddrs
+    /// [`Resolver`]: super::Resolver
     pub async fn send_to<A: ToSocketAddrs>(&self, buf: &[u8], addr: A) -> Result<usize> {
-        let addr = addr
-            .to_socket_addrs()
-            .unwrap()
-            .next()
-            .ok_or_else(|| io::Error::other("empty address"))?;
-
+        let addr = dns::resolve(&addr).await?[0];
         let sockaddr = SockaddrStorage::from(addr);
         self.socket.send_to(buf, sockaddr).await.map_err(Into::into)
     }
@@ -660,6 +665,134 @@ impl UdpSocket {
     pub async fn send(&self, buf: &[u8]) -> Result<usize> {
         self.socket.send(buf).await.map_err(Into::into)
     }
+
+    /// Sends several datagrams with as few system calls as possible.
+    ///
+    /// Each [`Transmit`] goes to its destination, or to the connected peer
+    /// if it has none, and can carry many datagrams of the same size with
+    /// [`Transmit::with_segment_size`]. Returns how many transmits were
+    /// sent: fewer than all only if an error stopped the batch after some
+    /// were, in which case the rest can be sent again.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use glommio::{
+    ///     net::{Transmit, UdpSocket},
+    ///     LocalExecutor,
+    /// };
+    ///
+    /// let ex = LocalExecutor::default();
+    /// ex.run(async move {
+    ///     let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    ///     let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    ///     let addr = receiver.local_addr().unwrap();
+    ///     let transmits = [
+    ///         Transmit::new(b"one").with_destination(addr),
+    ///         Transmit::new(b"two").with_destination(addr),
+    ///     ];
+    ///     assert_eq!(sender.send_many(&transmits).await.unwrap(), 2);
+    /// })
+    /// ```
+    pub async fn send_many(&self, transmits: &[Transmit<'_>]) -> Result<usize> {
+        let transmits: Vec<_> = transmits
+            .iter()
+            .map(|t| t.map_destination(|addr| SockaddrStorage::from(*addr)))
+            .collect();
+        self.socket.send_many(&transmits).await.map_err(Into::into)
+    }
+
+    /// Receives several datagrams, one into each of `bufs`, waiting only for
+    /// the first one. On success, returns a [`RecvMeta`] for each buffer
+    /// filled, in order.
+    ///
+    /// A datagram too long for its buffer is truncated. With
+    /// [`set_gro`](UdpSocket::set_gro) enabled, a buffer can hold several
+    /// datagrams, as [`RecvMeta::segment_size`] reports, so make them large.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use glommio::{
+    ///     net::{Transmit, UdpSocket},
+    ///     LocalExecutor,
+    /// };
+    /// use std::io::IoSliceMut;
+    ///
+    /// let ex = LocalExecutor::default();
+    /// ex.run(async move {
+    ///     let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    ///     let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    ///     let addr = receiver.local_addr().unwrap();
+    ///     sender.send_to(b"one", addr).await.unwrap();
+    ///
+    ///     let mut bufs = [[0; 1500]; 8];
+    ///     let mut slices: Vec<_> = bufs.iter_mut().map(|b| IoSliceMut::new(b)).collect();
+    ///     let received = receiver.recv_many(&mut slices).await.unwrap();
+    ///     assert_eq!(received[0].len(), 3);
+    ///     assert_eq!(*received[0].addr(), sender.local_addr().unwrap());
+    /// })
+    /// ```
+    pub async fn recv_many(&self, bufs: &mut [IoSliceMut<'_>]) -> Result<Vec<RecvMeta>> {
+        let received = self.socket.recv_many::<SockaddrStorage>(bufs).await?;
+        Ok(received
+            .into_iter()
+            .map(|meta| {
+                meta.map_addr(|addr| {
+                    sockaddr_storage_to_std(addr).expect("invalid socket addr for this family!")
+                })
+            })
+            .collect())
+    }
+
+    /// Gets the value of the `UDP_GRO` option on this socket.
+    ///
+    /// For more information about this option, see
+    /// [`set_gro`](UdpSocket::set_gro).
+    pub fn gro(&self) -> Result<bool> {
+        Ok(getsockopt(&self.socket.socket, sockopt::UdpGroSegment).map_err(io::Error::from)?)
+    }
+
+    /// Sets the value of the `UDP_GRO` option on this socket.
+    ///
+    /// With generic receive offload, the kernel coalesces consecutive
+    /// datagrams of the same size from the same sender, and
+    /// [`recv_many`](UdpSocket::recv_many) returns them in a single buffer
+    /// along with their size. Other receive methods do not report the size,
+    /// so only use them on a socket with this option disabled.
+    pub fn set_gro(&self, gro: bool) -> Result<()> {
+        setsockopt(&self.socket.socket, sockopt::UdpGroSegment, &gro).map_err(io::Error::from)?;
+        Ok(())
+    }
+
+    /// Gets whether [`recv_many`](UdpSocket::recv_many) reports the TOS byte,
+    /// or the traffic class, of the datagrams received.
+    ///
+    /// For more information about this option, see
+    /// [`set_recv_tos`](UdpSocket::set_recv_tos).
+    pub fn recv_tos(&self) -> Result<bool> {
+        let socket = &self.socket.socket;
+        let recv_tos = if self.local_addr()?.is_ipv6() {
+            getsockopt(socket, sockopt::Ipv6RecvTClass)
+        } else {
+            getsockopt(socket, sockopt::IpRecvTos)
+        };
+        Ok(recv_tos.map_err(io::Error::from)?)
+    }
+
+    /// Sets whether [`recv_many`](UdpSocket::recv_many) reports the TOS byte,
+    /// or the traffic class, of the datagrams received, which holds their
+    /// ECN codepoint. This sets the `IP_RECVTOS` option and, on IPv6
+    /// sockets, `IPV6_RECVTCLASS` too.
+    pub fn set_recv_tos(&self, recv_tos: bool) -> Result<()> {
+        let socket = &self.socket.socket;
+        // IPv4 datagrams reach dual-stack sockets with their TOS byte
+        setsockopt(socket, sockopt::IpRecvTos, &recv_tos).map_err(io::Error::from)?;
+        if self.local_addr()?.is_ipv6() {
+            setsockopt(socket, sockopt::Ipv6RecvTClass, &recv_tos).map_err(io::Error::from)?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -1066,4 +1199,118 @@ mod tests {
             assert_eq!(s.ttl().unwrap(), 42);
         });
     }
+
+    // Receives until `count` datagrams arrived, as recv_many may return fewer
+    async fn recv_all(socket: &UdpSocket, count: usize) -> Vec<(Vec<u8>, RecvMeta)> {
+        let mut received = Vec::new();
+        let mut bufs = vec![[0u8; 1024]; count];
+        while received.len() < count {
+            let mut slices: Vec<_> = bufs.iter_mut().map(|b| IoSliceMut::new(b)).collect();
+            let metas = socket.recv_many(&mut slices).await.unwrap();
+            for (meta, buf) in metas.into_iter().zip(bufs.iter()) {
+                received.push((buf[..meta.len()].to_vec(), meta));
+            }
+        }
+        received
+    }
+
+    #[test]
+    fn send_many_recv_many() {
+        test_executor!(async move {
+            let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let addr = receiver.local_addr().unwrap();
+            let payloads: Vec<_> = (0..10u8).map(|i| vec![i; i as usize + 1]).collect();
+            let transmits: Vec<_> = payloads
+                .iter()
+                .map(|p| Transmit::new(p).with_destination(addr))
+                .collect();
+            assert_eq!(sender.send_many(&transmits).await.unwrap(), 10);
+
+            let received = recv_all(&receiver, 10).await;
+            for ((data, meta), payload) in received.iter().zip(&payloads) {
+                assert_eq!(data, payload);
+                assert_eq!(*meta.addr(), sender.local_addr().unwrap());
+                assert_eq!(meta.segment_size(), None);
+                assert_eq!(meta.tos(), None);
+            }
+            assert!(receiver.recv_many(&mut []).await.unwrap().is_empty());
+        });
+    }
+
+    #[test]
+    fn recv_many_waits_for_the_first_datagram() {
+        test_executor!(async move {
+            let (s1, s2) = connected_pair!();
+            let receiver = crate::spawn_local(async move {
+                let mut buf = [0; 16];
+                let received = s2
+                    .recv_many(&mut [IoSliceMut::new(&mut buf)])
+                    .await
+                    .unwrap();
+                (received[0].len(), buf)
+            });
+            Timer::new(Duration::from_millis(10)).await;
+            // through the ring, for a connected peer
+            s1.socket.tx_yolo.set(false);
+            let transmits = [Transmit::new(b"hello")];
+            assert_eq!(s1.send_many(&transmits).await.unwrap(), 1);
+            let (len, buf) = receiver.await;
+            assert_eq!(&buf[..len], b"hello");
+        });
+    }
+
+    #[test]
+    fn segmentation_offload() {
+        test_executor!(async move {
+            let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let addr = receiver.local_addr().unwrap();
+            let contents: Vec<u8> = (0..350).map(|i| i as u8).collect();
+            let transmit = Transmit::new(&contents)
+                .with_destination(addr)
+                .with_segment_size(100);
+
+            // split into datagrams on the way
+            assert_eq!(sender.send_many(&[transmit]).await.unwrap(), 1);
+            let received = recv_all(&receiver, 4).await;
+            let sizes: Vec<_> = received.iter().map(|(data, _)| data.len()).collect();
+            assert_eq!(sizes, [100, 100, 100, 50]);
+            let joined: Vec<u8> = received.into_iter().flat_map(|(data, _)| data).collect();
+            assert_eq!(joined, contents);
+
+            // and kept together with GRO
+            receiver.set_gro(true).unwrap();
+            assert!(receiver.gro().unwrap());
+            assert_eq!(sender.send_many(&[transmit]).await.unwrap(), 1);
+            let (data, meta) = recv_all(&receiver, 1).await.pop().unwrap();
+            assert_eq!(data, contents);
+            assert_eq!(meta.segment_size(), Some(100));
+
+            let invalid = Transmit::new(&contents)
+                .with_destination(addr)
+                .with_segment_size(0);
+            let err = sender.send_many(&[invalid]).await.unwrap_err();
+            assert_eq!(io::Error::from(err).kind(), io::ErrorKind::InvalidInput);
+        });
+    }
+
+    #[test]
+    fn recv_tos_reports_ecn() {
+        test_executor!(async move {
+            let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+            receiver.set_recv_tos(true).unwrap();
+            assert!(receiver.recv_tos().unwrap());
+            // ECT(0), in the low two bits of a TOS byte of 0x12
+            setsockopt(&sender.socket.socket, sockopt::Ipv4Tos, &0x12).unwrap();
+            sender
+                .send_to(b"ecn", receiver.local_addr().unwrap())
+                .await
+                .unwrap();
+            let (_, meta) = recv_all(&receiver, 1).await.pop().unwrap();
+            assert_eq!(meta.tos(), Some(0x12));
+            assert_eq!(meta.ecn(), Some(0b10));
+        });
+    }
 }
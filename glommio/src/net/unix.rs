@@ -3,7 +3,11 @@
 //
 // This product includes software developed at Datadog (https://www.datadoghq.com/). Copyright 2020 Datadog, Inc.
 //
-use super::{datagram::GlommioDatagram, stream::GlommioStream};
+use super::{
+    cmsg::{self, ControlMessage, ControlMessageOwned},
+    datagram::{GlommioDatagram, RecvMeta, Transmit},
+    stream::GlommioStream,
+};
 use crate::{
     net::stream::{Buffered, NonBuffered, Preallocated, RxBuf},
     reactor::Reactor,
@@ -13,14 +17,14 @@ use futures_lite::{
     io::{AsyncBufRead, AsyncRead, AsyncWrite},
     stream::{self, Stream},
 };
-use nix::sys::socket::UnixAddr;
+use nix::sys::socket::{getsockopt, setsockopt, sockopt, UnixAddr};
 use pin_project_lite::pin_project;
 use socket2::{Domain, Socket, Type};
 use std::{
-    io,
+    io::{self, IoSliceMut},
     net::Shutdown,
     os::unix::{
-        io::{AsRawFd, FromRawFd, RawFd},
+        io::{AsRawFd, FromRawFd, OwnedFd, RawFd},
         net::{self, SocketAddr},
     },
     path::Path,
@@ -349,6 +353,101 @@ impl UnixStream {
             stream: self.stream.buffered_with(buf),
         }
     }
+
+    /// Sends data along with file descriptors, which the receiver gets as
+    /// new descriptors for the same open files: a listening socket, or a
+    /// [`DmaFile`](crate::io::DmaFile), can move to another process this
+    /// way. On success, returns the number of bytes written.
+    ///
+    /// The descriptors stay open here.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use glommio::{net::UnixStream, LocalExecutor};
+    /// use std::{fs::File, io::Read, os::unix::io::AsRawFd};
+    ///
+    /// let ex = LocalExecutor::default();
+    /// ex.run(async move {
+    ///     let (p1, p2) = UnixStream::pair().unwrap();
+    ///     let file = File::open("/proc/self/stat").unwrap();
+    ///     p1.send_with_fds(b"a file", &[file.as_raw_fd()])
+    ///         .await
+    ///         .unwrap();
+    ///
+    ///     let mut buf = [0; 16];
+    ///     let (size, mut fds) = p2.recv_with_fds(&mut buf).await.unwrap();
+    ///     assert_eq!(&buf[..size], b"a file");
+    ///     let mut file = File::from(fds.pop().unwrap());
+    ///     let mut stat = String::new();
+    ///     file.read_to_string(&mut stat).unwrap();
+    /// })
+    /// ```
+    pub async fn send_with_fds(&self, buf: &[u8], fds: &[RawFd]) -> Result<usize> {
+        self.send_with_control(buf, &[ControlMessage::Rights(fds)])
+            .await
+    }
+
+    /// Receives data along with the file descriptors sent with it, if any.
+    /// On success, returns the number of bytes read and the descriptors.
+    ///
+    /// Other control messages are discarded.
+    pub async fn recv_with_fds(&self, buf: &mut [u8]) -> Result<(usize, Vec<OwnedFd>)> {
+        let (size, messages) = self.recv_with_control(buf).await?;
+        Ok((size, cmsg::into_fds(messages)))
+    }
+
+    /// Sends data along with control messages. On success, returns the
+    /// number of bytes written.
+    pub async fn send_with_control(
+        &self,
+        buf: &[u8],
+        messages: &[ControlMessage<'_>],
+    ) -> Result<usize> {
+        cmsg::send_with_control(
+            self.stream.stream().as_raw_fd(),
+            buf,
+            messages,
+            self.stream.write_timeout(),
+        )
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Receives data along with the control messages sent with it. On
+    /// success, returns the number of bytes read and the messages.
+    ///
+    /// Received descriptors are closed on exec.
+    pub async fn recv_with_control(
+        &self,
+        buf: &mut [u8],
+    ) -> Result<(usize, Vec<ControlMessageOwned>)> {
+        cmsg::recv_with_control(
+            self.stream.stream().as_raw_fd(),
+            buf,
+            self.stream.read_timeout(),
+        )
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Gets the value of the `SO_PASSCRED` option on this socket.
+    ///
+    /// For more information about this option, see
+    /// [`set_pass_credentials`](UnixStream::set_pass_credentials).
+    pub fn pass_credentials(&self) -> Result<bool> {
+        Ok(getsockopt(self.stream.stream(), sockopt::PassCred).map_err(io::Error::from)?)
+    }
+
+    /// Sets the value of the `SO_PASSCRED` option on this socket.
+    ///
+    /// When enabled, [`recv_with_control`](UnixStream::recv_with_control)
+    /// returns the credentials of the sender with everything received,
+    /// whether it sent them or not.
+    pub fn set_pass_credentials(&self, pass: bool) -> Result<()> {
+        setsockopt(self.stream.stream(), sockopt::PassCred, &pass).map_err(io::Error::from)?;
+        Ok(())
+    }
 }
 
 impl<B: RxBuf> UnixStream<B> {
@@ -709,6 +808,161 @@ impl UnixDatagram {
     pub async fn send(&self, buf: &[u8]) -> Result<usize> {
         self.socket.send(buf).await.map_err(Into::into)
     }
+
+    /// Sends several datagrams with as few system calls as possible.
+    ///
+    /// Each [`Transmit`] goes to its destination, or to the connected peer
+    /// if it has none. Segmentation offload is for UDP sockets only, so
+    /// setting a segment size is an error. Returns how many transmits were
+    /// sent: fewer than all only if an error stopped the batch after some
+    /// were, in which case the rest can be sent again.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use glommio::{
+    ///     net::{Transmit, UnixDatagram},
+    ///     LocalExecutor,
+    /// };
+    ///
+    /// let ex = LocalExecutor::default();
+    /// ex.run(async move {
+    ///     let (p1, _p2) = UnixDatagram::pair().unwrap();
+    ///     let transmits = [Transmit::new(b"one"), Transmit::new(b"two")];
+    ///     assert_eq!(p1.send_many(&transmits).await.unwrap(), 2);
+    /// })
+    /// ```
+    pub async fn send_many(&self, transmits: &[Transmit<'_, &Path>]) -> Result<usize> {
+        let transmits = transmits
+            .iter()
+            .map(|t| {
+                if t.segment_size().is_some() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "segmentation offload is only for UDP sockets",
+                    ));
+                }
+                t.try_map_destination(|path| UnixAddr::new(*path).map_err(io::Error::from))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        self.socket.send_many(&transmits).await.map_err(Into::into)
+    }
+
+    /// Receives several datagrams, one into each of `bufs`, waiting only for
+    /// the first one. On success, returns a [`RecvMeta`] for each buffer
+    /// filled, in order. A datagram too long for its buffer is truncated.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use glommio::{net::UnixDatagram, LocalExecutor};
+    /// use std::io::IoSliceMut;
+    ///
+    /// let ex = LocalExecutor::default();
+    /// ex.run(async move {
+    ///     let (p1, p2) = UnixDatagram::pair().unwrap();
+    ///     p1.send(b"one").await.unwrap();
+    ///
+    ///     let mut bufs = [[0; 64]; 4];
+    ///     let mut slices: Vec<_> = bufs.iter_mut().map(|b| IoSliceMut::new(b)).collect();
+    ///     let received = p2.recv_many(&mut slices).await.unwrap();
+    ///     assert_eq!(received[0].len(), 3);
+    /// })
+    /// ```
+    pub async fn recv_many(&self, bufs: &mut [IoSliceMut<'_>]) -> Result<Vec<RecvMeta<UnixAddr>>> {
+        self.socket.recv_many(bufs).await.map_err(Into::into)
+    }
+
+    /// Sends data along with file descriptors, which the receiver gets as
+    /// new descriptors for the same open files: a listening socket, or a
+    /// [`DmaFile`](crate::io::DmaFile), can move to another process this
+    /// way. On success, returns the number of bytes written.
+    ///
+    /// The descriptors stay open here.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use glommio::{net::UnixDatagram, LocalExecutor};
+    /// use std::{fs::File, io::Read, os::unix::io::AsRawFd};
+    ///
+    /// let ex = LocalExecutor::default();
+    /// ex.run(async move {
+    ///     let (p1, p2) = UnixDatagram::pair().unwrap();
+    ///     let file = File::open("/proc/self/stat").unwrap();
+    ///     p1.send_with_fds(b"a file", &[file.as_raw_fd()])
+    ///         .await
+    ///         .unwrap();
+    ///
+    ///     let mut buf = [0; 16];
+    ///     let (size, mut fds) = p2.recv_with_fds(&mut buf).await.unwrap();
+    ///     assert_eq!(&buf[..size], b"a file");
+    ///     let mut file = File::from(fds.pop().unwrap());
+    ///     let mut stat = String::new();
+    ///     file.read_to_string(&mut stat).unwrap();
+    /// })
+    /// ```
+    pub async fn send_with_fds(&self, buf: &[u8], fds: &[RawFd]) -> Result<usize> {
+        self.send_with_control(buf, &[ControlMessage::Rights(fds)])
+            .await
+    }
+
+    /// Receives data along with the file descriptors sent with it, if any.
+    /// On success, returns the number of bytes read and the descriptors.
+    ///
+    /// Other control messages are discarded.
+    pub async fn recv_with_fds(&self, buf: &mut [u8]) -> Result<(usize, Vec<OwnedFd>)> {
+        let (size, messages) = self.recv_with_control(buf).await?;
+        Ok((size, cmsg::into_fds(messages)))
+    }
+
+    /// Sends data along with control messages. On success, returns the
+    /// number of bytes written.
+    pub async fn send_with_control(
+        &self,
+        buf: &[u8],
+        messages: &[ControlMessage<'_>],
+    ) -> Result<usize> {
+        cmsg::send_with_control(
+            self.socket.as_raw_fd(),
+            buf,
+            messages,
+            self.socket.write_timeout(),
+        )
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Receives data along with the control messages sent with it. On
+    /// success, returns the number of bytes read and the messages.
+    ///
+    /// Received descriptors are closed on exec.
+    pub async fn recv_with_control(
+        &self,
+        buf: &mut [u8],
+    ) -> Result<(usize, Vec<ControlMessageOwned>)> {
+        cmsg::recv_with_control(self.socket.as_raw_fd(), buf, self.socket.read_timeout())
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Gets the value of the `SO_PASSCRED` option on this socket.
+    ///
+    /// For more information about this option, see
+    /// [`set_pass_credentials`](UnixDatagram::set_pass_credentials).
+    pub fn pass_credentials(&self) -> Result<bool> {
+        Ok(getsockopt(&self.socket.socket, sockopt::PassCred).map_err(io::Error::from)?)
+    }
+
+    /// Sets the value of the `SO_PASSCRED` option on this socket.
+    ///
+    /// When enabled, [`recv_with_control`](UnixDatagram::recv_with_control)
+    /// returns the credentials of the sender with everything received,
+    /// whether it sent them or not.
+    pub fn set_pass_credentials(&self, pass: bool) -> Result<()> {
+        setsockopt(&self.socket.socket, sockopt::PassCred, &pass).map_err(io::Error::from)?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -828,4 +1082,91 @@ mod tests {
         let p2 = UnixDatagram::unbound().unwrap();
         p2.connect(&file).await.unwrap();
     });
+
+    unix_socket_test!(datagram_send_many_recv_many, dir, {
+        let mut file = dir.clone();
+        file.push("name");
+
+        let p1 = UnixDatagram::bind(&file).unwrap();
+        let p2 = UnixDatagram::unbound().unwrap();
+        let transmits: Vec<_> = [&b"msg1"[..], b"msg22", b"msg333"]
+            .into_iter()
+            .map(|msg| Transmit::new(msg).with_destination(file.as_path()))
+            .collect();
+        assert_eq!(p2.send_many(&transmits).await.unwrap(), 3);
+
+        let mut bufs = [[0u8; 10]; 4];
+        let mut slices: Vec<_> = bufs.iter_mut().map(|b| IoSliceMut::new(b)).collect();
+        let received = p1.recv_many(&mut slices).await.unwrap();
+        let sizes: Vec<_> = received.iter().map(|meta| meta.len()).collect();
+        assert_eq!(sizes, [4, 5, 6]);
+        assert!(received[0].addr().path().is_none());
+        assert_eq!(&bufs[2][..6], b"msg333");
+
+        let segmented = Transmit::new(b"msg").with_segment_size(1);
+        let err = p2.send_many(&[segmented]).await.unwrap_err();
+        assert_eq!(io::Error::from(err).kind(), io::ErrorKind::InvalidInput);
+    });
+
+    unix_socket_test!(stream_passes_fds, dir, {
+        let mut path = dir.clone();
+        path.push("passed");
+        std::fs::write(&path, b"contents").unwrap();
+        let file = std::fs::File::open(&path).unwrap();
+
+        let (p1, p2) = UnixStream::pair().unwrap();
+        // waits through the ring
+        let receiver = crate::spawn_local(async move {
+            let mut buf = [0; 16];
+            let (size, fds) = p2.recv_with_fds(&mut buf).await.unwrap();
+            (buf[..size].to_vec(), fds)
+        });
+        crate::timer::Timer::new(std::time::Duration::from_millis(10)).await;
+        p1.send_with_fds(b"fd", &[file.as_raw_fd()]).await.unwrap();
+        drop(file);
+
+        let (data, mut fds) = receiver.await;
+        assert_eq!(data, b"fd");
+        assert_eq!(fds.len(), 1);
+        let mut contents = String::new();
+        std::io::Read::read_to_string(&mut std::fs::File::from(fds.pop().unwrap()), &mut contents)
+            .unwrap();
+        assert_eq!(contents, "contents");
+    });
+
+    unix_socket_test!(datagram_passes_credentials_and_fds, _dir, {
+        let (p1, p2) = UnixDatagram::pair().unwrap();
+        p2.set_pass_credentials(true).unwrap();
+        assert!(p2.pass_credentials().unwrap());
+
+        let (r, w) = nix::unistd::pipe().unwrap();
+        let credentials = crate::net::UnixCredentials::current();
+        let messages = [
+            ControlMessage::Credentials(credentials),
+            ControlMessage::Rights(&[r.as_raw_fd(), w.as_raw_fd()]),
+        ];
+        p1.send_with_control(b"msg", &messages).await.unwrap();
+
+        let mut buf = [0; 8];
+        let (size, received) = p2.recv_with_control(&mut buf).await.unwrap();
+        assert_eq!(&buf[..size], b"msg");
+        let mut got_credentials = false;
+        let mut fds = Vec::new();
+        for message in received {
+            match message {
+                ControlMessageOwned::Credentials(c) => {
+                    assert_eq!(c, credentials);
+                    got_credentials = true;
+                }
+                ControlMessageOwned::Rights(rights) => fds.extend(rights),
+                other => panic!("unexpected control message {other:?}"),
+            }
+        }
+        assert!(got_credentials);
+        // a pipe through the passed descriptors
+        assert_eq!(fds.len(), 2);
+        nix::unistd::write(&fds[1], b"x").unwrap();
+        let mut byte = [0; 1];
+        assert_eq!(nix::unistd::read(&r, &mut byte).unwrap(), 1);
+    });
 }
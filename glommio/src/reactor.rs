@@ -25,7 +25,7 @@ use nix::sys::socket::{MsgFlags, SockaddrLike, SockaddrStorage};
 use smallvec::SmallVec;
 
 use crate::{
-    io::{FileScheduler, IoScheduler, ScheduledSource},
+    io::{FileScheduler, IoRateLimiter, IoScheduler, ScheduledSource},
     sys::SockAddrStorage,
     sys::{
         self, blocking::BlockingThreadPool, common_flags, read_flags, DirectIo, DmaBuffer,
@@ -242,14 +242,28 @@ impl Reactor {
         channels.connection_wakers.push(waker);
     }
 
-    pub(crate) fn add_shared_channel_waker(&self, id: u64, waker: Waker) {
+    pub(crate) fn add_shared_channel_waker(&self, id: u64, waker: &Waker) {
         let mut channels = self.shared_channels.borrow_mut();
         let map = channels
             .wakers_map
             .entry(id)
             .or_insert_with(|| (SmallVec::new(), None));
 
-        map.0.push(waker);
+        // A task polled again before it was woken, as a stream next to busier
+        // ones is, is registered already
+        if !map.0.iter().any(|w| w.will_wake(waker)) {
+            map.0.push(waker.clone());
+        }
+    }
+
+    /// How many wakers are waiting on the shared channel `id`.
+    #[cfg(test)]
+    pub(crate) fn shared_channel_wakers(&self, id: u64) -> usize {
+        self.shared_channels
+            .borrow()
+            .wakers_map
+            .get(&id)
+            .map_or(0, |(wakers, _)| wakers.len())
     }
 
     pub(crate) fn alloc_dma_buffer(&self, size: usize) -> DmaBuffer {
@@ -391,23 +405,29 @@ impl Reactor {
         Ok(source)
     }
 
+    /// Sends `buf` to `addr`, or to the connected peer if it is `None`, along
+    /// with the control messages in `control`.
     pub(crate) fn rushed_sendmsg(
         &self,
         fd: RawFd,
         buf: DmaBuffer,
-        addr: impl nix::sys::socket::SockaddrLike,
+        addr: Option<SockaddrStorage>,
+        control: Vec<u8>,
         timeout: Option<Duration>,
     ) -> io::Result<Source> {
         let iov = libc::iovec {
             iov_base: buf.as_ptr() as *mut libc::c_void,
-            iov_len: 1,
+            iov_len: buf.len(),
         };
         // Note that the iov and addresses we have above are stack addresses. We will
         // leave it blank and the `io_uring` callee will fill that up
         let hdr = unsafe { std::mem::zeroed::<libc::msghdr>() };
 
-        let addr = unsafe { SockaddrStorage::from_raw(addr.as_ptr(), Some(addr.len())) }.unwrap();
-        let source = self.new_source(fd, SourceType::SockSendMsg(buf, iov, hdr, addr), None);
+        let source = self.new_source(
+            fd,
+            SourceType::SockSendMsg(buf, iov, hdr, addr, control),
+            None,
+        );
         if let Some(timeout) = timeout {
             source.set_timeout(timeout);
         }
@@ -417,11 +437,14 @@ impl Reactor {
         Ok(source)
     }
 
+    /// Receives up to `size` bytes, and up to `control_len` bytes of control
+    /// messages.
     pub(crate) fn rushed_recvmsg(
         &self,
         fd: RawFd,
         size: usize,
         flags: MsgFlags,
+        control_len: usize,
         timeout: Option<Duration>,
     ) -> io::Result<Source> {
         let hdr = unsafe { std::mem::zeroed::<libc::msghdr>() };
@@ -436,6 +459,7 @@ impl Reactor {
                 iov,
                 hdr,
                 std::mem::MaybeUninit::<nix::sys::socket::sockaddr_storage>::uninit(),
+                vec![0; control_len],
             ),
             None,
         );
@@ -590,6 +614,87 @@ impl Reactor {
         source
     }
 
+    pub(crate) fn fadvise(
+        &self,
+        raw: RawFd,
+        offset: u64,
+        len: u64,
+        advice: libc::c_int,
+    ) -> impl Future<Output = Source> {
+        let source = self.new_source(raw, SourceType::Fadvise, None);
+        let waiter = self.sys.fadvise(&source, offset, len, advice);
+
+        async move {
+            waiter.await;
+            source
+        }
+    }
+
+    pub(crate) fn sync_file_range(
+        &self,
+        raw: RawFd,
+        offset: u64,
+        len: u64,
+        flags: libc::c_uint,
+    ) -> impl Future<Output = Source> {
+        let source = self.new_source(raw, SourceType::SyncFileRange, None);
+        let waiter = self.sys.sync_file_range(&source, offset, len, flags);
+
+        async move {
+            waiter.await;
+            source
+        }
+    }
+
+    /// Reads the extended attribute `name` into a buffer of `len` bytes, which
+    /// can be taken back from the source. A `len` of zero asks for the size.
+    pub(crate) fn get_xattr(
+        &self,
+        raw: RawFd,
+        name: CString,
+        len: usize,
+    ) -> impl Future<Output = Source> {
+        let source = self.new_source(raw, SourceType::Xattr(name, vec![0; len]), None);
+        let waiter = self.sys.get_xattr(&source);
+
+        async move {
+            waiter.await;
+            source
+        }
+    }
+
+    pub(crate) fn set_xattr(
+        &self,
+        raw: RawFd,
+        name: CString,
+        value: Vec<u8>,
+        flags: libc::c_int,
+    ) -> impl Future<Output = Source> {
+        let source = self.new_source(raw, SourceType::Xattr(name, value), None);
+        let waiter = self.sys.set_xattr(&source, flags);
+
+        async move {
+            waiter.await;
+            source
+        }
+    }
+
+    /// Lists extended attribute names into a buffer of `len` bytes, which can
+    /// be taken back from the source. A `len` of zero asks for the size.
+    pub(crate) fn list_xattr(&self, raw: RawFd, len: usize) -> impl Future<Output = Source> {
+        let source = self.new_source(
+            raw,
+            SourceType::Xattr(CString::default(), vec![0; len]),
+            None,
+        );
+        let waiter = self.sys.list_xattr(&source);
+
+        async move {
+            waiter.await;
+            source
+        }
+    }
+
     pub(crate) fn truncate(&self, raw: RawFd, size: u64) -> impl Future<Output = Source> {
         let source = self.new_source(raw, SourceType::Truncate, None);
         let waiter = self.sys.truncate(&source, size);
@@ -628,6 +733,25 @@ impl Reactor {
         }
     }
 
+    pub(crate) fn link_at<P: AsRef<Path>>(
+        &self,
+        raw: RawFd,
+        dir: RawFd,
+        new_path: P,
+    ) -> impl Future<Output = Source> {
+        let source = self.new_source(
+            raw,
+            SourceType::Link(dir, new_path.as_ref().to_owned()),
+            None,
+        );
+        let waiter = self.sys.link_at(&source);
+
+        async move {
+            waiter.await;
+            source
+        }
+    }
+
     pub(crate) fn create_dir<P: AsRef<Path>>(
         &self,
         path: P,
@@ -759,6 +883,8 @@ impl Reactor {
         let mut channels = self.shared_channels.borrow_mut();
         let mut processed = channels.process_shared_channels();
         processed += self.sys.process_foreign_wakes();
+        // Frees wake nobody up, so they do not count as processed
+        self.sys.process_foreign_frees();
         processed
     }
 
@@ -823,6 +949,16 @@ impl Reactor {
     pub(crate) fn io_scheduler(&self) -> &Rc<IoScheduler> {
         &self.io_scheduler
     }
+
+    /// Returns how long `bytes` of file I/O, on a file limited by `file`, must
+    /// wait for the rate limiters that apply to it, and records that wait.
+    pub(crate) fn throttle(&self, file: Option<&IoRateLimiter>, bytes: u64) -> Duration {
+        let delay = self.io_scheduler.reserve(file, bytes);
+        if !delay.is_zero() {
+            self.sys.record_throttled(delay);
+        }
+        delay
+    }
 }
 
 impl fmt::Debug for Reactor {
@@ -58,12 +58,24 @@ macro_rules! c_str {
     };
 }
 
+/// Memory owned by the source a request belongs to. The pool keeps that source
+/// alive until the response is processed, so the thread can write into it.
+pub(super) struct SourceBuffer(pub(super) *mut u8, pub(super) usize);
+
+unsafe impl Send for SourceBuffer {}
+
 pub(super) enum BlockingThreadOp {
     Rename(PathBuf, PathBuf),
     Remove(PathBuf),
+    Link(RawFd, RawFd, PathBuf),
     CreateDir(PathBuf, libc::c_int),
     Truncate(RawFd, i64),
     CopyFileRange(RawFd, i64, RawFd, i64, usize),
+    Fadvise(RawFd, i64, i64, libc::c_int),
+    SyncFileRange(RawFd, i64, i64, libc::c_uint),
+    GetXattr(RawFd, CString, SourceBuffer),
+    SetXattr(RawFd, CString, SourceBuffer, libc::c_int),
+    ListXattr(RawFd, SourceBuffer),
     Fn(Box<dyn FnOnce() + Send + 'static>),
 }
 
@@ -72,6 +84,9 @@ impl Debug for BlockingThreadOp {
         match self {
             BlockingThreadOp::Rename(from, to) => write!(f, "rename `{from:?}` -> `{to:?}`"),
             BlockingThreadOp::Remove(path) => write!(f, "remove `{path:?}`"),
+            BlockingThreadOp::Link(fd, dir, path) => {
+                write!(f, "link `{fd}` -> `{dir}`/`{path:?}`")
+            }
             BlockingThreadOp::CreateDir(path, flags) => {
                 write!(f, "create dir `{path:?}` (`{flags:b}`)")
             }
@@ -80,6 +95,18 @@ impl Debug for BlockingThreadOp {
                 f,
                 "copy_file_range `{fd_in}` @ `{off_in}` -> {fd_out} @ `{off_out}` for {len} bytes"
             ),
+            BlockingThreadOp::Fadvise(fd, off, len, advice) => {
+                write!(f, "fadvise `{fd}` @ `{off}` for {len} bytes (`{advice}`)")
+            }
+            BlockingThreadOp::SyncFileRange(fd, off, len, flags) => write!(
+                f,
+                "sync_file_range `{fd}` @ `{off}` for {len} bytes (`{flags:b}`)"
+            ),
+            BlockingThreadOp::GetXattr(fd, name, _) => write!(f, "getxattr `{fd}` {name:?}"),
+            BlockingThreadOp::SetXattr(fd, name, _, flags) => {
+                write!(f, "setxattr `{fd}` {name:?} (`{flags:b}`)")
+            }
+            BlockingThreadOp::ListXattr(fd, _) => write!(f, "listxattr `{fd}`"),
             BlockingThreadOp::Fn(_) => write!(f, "user function"),
         }
     }
@@ -101,6 +128,19 @@ impl BlockingThreadOp {
                 let p = c_str!(&path);
                 raw_syscall!(unlink(p.as_ptr()))
             }
+            BlockingThreadOp::Link(fd, dir, path) => {
+                // Linking an fd with AT_EMPTY_PATH needs CAP_DAC_READ_SEARCH, so
+                // go through procfs as linkat(2) suggests for O_TMPFILE files.
+                let o = c_str!(Path::new(&format!("/proc/self/fd/{fd}")));
+                let n = c_str!(&path);
+                raw_syscall!(linkat(
+                    libc::AT_FDCWD,
+                    o.as_ptr(),
+                    dir,
+                    n.as_ptr(),
+                    libc::AT_SYMLINK_FOLLOW
+                ))
+            }
             BlockingThreadOp::Truncate(fd, sz) => {
                 raw_syscall!(ftruncate(fd, sz))
             }
@@ -114,6 +154,23 @@ impl BlockingThreadOp {
                     0
                 ))
             }
+            BlockingThreadOp::Fadvise(fd, off, len, advice) => {
+                // returns the error number instead of setting errno
+                let res = unsafe { libc::posix_fadvise(fd, off, len, advice) };
+                BlockingThreadResult::Syscall(-res as i64)
+            }
+            BlockingThreadOp::SyncFileRange(fd, off, len, flags) => {
+                raw_syscall!(sync_file_range(fd, off, len, flags))
+            }
+            BlockingThreadOp::GetXattr(fd, name, buf) => {
+                raw_syscall!(fgetxattr(fd, name.as_ptr(), buf.0 as _, buf.1))
+            }
+            BlockingThreadOp::SetXattr(fd, name, buf, flags) => {
+                raw_syscall!(fsetxattr(fd, name.as_ptr(), buf.0 as _, buf.1, flags))
+            }
+            BlockingThreadOp::ListXattr(fd, buf) => {
+                raw_syscall!(flistxattr(fd, buf.0 as _, buf.1))
+            }
             BlockingThreadOp::Fn(f) => {
                 f();
                 BlockingThreadResult::Fn
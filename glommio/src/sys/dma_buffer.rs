@@ -87,6 +87,11 @@ impl BufferStorage {
 ///
 /// The `DmaBuffer` is a buffer that adheres to those properties making it
 /// suitable for io_uring's Direct I/O.
+///
+/// It belongs to the executor that allocated it. To read it on another one
+/// without a copy, turn it into a [`ForeignBuffer`].
+///
+/// [`ForeignBuffer`]: crate::io::ForeignBuffer
 pub struct DmaBuffer {
     storage: BufferStorage,
     // Invariant: trim + size are at most one byte past the original allocation.
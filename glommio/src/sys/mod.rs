@@ -170,6 +170,152 @@ pub(crate) fn sendmsg_syscall(
     syscall!(sendmsg(fd, &hdr, flags)).map(|x| x as usize)
 }
 
+/// Like `recvmsg_syscall` for connected sockets, but also receiving control
+/// messages into `control`. Returns the size of the data and of the control
+/// messages received.
+pub(crate) fn recvmsg_control_syscall(
+    fd: RawFd,
+    buf: &mut [u8],
+    control: &mut [u8],
+    flags: i32,
+) -> io::Result<(usize, usize)> {
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+
+    let mut hdr = unsafe { std::mem::zeroed::<libc::msghdr>() };
+    hdr.msg_iov = &mut iov as *mut libc::iovec;
+    hdr.msg_iovlen = 1;
+    hdr.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    hdr.msg_controllen = control.len() as _;
+
+    let x = syscall!(recvmsg(fd, &mut hdr, flags)).map(|x| x as usize)?;
+    Ok((x, hdr.msg_controllen))
+}
+
+/// Like `sendmsg_syscall` for connected sockets, but also sending the
+/// control messages in `control`.
+pub(crate) fn sendmsg_control_syscall(
+    fd: RawFd,
+    buf: &[u8],
+    control: &[u8],
+    flags: i32,
+) -> io::Result<usize> {
+    let mut iov = libc::iovec {
+        iov_base: buf.as_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+
+    let mut hdr = unsafe { std::mem::zeroed::<libc::msghdr>() };
+    hdr.msg_iov = &mut iov as *mut libc::iovec;
+    hdr.msg_iovlen = 1;
+    hdr.msg_control = control.as_ptr() as *mut libc::c_void;
+    hdr.msg_controllen = control.len() as _;
+
+    syscall!(sendmsg(fd, &hdr, flags)).map(|x| x as usize)
+}
+
+// The size of a control message header, padding included: the data starts
+// right after it.
+const CMSG_HEADER_LEN: usize = unsafe { libc::CMSG_LEN(0) } as usize;
+
+/// Appends a control message to `control`, laid out the way `sendmsg`
+/// expects.
+pub(crate) fn push_control_message(control: &mut Vec<u8>, level: i32, kind: i32, data: &[u8]) {
+    let start = control.len();
+    let space = unsafe { libc::CMSG_SPACE(data.len() as u32) } as usize;
+    control.resize(start + space, 0);
+    let header = libc::cmsghdr {
+        cmsg_len: unsafe { libc::CMSG_LEN(data.len() as u32) } as _,
+        cmsg_level: level,
+        cmsg_type: kind,
+    };
+    // the buffer is only byte-aligned
+    unsafe {
+        std::ptr::write_unaligned(control[start..].as_mut_ptr() as *mut libc::cmsghdr, header);
+    }
+    control[start + CMSG_HEADER_LEN..start + CMSG_HEADER_LEN + data.len()].copy_from_slice(data);
+}
+
+/// Iterates over the control messages `recvmsg` wrote into `control`, as
+/// their level, type and data.
+pub(crate) fn control_messages(control: &[u8]) -> impl Iterator<Item = (i32, i32, &[u8])> {
+    let mut offset = 0;
+    std::iter::from_fn(move || {
+        if offset + CMSG_HEADER_LEN > control.len() {
+            return None;
+        }
+        let header =
+            unsafe { std::ptr::read_unaligned(control[offset..].as_ptr() as *const libc::cmsghdr) };
+        let len = header.cmsg_len;
+        if len < CMSG_HEADER_LEN || offset + len > control.len() {
+            return None;
+        }
+        let data = &control[offset + CMSG_HEADER_LEN..offset + len];
+        offset += unsafe { libc::CMSG_SPACE((len - CMSG_HEADER_LEN) as u32) } as usize;
+        Some((header.cmsg_level, header.cmsg_type, data))
+    })
+}
+
+pub(crate) fn setsockopt_bytes(fd: RawFd, level: i32, name: i32, value: &[u8]) -> io::Result<()> {
+    syscall!(setsockopt(
+        fd,
+        level,
+        name,
+        value.as_ptr() as *const libc::c_void,
+        value.len() as libc::socklen_t
+    ))?;
+    Ok(())
+}
+
+/// Reads a socket option into `value`, returning how many bytes the kernel
+/// wrote.
+pub(crate) fn getsockopt_bytes(
+    fd: RawFd,
+    level: i32,
+    name: i32,
+    value: &mut [u8],
+) -> io::Result<usize> {
+    let mut len = value.len() as libc::socklen_t;
+    syscall!(getsockopt(
+        fd,
+        level,
+        name,
+        value.as_mut_ptr() as *mut libc::c_void,
+        &mut len
+    ))?;
+    Ok(len as usize)
+}
+
+/// Sends the messages described by `msgs`, returning how many were sent.
+/// Each header's `msg_len` is set to the bytes sent for that message.
+pub(crate) fn sendmmsg_syscall(
+    fd: RawFd,
+    msgs: &mut [libc::mmsghdr],
+    flags: i32,
+) -> io::Result<usize> {
+    syscall!(sendmmsg(fd, msgs.as_mut_ptr(), msgs.len() as _, flags as _)).map(|x| x as usize)
+}
+
+/// Receives into the messages described by `msgs`, returning how many were
+/// received. Each header's `msg_len` is set to the bytes received for that
+/// message.
+pub(crate) fn recvmmsg_syscall(
+    fd: RawFd,
+    msgs: &mut [libc::mmsghdr],
+    flags: i32,
+) -> io::Result<usize> {
+    syscall!(recvmmsg(
+        fd,
+        msgs.as_mut_ptr(),
+        msgs.len() as _,
+        flags as _,
+        std::ptr::null_mut()
+    ))
+    .map(|x| x as usize)
+}
+
 mod dma_buffer;
 mod membarrier;
 pub(crate) use membarrier::initialize_strategy as initialize_membarrier_strategy;
@@ -263,6 +409,8 @@ pub(crate) struct SleepNotifier {
     should_notify: AtomicBool,
     foreign_wakes: crossbeam::channel::Receiver<Waker>,
     waker_sender: crossbeam::channel::Sender<Waker>,
+    foreign_frees: crossbeam::channel::Receiver<Parked>,
+    free_sender: crossbeam::channel::Sender<Parked>,
 }
 
 lazy_static! {
@@ -289,6 +437,7 @@ impl SleepNotifier {
     pub(crate) fn new(id: usize) -> io::Result<Arc<Self>> {
         let eventfd = unsafe { std::fs::File::from_raw_fd(create_eventfd()?) };
         let (waker_sender, foreign_wakes) = crossbeam::channel::unbounded();
+        let (free_sender, foreign_frees) = crossbeam::channel::unbounded();
 
         Ok(Arc::new(Self {
             eventfd: std::sync::Mutex::new(Some(eventfd)),
@@ -296,6 +445,8 @@ impl SleepNotifier {
             should_notify: AtomicBool::new(false),
             waker_sender,
             foreign_wakes,
+            free_sender,
+            foreign_frees,
         }))
     }
 
@@ -311,7 +462,10 @@ impl SleepNotifier {
         self.id
     }
 
-    pub(crate) fn notify(&self, force: bool) {
+    /// Wakes the executor up if it is asleep, or regardless with `force`.
+    ///
+    /// Returns whether the eventfd was written to.
+    pub(crate) fn notify(&self, force: bool) -> bool {
         if self
             .should_notify
             .compare_exchange(true, false, Ordering::Relaxed, Ordering::Relaxed)
@@ -322,9 +476,11 @@ impl SleepNotifier {
             if fd >= 0 {
                 // Only write if eventfd is still open
                 write_eventfd(fd);
+                return true;
             }
             // Silently ignore if closed - executor is shutting down
         }
+        false
     }
 
     /// Explicitly closes the eventfd file descriptor.
@@ -369,6 +525,22 @@ impl SleepNotifier {
         processed
     }
 
+    /// Hands a value back to this executor to be dropped there.
+    pub(crate) fn queue_free(&self, parked: Parked) {
+        // The receiver lives as long as `self`, so this cannot fail
+        let _ = self.free_sender.send(parked);
+        self.notify(false);
+    }
+
+    pub(crate) fn process_foreign_frees(&self) -> usize {
+        let mut processed = 0;
+        while let Ok(parked) = self.foreign_frees.try_recv() {
+            processed += 1;
+            drop(parked);
+        }
+        processed
+    }
+
     pub(super) fn prepare_to_sleep(&self) {
         // This will allow this `eventfd` to be notified. This should not happen
         // for the placeholder (disconnected) case.
@@ -392,6 +564,59 @@ impl Drop for SleepNotifier {
     }
 }
 
+/// A value that must be dropped on the executor it was created on, boxed so
+/// that it can travel to other executors in the meantime.
+///
+/// Dropping it elsewhere queues it back to its executor, and leaks it if that
+/// executor is gone.
+pub(crate) struct Parked {
+    value: ManuallyDrop<Box<dyn std::any::Any>>,
+    executor_id: usize,
+}
+
+// SAFETY: the value is never touched away from its executor: it is dropped
+// there, or leaked
+unsafe impl Send for Parked {}
+unsafe impl Sync for Parked {}
+
+impl Parked {
+    pub(crate) fn new(value: Box<dyn std::any::Any>) -> Parked {
+        Parked {
+            value: ManuallyDrop::new(value),
+            executor_id: crate::executor().id(),
+        }
+    }
+
+    pub(crate) fn executor_id(&self) -> usize {
+        self.executor_id
+    }
+}
+
+impl fmt::Debug for Parked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Parked")
+            .field("executor_id", &self.executor_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for Parked {
+    fn drop(&mut self) {
+        // SAFETY: `value` is not used again
+        let value = unsafe { ManuallyDrop::take(&mut self.value) };
+        if crate::executor::executor_id() == Some(self.executor_id) {
+            drop(value);
+        } else if let Some(notifier) = get_sleep_notifier_for(self.executor_id) {
+            notifier.queue_free(Parked {
+                value: ManuallyDrop::new(value),
+                executor_id: self.executor_id,
+            });
+        } else {
+            std::mem::forget(value);
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(crate) enum DmaSource {
     Owned(DmaBuffer),
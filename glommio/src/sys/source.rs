@@ -9,7 +9,7 @@ use crate::{
         DmaBuffer, IoBuffer, OsResult, PollableStatus, ReactorQueue, SourceId, Statx, TimeSpec64,
         Wakers,
     },
-    GlommioError, IoRequirements, ReactorErrorKind, RingIoStats, TaskQueueHandle,
+    GlommioError, IoPriorityClass, IoRequirements, ReactorErrorKind, RingIoStats, TaskQueueHandle,
 };
 use futures_lite::{future, io};
 use std::{
@@ -35,21 +35,31 @@ pub(crate) enum SourceType {
     PollAdd,
     SockSend(DmaBuffer),
     SockRecv(Option<DmaBuffer>),
+    /// The last field receives control messages, if it is not empty.
     SockRecvMsg(
         Option<DmaBuffer>,
         libc::iovec,
         libc::msghdr,
         MaybeUninit<nix::sys::socket::sockaddr_storage>,
+        Vec<u8>,
     ),
+    /// The address is `None` on connected sockets, and the control messages
+    /// are sent if not empty.
     SockSendMsg(
         DmaBuffer,
         libc::iovec,
         libc::msghdr,
-        nix::sys::socket::SockaddrStorage,
+        Option<nix::sys::socket::SockaddrStorage>,
+        Vec<u8>,
     ),
     Open(CString),
     FdataSync,
     Fallocate,
+    Fadvise,
+    SyncFileRange,
+    /// The attribute name and the value: read into for gets and lists, written
+    /// from for sets.
+    Xattr(CString, Vec<u8>),
     Truncate,
     Close,
     LinkRings,
@@ -61,6 +71,7 @@ pub(crate) enum SourceType {
     Rename(PathBuf, PathBuf),
     CreateDir(PathBuf),
     Remove(PathBuf),
+    Link(RawFd, PathBuf),
     BlockingFn,
     Invalid,
     CopyFileRange(RawFd, u64, usize),
@@ -189,6 +200,14 @@ impl Source {
         old.map(Duration::from)
     }
 
+    pub(super) fn is_realtime_io(&self) -> bool {
+        self.inner
+            .borrow()
+            .io_requirements
+            .io_priority()
+            .is_some_and(|prio| prio.class() == IoPriorityClass::Realtime)
+    }
+
     pub(super) fn timeout_ref(&self) -> Ref<'_, Option<TimeSpec64>> {
         Ref::map(self.inner.borrow(), |x| &x.timeout)
     }
@@ -238,22 +257,21 @@ impl Source {
         // if there is a scheduler latency collection function present, invoke it once
         if let Some(Some(stat_fn)) = inner.stats_collection.as_ref().map(|x| x.latency) {
             if let Some(lat) = inner.wakers.timestamps() {
+                let class = inner.io_requirements.io_priority().map(|prio| prio.class());
                 drop(inner);
                 let pre_lat = lat.submitted_at - lat.queued_at;
                 let io_lat = lat.fulfilled_at - lat.submitted_at;
                 let post_lat = lat.fulfilled_at.elapsed();
 
+                let record = |stats: &mut RingIoStats| {
+                    (stat_fn)(pre_lat, io_lat, post_lat, stats);
+                    if let Some(class) = class {
+                        stats.io_latency_by_class_us[class.index()].add(io_lat.as_micros() as f64);
+                    }
+                };
                 let reactor = &crate::executor().reactor().sys;
-                (stat_fn)(
-                    pre_lat,
-                    io_lat,
-                    post_lat,
-                    reactor.ring_for_source(self).io_stats_mut(),
-                );
-                (stat_fn)(
-                    pre_lat,
-                    io_lat,
-                    post_lat,
+                record(reactor.ring_for_source(self).io_stats_mut());
+                record(
                     reactor
                         .ring_for_source(self)
                         .io_stats_for_task_queue_mut(crate::executor().current_task_queue()),
@@ -14,6 +14,7 @@ use std::{
     cell::{Cell, Ref, RefCell, RefMut},
     collections::VecDeque,
     convert::TryFrom,
+    ffi::CStr,
     fmt,
     future::Future,
     io,
@@ -33,7 +34,7 @@ use crate::{
     free_list::{FreeList, Idx},
     sys::{
         self,
-        blocking::{BlockingThreadOp, BlockingThreadPool},
+        blocking::{BlockingThreadOp, BlockingThreadPool, SourceBuffer},
         dma_buffer::{BufferStorage, DmaBuffer},
         membarrier, DirectIo, EnqueuedSource, EnqueuedStatus, InnerSource, IoBuffer,
         PollableStatus, SockAddrStorage, Source, SourceType, Statx, TimeSpec64,
@@ -64,6 +65,10 @@ enum UringOpDescriptor {
     LinkTimeout(*const crate::sys::KernelTimespec),
     Accept(*mut SockAddrStorage),
     Fallocate(u64, u64, libc::c_int),
+    Fadvise(u64, i64, libc::c_int),
+    SyncFileRange(u64, u32, u32),
+    FGetXattr(*const libc::c_char, *mut u8, u32),
+    FSetXattr(*const libc::c_char, *const u8, u32, libc::c_int),
     StatxFd(RawFd, *mut Statx),
     Timeout(*const crate::sys::KernelTimespec, u32),
     TimeoutRemove(u64),
@@ -79,6 +84,8 @@ pub(crate) struct UringDescriptor {
     fd: RawFd,
     flags: squeue::Flags,
     user_data: u64,
+    // Only applied to file reads and writes; see `IoPriority`.
+    ioprio: u16,
     args: UringOpDescriptor,
 }
 
@@ -225,6 +232,16 @@ static GLOMMIO_URING_OPS: &[(&str, u8)] = &[
     ("ASYNC_CANCEL", io_uring::opcode::AsyncCancel::CODE),
 ];
 
+/// Opcodes glommio submits only if the running kernel has them, running the
+/// equivalent syscall on the blocking thread pool otherwise. Unlike the ones in
+/// [`GLOMMIO_URING_OPS`], a kernel missing them is still supported.
+static OPTIONAL_URING_OPS: &[(&str, u8)] = &[
+    ("FADVISE", io_uring::opcode::Fadvise::CODE),
+    ("SYNC_FILE_RANGE", io_uring::opcode::SyncFileRange::CODE),
+    ("FGETXATTR", io_uring::opcode::FGetXattr::CODE),
+    ("FSETXATTR", io_uring::opcode::FSetXattr::CODE),
+];
+
 /// Why this kernel cannot run glommio.
 #[derive(Debug)]
 pub(crate) enum UringUnsupported {
@@ -327,9 +344,33 @@ fn check_supported_operations(ops: &[(&'static str, u8)]) -> Result<(), UringUns
     }
 }
 
+/// Returns which of `ops` the running kernel implements. Any failure to
+/// probe is reported by [`check_supported_operations`], so here it just means
+/// none of them are.
+fn probe_optional_operations(ops: &[(&'static str, u8)]) -> Vec<u8> {
+    let mut probe = io_uring::Probe::new();
+    let probed =
+        io_uring::IoUring::new(1).and_then(|ring| ring.submitter().register_probe(&mut probe));
+    match probed {
+        Ok(()) => ops
+            .iter()
+            .map(|(_, opcode)| *opcode)
+            .filter(|opcode| probe.is_supported(*opcode))
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
 lazy_static! {
     static ref IO_URING_SUPPORT: Result<(), String> =
         check_supported_operations(GLOMMIO_URING_OPS).map_err(|reason| reason.to_string());
+    static ref OPTIONAL_URING_SUPPORT: Vec<u8> = probe_optional_operations(OPTIONAL_URING_OPS);
+}
+
+/// Whether `opcode`, one of [`OPTIONAL_URING_OPS`], can be submitted to the
+/// ring. Probed once per process.
+fn optional_op_supported(opcode: u8) -> bool {
+    OPTIONAL_URING_SUPPORT.contains(&opcode)
 }
 
 /// Returns `Err` with a description of what is wrong if this kernel cannot run
@@ -374,9 +415,10 @@ where
                 user_data = 0;
                 opcode::AsyncCancel::new(to_remove).build()
             }
-            UringOpDescriptor::Write(ptr, len, pos) => {
-                opcode::Write::new(fd, ptr, len as u32).offset(pos).build()
-            }
+            UringOpDescriptor::Write(ptr, len, pos) => opcode::Write::new(fd, ptr, len as u32)
+                .offset(pos)
+                .ioprio(op.ioprio)
+                .build(),
             UringOpDescriptor::Read(pos, len) => {
                 source_map.peek_source_mut(from_user_data(op.user_data), |mut x| {
                     match &mut x.source_type {
@@ -389,6 +431,7 @@ where
                             let mut buf = buffer_allocation(len).expect("Buffer allocation failed");
                             let entry = opcode::Read::new(fd, buf.as_mut_ptr(), len as u32)
                                 .offset(pos)
+                                .ioprio(op.ioprio)
                                 .build();
                             // If you have a buffer here, that very likely means you are reusing the
                             // source. The kernel knows about that buffer already, and will write to
@@ -430,6 +473,23 @@ where
                 .offset(offset)
                 .mode(flags)
                 .build(),
+            UringOpDescriptor::Fadvise(offset, len, advice) => {
+                opcode::Fadvise::new(fd, len, advice).offset(offset).build()
+            }
+            UringOpDescriptor::SyncFileRange(offset, len, flags) => {
+                opcode::SyncFileRange::new(fd, len)
+                    .offset(offset)
+                    .flags(flags)
+                    .build()
+            }
+            UringOpDescriptor::FGetXattr(name, value, len) => {
+                opcode::FGetXattr::new(fd, name, value as *mut libc::c_void, len).build()
+            }
+            UringOpDescriptor::FSetXattr(name, value, len, flags) => {
+                opcode::FSetXattr::new(fd, name, value as *const libc::c_void, len)
+                    .flags(flags)
+                    .build()
+            }
             UringOpDescriptor::StatxFd(statx_fd, statx_buf) => {
                 const EMPTY_PATH: &[u8] = b"\0";
                 // The libc crate does not define this one for musl targets.
@@ -461,6 +521,7 @@ where
                         SourceType::Read(PollableStatus::NonPollable(DirectIo::Disabled), slot) => {
                             let entry = opcode::Read::new(fd, buf.as_mut_ptr(), len as u32)
                                 .offset(pos)
+                                .ioprio(op.ioprio)
                                 .build();
                             *slot = Some(IoBuffer::DmaSink(buf));
                             entry
@@ -469,6 +530,7 @@ where
                             let entry = match buf.uring_buffer_id() {
                                 None => opcode::Read::new(fd, buf.as_mut_ptr(), len as u32)
                                     .offset(pos)
+                                    .ioprio(op.ioprio)
                                     .build(),
                                 Some(idx) => opcode::ReadFixed::new(
                                     fd,
@@ -477,6 +539,7 @@ where
                                     idx as u16,
                                 )
                                 .offset(pos)
+                                .ioprio(op.ioprio)
                                 .build(),
                             };
                             *slot = Some(IoBuffer::DmaSink(buf));
@@ -489,6 +552,7 @@ where
             UringOpDescriptor::WriteFixed(ptr, len, pos, buf_index) => {
                 opcode::WriteFixed::new(fd, ptr, len as u32, buf_index as u16)
                     .offset(pos)
+                    .ioprio(op.ioprio)
                     .build()
             }
             UringOpDescriptor::SockSend(ptr, len, flags) => opcode::Send::new(fd, ptr, len as u32)
@@ -518,7 +582,7 @@ where
                 let mut buf = DmaBuffer::new(len).expect("failed to allocate buffer");
                 source_map.peek_source_mut(from_user_data(op.user_data), |mut src| {
                     match &mut src.source_type {
-                        SourceType::SockRecvMsg(slot, iov, hdr, msg_name) => {
+                        SourceType::SockRecvMsg(slot, iov, hdr, msg_name, control) => {
                             iov.iov_base = buf.as_mut_ptr() as *mut libc::c_void;
                             iov.iov_len = len;
 
@@ -529,6 +593,10 @@ where
                             hdr.msg_namelen = msg_namelen;
                             hdr.msg_iov = iov as *mut libc::iovec;
                             hdr.msg_iovlen = 1;
+                            if !control.is_empty() {
+                                hdr.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+                                hdr.msg_controllen = control.len() as _;
+                            }
 
                             let entry = opcode::RecvMsg::new(fd, hdr as *mut libc::msghdr)
                                 .flags(flags as u32)
@@ -789,6 +857,7 @@ impl UringQueueState {
             fd: -1,
             flags: squeue::Flags::empty(),
             user_data: 0,
+            ioprio: 0,
         });
     }
 }
@@ -1095,6 +1164,7 @@ impl SleepableRing {
                         .borrow_mut()
                         .add_source(&source, self.submission_queue.clone()),
                 ),
+                ioprio: 0,
             });
         source
     }
@@ -1119,6 +1189,7 @@ impl SleepableRing {
             flags: squeue::Flags::empty(),
             user_data: 0,
             args: UringOpDescriptor::Write(EVENTFD_WAKEUP as *const u64 as _, 8, 0),
+            ioprio: 0,
         };
 
         let op = match &*timer_source.source_type() {
@@ -1138,6 +1209,7 @@ impl SleepableRing {
                     .borrow_mut()
                     .add_source(&timer_source, queue.clone()),
             ),
+            ioprio: 0,
         });
         timer_source
     }
@@ -1155,6 +1227,7 @@ impl SleepableRing {
                         .add_source(eventfd_src, self.submission_queue.clone()),
                 ),
                 args: UringOpDescriptor::Read(0, 8),
+                ioprio: 0,
             };
 
             let buffer_ptr = {
@@ -1212,6 +1285,7 @@ impl SleepableRing {
                         .add_source(link, self.submission_queue.clone()),
                 ),
                 args: UringOpDescriptor::PollAdd(common_flags() | read_flags()),
+                ioprio: 0,
             };
             let entry = fill_sqe(&op, DmaBuffer::new, &mut self.source_map.borrow_mut());
             // SAFETY: the poll targets the latency ring's fd, which outlives
@@ -1534,6 +1608,10 @@ impl Reactor {
         self.notifier.process_foreign_wakes()
     }
 
+    pub(crate) fn process_foreign_frees(&self) -> usize {
+        self.notifier.process_foreign_frees()
+    }
+
     pub(crate) fn alloc_dma_buffer(&self, size: usize) -> DmaBuffer {
         let mut poll_ring = self.poll_ring.borrow_mut();
         poll_ring.alloc_dma_buffer(size)
@@ -1622,14 +1700,17 @@ impl Reactor {
 
     pub(crate) fn sendmsg(&self, source: &Source, flags: MsgFlags) {
         let op = match &mut *source.source_type_mut() {
-            SourceType::SockSendMsg(_, iov, hdr, addr) => {
-                let msg_name = addr.as_ptr() as *mut libc::c_void;
-                let msg_namelen = addr.len();
-
+            SourceType::SockSendMsg(_, iov, hdr, addr, control) => {
                 hdr.msg_iov = iov as *mut libc::iovec;
                 hdr.msg_iovlen = 1;
-                hdr.msg_name = msg_name;
-                hdr.msg_namelen = msg_namelen;
+                if let Some(addr) = addr {
+                    hdr.msg_name = addr.as_ptr() as *mut libc::c_void;
+                    hdr.msg_namelen = addr.len();
+                }
+                if !control.is_empty() {
+                    hdr.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+                    hdr.msg_controllen = control.len() as _;
+                }
 
                 UringOpDescriptor::SockSendMsg(hdr, flags.bits())
             }
@@ -1708,6 +1789,108 @@ impl Reactor {
         );
     }
 
+    /// Submits `op` to the ring if `use_ring`, or runs `fallback` on the
+    /// blocking thread pool otherwise.
+    fn queue_request_or_blocking(
+        &self,
+        source: &Source,
+        use_ring: bool,
+        op: UringOpDescriptor,
+        fallback: BlockingThreadOp,
+    ) -> impl Future<Output = ()> {
+        let waiter = if use_ring {
+            queue_request_into_ring(
+                &mut *self.ring_for_source(source),
+                source,
+                op,
+                &mut self.source_map.borrow_mut(),
+            );
+            None
+        } else {
+            Some(self.enqueue_blocking_request(source.inner.clone(), fallback))
+        };
+        async move {
+            if let Some(waiter) = waiter {
+                waiter.await
+            }
+        }
+    }
+
+    pub(crate) fn fadvise(
+        &self,
+        source: &Source,
+        offset: u64,
+        len: u64,
+        advice: libc::c_int,
+    ) -> impl Future<Output = ()> {
+        // The ring keeps only 32 bits of the length, so larger ranges go to
+        // the pool
+        let use_ring = optional_op_supported(opcode::Fadvise::CODE) && u32::try_from(len).is_ok();
+        let op = UringOpDescriptor::Fadvise(offset, len as _, advice);
+        let fallback = BlockingThreadOp::Fadvise(source.raw(), offset as _, len as _, advice);
+        self.queue_request_or_blocking(source, use_ring, op, fallback)
+    }
+
+    pub(crate) fn sync_file_range(
+        &self,
+        source: &Source,
+        offset: u64,
+        len: u64,
+        flags: libc::c_uint,
+    ) -> impl Future<Output = ()> {
+        // The ring takes a 32-bit length, so larger ranges go to the pool
+        let use_ring =
+            optional_op_supported(opcode::SyncFileRange::CODE) && u32::try_from(len).is_ok();
+        let op = UringOpDescriptor::SyncFileRange(offset, len as u32, flags);
+        let fallback = BlockingThreadOp::SyncFileRange(source.raw(), offset as _, len as _, flags);
+        self.queue_request_or_blocking(source, use_ring, op, fallback)
+    }
+
+    pub(crate) fn get_xattr(&self, source: &Source) -> impl Future<Output = ()> {
+        let (name, value, len) = match &mut *source.source_type_mut() {
+            SourceType::Xattr(name, value) => (name.as_ptr(), value.as_mut_ptr(), value.len()),
+            _ => panic!("Unexpected source for getxattr operation"),
+        };
+        let op = UringOpDescriptor::FGetXattr(name, value, len as u32);
+        let fallback = BlockingThreadOp::GetXattr(
+            source.raw(),
+            unsafe { CStr::from_ptr(name) }.to_owned(),
+            SourceBuffer(value, len),
+        );
+        let use_ring = optional_op_supported(opcode::FGetXattr::CODE);
+        self.queue_request_or_blocking(source, use_ring, op, fallback)
+    }
+
+    pub(crate) fn set_xattr(
+        &self,
+        source: &Source,
+        flags: libc::c_int,
+    ) -> impl Future<Output = ()> {
+        let (name, value, len) = match &mut *source.source_type_mut() {
+            SourceType::Xattr(name, value) => (name.as_ptr(), value.as_mut_ptr(), value.len()),
+            _ => panic!("Unexpected source for setxattr operation"),
+        };
+        let op = UringOpDescriptor::FSetXattr(name, value, len as u32, flags);
+        let fallback = BlockingThreadOp::SetXattr(
+            source.raw(),
+            unsafe { CStr::from_ptr(name) }.to_owned(),
+            SourceBuffer(value, len),
+            flags,
+        );
+        let use_ring = optional_op_supported(opcode::FSetXattr::CODE);
+        self.queue_request_or_blocking(source, use_ring, op, fallback)
+    }
+
+    pub(crate) fn list_xattr(&self, source: &Source) -> impl Future<Output = ()> {
+        let buffer = match &mut *source.source_type_mut() {
+            SourceType::Xattr(_, value) => SourceBuffer(value.as_mut_ptr(), value.len()),
+            _ => panic!("Unexpected source for listxattr operation"),
+        };
+        // there is no io_uring opcode for this one
+        let op = BlockingThreadOp::ListXattr(source.raw(), buffer);
+        self.enqueue_blocking_request(source.inner.clone(), op)
+    }
+
     fn enqueue_blocking_request(
         &self,
         source: Pin<Rc<RefCell<InnerSource>>>,
@@ -1771,6 +1954,16 @@ impl Reactor {
         self.enqueue_blocking_request(source.inner.clone(), op)
     }
 
+    pub(crate) fn link_at(&self, source: &Source) -> impl Future<Output = ()> {
+        let (dir, path) = match &*source.source_type() {
+            SourceType::Link(dir, path) => (*dir, path.clone()),
+            _ => panic!("Unexpected source for link operation"),
+        };
+
+        let op = BlockingThreadOp::Link(source.raw(), dir, path);
+        self.enqueue_blocking_request(source.inner.clone(), op)
+    }
+
     pub(crate) fn run_blocking(
         &self,
         source: &Source,
@@ -2078,13 +2271,15 @@ impl Reactor {
             fd,
             flags: squeue::Flags::empty(),
             user_data: 0,
+            ioprio: 0,
         });
     }
 
     pub(crate) fn ring_for_source(&self, source: &Source) -> RefMut<'_, dyn UringCommon> {
         // Dispatch requests according to the following rules:
         // * Disk reads/writes go to the poll ring if possible, or the main ring
-        //   otherwise;
+        //   otherwise, unless they are in the realtime I/O priority class, in
+        //   which case they go to the latency ring;
         // * Network Rx and connect/accept go the latency ring;
         // * Every other request are dispatched to the main ring;
         // We avoid putting requests that come in high numbers on the latency ring
@@ -2093,10 +2288,13 @@ impl Reactor {
         match &*source.source_type() {
             SourceType::Read(p, _) | SourceType::Write(p, _) => match p {
                 PollableStatus::Pollable => self.poll_ring.borrow_mut(),
+                PollableStatus::NonPollable(_) if source.is_realtime_io() => {
+                    self.latency_ring.borrow_mut()
+                }
                 PollableStatus::NonPollable(_) => self.main_ring.borrow_mut(),
             },
             SourceType::SockRecv(_)
-            | SourceType::SockRecvMsg(_, _, _, _)
+            | SourceType::SockRecvMsg(..)
             | SourceType::Accept(_)
             | SourceType::Connect(_) => self.latency_ring.borrow_mut(),
             SourceType::Invalid => {
@@ -2106,6 +2304,31 @@ impl Reactor {
         }
     }
 
+    // Mapped reads never become sources, so they are accounted to the main
+    // ring by hand.
+    pub(crate) fn record_mapped_read(&self, bytes: usize, fault_time: Duration) {
+        let record = |stats: &mut RingIoStats| {
+            stats.file_mapped_reads += 1;
+            stats.file_mapped_bytes_read += bytes as u64;
+            stats
+                .mapped_prefault_latency_us
+                .add(fault_time.as_micros() as f64);
+        };
+        let mut ring = self.main_ring.borrow_mut();
+        record(ring.io_stats_mut());
+        record(ring.io_stats_for_task_queue_mut(crate::executor().current_task_queue()));
+    }
+
+    pub(crate) fn record_throttled(&self, delay: Duration) {
+        let record = |stats: &mut RingIoStats| {
+            stats.io_throttled += 1;
+            stats.io_throttled_us += delay.as_micros() as u64;
+        };
+        let mut ring = self.main_ring.borrow_mut();
+        record(ring.io_stats_mut());
+        record(ring.io_stats_for_task_queue_mut(crate::executor().current_task_queue()));
+    }
+
     pub fn io_stats(&self) -> IoStats {
         IoStats::new(
             std::mem::take(&mut self.main_ring.borrow_mut().stats),
@@ -2167,6 +2390,7 @@ fn queue_request_into_ring(
         fd: source.raw(),
         flags,
         user_data: to_user_data(id),
+        ioprio: source.inner.borrow().io_requirements.ioprio(),
     });
 
     if let Some(ref ts) = &*source.timeout_ref() {
@@ -2175,6 +2399,7 @@ fn queue_request_into_ring(
             flags: squeue::Flags::empty(),
             fd: -1,
             user_data: 0,
+            ioprio: 0,
         });
     }
 }
@@ -2224,11 +2449,18 @@ mod tests {
             opcode::Send::CODE,
             opcode::Recv::CODE,
             opcode::AsyncCancel::CODE,
+            opcode::Fadvise::CODE,
+            opcode::SyncFileRange::CODE,
+            opcode::FGetXattr::CODE,
+            opcode::FSetXattr::CODE,
         ];
 
         for code in submitted {
             assert!(
-                GLOMMIO_URING_OPS.iter().any(|(_, probed)| *probed == code),
+                GLOMMIO_URING_OPS
+                    .iter()
+                    .chain(OPTIONAL_URING_OPS)
+                    .any(|(_, probed)| *probed == code),
                 "opcode {code} is submitted but never probed"
             );
         }
@@ -2388,6 +2620,7 @@ mod tests {
                     squeue::Flags::empty()
                 },
                 user_data: 0,
+                ioprio: 0,
             });
         }
 
@@ -2416,6 +2649,7 @@ mod tests {
             fd: -1,
             flags: squeue::Flags::IO_LINK,
             user_data: 0,
+            ioprio: 0,
         });
 
         // If the link chain points outside of the queue, we panic
@@ -2437,6 +2671,7 @@ mod tests {
                 fd: -1,
                 flags: squeue::Flags::IO_LINK,
                 user_data: 0,
+                ioprio: 0,
             });
         }
 
@@ -2445,6 +2680,7 @@ mod tests {
             fd: -1,
             flags: squeue::Flags::empty(),
             user_data: 0,
+            ioprio: 0,
         });
 
         // If the link chain is longer than the io_uring submission queue, we panic
@@ -50,6 +50,16 @@ impl WakerList {
         self.0.push(waker);
     }
 
+    /// Registers `waker` unless one that wakes the same task already is.
+    ///
+    /// For a waiter polled again before it was woken, such as a future raced
+    /// against others, which would otherwise add a waker on every poll.
+    pub(crate) fn register(&mut self, waker: &Waker) {
+        if !self.0.iter().any(|w| w.will_wake(waker)) {
+            self.0.push(waker.clone());
+        }
+    }
+
     /// Takes every waker, handing back the obligation to wake them.
     ///
     /// Call this while holding whatever lock guards the list, then release the
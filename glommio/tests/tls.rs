@@ -0,0 +1,229 @@
+#![cfg(feature = "rustls")]
+//! TLS over loopback with a self-signed certificate generated per test.
+
+use futures_lite::{AsyncReadExt, AsyncWriteExt};
+use glommio::{
+    net::{TcpListener, TcpStream, TlsAcceptor, TlsConnector},
+    spawn_local, LocalExecutor,
+};
+use rustls::{pki_types::PrivateKeyDer, ClientConfig, RootCertStore, ServerConfig};
+use std::{io, net::Shutdown, sync::Arc};
+
+// A server config presenting a certificate for `localhost`, and a client
+// config trusting it.
+fn configs() -> (ServerConfig, ClientConfig) {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let cert = certified.cert.der().clone();
+    let key = PrivateKeyDer::Pkcs8(certified.signing_key.serialize_der().into());
+
+    let server = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert.clone()], key)
+        .unwrap();
+    let mut roots = RootCertStore::empty();
+    roots.add(cert).unwrap();
+    let client = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    (server, client)
+}
+
+// Accepts `connections` connections and echoes each one until the client
+// closes it.
+fn echo_server(acceptor: TlsAcceptor, listener: TcpListener, connections: usize) {
+    spawn_local(async move {
+        for _ in 0..connections {
+            let stream = listener.accept().await.unwrap().buffered();
+            let mut stream = acceptor.accept(stream).await.unwrap();
+            let mut buf = vec![0; 4096];
+            loop {
+                let read = stream.read(&mut buf).await.unwrap();
+                if read == 0 {
+                    break;
+                }
+                stream.write_all(&buf[..read]).await.unwrap();
+            }
+            // the client may be gone already
+            let _ = stream.close().await;
+        }
+    })
+    .detach();
+}
+
+#[test]
+fn echo_with_alpn_and_sni() {
+    LocalExecutor::default().run(async {
+        let (mut server, mut client) = configs();
+        server.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        client.alpn_protocols = vec![b"http/1.1".to_vec()];
+        let acceptor = TlsAcceptor::new(Arc::new(server));
+        let connector = TlsConnector::new(Arc::new(client));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = spawn_local(async move {
+            let stream = listener.accept().await.unwrap();
+            let mut stream = acceptor.accept(stream).await.unwrap();
+            let peer_addr = stream.peer_addr().unwrap();
+            assert_eq!(stream.server_name(), Some("localhost"));
+            assert_eq!(stream.alpn_protocol(), Some(&b"http/1.1"[..]));
+            let mut request = String::new();
+            stream.read_to_string(&mut request).await.unwrap();
+            assert_eq!(request, "ping");
+            stream.write_all(b"pong").await.unwrap();
+            stream.close().await.unwrap();
+            peer_addr
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let local_addr = stream.local_addr().unwrap();
+        let mut stream = connector.connect("localhost", stream).await.unwrap();
+        assert_eq!(stream.alpn_protocol(), Some(&b"http/1.1"[..]));
+        assert_eq!(stream.server_name(), None);
+        assert_eq!(stream.peer_addr().unwrap(), addr);
+        stream.write_all(b"ping").await.unwrap();
+        stream.shutdown(Shutdown::Write).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert_eq!(response, "pong");
+        assert_eq!(server.await, local_addr);
+    });
+}
+
+#[test]
+fn large_transfer_over_buffered_streams() {
+    LocalExecutor::default().run(async {
+        let (server, client) = configs();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        echo_server(TlsAcceptor::new(Arc::new(server)), listener, 1);
+
+        let connector = TlsConnector::new(Arc::new(client));
+        let stream = TcpStream::connect(addr).await.unwrap().buffered();
+        let stream = connector.connect("localhost", stream).await.unwrap();
+        let (mut reader, mut writer) = futures_lite::io::split(stream);
+
+        let data: Vec<u8> = (0..4 << 20).map(|i| (i % 251) as u8).collect();
+        let expected = data.clone();
+        let writer = spawn_local(async move {
+            writer.write_all(&data).await.unwrap();
+            writer.close().await.unwrap();
+        });
+        let mut echoed = Vec::new();
+        reader.read_to_end(&mut echoed).await.unwrap();
+        writer.await;
+        assert!(echoed == expected);
+    });
+}
+
+#[test]
+fn resumes_sessions() {
+    LocalExecutor::default().run(async {
+        let (server, client) = configs();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        echo_server(TlsAcceptor::new(Arc::new(server)), listener, 2);
+
+        let connector = TlsConnector::new(Arc::new(client));
+        for resumed in [false, true] {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let mut stream = connector.connect("localhost", stream).await.unwrap();
+            assert_eq!(stream.is_resumed(), resumed);
+            // a round trip makes the client read the tickets the server sent
+            // after the handshake
+            stream.write_all(b"hello").await.unwrap();
+            let mut buf = [0; 5];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"hello");
+            stream.close().await.unwrap();
+        }
+    });
+}
+
+#[test]
+fn untrusted_certificate() {
+    LocalExecutor::default().run(async {
+        let (server, _) = configs();
+        let acceptor = TlsAcceptor::new(Arc::new(server));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = spawn_local(async move {
+            let stream = listener.accept().await.unwrap();
+            acceptor.accept(stream).await.is_err()
+        });
+
+        // trusts a different certificate for the same name
+        let (_, client) = configs();
+        let connector = TlsConnector::new(Arc::new(client));
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let err = connector.connect("localhost", stream).await.unwrap_err();
+        assert_eq!(io::Error::from(err).kind(), io::ErrorKind::InvalidData);
+        // the server is told why with an alert
+        assert!(server.await);
+    });
+}
+
+#[test]
+fn truncation_is_an_error() {
+    LocalExecutor::default().run(async {
+        let (server, client) = configs();
+        let acceptor = TlsAcceptor::new(Arc::new(server));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        spawn_local(async move {
+            let stream = listener.accept().await.unwrap();
+            let mut stream = acceptor.accept(stream).await.unwrap();
+            stream.write_all(b"partial").await.unwrap();
+            stream.flush().await.unwrap();
+            // closes the TCP connection without a close_notify
+            let (stream, _) = stream.into_inner();
+            stream.shutdown(Shutdown::Both).await.unwrap();
+        })
+        .detach();
+
+        let connector = TlsConnector::new(Arc::new(client));
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut stream = connector.connect("localhost", stream).await.unwrap();
+        let mut buf = Vec::new();
+        let err = stream.read_to_end(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(buf, b"partial");
+    });
+}
+
+#[test]
+fn into_ktls_after_the_handshake() {
+    LocalExecutor::default().run(async {
+        let (mut server, mut client) = configs();
+        server.enable_secret_extraction = true;
+        client.enable_secret_extraction = true;
+        let acceptor = TlsAcceptor::new(Arc::new(server));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = spawn_local(async move {
+            let stream = listener.accept().await.unwrap();
+            let stream = acceptor.accept(stream).await.unwrap();
+            let mut stream = stream.into_ktls().unwrap();
+            let mut request = [0; 4];
+            stream.read_exact(&mut request).await.unwrap();
+            stream.write_all(b"pong").await.unwrap();
+            stream.close().await.unwrap();
+            request
+        });
+
+        let connector = TlsConnector::new(Arc::new(client));
+        let stream = TcpStream::connect(addr).await.unwrap();
+        if let Err(err) = stream.set_tls_ulp() {
+            // no `tls` module in this kernel: TLS stays in user space
+            assert_eq!(io::Error::from(err).kind(), io::ErrorKind::NotFound);
+            return;
+        }
+        let stream = connector.connect("localhost", stream).await.unwrap();
+        let mut stream = stream.into_ktls().unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        assert_eq!(response, b"pong");
+        assert_eq!(&server.await, b"ping");
+    });
+}